@@ -2,24 +2,142 @@ use std::str::FromStr;
 
 use nanoserde::{DeJson, SerJson};
 
-use crate::{keys::Seed, network::Endpoint};
+use crate::{keys::Seed, network::{Compression, Endpoint}, protocol::{DEFAULT_CHAIN_ID, DEFAULT_GENESIS_TIME_MS}};
+
+/// An account funded at genesis, for bootstrapping testnets
+#[derive(SerJson, DeJson)]
+pub struct GenesisAllocation {
+    pub address: String,
+    /// The raw amount allocated to `address`
+    pub amount: u64
+}
+
+/// The most requests of an RPC action, such as `work_generate`, each client
+/// address may make a minute
+#[derive(SerJson, DeJson)]
+pub struct RpcRateLimit {
+    pub action: String,
+    pub per_minute: u32
+}
 
 #[derive(SerJson, DeJson)]
 pub struct Config {
     pub node_bind_endpoint: Endpoint,
     pub node_external_endpoint: Endpoint,
     pub rpc_endpoint: Endpoint,
+    /// The IP addresses permitted to make privileged RPC requests, such as submitting votes
+    pub rpc_allowlist: Vec<String>,
+    /// The maximum number of RPC connections waiting to be accepted
+    pub rpc_listen_backlog: u32,
+    /// The path of a Unix domain socket to also serve RPC on, or empty for none.
+    /// It is only accessible to the node's user, and permits privileged requests.
+    pub rpc_unix_socket: String,
+    /// The algorithm the node compresses what it sends with. Only RPC responses to
+    /// clients which send `AcceptCompression` are compressed so far.
+    pub compression: Compression,
+    /// Limits on how often each client address may make each action; actions not listed are unlimited
+    pub rpc_rate_limits: Vec<RpcRateLimit>,
+    /// The maximum number of confirmation subscriptions of a single RPC connection
+    pub max_subscriptions_per_connection: usize,
+    /// The maximum number of confirmation subscriptions of all RPC connections
+    pub max_subscriptions: usize,
     pub db_endpoint: Endpoint,
+    /// How long, in milliseconds, to wait for the db to be reachable at startup, or 0 to skip the check
+    pub db_health_check_timeout_ms: u64,
+    /// Whether to exit, rather than warn, if the db is unreachable at startup
+    pub db_required: bool,
+    /// The most time, in milliseconds, to wait between attempts to reconnect to the db
+    pub db_max_backoff_ms: u64,
     pub node_seed: Seed,
+    /// The node seed as a 24 word BIP39 mnemonic, used instead of `node_seed` unless empty
+    pub node_mnemonic: String,
+    /// The unix time, in milliseconds, at which slot 0 starts; must match the rest of the network
+    pub genesis_timestamp: u64,
+    /// The id of the network, which transactions and blocks are signed for so that
+    /// they can't be replayed on another; must match the rest of the network
+    pub chain_id: u32,
     pub initial_peers: Vec<Endpoint>,
+    /// Secondary peers, also contacted if none were found through `initial_peers`
+    /// within `peer_discovery_timeout_ms`
+    pub fallback_peers: Vec<Endpoint>,
+    /// How long, in milliseconds, to look for peers through `initial_peers` alone
+    pub peer_discovery_timeout_ms: u64,
     pub max_less_peers: usize,
     pub max_greater_peers: usize,
+    /// The number of highest-weight peers included in every broadcast, alongside a random sample
+    pub broadcast_priority_peers: usize,
     pub allow_peers_with_private_ip_addresses: bool,
     pub allow_peers_with_node_external_ip_address: bool,
+    /// How far ahead of local time, in milliseconds, the slot of a peer's
+    /// telemetry or block may be before it is rejected
+    pub max_clock_skew_ms: u64,
+    /// How much a peer's score is raised when it sends us shreds or finalized blocks;
+    /// among peers of equal weight, the lowest-scored are evicted first
+    pub peer_score_reward: i32,
+    /// How much a peer's score is lowered when it sends us undecodable data or misses a telemetry update
+    pub peer_score_penalty: i32,
+    /// Whether to snapshot the peer table to `data_dir` and restore it on start,
+    /// so that restarting doesn't rediscover peers from scratch
+    pub graceful_restart: bool,
+    /// How long, in milliseconds, broadcast notes are remembered to avoid re-broadcasting them
+    pub broadcast_dedup_window_ms: u64,
+    /// How many broadcast notes are remembered per dedup window
+    pub broadcast_dedup_capacity: usize,
+    /// The maximum number of datagrams broadcast per 10ms, or 0 for no limit
+    pub broadcast_max_packets_per_tick: usize,
+    /// The maximum outbound broadcast bandwidth in Mbps, or 0 for no limit
+    pub broadcast_max_mbps: u64,
+    /// How long, in milliseconds, the receiver waits for a packet before doing periodic maintenance
+    pub receiver_read_timeout_ms: u64,
+    /// The maximum size, in bytes, of the data broadcast for a single slot
+    pub max_block_size: u32,
+    /// The most slots whose shreds are assembled at once; the least recently active is evicted beyond it
+    pub assembler_max_pending: usize,
+    /// How many slots behind the newest slot a partially received slot is kept before being evicted
+    pub assembler_max_slot_lag: u64,
     pub tx_pool_size: usize,
+    /// The most transactions a leader includes in a single slot, highest work first;
+    /// the rest stay pooled for later slots
+    pub max_txs_per_slot: usize,
+    /// The number of finalized account writes batched into a single db write
+    pub db_batch_size: usize,
+    /// The smallest raw amount a transfer may send, or 0 to accept any amount
+    pub min_transaction_amount: u64,
     pub open_pool_size: usize,
+    /// The most opens a leader includes in a single block, highest work first
+    pub max_opens_per_block: usize,
     pub vote_pool_size: usize,
-    pub data_dir: String
+    pub data_dir: String,
+    /// Whether to prune accounts that hold, represent and do nothing at each epoch boundary.
+    /// Pruning changes the accounts, so every node of the network must agree on it.
+    pub prune_empty_accounts: bool,
+    /// The most unfinalized blocks of a single leader kept at once, limiting competing blocks
+    pub max_active_blocks_per_leader: usize,
+    /// Whether to reject blocks whose state hash doesn't match the accounts after applying them;
+    /// off until all leaders commit to state hashes
+    pub verify_state_hash: bool,
+    /// The most finalized blocks kept in memory; older ones are spilled to `data_dir`
+    pub max_finalized_in_memory: usize,
+    /// How often, in milliseconds, persistent stores are flushed to disk
+    pub flush_interval_ms: u64,
+    /// How long, in milliseconds, a `work_generate` RPC may search for work before failing
+    pub work_generate_timeout_ms: u64,
+    /// The maximum number of `work_generate` RPCs generating work at once
+    pub max_concurrent_work_generations: usize,
+    /// The maximum number of `work_generate` RPCs waiting for a running one to finish;
+    /// beyond it, they are rejected as busy
+    pub max_queued_work_generations: usize,
+    /// The maximum number of blocks verified at once
+    pub max_concurrent_block_verifications: usize,
+    /// The maximum number of votes in a block; blocks with more are rejected
+    pub max_votes_per_block: usize,
+    pub genesis_allocations: Vec<GenesisAllocation>,
+    /// The initial representatives, each allocated `amount` which it represents itself,
+    /// so that testnets have several leaders from the first slot
+    pub genesis_representatives: Vec<GenesisAllocation>,
+    /// The raw amount burned from the first funding of each opened account, or 0 for no fee.
+    /// Like the rest of the genesis, it must be the same on every node of the network.
+    pub genesis_open_fee: u64
 }
 
 impl Config {
@@ -28,17 +146,62 @@ impl Config {
             node_bind_endpoint: Endpoint::from_str("0.0.0.0:41594").unwrap(),
             node_external_endpoint: Endpoint::from_str("127.0.0.1:41594").unwrap(),
             rpc_endpoint: Endpoint::from_str("127.0.0.1:41595").unwrap(),
+            rpc_allowlist: vec!["127.0.0.1".to_string()],
+            rpc_listen_backlog: 1_024,
+            rpc_unix_socket: String::new(),
+            compression: Compression::default(),
+            rpc_rate_limits: Vec::new(),
+            max_subscriptions_per_connection: 16,
+            max_subscriptions: 10_000,
             db_endpoint: Endpoint::from_str("127.0.0.1:41596").unwrap(),
+            db_health_check_timeout_ms: 2_000,
+            db_required: false,
+            db_max_backoff_ms: 5_000,
             node_seed: Seed::random(),
+            node_mnemonic: String::new(),
+            genesis_timestamp: DEFAULT_GENESIS_TIME_MS,
+            chain_id: DEFAULT_CHAIN_ID,
             initial_peers: Vec::new(),
+            fallback_peers: Vec::new(),
+            peer_discovery_timeout_ms: 60_000,
             max_less_peers: 250,
             max_greater_peers: 250,
+            broadcast_priority_peers: 4,
             allow_peers_with_private_ip_addresses: false,
             allow_peers_with_node_external_ip_address: false,
+            max_clock_skew_ms: 2_000,
+            peer_score_reward: 1,
+            peer_score_penalty: 4,
+            graceful_restart: true,
+            broadcast_dedup_window_ms: 5_000,
+            broadcast_dedup_capacity: 100_000,
+            broadcast_max_packets_per_tick: 0,
+            broadcast_max_mbps: 0,
+            receiver_read_timeout_ms: 1_000,
+            max_block_size: 8 * 1024 * 1024,
+            assembler_max_pending: 64,
+            assembler_max_slot_lag: 64,
             tx_pool_size: 50_000,
+            max_txs_per_slot: 20_000,
+            db_batch_size: 1_024,
+            min_transaction_amount: 0,
             open_pool_size: 25,
+            max_opens_per_block: 16,
             vote_pool_size: 1_000,
-            data_dir: "./data".to_string()
+            data_dir: "./data".to_string(),
+            prune_empty_accounts: false,
+            max_active_blocks_per_leader: 64,
+            verify_state_hash: false,
+            max_finalized_in_memory: 100_000,
+            flush_interval_ms: 1_000,
+            work_generate_timeout_ms: 10_000,
+            max_concurrent_work_generations: 2,
+            max_queued_work_generations: 16,
+            max_concurrent_block_verifications: 4,
+            max_votes_per_block: 1_024,
+            genesis_allocations: Vec::new(),
+            genesis_representatives: Vec::new(),
+            genesis_open_fee: 0
         }
     }
 }
@@ -8,41 +8,41 @@ pub fn manual(level: &str, message: &str) {
 #[macro_export]
 macro_rules! log_trace {
     ($($arg:tt)*) => {
-        crate::app::log::manual("TRACE", &format!($($arg)*));
+        $crate::app::log::manual("TRACE", &format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! log_debug {
     ($($arg:tt)*) => {
-        crate::app::log::manual("DEBUG", &format!($($arg)*));
+        $crate::app::log::manual("DEBUG", &format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! log_info {
     ($($arg:tt)*) => {
-        crate::app::log::manual("INFO", &format!($($arg)*));
+        $crate::app::log::manual("INFO", &format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! log_warn {
     ($($arg:tt)*) => {
-        crate::app::log::manual("WARN", &format!($($arg)*));
+        $crate::app::log::manual("WARN", &format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! log_error {
     ($($arg:tt)*) => {
-        crate::app::log::manual("ERROR", &format!($($arg)*));
+        $crate::app::log::manual("ERROR", &format!($($arg)*));
     };
 }
 
 #[macro_export]
 macro_rules! log_critical {
     ($($arg:tt)*) => {
-        crate::app::log::manual("CRITICAL", &format!($($arg)*));
+        $crate::app::log::manual("CRITICAL", &format!($($arg)*));
     };
 }
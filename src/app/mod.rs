@@ -1,23 +1,27 @@
 mod config;
 #[macro_use]
 pub mod log;
+mod shutdown;
 
-use crate::network::{Assembler, Broadcaster, Endpoint, Receiver, Transmitter};
-use crate::process::{self, Handle};
-use crate::protocol::{Amount, Scheduler};
-use crate::rpc::RpcServer;
-use crate::state::{Block, State};
+use crate::network::{Assembler, AssemblerConfig, Broadcaster, DedupWindow, Endpoint, Receiver, Transmitter, TransmitterConfig, MAX_SHRED_DATA_SIZE};
+use crate::process::{self, Handle, Message, Oncebox};
+use crate::protocol::{self, Amount, Scheduler};
+use crate::rpc::{self, RateLimiter, RpcServer, Subscriptions};
+use crate::state::{Block, BlockVerifier, State, StateConfig, WorkGenerator};
+use crate::storage::{self, Flusher};
 use crate::waiting::{OpenPool, TxPool};
 use crate::{
-    keys::{Identity, Private, Seed},
-    util::{Error, Version},
+    error,
+    keys::{Identity, Public, Seed},
+    util::{Error, NodeInfo, Version},
 };
-use config::Config;
+use config::{Config, GenesisAllocation};
 use nanoserde::{DeJson, SerJson};
-use std::net::{TcpListener, UdpSocket};
+use std::net::{IpAddr, UdpSocket};
 use std::process::exit;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use std::{
     fs::{self, File},
     io::Write
@@ -25,9 +29,28 @@ use std::{
 
 const VERSION: Version = Version::new(0, 1, 0);
 const CONFIG_FILE: &str = "config.json";
+/// How long the state may take to persist its accounts when shutting down
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check the db at `endpoint` is reachable within `timeout_ms` (0 to skip the check),
+/// warning if it isn't, or failing if the db is `required`.
+fn check_db(endpoint: Endpoint, timeout_ms: u64, required: bool) -> Result<(), Error> {
+    if timeout_ms == 0 {
+        return Ok(());
+    }
+    if let Err(e) = process::check_remote(endpoint, Duration::from_millis(timeout_ms)) {
+        if required {
+            return Err(error!("Failed to reach db at tcp://{}: {}", endpoint, e));
+        }
+        log_warn!("Failed to reach db at tcp://{}: {}; finalized state will not be persisted until it is reachable", endpoint, e);
+    }
+    Ok(())
+}
 
 pub fn start() {
+    let node_info = NodeInfo::start(VERSION);
     log_info!("Starting Starlight node version {}", VERSION);
+    shutdown::listen();
     
     // Initialize the configuration for the Starlight node
     let config = match fs::read_to_string(CONFIG_FILE) {
@@ -59,11 +82,30 @@ pub fn start() {
         }
     };
 
+    // Set the network-wide slot origin before any slot is computed
+    if let Err(e) = protocol::set_genesis_time_ms(config.genesis_timestamp, SystemTime::now()) {
+        log_error!("Invalid genesis timestamp: {}", e);
+        exit(1);
+    }
+    // ...and the network id, before anything is signed or verified
+    protocol::set_chain_id(config.chain_id);
+
     // Derive node identity from the configuration
-    let private = config.node_seed.derive(0);
+    let seed = match config.node_mnemonic.is_empty() {
+        true => config.node_seed.clone(),
+        false => match Seed::from_mnemonic(&config.node_mnemonic, "") {
+            Ok(seed) => seed,
+            Err(e) => {
+                log_error!("Invalid node mnemonic: {}", e);
+                exit(1);
+            }
+        }
+    };
+    let private = seed.derive(0);
     let public = private.to_public();
     log_info!("Using public key {}", public);
     log_info!("Using address {}", public.to_address());
+    log_info!("Using {} compression", config.compression);
 
     // Setup network identity and UDP socket for communication
     let id = Identity { private, public };
@@ -77,41 +119,188 @@ pub fn start() {
         }
     });
 
-    // Start the network broadcaster and transmitter process
-    let broadcaster = process::spawn_endless(Broadcaster::new(
-        network_socket.clone()
-    ));
-    let transmitter = process::spawn_endless(Transmitter::new(
-        network_socket.clone(),
-        config.node_external_endpoint,
-        id,
-        Arc::new(config.initial_peers),
-        config.max_less_peers,
-        config.max_greater_peers,
-        Box::new(|_| Amount::from_raw(1)),
-        VERSION,
-        config.allow_peers_with_private_ip_addresses,
-        config.allow_peers_with_node_external_ip_address,
-        broadcaster
-    ));
+    // Setup deduplication of broadcast notes
+    let dedup = match DedupWindow::new(
+        Duration::from_millis(config.broadcast_dedup_window_ms),
+        config.broadcast_dedup_capacity,
+        Instant::now()
+    ) {
+        Ok(dedup) => dedup,
+        Err(e) => {
+            log_error!("Invalid broadcast dedup configuration: {}", e);
+            exit(1);
+        }
+    };
+
+    // A zero read timeout would make the socket block forever
+    if config.receiver_read_timeout_ms == 0 {
+        log_error!("Invalid receiver configuration: receiver_read_timeout_ms must be positive");
+        exit(1);
+    }
+
+    // Assembled blocks must fit in the shreds of a single slot
+    if config.max_block_size as usize > MAX_SHRED_DATA_SIZE {
+        log_error!("Invalid network configuration: max_block_size must be at most {}", MAX_SHRED_DATA_SIZE);
+        exit(1);
+    }
+    // ...and a zero flush interval would never wait between flushes
+    if config.flush_interval_ms == 0 {
+        log_error!("Invalid storage configuration: flush_interval_ms must be positive");
+        exit(1);
+    }
+
+    // Parse the RPC settings, so that no process is started with invalid ones
+    let rpc_allowlist = match config.rpc_allowlist.iter()
+        .map(|ip| ip.parse::<IpAddr>().map_err(|_| ip))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(rpc_allowlist) => rpc_allowlist,
+        Err(ip) => {
+            log_error!("Invalid RPC allowlist address: {}", ip);
+            exit(1);
+        }
+    };
+    let rpc_rate_limits: Vec<(String, u32)> = config.rpc_rate_limits.iter()
+        .map(|limit| (limit.action.clone(), limit.per_minute))
+        .collect();
+    let rate_limiter = match RateLimiter::new(&rpc_rate_limits) {
+        Ok(rate_limiter) => Arc::new(rate_limiter),
+        Err(e) => {
+            log_error!("Invalid RPC rate limits: {}", e);
+            exit(1);
+        }
+    };
+
+    // Check the database is reachable, so writes aren't silently lost
+    if let Err(e) = check_db(config.db_endpoint, config.db_health_check_timeout_ms, config.db_required) {
+        log_error!("{}", e);
+        exit(1);
+    }
 
     // Initialize blockchain state
     let genesis = Block::genesis(id);
-    let state = process::spawn(match State::new(
+    let parse_allocations = |allocations: &[GenesisAllocation]| allocations.iter().map(|x| {
+        Ok((Public::from_address(&x.address)?, Amount::from_raw(x.amount)))
+    }).collect::<Result<Vec<_>, Error>>();
+    let genesis_allocations = match parse_allocations(&config.genesis_allocations) {
+        Ok(genesis_allocations) => genesis_allocations,
+        Err(e) => {
+            log_error!("Invalid genesis allocation: {}", e);
+            exit(1);
+        }
+    };
+    let genesis_representatives = match parse_allocations(&config.genesis_representatives) {
+        Ok(genesis_representatives) => genesis_representatives,
+        Err(e) => {
+            log_error!("Invalid genesis representative: {}", e);
+            exit(1);
+        }
+    };
+    let work_generator = match WorkGenerator::new(
+        Duration::from_millis(config.work_generate_timeout_ms),
+        config.max_concurrent_work_generations,
+        config.max_queued_work_generations
+    ) {
+        Ok(work_generator) => work_generator,
+        Err(e) => {
+            log_error!("Invalid work generation configuration: {}", e);
+            exit(1);
+        }
+    };
+    let block_verifier = match BlockVerifier::new(
+        config.max_concurrent_block_verifications,
+        config.max_votes_per_block
+    ) {
+        Ok(block_verifier) => block_verifier,
+        Err(e) => {
+            log_error!("Invalid block verification configuration: {}", e);
+            exit(1);
+        }
+    };
+    let state_config = StateConfig {
+        data_dir: config.data_dir.clone(),
+        prune_empty_accounts: config.prune_empty_accounts,
+        genesis_allocations,
+        genesis_representatives,
+        genesis_open_fee: Amount::from_raw(config.genesis_open_fee),
+        vote_pool_size: config.vote_pool_size,
+        max_active_blocks_per_leader: config.max_active_blocks_per_leader,
+        max_clock_skew: Duration::from_millis(config.max_clock_skew_ms),
+        verify_state_hash: config.verify_state_hash,
+        max_finalized_in_memory: config.max_finalized_in_memory
+    };
+    let state = match State::new(
         id,
-        &config.data_dir,
-        Arc::new(genesis)
+        Arc::new(genesis),
+        state_config,
+        work_generator,
+        node_info,
+        block_verifier,
+        Subscriptions::new(config.max_subscriptions_per_connection, config.max_subscriptions)
     ) {
         Ok(state) => state,
         Err(e) => {
             log_error!("Failed to create state: {}", e);
             exit(1);
         }
-    });
+    };
+
+    // Start the network broadcaster and transmitter process
+    let broadcaster = process::spawn_endless(Broadcaster::new(
+        network_socket.clone(),
+        match config.broadcast_max_packets_per_tick {
+            0 => None,
+            n => Some(n)
+        },
+        match config.broadcast_max_mbps {
+            0 => None,
+            n => Some(n)
+        },
+        state.broadcast_send_errors(),
+        state.broadcast_dropped()
+    ));
+    let transmitter_config = TransmitterConfig {
+        visible_ep: config.node_external_endpoint,
+        initial_peers: Arc::new(config.initial_peers),
+        fallback_peers: Arc::new(config.fallback_peers),
+        discovery_timeout: Duration::from_millis(config.peer_discovery_timeout_ms),
+        max_less: config.max_less_peers,
+        max_greater: config.max_greater_peers,
+        priority_peers: config.broadcast_priority_peers,
+        version: VERSION,
+        allow_peers_with_private_ip_addresses: config.allow_peers_with_private_ip_addresses,
+        allow_peers_with_node_external_ip_address: config.allow_peers_with_node_external_ip_address,
+        max_clock_skew: Duration::from_millis(config.max_clock_skew_ms),
+        dedup,
+        score_reward: config.peer_score_reward,
+        score_penalty: config.peer_score_penalty,
+        peer_snapshot: config.graceful_restart.then(|| storage::peers_path(&config.data_dir))
+    };
+    let transmitter = process::spawn_endless(Transmitter::new(
+        id,
+        transmitter_config,
+        state.weight_fn(),
+        broadcaster,
+        state.peer_count(),
+        state.finalized_height(),
+        state.sync_target()
+    ));
+
+    // Periodically flush the persistent stores of the state
+    let stores = state.stores();
+    process::spawn_solitary_endless(Flusher::new(
+        stores.clone(),
+        Duration::from_millis(config.flush_interval_ms)
+    ));
+    let account_exists = state.account_exists_fn();
+    let leader_of = state.leader_fn();
+    let evicted_shred_lists = state.evicted_shred_lists();
+    let state = process::spawn(state);
 
     // Initialize and start the RPC server
-    let rpc_socket = match TcpListener::bind(
-        config.rpc_endpoint.to_socket_addr()
+    let rpc_socket = match rpc::bind_listener(
+        config.rpc_endpoint,
+        config.rpc_listen_backlog
     ) {
         Ok(socket) => socket,
         Err(e) => {
@@ -119,36 +308,74 @@ pub fn start() {
             exit(1);
         }
     };
-    let rpc = RpcServer::new(state.clone(), rpc_socket);
+    let rpc = RpcServer::new(state.clone(), transmitter.clone(), rpc_socket, rpc_allowlist, config.compression, rate_limiter);
     process::spawn_solitary(rpc);
     log_info!("RPC listening on tcp://{}", config.rpc_endpoint);
+    if !config.rpc_unix_socket.is_empty() {
+        #[cfg(unix)]
+        match rpc::UnixRpcServer::bind(state.clone(), transmitter.clone(), &config.rpc_unix_socket, config.compression) {
+            Ok(rpc) => {
+                process::spawn_solitary(rpc);
+                log_info!("RPC listening on unix://{}", config.rpc_unix_socket);
+            }
+            Err(e) => {
+                log_error!("Failed to bind to {}: {}", config.rpc_unix_socket, e);
+                exit(1);
+            }
+        }
+        #[cfg(not(unix))]
+        log_warn!("Unix domain sockets are unsupported on this platform; not serving RPC on {}", config.rpc_unix_socket);
+    }
     
     // Connect to database
-    let db = process::connect_remote(config.db_endpoint);
+    let db = process::connect_remote(config.db_endpoint, Duration::from_millis(config.db_max_backoff_ms));
 
     // Initialize transaction pools
     let n_cores = thread::available_parallelism().unwrap().get();
     let tx_pools: Vec<Handle> = (0..n_cores).map(|_| process::spawn(TxPool::new(
         config.tx_pool_size / n_cores,
         db.clone(),
-        state.clone()
+        state.clone(),
+        config.db_batch_size,
+        Amount::from_raw(config.min_transaction_amount),
+        (config.max_txs_per_slot / n_cores).max(1)
     ))).collect();
-    let open_pool = process::spawn(OpenPool::new(config.open_pool_size, state));
+    state.send(Message::TxPools(Box::new(tx_pools.clone())));
+    let open_pool = process::spawn(OpenPool::new(
+        config.open_pool_size,
+        state.clone(),
+        config.max_opens_per_block,
+        account_exists
+    ));
 
     // Create scheduler to synchronize open pool and transaction pools
     let notified = Some(open_pool.clone()).into_iter().chain(tx_pools.iter().cloned()).collect();
     process::spawn_solitary_endless(Scheduler::new(notified));
 
     // Create assembler
-    let assembler = process::spawn(Assembler::new());
+    let assembler_config = AssemblerConfig {
+        max_block_size: config.max_block_size,
+        max_pending: config.assembler_max_pending,
+        max_slot_lag: config.assembler_max_slot_lag
+    };
+    let assembler = process::spawn(Assembler::new(
+        id,
+        assembler_config,
+        transmitter.clone(),
+        state.clone(),
+        leader_of,
+        evicted_shred_lists
+    ));
 
     // Start the network receiver process
     process::spawn(Receiver::new(
         network_socket,
+        Duration::from_millis(config.receiver_read_timeout_ms),
         transmitter,
         assembler,
         tx_pools,
-        open_pool
+        open_pool,
+        state.clone()
     ));
     log_info!("SLP listening on udp://{}", config.node_bind_endpoint);
     log_info!(
@@ -158,4 +385,39 @@ pub fn start() {
     if config.node_external_endpoint.addr == [127, 0, 0, 1] {
         log_warn!("SLP external endpoint is localhost; this node will not be able to communicate over the network");
     }
-}
\ No newline at end of file
+
+    // Run until asked to stop, then flush the persistent stores so no writes are lost
+    shutdown::wait();
+    log_info!("Shutting down");
+    let stopped = Oncebox::new();
+    state.send(Message::Stop(Box::new(stopped.handle())));
+    if stopped.recv_timeout(STOP_TIMEOUT).is_none() {
+        log_warn!("State didn't stop in time; its accounts will be rebuilt on restart");
+    }
+    storage::flush_all(&stores);
+    exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, str::FromStr};
+
+    #[test]
+    fn test_check_db() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = Endpoint::from_str(&listener.local_addr().unwrap().to_string()).unwrap();
+
+        // a healthy db passes whether or not it is required
+        assert!(check_db(endpoint, 1_000, false).is_ok());
+        assert!(check_db(endpoint, 1_000, true).is_ok());
+
+        // an unhealthy db is only a warning unless it is required
+        drop(listener);
+        assert!(check_db(endpoint, 1_000, false).is_ok());
+        assert!(check_db(endpoint, 1_000, true).is_err());
+
+        // no check is made when disabled
+        assert!(check_db(endpoint, 0, true).is_ok());
+    }
+}
@@ -0,0 +1,43 @@
+use std::{sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use crate::process;
+
+/// How often `wait` checks whether a signal has arrived
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    // only async-signal-safe work may be done here
+    SIGNALED.store(true, Ordering::SeqCst);
+}
+
+/// Catch SIGINT and SIGTERM, rather than letting them kill the node, so that `wait` returns
+pub fn listen() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // safety: the handler only stores to an atomic
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Block until SIGINT or SIGTERM is received
+pub fn wait() {
+    while !SIGNALED.load(Ordering::SeqCst) {
+        process::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_for_signal() {
+        listen();
+        // safety: the handler installed by `listen` is async-signal-safe
+        assert_eq!(unsafe { libc::raise(libc::SIGTERM) }, 0);
+        wait();
+    }
+}
@@ -1,11 +1,30 @@
 use std::time::{Duration, Instant};
 use rand::seq::SliceRandom;
 
-use crate::{keys::Public, storage::Database};
+use crate::{keys::Public, network::{Shred, ShredParams}, storage::Database};
 
 use super::keys::Hash;
 use std::hint::black_box;
 
+/// A uniquely named temporary directory, removed when dropped, even during a panic.
+struct TempDir(String);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        Self(format!("{}/starlight_{}_{}", std::env::temp_dir().display(), name, Hash::random()))
+    }
+
+    fn path(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 fn hashing() {
     // Benchmark hashing of 1M-byte chunks
     let iterations = 5_000;
@@ -40,8 +59,8 @@ fn hashing() {
 }
 
 fn database() {
-    let dir = "./test_directory";
-    let db: Database<Public, u64> = Database::open(dir).unwrap();
+    let dir = TempDir::new("bench_database");
+    let db: Database<Public, u64> = Database::open(dir.path()).unwrap();
     let key_count = 10_000_000;
     let mut keys: Vec<Public> = (0..key_count)
         .map(|_| Public::random())
@@ -60,12 +79,56 @@ fn database() {
     println!("  Total time: {:?}", elapsed);
     println!("  Average time per read: {:?}", avg);
     println!("  Reads per second: {}", Duration::from_secs(1).as_nanos() / avg.as_nanos());
-    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn shredding() {
+    // Benchmark shredding an 8MB block, as a leader would, on one thread and on all
+    let iterations = 10;
+    let data = vec![0xA5u8; 8 * 1024 * 1024];
+    let params = ShredParams::default();
+    let single = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+    println!("Shredding 8MB:");
+    for (name, pool) in [("single thread", single), ("multi thread", rayon::ThreadPoolBuilder::new().build().unwrap())] {
+        let start = Instant::now();
+        for _ in 0..iterations {
+            black_box(pool.install(|| Shred::from_data(&data, 1024, &params)));
+        }
+        let elapsed = start.elapsed();
+        println!("  {} ({} threads): {:?} per iteration", name, pool.current_num_threads(), elapsed / iterations);
+    }
+    println!();
 }
 
 pub fn start() {
     println!("Starting Starlight benchmark suite");
     println!();
     hashing();
+    shredding();
     database();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_temp_dir_removed_on_panic() {
+        let mut path = String::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let dir = TempDir::new("bench_test");
+            std::fs::create_dir_all(dir.path()).unwrap();
+            std::fs::write(format!("{}/data", dir.path()), [1, 2, 3]).unwrap();
+            path = dir.path().to_string();
+            panic!("bench failed");
+        }));
+        assert!(result.is_err());
+        assert!(!path.is_empty());
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_temp_dirs_unique() {
+        assert_ne!(TempDir::new("bench_test").path(), TempDir::new("bench_test").path());
+    }
+}
@@ -16,10 +16,6 @@ impl Difficulty {
     const LEN: usize = 8;
     const HEX_LEN: usize = Self::LEN * 2;
 
-    pub const fn new(v: u64) -> Self {
-        Self(v)
-    }
-
     pub fn from_le_fixed(s: &[u8; Self::LEN]) -> Self {
         Difficulty(u64::from_le_bytes(*s))
     }
@@ -29,14 +25,14 @@ impl Difficulty {
         Ok(Difficulty(u64::from_be_bytes(b)))
     }
 
-    pub fn from_le_slice(s: &[u8]) -> Result<Self, Error> {
-        let b = <[u8; Self::LEN]>::try_from(s)?;
-        Ok(Difficulty(u64::from_le_bytes(b)))
-    }
-
     pub fn as_u64(&self) -> u64 {
         self.0
     }
+
+    /// How many times more work this difficulty takes than `base`, on average.
+    pub fn multiplier(self, base: Difficulty) -> f64 {
+        (u64::MAX - base.0) as f64 / (u64::MAX - self.0).max(1) as f64
+    }
 }
 
 impl Debug for Difficulty {
@@ -72,4 +68,11 @@ mod tests {
             Difficulty::from_str("fffffff800000000").unwrap()
         )
     }
+
+    #[test]
+    fn multiplier() {
+        assert_eq!(Difficulty::BASE.multiplier(Difficulty::BASE), 1.0);
+        let harder = Difficulty::from_str("fffffffc00000000").unwrap();
+        assert!((harder.multiplier(Difficulty::BASE) - 2.0).abs() < 1e-6);
+    }
 }
@@ -23,17 +23,47 @@ impl Hash {
         Self(blake3::hash(slice).into())
     }
 
+    /// Hash `slice` behind the tag of `domain`
+    pub fn digest_in(domain: Domain, slice: &[u8]) -> Self {
+        let mut hb = HashBuilder::with_domain(domain);
+        hb.update(slice);
+        hb.finish()
+    }
+
     pub const fn zero() -> Self {
         Self([0u8; 32])
     }
 }
 
+/// The kinds of signed structures, whose tag is prepended to the preimage of
+/// their hash so that the preimages of different kinds can never overlap
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Domain {
+    Block = 1,
+    Telemetry = 2,
+    Shred = 3,
+    ShredRequest = 4,
+    SyncRequest = 5,
+    Vote = 6,
+    Tx = 7,
+    Open = 8,
+    SyncResponse = 9,
+}
+
 pub struct HashBuilder(Hasher);
 impl HashBuilder {
     pub fn new() -> Self {
         Self(Hasher::new())
     }
 
+    /// A `HashBuilder` which has already been fed the tag of `domain`
+    pub fn with_domain(domain: Domain) -> Self {
+        let mut hb = Self::new();
+        hb.update(&[domain as u8]);
+        hb
+    }
+
     pub fn update(&mut self, data: &[u8]) {
         self.0.update(data);
     }
@@ -53,3 +83,45 @@ impl Write for HashBuilder {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOMAINS: [Domain; 9] = [
+        Domain::Block,
+        Domain::Telemetry,
+        Domain::Shred,
+        Domain::ShredRequest,
+        Domain::SyncRequest,
+        Domain::Vote,
+        Domain::Tx,
+        Domain::Open,
+        Domain::SyncResponse,
+    ];
+
+    #[test]
+    fn test_domain_changes_hash() {
+        let bytes = [7u8; 40];
+        for domain in DOMAINS {
+            assert!(Hash::digest_in(domain, &bytes) != Hash::digest(&bytes));
+            let mut hb = HashBuilder::with_domain(domain);
+            hb.update(&bytes[..10]);
+            hb.update(&bytes[10..]);
+            assert!(hb.finish() == Hash::digest_in(domain, &bytes));
+        }
+    }
+
+    #[test]
+    fn test_domains_dont_collide() {
+        // the same preimage hashes differently in every domain
+        let bytes = [7u8; 40];
+        let hashes: std::collections::HashSet<Hash> = DOMAINS.iter().map(|&d| Hash::digest_in(d, &bytes)).collect();
+        assert_eq!(hashes.len(), DOMAINS.len());
+        // nor can a preimage in one domain be crafted to begin with another's tag
+        let mut forged = vec![Domain::Tx as u8];
+        forged.extend_from_slice(&bytes);
+        assert!(Hash::digest_in(Domain::Vote, &forged) != Hash::digest_in(Domain::Tx, &bytes));
+        assert!(Hash::digest(&forged) == Hash::digest_in(Domain::Tx, &bytes));
+    }
+}
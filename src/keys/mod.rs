@@ -9,13 +9,15 @@ mod signature;
 mod work;
 
 pub use difficulty::Difficulty;
-pub use hash::{Hash, HashBuilder};
+pub use hash::{Domain, Hash, HashBuilder};
 pub use identity::Identity;
 pub use private::Private;
 pub use public::Public;
 pub use seed::Seed;
 pub use signature::Signature;
 pub use work::Work;
+#[cfg(test)]
+pub use work::fixtures as work_fixtures;
 
 #[cfg(test)]
 mod tests {
@@ -4,7 +4,6 @@ use crate::hexify;
 use crate::keys::public::Public;
 use crate::keys::signature::Signature;
 use ed25519_dalek_blake2_feeless::{ExpandedSecretKey, PublicKey, SecretKey};
-use rand::RngCore;
 
 /// 256 bit private key which can generate a public key.
 #[derive(Clone, Copy)]
@@ -17,22 +16,17 @@ impl Private {
     const LEN: usize = 32;
 
     /// The zero private key
+    #[cfg(test)]
     pub(super) const fn zero() -> Self {
         Self([0u8; 32])
     }
 
-    pub fn random() -> Self {
-        let mut private = Private::zero();
-        rand::thread_rng().fill_bytes(&mut private.0);
-        private
-    }
-
     /// Generate the public key for this private key.
-    pub fn to_public(&self) -> Public {
+    pub fn to_public(self) -> Public {
         Public::from(self.internal_public())
     }
 
-    fn to_ed25519_dalek(&self) -> SecretKey {
+    fn to_ed25519_dalek(self) -> SecretKey {
         SecretKey::from_bytes(&self.0).unwrap()
     }
 
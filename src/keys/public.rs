@@ -114,7 +114,7 @@ impl Public {
     }
 
     /// Convert the public key to an address string
-    pub fn to_address(&self) -> String {
+    pub fn to_address(self) -> String {
         let mut number = U512::from_big_endian(&self.0);
         let check = U512::from_little_endian(&self.checksum());
         number <<= 40;
@@ -145,7 +145,7 @@ impl Public {
 #[static_init::dynamic]
 static PARAMS: Params = {
     let mut params = Params::new();
-    params.hash_length(5);
+    params.hash_length(Public::ADDRESS_CHECKSUM_LEN);
     params
 };
 
@@ -157,7 +157,7 @@ impl Public {
         PublicKey::from_bytes(&self.0).map_err(|_| error!("invalid public key"))
     }
 
-    fn checksum(&self) -> [u8; 5] {
+    fn checksum(&self) -> [u8; Self::ADDRESS_CHECKSUM_LEN] {
         PARAMS.hash(&self.0).as_bytes().try_into().unwrap()
     }
 
@@ -167,14 +167,10 @@ impl Public {
 
     pub fn verify(&self, hash: &Hash, signature: &Signature) -> Result<(), Error> {
         let dalek_key = self.dalek_key()?;
-        let signature_internal = signature.internal().or_else(|_| {
-            return Err(error!("invalid signature"));
-        })?;
+        let signature_internal = signature.internal()?;
         dalek_key
             .verify(hash.as_bytes(), &signature_internal)
-            .or_else(|_| {
-                return Err(error!("verification failed"));
-            })
+            .map_err(|_| error!("verification failed"))
     }
 }
 
@@ -208,6 +204,6 @@ mod tests {
     #[test]
     fn hex() {
         let s = "19D3D919475DEED4696B5D13018151D1AF88B2BD3BCFF048B45031C1F36D1858";
-        assert_eq!(s, &Public::from_str(&s).unwrap().as_hex());
+        assert_eq!(s, &Public::from_str(s).unwrap().as_hex());
     }
 }
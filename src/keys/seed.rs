@@ -1,8 +1,10 @@
 // Derived from the keys module of github.com/feeless/feeless@978eba7.
 use crate::hexify;
 use crate::keys::private::Private;
+use crate::{error, util::Error};
 use bincode::{Decode, Encode};
 use rand::RngCore;
+use sha2::{Digest, Sha256};
 
 use blake2b_simd::Params;
 
@@ -13,6 +15,14 @@ static BLAKE2B_PARAMS: Params = {
     params
 };
 
+/// The BIP39 English wordlist, in order
+#[static_init::dynamic]
+static WORDLIST: Vec<&'static str> = include_str!("english.txt").lines().collect();
+
+/// The number of words in a mnemonic: 11 bits each for the 256 bits of the
+/// seed and the 8 bit checksum
+const MNEMONIC_WORDS: usize = 24;
+
 /// 256 bit seed used to derive multiple addresses.
 ///
 /// See https://docs.nano.org/integration-guides/the-basics/#seed for details.
@@ -30,11 +40,72 @@ impl Seed {
 
     /// Generate a secure random seed.
     pub fn random() -> Self {
+        Self::from_entropy(&mut rand::thread_rng())
+    }
+
+    /// Generate a seed from `rng`. Passing a seeded RNG yields a
+    /// reproducible seed, which is useful for test fixtures.
+    pub fn from_entropy(rng: &mut impl RngCore) -> Self {
         let mut seed = Seed::zero();
-        rand::thread_rng().fill_bytes(&mut seed.0);
+        rng.fill_bytes(&mut seed.0);
         seed
     }
 
+    /// Parse a 24 word BIP39 mnemonic, as written by `to_mnemonic`. The seed is
+    /// the entropy of the mnemonic, so that it round-trips. A non-empty
+    /// `passphrase` instead yields a hash of the entropy and the passphrase,
+    /// which `to_mnemonic` can't recover.
+    pub fn from_mnemonic(words: &str, passphrase: &str) -> Result<Seed, Error> {
+        let words: Vec<String> = words.split_whitespace().map(|word| word.to_lowercase()).collect();
+        if words.len() != MNEMONIC_WORDS {
+            return Err(error!("mnemonic has {} words, expected {}", words.len(), MNEMONIC_WORDS));
+        }
+        // 24 * 11 bits, the last 8 of which are the checksum
+        let mut bytes = [0u8; Self::LEN + 1];
+        for (i, word) in words.iter().enumerate() {
+            let index = WORDLIST.binary_search(&word.as_str())
+                .map_err(|_| error!("unknown mnemonic word: {}", word))?;
+            for bit in 0..11 {
+                if index & (1 << (10 - bit)) != 0 {
+                    let position = i * 11 + bit;
+                    bytes[position / 8] |= 0x80 >> (position % 8);
+                }
+            }
+        }
+        let mut seed = Seed::zero();
+        seed.0.copy_from_slice(&bytes[..Self::LEN]);
+        if bytes[Self::LEN] != seed.checksum() {
+            return Err(error!("invalid mnemonic checksum"));
+        }
+        if passphrase.is_empty() {
+            return Ok(seed);
+        }
+        let mut state = BLAKE2B_PARAMS.to_state();
+        state.update(&seed.0).update(passphrase.as_bytes());
+        Ok(Seed(state.finalize().as_bytes().try_into().unwrap()))
+    }
+
+    /// Write the seed as a 24 word BIP39 mnemonic from the English wordlist
+    #[cfg(test)]
+    pub fn to_mnemonic(&self) -> String {
+        let mut bytes = [0u8; Self::LEN + 1];
+        bytes[..Self::LEN].copy_from_slice(&self.0);
+        bytes[Self::LEN] = self.checksum();
+        let words: Vec<&str> = (0..MNEMONIC_WORDS).map(|i| {
+            let index = (0..11).fold(0, |index, bit| {
+                let position = i * 11 + bit;
+                (index << 1) | ((bytes[position / 8] >> (7 - position % 8)) & 1) as usize
+            });
+            WORDLIST[index]
+        }).collect();
+        words.join(" ")
+    }
+
+    /// The first byte of the SHA-256 of the seed, the checksum of its mnemonic
+    fn checksum(&self) -> u8 {
+        Sha256::digest(&self.0)[0]
+    }
+
     /// Derive a private key from the seed with an index.
     ///
     /// https://docs.nano.org/integration-guides/the-basics/#seed
@@ -44,4 +115,53 @@ impl Seed {
         buf[Self::LEN..].copy_from_slice(&index.to_be_bytes());
         Private(BLAKE2B_PARAMS.hash(&buf).as_bytes().try_into().unwrap())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_from_entropy_reproducible() {
+        let a = Seed::from_entropy(&mut StdRng::seed_from_u64(42));
+        let b = Seed::from_entropy(&mut StdRng::seed_from_u64(42));
+        assert!(a == b);
+        assert_eq!(
+            a.derive(0).to_public().to_address(),
+            b.derive(0).to_public().to_address()
+        );
+        let c = Seed::from_entropy(&mut StdRng::seed_from_u64(43));
+        assert!(a != c);
+    }
+
+    #[test]
+    fn test_mnemonic() {
+        // a BIP39 test vector
+        let seed = Seed::from_str("8080808080808080808080808080808080808080808080808080808080808080").unwrap();
+        let words = "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd \
+            amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless";
+        assert_eq!(seed.to_mnemonic(), words.split_whitespace().collect::<Vec<_>>().join(" "));
+        assert!(Seed::from_mnemonic(words, "").unwrap() == seed);
+        let zero = "abandon ".repeat(23) + "art";
+        assert!(Seed::from_mnemonic(&zero, "").unwrap() == Seed::zero());
+
+        let seed = Seed::from_entropy(&mut StdRng::seed_from_u64(42));
+        assert!(Seed::from_mnemonic(&seed.to_mnemonic(), "").unwrap() == seed);
+        // a passphrase yields a different, but deterministic, seed
+        let protected = Seed::from_mnemonic(&seed.to_mnemonic(), "hunter2").unwrap();
+        assert!(protected != seed);
+        assert!(Seed::from_mnemonic(&seed.to_mnemonic(), "hunter2").unwrap() == protected);
+    }
+
+    #[test]
+    fn test_invalid_mnemonic() {
+        let words = |n: usize| "abandon ".repeat(n - 1) + "art";
+        assert!(Seed::from_mnemonic(&words(12), "").is_err());
+        assert!(Seed::from_mnemonic(&words(25), "").is_err());
+        // the last word carries the checksum
+        assert!(Seed::from_mnemonic(&("abandon ".repeat(23) + "abandon"), "").is_err());
+        assert!(Seed::from_mnemonic(&("abandon ".repeat(23) + "satoshis"), "").is_err());
+    }
+}
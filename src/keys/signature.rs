@@ -1,7 +1,7 @@
 use bincode::{Decode, Encode};
 
 // Derived from the keys module of github.com/feeless/feeless@978eba7.
-use crate::hexify;
+use crate::{error, hexify, util::Error};
 
 /// A ed25519+blake2 signature that can be generated with [Private](crate::Private) and
 /// checked with [Public](crate::Public).
@@ -22,7 +22,39 @@ impl Signature {
         Self(bytes)
     }
 
-    pub(super) fn internal(&self) -> Result<ed25519_dalek_blake2_feeless::Signature, ()> {
-        ed25519_dalek_blake2_feeless::Signature::from_bytes(&self.0).or(Err(()))
+    /// Parse the signature, failing if it is structurally invalid,
+    /// such as when its scalar is out of range.
+    pub(super) fn internal(&self) -> Result<ed25519_dalek_blake2_feeless::Signature, Error> {
+        ed25519_dalek_blake2_feeless::Signature::from_bytes(&self.0)
+            .map_err(|e| error!("malformed signature: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::{Hash, Seed}, util};
+
+    #[test]
+    fn test_garbage_signature() {
+        let private = Seed([3; 32]).derive(0);
+        let hash = Hash::random();
+        // the high bits of the scalar are set, so the signature is malformed
+        let garbage = Signature::from_bytes([0xff; 64]);
+        assert!(garbage.internal().is_err());
+        let e = private.to_public().verify(&hash, &garbage).unwrap_err();
+        assert!(e.as_str().contains("malformed signature"));
+
+        // a well-formed signature of the wrong hash fails verification
+        let decoded: Signature = util::decode_from_slice(&[0x11; 64]).unwrap();
+        assert!(decoded.internal().is_ok());
+        assert!(private.to_public().verify(&hash, &decoded).is_err());
+        assert!(private.to_public().verify(&hash, &private.sign(&hash)).is_ok());
+    }
+
+    #[test]
+    fn test_decode_invalid_length() {
+        assert!(util::decode_from_slice::<Signature>(&[0x11; 63]).is_err());
+        assert!(util::decode_from_slice::<Signature>(&[]).is_err());
     }
 }
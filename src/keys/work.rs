@@ -8,6 +8,10 @@ use bincode::{Decode, Encode};
 use blake2b_simd::Params;
 use rand::RngCore;
 use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+/// The number of attempts between checks of the deadline while generating work
+const DEADLINE_CHECK_ATTEMPTS: u32 = 4096;
 
 /// The result of some proof of work (PoW). Can verify and inefficiently generate PoW using the CPU.
 #[derive(Clone, Copy, PartialEq, Eq, Encode, Decode)]
@@ -26,16 +30,11 @@ static BLAKE2B_PARAMS: Params = {
 impl Work {
     const LEN: usize = 8;
 
+    #[cfg(test)]
     pub fn zero() -> Self {
         Self([0u8; Self::LEN])
     }
 
-    pub fn random() -> Self {
-        let mut s = Self([0u8; Self::LEN]);
-        rand::thread_rng().fill_bytes(&mut s.0);
-        s
-    }
-
     pub fn hash(work_and_subject: &[u8]) -> [u8; Self::LEN] {
         BLAKE2B_PARAMS
             .hash(work_and_subject)
@@ -45,7 +44,17 @@ impl Work {
     }
 
     /// Block and generate forever until we find a solution.
+    #[cfg(test)]
     pub fn generate(subject: &Hash, threshold: Difficulty) -> Self {
+        Self::generate_until(subject, threshold, None).unwrap()
+    }
+
+    /// Generate until we find a solution, or return `None` once `timeout` has elapsed.
+    pub fn generate_timeout(subject: &Hash, threshold: Difficulty, timeout: Duration) -> Option<Self> {
+        Self::generate_until(subject, threshold, Some(Instant::now() + timeout))
+    }
+
+    fn generate_until(subject: &Hash, threshold: Difficulty, deadline: Option<Instant>) -> Option<Self> {
         let mut work_and_subject = [0u8; 40];
         // We can place the subject in the second part of the slice which will not change.
         work_and_subject[Self::LEN..].copy_from_slice(subject.as_bytes());
@@ -54,6 +63,7 @@ impl Work {
         let work_slice = &mut work_and_subject[0..Self::LEN];
         rand::thread_rng().fill_bytes(work_slice);
 
+        let mut attempts = 0u32;
         loop {
             // Pick a random byte position and increment.
             // I'm guessing this is slightly faster than using fill_bytes for a new set of numbers.
@@ -68,13 +78,21 @@ impl Work {
             if difficulty >= threshold {
                 break;
             }
+
+            // Only check the clock occasionally, as it's slower than hashing
+            attempts = attempts.wrapping_add(1);
+            if attempts.is_multiple_of(DEADLINE_CHECK_ATTEMPTS)
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return None;
+            }
         }
 
         let work_slice = &work_and_subject[0..Self::LEN];
         let mut work_bytes = Vec::from(work_slice);
         work_bytes.reverse();
         let work = Work::try_from(work_bytes.as_slice()).unwrap();
-        work
+        Some(work)
     }
 
     pub fn verify(&self, subject: &Hash, threshold: Difficulty) -> Result<(), Error> {
@@ -100,10 +118,43 @@ impl Work {
     }
 }
 
+/// Work precomputed at `Difficulty::BASE` for the accounts of the all-zero seed,
+/// since generating it would make each test take minutes
+#[cfg(test)]
+pub mod fixtures {
+    use super::Work;
+    use crate::keys::{Identity, Seed};
+    use std::str::FromStr;
+
+    /// The identity of account `index` of the all-zero seed
+    pub fn identity(index: u32) -> Identity {
+        let private = Seed([0; 32]).derive(index);
+        Identity { private, public: private.to_public() }
+    }
+
+    /// Work for `Tx::work_hash(0, &identity(0).public)`, the first transaction of account 0
+    pub fn first_tx_work() -> Work {
+        Work::from_str("8AEC5CFF71660A63").unwrap()
+    }
+
+    /// Work for `Open::work_hash(&identity(index).public, &identity(1).public)`,
+    /// opening account `index` represented by account 1. Only accounts 0, 3, 4 and 5 have any.
+    pub fn open_work(index: u32) -> Work {
+        let work = match index {
+            0 => "8B561D9C5C6A92EA",
+            3 => "224FD57C36346B57",
+            4 => "83B474D1B0B82159",
+            5 => "70BA80CD45A6635F",
+            _ => panic!("no open work precomputed for account {}", index),
+        };
+        Work::from_str(work).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::keys::Seed;
+    use crate::protocol::{Open, Tx};
     use std::str::FromStr;
 
     #[test]
@@ -167,4 +218,29 @@ mod tests {
         dbg!(&work);
         assert!(work.verify(&hash, threshold).is_ok());
     }
+
+    #[test]
+    fn generate_work_timeout() {
+        let hash = Hash::random();
+        let start = Instant::now();
+        let timeout = Duration::from_millis(50);
+        assert!(Work::generate_timeout(&hash, Difficulty::from_str("ffffffffffffffff").unwrap(), timeout).is_none());
+        assert!(start.elapsed() >= timeout);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let threshold = Difficulty::from_str("ff00000000000000").unwrap();
+        let work = Work::generate_timeout(&hash, threshold, Duration::from_secs(60)).unwrap();
+        assert!(work.verify(&hash, threshold).is_ok());
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let first_tx = Tx::work_hash(0, &fixtures::identity(0).public);
+        assert!(fixtures::first_tx_work().verify(&first_tx, Difficulty::BASE).is_ok());
+        let representative = fixtures::identity(1).public;
+        for index in [0, 3, 4, 5] {
+            let open = Open::work_hash(&fixtures::identity(index).public, &representative);
+            assert!(fixtures::open_work(index).verify(&open, Difficulty::BASE).is_ok());
+        }
+    }
 }
@@ -1,14 +1,231 @@
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::{Duration, Instant},
+};
 
-use crate::{process::{self, Handle, Mailbox, Process}, util::Error};
+use crate::{
+    error,
+    keys::{Identity, Public},
+    log_warn,
+    process::{Handle, Mailbox, Message, Process},
+    protocol::Slot,
+    state::Block,
+    util::{self, Error},
+};
 
+use super::{
+    dedup::{MIN_DEDUP_CAPACITY, MIN_DEDUP_WINDOW},
+    shred::{ShredList, MAX_TOTAL_SHREDS_PER_BATCH},
+    DedupWindow, ShredNote, ShredRequest,
+};
+
+/// How long to wait for missing shreds before requesting them from peers
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(200);
+
+struct SlotEntry {
+    /// The scheduled leader of the slot, whose shreds are being assembled
+    leader: Public,
+    shred_list: ShredList,
+    /// The shred notes received, by `(batch_index, shred_index)`, to serve requests
+    held: HashMap<(usize, usize), ShredNote>,
+    /// Whether the data has been assembled
+    assembled: bool,
+    /// When shreds were last received or requested
+    last_activity: Instant,
+}
+
+/// The settings of an `Assembler`
+pub struct AssemblerConfig {
+    /// The maximum size of the data assembled for a single slot
+    pub max_block_size: u32,
+    /// The maximum number of slots being assembled at once
+    pub max_pending: usize,
+    /// How many slots behind the newest a slot may fall before it's evicted
+    pub max_slot_lag: u64,
+}
+
+/// Assembles the shreds broadcast by the leader of each slot back into its block,
+/// requesting missing shreds from peers, and serving peers' requests, passing
+/// those of slots it holds nothing of to the `Transmitter`.
+/// Assembled blocks are delivered to `State`.
 pub struct Assembler {
+    id: Identity,
+    transmitter: Handle,
+    state: Handle,
+    /// Looks up the scheduled leader of a slot
+    leader_of: Box<dyn Fn(Slot) -> Option<Public> + Send>,
+    slots: BTreeMap<Slot, SlotEntry>,
+    /// The maximum size of the data assembled for a single slot
+    max_block_size: u32,
+    /// The maximum number of slots being assembled at once; beyond it, the
+    /// least recently active is evicted
+    max_pending: usize,
+    /// How many slots behind the newest a slot may fall before it's evicted
+    max_slot_lag: u64,
+    /// The number of slots evicted before being assembled, shared for telemetry
+    evicted: Arc<AtomicU64>,
+    /// The hashes of peers' recent shred requests, so each is answered once. The window
+    /// is shorter than `REQUEST_TIMEOUT`, so a peer's retried request is answered again
+    requests_seen: DedupWindow,
+}
+
+impl Assembler {
+    pub fn new(
+        id: Identity,
+        config: AssemblerConfig,
+        transmitter: Handle,
+        state: Handle,
+        leader_of: Box<dyn Fn(Slot) -> Option<Public> + Send>,
+        evicted: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            id,
+            transmitter,
+            state,
+            leader_of,
+            slots: BTreeMap::new(),
+            max_block_size: config.max_block_size,
+            max_pending: config.max_pending.max(1),
+            max_slot_lag: config.max_slot_lag,
+            evicted,
+            // within the bounds `DedupWindow::new` checks
+            requests_seen: DedupWindow::new(MIN_DEDUP_WINDOW, MIN_DEDUP_CAPACITY, Instant::now()).unwrap(),
+        }
+    }
+
+    /// Remove the entry of `slot`, counting it as evicted if it wasn't assembled
+    fn evict(&mut self, slot: Slot) {
+        if let Some(entry) = self.slots.remove(&slot) {
+            if !entry.assembled {
+                self.evicted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Make room for an entry of `slot`, returning whether there is room
+    fn make_room(&mut self, slot: Slot) -> bool {
+        // slots too far behind this one are never going to complete
+        let stale: Vec<Slot> = self.slots.keys()
+            .take_while(|&&pending| slot.saturating_sub(pending) > self.max_slot_lag)
+            .copied()
+            .collect();
+        for pending in stale {
+            self.evict(pending);
+        }
+        if self.slots.last_key_value().is_some_and(|(&newest, _)| newest.saturating_sub(slot) > self.max_slot_lag) {
+            return false;
+        }
+        if self.slots.len() >= self.max_pending {
+            let (&least_recent, _) = self.slots.iter().min_by_key(|(_, entry)| entry.last_activity).unwrap();
+            self.evict(least_recent);
+        }
+        true
+    }
+
+    /// Provide a shred note, returning the assembled data of its slot if it's now complete,
+    /// or an error if the shred is rejected. Only new shreds signed by the scheduled leader
+    /// of their slot are accepted.
+    fn on_shred_note(&mut self, shred_note: ShredNote, now: Instant) -> Result<Option<Vec<u8>>, Error> {
+        shred_note.verify()?;
+        if !self.slots.contains_key(&shred_note.slot) {
+            if (self.leader_of)(shred_note.slot) != Some(shred_note.from) {
+                return Err(error!("shred not from the scheduled leader"));
+            }
+            if !self.make_room(shred_note.slot) {
+                return Err(error!("shred of a stale slot"));
+            }
+            self.slots.insert(shred_note.slot, SlotEntry {
+                leader: shred_note.from,
+                shred_list: ShredList::new(self.max_block_size),
+                held: HashMap::new(),
+                assembled: false,
+                last_activity: now,
+            });
+        }
+        let entry = self.slots.get_mut(&shred_note.slot).unwrap();
+        if shred_note.from != entry.leader {
+            return Err(error!("shred not from the scheduled leader"));
+        }
+        if entry.assembled {
+            return Err(error!("shred of an assembled slot"));
+        }
+        let key = (shred_note.shred.get_batch_index(), shred_note.shred.get_shred_index());
+        if !entry.shred_list.try_provide(shred_note.shred.clone()) {
+            return Err(error!("duplicate or oversized shred"));
+        }
+        entry.held.insert(key, shred_note);
+        entry.last_activity = now;
+        let Some(data) = entry.shred_list.try_reconstruct() else {
+            return Ok(None);
+        };
+        entry.assembled = true;
+        Ok(Some(data))
+    }
+
+    /// Build requests for the shreds missing from slots which haven't progressed in `REQUEST_TIMEOUT`.
+    fn request_missing(&mut self, now: Instant) -> Vec<ShredRequest> {
+        let mut requests = Vec::new();
+        for (&slot, entry) in self.slots.iter_mut() {
+            if entry.assembled || now.duration_since(entry.last_activity) < REQUEST_TIMEOUT {
+                continue;
+            }
+            for batch_index in 0..entry.shred_list.n_batches() {
+                let mut bitmap = vec![0u8; MAX_TOTAL_SHREDS_PER_BATCH / 8];
+                for shred_index in 0..MAX_TOTAL_SHREDS_PER_BATCH {
+                    if entry.shred_list.need_shred(batch_index, shred_index) {
+                        bitmap[shred_index / 8] |= 1 << (shred_index % 8);
+                    }
+                }
+                while bitmap.last() == Some(&0) {
+                    bitmap.pop();
+                }
+                if !bitmap.is_empty() {
+                    requests.push(ShredRequest::new(self.id.private, slot, batch_index as u32, bitmap));
+                }
+            }
+            entry.last_activity = now;
+        }
+        requests
+    }
 
+    /// Get the shred notes held which are requested by `shred_request`.
+    fn on_shred_request(&self, shred_request: &ShredRequest) -> Vec<ShredNote> {
+        if shred_request.from == self.id.public || shred_request.verify().is_err() {
+            return Vec::new();
+        }
+        let entry = match self.slots.get(&shred_request.slot) {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+        let batch_index = shred_request.batch_index as usize;
+        (0..MAX_TOTAL_SHREDS_PER_BATCH)
+            .filter(|&i| shred_request.is_requested(i))
+            .filter_map(|i| entry.held.get(&(batch_index, i)).cloned())
+            .collect()
+    }
 }
 
 impl Assembler {
-    pub fn new() -> Self {
-        Self {}
+    /// Answer a peer's shred request with the shreds held of its slot, or pass it on to
+    /// the `Transmitter` to answer from the shreds we broadcast if we hold none of them.
+    /// Our own requests relayed back to us, invalid requests and repeats of recent
+    /// requests are dropped.
+    fn on_peer_shred_request(&mut self, shred_request: Box<ShredRequest>, now: Instant) {
+        if shred_request.from == self.id.public || shred_request.verify().is_err() {
+            return;
+        }
+        if !self.requests_seen.insert(shred_request.id(), now) {
+            return;
+        }
+        if !self.slots.contains_key(&shred_request.slot) {
+            self.transmitter.send(Message::ShredRequest(shred_request));
+            return;
+        }
+        let shred_notes = self.on_shred_request(&shred_request);
+        if !shred_notes.is_empty() {
+            self.transmitter.send(Message::ShredResponse(Box::new((shred_request.from, shred_notes))));
+        }
     }
 }
 
@@ -16,10 +233,228 @@ impl Process for Assembler {
     const NAME: &'static str = "Assembler";
     const RESTART_ON_CRASH: bool = true;
 
-    fn run(&mut self, mailbox: Mailbox, handle: Handle) -> Result<(), Error> {
+    fn run(&mut self, mut mailbox: Mailbox, _: Handle) -> Result<(), Error> {
         loop {
-            process::sleep(Duration::from_secs(1));
+            match mailbox.recv_timeout(REQUEST_TIMEOUT) {
+                Some(Message::PeerShredNote(v)) => {
+                    let (from, shred_note) = *v;
+                    let (slot, leader) = (shred_note.slot, shred_note.from);
+                    let assembled = match self.on_shred_note(shred_note, Instant::now()) {
+                        Ok(assembled) => assembled,
+                        Err(_) => continue,
+                    };
+                    // Only a peer relaying a verified, new shred is credited
+                    self.transmitter.send(Message::PeerUseful(Box::new(from)));
+                    if let Some(data) = assembled {
+                        match util::decode_from_slice::<Block>(&data) {
+                            Ok(block) if block.slot == slot && block.leader == leader => {
+                                self.state.send(Message::Block(Box::new(block)));
+                            }
+                            _ => {
+                                log_warn!("Assembled an invalid block from {}", leader.to_address());
+                            }
+                        }
+                    }
+                }
+                Some(Message::ShredRequest(shred_request)) => {
+                    self.on_peer_shred_request(shred_request, Instant::now());
+                }
+                _ => {}
+            }
+            for shred_request in self.request_missing(Instant::now()) {
+                self.transmitter.send(Message::ShredRequest(Box::new(shred_request)));
+            }
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::Seed, network::shred::{Shred, ShredParams}, process};
+
+    fn assembler(index: u32) -> Assembler {
+        let private = Seed([9; 32]).derive(index);
+        let (transmitter, _) = process::channel();
+        let (state, _) = process::channel();
+        let leader = Seed([9; 32]).derive(100).to_public();
+        let config = AssemblerConfig { max_block_size: 8 * 1024 * 1024, max_pending: 64, max_slot_lag: 64 };
+        Assembler::new(
+            Identity { private, public: private.to_public() },
+            config,
+            transmitter,
+            state,
+            Box::new(move |_| Some(leader)),
+            Arc::new(AtomicU64::new(0))
+        )
+    }
+
+    /// Provide the `index`th of the shreds of some data in `slot`, which alone can't be assembled
+    fn provide_incomplete(assembler: &mut Assembler, slot: Slot, index: usize, now: Instant) {
+        let leader = Seed([9; 32]).derive(100);
+        let shred = Shred::from_data(&[7; 100], 10, &ShredParams::default()).swap_remove(index);
+        assert!(assembler.on_shred_note(ShredNote::new(leader, slot, shred), now).unwrap_or_default().is_none());
+    }
+
+    #[test]
+    fn test_evict_least_recently_active() {
+        let mut assembler = assembler(0);
+        assembler.max_pending = 4;
+        let start = Instant::now();
+        let slots: Vec<Slot> = std::iter::successors(Some(Slot::zero().next()), |slot| Some(slot.next())).take(6).collect();
+        for (i, &slot) in slots.iter().take(4).enumerate() {
+            provide_incomplete(&mut assembler, slot, 0, start + Duration::from_millis(i as u64));
+        }
+        // the first slot is active again, so the second is the least recently active
+        provide_incomplete(&mut assembler, slots[0], 1, start + Duration::from_millis(10));
+        provide_incomplete(&mut assembler, slots[4], 0, start + Duration::from_millis(11));
+        provide_incomplete(&mut assembler, slots[5], 0, start + Duration::from_millis(12));
+        assert_eq!(assembler.slots.keys().copied().collect::<Vec<_>>(), vec![slots[0], slots[3], slots[4], slots[5]]);
+        assert_eq!(assembler.evicted.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_evict_stale_slots() {
+        let mut assembler = assembler(0);
+        assembler.max_slot_lag = 2;
+        let start = Instant::now();
+        let slot = |n: u64| Slot::from_bytes(n.to_le_bytes());
+        for n in [10, 11, 12] {
+            provide_incomplete(&mut assembler, slot(n), 0, start);
+        }
+        // slot 14 is more than 2 slots ahead of 10 and 11
+        provide_incomplete(&mut assembler, slot(14), 0, start);
+        assert_eq!(assembler.slots.keys().copied().collect::<Vec<_>>(), vec![slot(12), slot(14)]);
+        assert_eq!(assembler.evicted.load(Ordering::Relaxed), 2);
+        // and shreds of slots that far behind the newest are ignored
+        provide_incomplete(&mut assembler, slot(11), 0, start);
+        assert!(!assembler.slots.contains_key(&slot(11)));
+    }
+
+    #[test]
+    fn test_request_missing_shreds() {
+        let leader = Seed([9; 32]).derive(100);
+        let slot = Slot::zero().next();
+        let data: Vec<u8> = (0..200).map(|x| x as u8).collect();
+        let shred_notes: Vec<ShredNote> = Shred::from_data(&data, 10, &ShredParams::default())
+            .into_iter()
+            .map(|shred| ShredNote::new(leader, slot, shred))
+            .collect();
+        let start = Instant::now();
+
+        // `holder` receives every shred, `requester` only a few
+        let mut holder = assembler(0);
+        let mut requester = assembler(1);
+        let mut assembled = None;
+        for shred_note in shred_notes.iter() {
+            assembled = assembled.or(holder.on_shred_note(shred_note.clone(), start).unwrap_or_default());
+        }
+        assert_eq!(assembled.unwrap(), data);
+        for shred_note in shred_notes.iter().take(3) {
+            assert!(requester.on_shred_note(shred_note.clone(), start).unwrap().is_none());
+        }
+
+        // nothing is requested until the timeout
+        assert!(requester.request_missing(start).is_empty());
+        let requests = requester.request_missing(start + REQUEST_TIMEOUT);
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].is_requested(0));
+        assert!(requests[0].is_requested(3));
+
+        // the holder responds with the missing shreds, completing reconstruction
+        let response = holder.on_shred_request(&requests[0]);
+        assert_eq!(response.len(), holder.slots[&slot].held.len() - 3);
+        let mut assembled = None;
+        for shred_note in response {
+            assembled = assembled.or(requester.on_shred_note(shred_note, start + REQUEST_TIMEOUT).unwrap_or_default());
+        }
+        assert_eq!(assembled.unwrap(), data);
+        assert!(requester.request_missing(start + 2 * REQUEST_TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn test_forged_shred_request() {
+        let leader = Seed([9; 32]).derive(100);
+        let slot = Slot::zero().next();
+        let mut holder = assembler(0);
+        for shred in Shred::from_data(&[1, 2, 3, 4], 2, &ShredParams::default()) {
+            let _ = holder.on_shred_note(ShredNote::new(leader, slot, shred), Instant::now());
+        }
+        let mut request = ShredRequest::new(Seed([9; 32]).derive(1), slot, 0, vec![0xff]);
+        assert!(!holder.on_shred_request(&request).is_empty());
+        request.from = Seed([9; 32]).derive(2).to_public();
+        assert!(holder.on_shred_request(&request).is_empty());
+    }
+
+    #[test]
+    fn test_reject_data_above_max_block_size() {
+        let leader = Seed([9; 32]).derive(100);
+        let slot = Slot::zero().next();
+        let mut assembler = assembler(0);
+        assembler.max_block_size = 100;
+        let data: Vec<u8> = (0..200).map(|x| x as u8).collect();
+        for shred in Shred::from_data(&data, 10, &ShredParams::default()) {
+            assert!(assembler.on_shred_note(ShredNote::new(leader, slot, shred), Instant::now()).is_err());
+        }
+        assert!(assembler.slots[&slot].held.is_empty());
+
+        // data within the maximum still assembles
+        let slot = slot.next();
+        let mut assembled = None;
+        for shred in Shred::from_data(&data[..100], 10, &ShredParams::default()) {
+            assembled = assembled.or(assembler.on_shred_note(ShredNote::new(leader, slot, shred), Instant::now()).unwrap_or_default());
+        }
+        assert_eq!(assembled.unwrap(), &data[..100]);
+    }
+
+    #[test]
+    fn test_reject_unscheduled_leader() {
+        let (leader, impostor) = (Seed([9; 32]).derive(100), Seed([9; 32]).derive(101));
+        let slot = Slot::zero().next();
+        let mut assembler = assembler(0);
+        let shreds = Shred::from_data(&[1, 2, 3, 4], 2, &ShredParams::default());
+
+        // an impostor's shreds don't claim the slot, so the leader's still assemble
+        for shred in shreds.iter() {
+            assert!(assembler.on_shred_note(ShredNote::new(impostor, slot, shred.clone()), Instant::now()).is_err());
+        }
+        assert!(!assembler.slots.contains_key(&slot));
+        let mut assembled = None;
+        for shred in shreds {
+            assembled = assembled.or(assembler.on_shred_note(ShredNote::new(leader, slot, shred), Instant::now()).unwrap_or_default());
+        }
+        assert_eq!(assembled.unwrap(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_answer_peer_shred_requests_once() {
+        let (leader, requester) = (Seed([9; 32]).derive(100), Seed([9; 32]).derive(1));
+        let slot = Slot::zero().next();
+        let mut holder = assembler(0);
+        let (transmitter, mut mailbox) = process::channel();
+        holder.transmitter = transmitter;
+        for shred in Shred::from_data(&[1, 2, 3, 4], 2, &ShredParams::default()) {
+            let _ = holder.on_shred_note(ShredNote::new(leader, slot, shred), Instant::now());
+        }
+        let now = Instant::now();
+
+        // a request is answered once, however many times it arrives
+        let request = ShredRequest::new(requester, slot, 0, vec![0xff]);
+        holder.on_peer_shred_request(Box::new(request.clone()), now);
+        holder.on_peer_shred_request(Box::new(request.clone()), now);
+        assert!(matches!(mailbox.recv_timeout(Duration::from_millis(100)), Some(Message::ShredResponse(_))));
+        assert!(mailbox.recv_timeout(Duration::from_millis(100)).is_none());
+        // but the same request from another peer is answered too
+        holder.on_peer_shred_request(Box::new(ShredRequest::new(Seed([9; 32]).derive(2), slot, 0, vec![0xff])), now);
+        assert!(matches!(mailbox.recv_timeout(Duration::from_millis(100)), Some(Message::ShredResponse(_))));
+
+        // our own requests relayed back aren't answered or passed on
+        holder.on_peer_shred_request(Box::new(ShredRequest::new(holder.id.private, slot, 0, vec![0xff])), now);
+        assert!(mailbox.recv_timeout(Duration::from_millis(100)).is_none());
+
+        // requests of slots we hold nothing of are passed on to the transmitter's cache
+        let request = ShredRequest::new(requester, slot.next(), 0, vec![0xff]);
+        holder.on_peer_shred_request(Box::new(request), now);
+        assert!(matches!(mailbox.recv_timeout(Duration::from_millis(100)), Some(Message::ShredRequest(_))));
+    }
+}
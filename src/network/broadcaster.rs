@@ -1,29 +1,251 @@
-use std::{net::UdpSocket, sync::Arc};
+use std::{
+    collections::VecDeque,
+    net::UdpSocket,
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::{Duration, Instant},
+};
 
-use crate::{process::{Handle, Mailbox, Message, Process, ProcessEndless}, util::Error};
+use crate::process::{self, Handle, Mailbox, Message, ProcessEndless};
 
+use super::{Endpoint, MTU};
+
+/// The duration over which at most `max_packets_per_tick` datagrams are sent
+const PACING_TICK: Duration = Duration::from_millis(10);
+/// The burst allowed by the token bucket, as a duration of sending at the full rate
+const MAX_BURST: Duration = Duration::from_millis(100);
+/// The maximum number of datagrams waiting to be sent before new ones are dropped
+const MAX_QUEUE_LEN: usize = 4096;
+
+/// Limits the outbound bandwidth to a rate, allowing short bursts.
+struct TokenBucket {
+    bytes_per_sec: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(max_mbps: u64, now: Instant) -> Self {
+        let bytes_per_sec = max_mbps as f64 * 1_000_000.0 / 8.0;
+        let capacity = (bytes_per_sec * MAX_BURST.as_secs_f64()).max(MTU as f64);
+        Self {
+            bytes_per_sec,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Take tokens for `n` bytes if there are enough.
+    fn try_take(&mut self, n: usize, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < n as f64 {
+            return false;
+        }
+        self.tokens -= n as f64;
+        true
+    }
+
+    /// Get the time until there are enough tokens for `n` bytes.
+    fn wait_time(&self, n: usize) -> Duration {
+        let missing = (n as f64 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing / self.bytes_per_sec)
+    }
+}
+
+/// Sends broadcast datagrams to each of their endpoints over the shared UDP socket.
 pub struct Broadcaster {
-    socket: Arc<UdpSocket>
+    socket: Arc<UdpSocket>,
+    /// The maximum number of datagrams sent per `PACING_TICK`, or `None` for no pacing
+    max_packets_per_tick: Option<usize>,
+    tick_start: Instant,
+    sent_this_tick: usize,
+    /// The outbound bandwidth limit, or `None` for no limit
+    bucket: Option<TokenBucket>,
+    /// Datagrams waiting for the bandwidth limit
+    queue: VecDeque<(Endpoint, Arc<Vec<u8>>)>,
+    /// The number of datagrams which failed to send, shared with the `State`
+    send_errors: Arc<AtomicU64>,
+    /// The number of datagrams dropped because the queue was full, shared with the `State`
+    dropped: Arc<AtomicU64>,
 }
 
 impl Broadcaster {
-    pub fn new(socket: Arc<UdpSocket>) -> Self {
-        Self { socket }
+    pub fn new(
+        socket: Arc<UdpSocket>,
+        max_packets_per_tick: Option<usize>,
+        max_mbps: Option<u64>,
+        send_errors: Arc<AtomicU64>,
+        dropped: Arc<AtomicU64>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            socket,
+            max_packets_per_tick,
+            tick_start: now,
+            sent_this_tick: 0,
+            bucket: max_mbps.map(|x| TokenBucket::new(x, now)),
+            queue: VecDeque::new(),
+            send_errors,
+            dropped,
+        }
+    }
+
+    /// Wait until another datagram may be sent without exceeding `max_packets_per_tick`.
+    fn pace(&mut self) {
+        let max_packets_per_tick = match self.max_packets_per_tick {
+            Some(v) => v,
+            None => return,
+        };
+        let now = Instant::now();
+        if now.duration_since(self.tick_start) >= PACING_TICK {
+            self.tick_start = now;
+            self.sent_this_tick = 0;
+        } else if self.sent_this_tick >= max_packets_per_tick {
+            process::sleep(PACING_TICK - now.duration_since(self.tick_start));
+            self.tick_start = Instant::now();
+            self.sent_this_tick = 0;
+        }
+        self.sent_this_tick += 1;
+    }
+
+    /// Queue `bytes` to be sent to each of `endpoints`, dropping what doesn't fit.
+    fn enqueue(&mut self, endpoints: &[Endpoint], bytes: Vec<u8>) {
+        let bytes = Arc::new(bytes);
+        for &ep in endpoints.iter() {
+            if self.queue.len() >= MAX_QUEUE_LEN {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            self.queue.push_back((ep, bytes.clone()));
+        }
+    }
+
+    /// Send as many queued datagrams as the bandwidth limit allows,
+    /// returning the time until the next one may be sent, if any remain.
+    fn flush(&mut self) -> Option<Duration> {
+        while let Some((ep, bytes)) = self.queue.front() {
+            if let Some(bucket) = self.bucket.as_mut() {
+                if !bucket.try_take(bytes.len(), Instant::now()) {
+                    return Some(bucket.wait_time(bytes.len()));
+                }
+            }
+            let (ep, bytes) = (*ep, bytes.clone());
+            self.queue.pop_front();
+            self.pace();
+            if self.socket.send_to(&bytes, ep.to_socket_addr()).is_err() {
+                self.send_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        None
     }
 }
 
 impl ProcessEndless for Broadcaster {
-    fn run(&mut self, mut mailbox: Mailbox, handle: Handle) -> ! {
+    fn run(&mut self, mut mailbox: Mailbox, _: Handle) -> ! {
+        let mut wait = None;
         loop {
-            let msg = match mailbox.recv() {
-                Message::Broadcast(v) => v,
-                _ => continue                
+            let msg = match wait {
+                None => Some(mailbox.recv()),
+                Some(wait) => mailbox.recv_timeout(wait),
             };
-            let endpoints = msg.0;
-            let msg = msg.1;
-            for ep in endpoints.iter() {
-                _ = self.socket.send_to(&msg, ep.to_socket_addr());
+            if let Some(Message::Broadcast(v)) = msg {
+                let (endpoints, bytes) = *v;
+                self.enqueue(&endpoints, bytes);
             }
+            wait = self.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn receivers(n: usize) -> (Vec<UdpSocket>, Vec<Endpoint>) {
+        let sockets: Vec<UdpSocket> = (0..n)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect();
+        for socket in sockets.iter() {
+            socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
         }
+        let endpoints = sockets
+            .iter()
+            .map(|x| Endpoint::from_str(&x.local_addr().unwrap().to_string()).unwrap())
+            .collect();
+        (sockets, endpoints)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_broadcast() {
+        let (sockets, endpoints) = receivers(5);
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let send_errors = Arc::new(AtomicU64::new(0));
+        let broadcaster = Broadcaster::new(socket, Some(2), None, send_errors.clone(), Arc::new(AtomicU64::new(0)));
+        let handle = process::spawn_endless(broadcaster);
+        handle.send(Message::Broadcast(Box::new((Arc::new(endpoints), vec![1, 2, 3]))));
+        for socket in sockets.iter() {
+            let mut buf = [0u8; 16];
+            let n = socket.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &[1, 2, 3]);
+        }
+        assert_eq!(send_errors.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_pacing() {
+        let (_sockets, endpoints) = receivers(5);
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let mut broadcaster = Broadcaster::new(socket, Some(2), None, Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+        let start = Instant::now();
+        broadcaster.enqueue(&endpoints, vec![1, 2, 3]);
+        assert!(broadcaster.flush().is_none());
+        // 5 datagrams at 2 per tick take at least 2 full ticks
+        assert!(start.elapsed() >= 2 * PACING_TICK);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        const N: usize = 20;
+        let (sockets, endpoints) = receivers(1);
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        // 1 Mbps allows a burst of 12500 bytes, then 12500 bytes per 100ms
+        let dropped = Arc::new(AtomicU64::new(0));
+        let broadcaster = Broadcaster::new(socket, None, Some(1), Arc::new(AtomicU64::new(0)), dropped.clone());
+        let handle = process::spawn_endless(broadcaster);
+        let endpoints = Arc::new(endpoints);
+        let start = Instant::now();
+        for _ in 0..N {
+            handle.send(Message::Broadcast(Box::new((endpoints.clone(), vec![7; 1250]))));
+        }
+        let mut buf = [0u8; MTU];
+        for _ in 0..N {
+            assert_eq!(sockets[0].recv(&mut buf).unwrap(), 1250);
+        }
+        // the 12500 bytes beyond the burst take 100ms at the full rate
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(90), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_queue_overflow() {
+        let (_sockets, endpoints) = receivers(1);
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut broadcaster = Broadcaster::new(socket, None, Some(1), Arc::new(AtomicU64::new(0)), dropped.clone());
+        for _ in 0..MAX_QUEUE_LEN + 10 {
+            broadcaster.enqueue(&endpoints, vec![7; 100]);
+        }
+        assert_eq!(broadcaster.queue.len(), MAX_QUEUE_LEN);
+        assert_eq!(dropped.load(Ordering::Relaxed), 10);
+    }
+}
@@ -16,9 +16,23 @@ struct KeyValue<K, V> {
 struct KeyPriorityIndex<K: Ord, P: Ord> {
     key: K,
     priority: P,
+    // The value's score, negated on the "greater" side so that the lowest score is evicted first on both sides
+    tiebreak: i64,
     index: usize,
 }
 
+impl<K: Ord, P: Ord> KeyPriorityIndex<K, P> {
+    fn new<V: CenterMapValue<P>>(key: K, value: &V, index: usize, less: bool) -> Self {
+        let score = value.score() as i64;
+        Self {
+            key,
+            priority: value.priority(),
+            tiebreak: if less { score } else { -score },
+            index,
+        }
+    }
+}
+
 // Implement equality comparison for KeyPriorityIndex based on the key
 impl<K: Ord, P: Ord> PartialEq for KeyPriorityIndex<K, P> {
     fn eq(&self, other: &Self) -> bool {
@@ -28,13 +42,12 @@ impl<K: Ord, P: Ord> PartialEq for KeyPriorityIndex<K, P> {
 
 impl<K: Ord, P: Ord> Eq for KeyPriorityIndex<K, P> {}
 
-// Implement ordering for KeyPriorityIndex based on the priority, then the key
+// Implement ordering for KeyPriorityIndex based on the priority, then the tiebreak, then the key
 impl<K: Ord, P: Ord> Ord for KeyPriorityIndex<K, P> {
     fn cmp(&self, other: &Self) -> Ordering {
-        match self.priority.cmp(&other.priority) {
-            Ordering::Equal => self.key.cmp(&other.key),
-            ord => ord,
-        }
+        self.priority.cmp(&other.priority)
+            .then(self.tiebreak.cmp(&other.tiebreak))
+            .then_with(|| self.key.cmp(&other.key))
     }
 }
 
@@ -52,10 +65,14 @@ impl<K: Ord + Hash, P: Ord> Hash for KeyPriorityIndex<K, P> {
 
 pub trait CenterMapValue<P: Ord> {
     fn priority(&self) -> P;
+    /// Among values of equal priority, those with the lowest score are evicted first
+    fn score(&self) -> i32 {
+        0
+    }
 }
 
 /// CenterMap struct to maintain a sorted list of key-value pairs around a center value
-/// Elements are sorted first by priority, then by key
+/// Elements are sorted first by priority, then by score, then by key
 pub struct CenterMap<K: Hash + Eq + Clone + Ord, P: Ord, V: CenterMapValue<P>> {
     less: DoublePriorityQueue<K, KeyPriorityIndex<K, P>>, // Set of key-value pairs less than the center
     greater: DoublePriorityQueue<K, KeyPriorityIndex<K, P>>, // Set of key-value pairs greater than the center
@@ -79,45 +96,28 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
 
     // Insert a new key-value pair into the CenterMap
     pub fn insert(&mut self, key: K, value: V) -> bool {
-        let priority = value.priority();
-        if priority < self.center {
+        if value.priority() < self.center {
             // If the priority is less than the center
             if self.less.len() < self.max_less {
                 // If there's still room on the "less" side
                 let index = self.list.len();
-                self.list.push(KeyValue {
-                    key: key.clone(),
-                    value,
-                });
-                self.less.push(
-                    key.clone(),
-                    KeyPriorityIndex {
-                        key,
-                        priority,
-                        index,
-                    },
-                );
+                self.less.push(key.clone(), KeyPriorityIndex::new(key.clone(), &value, index, true));
+                self.list.push(KeyValue { key, value });
                 true
             } else {
                 // If the "less" side is full
                 let (_, lowest) = self.less.peek_min().unwrap();
-                if priority < lowest.priority {
+                let kpi = KeyPriorityIndex::new(key.clone(), &value, lowest.index, true);
+                if (&kpi.priority, kpi.tiebreak) < (&lowest.priority, lowest.tiebreak) {
                     // If the new priority is smaller than the smallest on the "less" side, don't insert it
                     return false;
                 }
                 // Remove the smallest priority from the "less" side
                 let (_, lowest) = self.less.pop_min().unwrap();
                 // Replace it with the new value in the list
-                self.list[lowest.index].value = value;
+                self.list[lowest.index] = KeyValue { key: key.clone(), value };
                 // Insert the new priority into the "less" set
-                self.less.push(
-                    key.clone(),
-                    KeyPriorityIndex {
-                        key,
-                        priority,
-                        index: lowest.index,
-                    },
-                );
+                self.less.push(key, kpi);
                 true
             }
         } else {
@@ -125,53 +125,35 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
             if self.greater.len() < self.max_greater {
                 // If there's still room on the "greater" side
                 let index = self.list.len();
-                self.list.push(KeyValue {
-                    key: key.clone(),
-                    value,
-                });
-                self.greater.push(
-                    key.clone(),
-                    KeyPriorityIndex {
-                        key,
-                        priority,
-                        index,
-                    },
-                );
+                self.greater.push(key.clone(), KeyPriorityIndex::new(key.clone(), &value, index, false));
+                self.list.push(KeyValue { key, value });
                 true
             } else {
                 // If the "greater" side is full
                 let (_, greatest) = self.greater.peek_max().unwrap();
-                if priority > greatest.priority {
+                let kpi = KeyPriorityIndex::new(key.clone(), &value, greatest.index, false);
+                if (&kpi.priority, kpi.tiebreak) > (&greatest.priority, greatest.tiebreak) {
                     // If the new priority is larger than the largest on the "greater" side, don't insert it
                     return false;
                 }
                 // Remove the largest priority from the "greater" side
                 let (_, greatest) = self.greater.pop_max().unwrap();
                 // Replace it with the new value in the list
-                self.list[greatest.index].value = value;
+                self.list[greatest.index] = KeyValue { key: key.clone(), value };
                 // Insert the new priority into the "greater" set
-                self.greater.push(
-                    key.clone(),
-                    KeyPriorityIndex {
-                        key,
-                        priority,
-                        index: greatest.index,
-                    },
-                );
+                self.greater.push(key, kpi);
                 true
             }
         }
     }
 
-    fn update_index(&mut self, index: usize) {
+    /// Re-sort the element at `index`, after its score has been changed through `get_mut` or `IndexMut`
+    pub fn update_index(&mut self, index: usize) {
         let kv = &self.list[index];
-        let kpi = KeyPriorityIndex {
-            key: kv.key.clone(),
-            priority: kv.value.priority(),
-            index,
-        };
+        let less = kv.value.priority() < self.center;
+        let kpi = KeyPriorityIndex::new(kv.key.clone(), &kv.value, index, less);
         // Insert into the appropriate set based on its priority relative to center
-        if kv.value.priority() < self.center {
+        if less {
             self.less.change_priority(&kv.key, kpi);
         } else {
             self.greater.change_priority(&kv.key, kpi);
@@ -194,30 +176,16 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
         value
     }
 
-    // Remove a key-value pair from the CenterMap by key and return its value
-    pub fn remove(&mut self, key: K) -> Option<V> {
-        // Try to find the KeyPriorityIndex in the "less" set, then the "greater" set
-        let KeyPriorityIndex { index, .. } = match self.less.remove(&key) {
-            Some((_, kpi)) => Some(kpi),
-            None => match self.greater.remove(&key) {
-                Some((_, kpi)) => Some(kpi),
-                None => None,
-            },
-        }?; // Return None if not found in either set
-
-        // Remove the KeyValue at the found index and return its value
-        let KeyValue { value, .. } = self.list.swap_remove(index);
-
-        // If there's still an element at the removal index after swapping, update its index
-        if index < self.list.len() {
-            self.update_index(index);
-        }
-
-        Some(value)
+    pub fn len(&self) -> usize {
+        self.list.len()
     }
 
-    pub fn len(&self) -> usize {
-        return self.list.len();
+    /// Get the index of `key` in the list, for `update_index` and `remove_index`
+    pub fn index_of(&self, key: &K) -> Option<usize> {
+        match self.less.get(key) {
+            Some((_, kpi)) => Some(kpi.index),
+            None => self.greater.get(key).map(|(_, kpi)| kpi.index),
+        }
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
@@ -240,6 +208,7 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
         }
     }
 
+    #[cfg(test)]
     pub fn contains(&self, key: &K) -> bool {
         self.get(key).is_some()
     }
@@ -248,6 +217,7 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
         self.list.is_empty()
     }
 
+    #[cfg(test)]
     pub fn clear(&mut self) {
         self.list.clear();
         self.less.clear();
@@ -258,6 +228,41 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
         self.list.iter().map(|kv| (&kv.key, &kv.value))
     }
 
+    /// Get the indices of the `k` elements with the highest priorities, highest first,
+    /// breaking ties by the highest score
+    pub fn top_indices(&self, k: usize) -> Vec<usize> {
+        self.sorted_indices().into_iter().rev().take(k).collect()
+    }
+
+    /// Iterate over the elements from the highest priority to the lowest, breaking
+    /// ties by the highest score, then the highest key: the "greater" side, then the "less" side
+    pub fn iter_by_priority(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.sorted_indices().into_iter().rev().map(|i| (&self.list[i].key, &self.list[i].value))
+    }
+
+    /// Iterate over the elements from the lowest priority to the highest, the reverse of `iter_by_priority`
+    #[cfg(test)]
+    pub fn iter_by_priority_rev(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.sorted_indices().into_iter().map(|i| (&self.list[i].key, &self.list[i].value))
+    }
+
+    // The indices of all elements, lowest priority first. The queues can't be walked in
+    // order without popping from them, so only the indices are sorted, never the elements.
+    fn sorted_indices(&self) -> Vec<usize> {
+        let mut kpis: Vec<&KeyPriorityIndex<K, P>> = self.less
+            .iter()
+            .chain(self.greater.iter())
+            .map(|(_, kpi)| kpi)
+            .collect();
+        let score = |kpi: &KeyPriorityIndex<K, P>| self.list[kpi.index].value.score();
+        kpis.sort_unstable_by(|a, b| {
+            a.priority.cmp(&b.priority)
+                .then_with(|| score(a).cmp(&score(b)))
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        kpis.into_iter().map(|kpi| kpi.index).collect()
+    }
+
     pub fn update_center(&mut self, new_center: P) {
         if new_center == self.center {
             return;
@@ -268,7 +273,8 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
         // Move elements from the "less" set to the "greater" set if their priority is greater than or equal to the new center
         while let Some((_, kpi)) = self.less.peek_max() {
             if kpi.priority >= self.center {
-                let (key, kpi) = self.less.pop_max().unwrap();
+                let (key, mut kpi) = self.less.pop_max().unwrap();
+                kpi.tiebreak = -kpi.tiebreak;
                 self.greater.push(key, kpi);
             } else {
                 break;
@@ -278,7 +284,8 @@ impl<K: Hash + Eq + Clone + Ord, P: Ord + Clone, V: CenterMapValue<P>> CenterMap
         // Move elements from the "greater" set to the "less" set if their priority is less than the new center
         while let Some((_, kpi)) = self.greater.peek_min() {
             if kpi.priority < self.center {
-                let (key, kpi) = self.greater.pop_min().unwrap();
+                let (key, mut kpi) = self.greater.pop_min().unwrap();
+                kpi.tiebreak = -kpi.tiebreak;
                 self.less.push(key, kpi);
             } else {
                 break;
@@ -414,4 +421,82 @@ mod tests {
         assert_eq!(map[0], 10);
         assert_eq!(map[1], 20);
     }
+
+    #[test]
+    fn test_top_indices() {
+        let mut map = CenterMap::new(0, 3, 3);
+        map.insert(1, 10);
+        map.insert(2, -10);
+        map.insert(3, 30);
+        map.insert(4, 20);
+
+        let top: Vec<i32> = map.top_indices(3).into_iter().map(|i| map[i]).collect();
+        assert_eq!(top, vec![30, 20, 10]);
+        let all: Vec<i32> = map.top_indices(10).into_iter().map(|i| map[i]).collect();
+        assert_eq!(all, vec![30, 20, 10, -10]);
+    }
+
+    #[derive(Debug)]
+    struct Scored(i32, i32);
+    impl CenterMapValue<i32> for Scored {
+        fn priority(&self) -> i32 {
+            self.0
+        }
+        fn score(&self) -> i32 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn test_evict_lowest_score_on_tie() {
+        // both sides are full of equal-priority elements, one of which has lost score
+        let mut map = CenterMap::new(0, 2, 2);
+        for (key, priority) in [(1, -10), (2, -10), (3, 10), (4, 10)] {
+            map.insert(key, Scored(priority, 5));
+        }
+        for key in [2, 3] {
+            map.get_mut(&key).unwrap().1 = -5;
+            let index = map.iter().position(|(k, _)| *k == key).unwrap();
+            map.update_index(index);
+        }
+        // an equal-priority newcomer replaces the lower-scored element on either side
+        assert!(map.insert(5, Scored(-10, 0)));
+        assert!(map.insert(6, Scored(10, 0)));
+        assert!(map.contains(&1) && map.contains(&4) && map.contains(&5) && map.contains(&6));
+        assert!(!map.contains(&2) && !map.contains(&3));
+        assert_eq!(map.get(&5).unwrap().1, 0);
+        // but not a higher-scored one
+        assert!(!map.insert(7, Scored(-10, -1)));
+        assert!(!map.insert(8, Scored(10, -1)));
+
+
+        // moving the center trims the lower-scored of the elements which change sides
+        let mut map = CenterMap::new(0, 1, 2);
+        map.insert(1, Scored(10, 5));
+        map.insert(2, Scored(10, 0));
+        map.update_center(20);
+        assert!(map.contains(&1) && !map.contains(&2));
+    }
+
+    #[test]
+    fn test_iter_by_priority() {
+        let mut map = CenterMap::new(0, 2, 3);
+        for (key, priority) in [(1, 5), (2, -5), (3, 20), (4, -1), (5, 10), (6, 15), (7, -20), (8, -3), (9, 0)] {
+            map.insert(key, Scored(priority, key));
+        }
+        // 20 and 15 were evicted from the "greater" side, -5 from the "less" side, and -20 was rejected
+        let keys: Vec<i32> = map.iter_by_priority().map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![5, 1, 9, 4, 8]);
+        let keys: Vec<i32> = map.iter_by_priority_rev().map(|(k, _)| *k).collect();
+        assert_eq!(keys, vec![8, 4, 9, 1, 5]);
+
+        // ties are broken by score, and the order follows a moved center
+        map.insert(10, Scored(-1, 100));
+        map.update_center(2);
+        let priorities: Vec<(i32, i32)> = map.iter_by_priority().map(|(_, v)| (v.0, v.1)).collect();
+        assert_eq!(priorities, vec![(10, 5), (5, 1), (0, 9), (-1, 100)]);
+        let mut keys: Vec<i32> = map.iter_by_priority_rev().map(|(k, _)| *k).collect();
+        keys.reverse();
+        assert!(map.iter_by_priority().map(|(k, _)| *k).eq(keys));
+    }
 }
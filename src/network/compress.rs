@@ -1,5 +1,6 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, fmt::Display, str::{Chars, FromStr}};
 
+use nanoserde::{DeJson, DeJsonErr, DeJsonState, SerJson, SerJsonState};
 use zstd_safe::{CCtx, DCtx};
 
 use crate::{
@@ -9,39 +10,167 @@ use crate::{
 
 const ZSTD_LEVEL: i32 = 6;
 
+const HEADER_NONE: u8 = 0;
+const HEADER_LZ4: u8 = 1;
+const HEADER_ZSTD: u8 = 2;
+
 thread_local! {
     static ZSTD_CCTX: RefCell<CCtx<'static>> = RefCell::new(CCtx::create());
     static ZSTD_DCTX: RefCell<DCtx<'static>> = RefCell::new(DCtx::create());
 }
 
-pub fn compress(bytes: &[u8]) -> Vec<u8> {
-    // safety: output is not read before initialized
-    let mut output = unsafe { Vec::uninit(zstd_safe::compress_bound(bytes.len())) };
-    let n = ZSTD_CCTX
-        .with(|cctx| {
-            cctx.borrow_mut()
-                .compress(&mut output[..], bytes, ZSTD_LEVEL)
+/// A compression algorithm. Compressed output is prefixed with a single
+/// header byte identifying the algorithm, so `decompress` needs no configuration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Compression {
+    None,
+    Lz4,
+    Zstd(i32),
+}
+
+impl Compression {
+    #[cfg(test)]
+    pub const ALL: [Compression; 3] = [
+        Compression::None,
+        Compression::Lz4,
+        Compression::Zstd(ZSTD_LEVEL),
+    ];
+
+    fn header(self) -> u8 {
+        match self {
+            Compression::None => HEADER_NONE,
+            Compression::Lz4 => HEADER_LZ4,
+            Compression::Zstd(_) => HEADER_ZSTD,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Zstd(ZSTD_LEVEL)
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd(level) => write!(f, "zstd:{}", level),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().ok_or(error!("no compression algorithm"))?;
+        let level = parts.next();
+        match (name, level) {
+            ("none", None) => Ok(Compression::None),
+            ("lz4", None) => Ok(Compression::Lz4),
+            ("zstd", None) => Ok(Compression::Zstd(ZSTD_LEVEL)),
+            ("zstd", Some(level)) => {
+                let level: i32 = level.parse()?;
+                if level < zstd_safe::min_c_level() || level > zstd_safe::max_c_level() {
+                    return Err(error!("zstd level {} out of range", level));
+                }
+                Ok(Compression::Zstd(level))
+            }
+            _ => Err(error!("unknown compression algorithm: {}", s)),
+        }
+    }
+}
+
+impl SerJson for Compression {
+    fn ser_json(&self, d: usize, s: &mut SerJsonState) {
+        self.to_string().ser_json(d, s)
+    }
+}
+
+impl DeJson for Compression {
+    fn de_json(state: &mut DeJsonState, input: &mut Chars) -> Result<Self, DeJsonErr> {
+        let s = String::de_json(state, input)?;
+        Self::from_str(&s).map_err(|e| DeJsonErr {
+            msg: e.to_string(),
+            line: state.line,
+            col: state.col
         })
-        .unwrap();
-    output.truncate(n);
+    }
+}
+
+pub fn compress(bytes: &[u8], algorithm: Compression) -> Vec<u8> {
+    let mut output = match algorithm {
+        Compression::None => {
+            let mut output = Vec::with_capacity(bytes.len() + 1);
+            output.push(0);
+            output.extend_from_slice(bytes);
+            output
+        }
+        Compression::Lz4 => {
+            let mut output = vec![0];
+            output.extend_from_slice(&lz4_flex::compress_prepend_size(bytes));
+            output
+        }
+        Compression::Zstd(level) => {
+            // safety: output is not read before initialized
+            let mut output = unsafe { Vec::uninit(zstd_safe::compress_bound(bytes.len()) + 1) };
+            let n = ZSTD_CCTX
+                .with(|cctx| {
+                    cctx.borrow_mut()
+                        .compress(&mut output[1..], bytes, level)
+                })
+                .unwrap();
+            output.truncate(n + 1);
+            output
+        }
+    };
+    output[0] = algorithm.header();
     output
 }
 
-pub fn decompress(bytes: &[u8], max_size: Option<usize>) -> Result<Vec<u8>, Error> {
-    let decompress_bound = zstd_safe::decompress_bound(bytes)
-        .or_else(|e| Err(error!("decompress_bound failed: {:?}", e)))?;
+#[cfg(test)]
+fn check_max_size(size: u64, max_size: Option<usize>) -> Result<(), Error> {
     if let Some(max_size) = max_size {
-        if decompress_bound > max_size as u64 {
+        if size > max_size as u64 {
             return Err(error!("decompressed size > max_size"));
         }
     }
-    // safety: output is not read before initialized
-    let mut output = unsafe { Vec::uninit(decompress_bound as usize) };
-    let n = ZSTD_DCTX
-        .with(|dctx| dctx.borrow_mut().decompress(&mut output[..], bytes))
-        .or_else(|e| Err(error!("decompress failed: {:?}", e)))?;
-    output.truncate(n);
-    Ok(output)
+    Ok(())
+}
+
+#[cfg(test)]
+pub fn decompress(bytes: &[u8], max_size: Option<usize>) -> Result<Vec<u8>, Error> {
+    let (&header, bytes) = bytes
+        .split_first()
+        .ok_or_else(|| error!("missing compression header"))?;
+    match header {
+        HEADER_NONE => {
+            check_max_size(bytes.len() as u64, max_size)?;
+            Ok(bytes.to_vec())
+        }
+        HEADER_LZ4 => {
+            let (size, _) = lz4_flex::block::uncompressed_size(bytes)
+                .map_err(|e| error!("uncompressed_size failed: {:?}", e))?;
+            check_max_size(size as u64, max_size)?;
+            lz4_flex::decompress_size_prepended(bytes)
+                .map_err(|e| error!("decompress failed: {:?}", e))
+        }
+        HEADER_ZSTD => {
+            let decompress_bound = zstd_safe::decompress_bound(bytes)
+                .map_err(|e| error!("decompress_bound failed: {:?}", e))?;
+            check_max_size(decompress_bound, max_size)?;
+            // safety: output is not read before initialized
+            let mut output = unsafe { Vec::uninit(decompress_bound as usize) };
+            let n = ZSTD_DCTX
+                .with(|dctx| dctx.borrow_mut().decompress(&mut output[..], bytes))
+                .map_err(|e| error!("decompress failed: {:?}", e))?;
+            output.truncate(n);
+            Ok(output)
+        }
+        _ => Err(error!("unknown compression header: {}", header)),
+    }
 }
 
 #[cfg(test)]
@@ -51,22 +180,48 @@ mod tests {
     #[test]
     fn test_compress_decompress() {
         let data = b"hello world";
-        let compressed = compress(data);
+        let compressed = compress(data, Compression::default());
         let decompressed = decompress(&compressed, None).unwrap();
         assert_eq!(data, &decompressed[..]);
     }
 
+    #[test]
+    fn test_compress_decompress_all_algorithms() {
+        let data = b"hello world hello world hello world hello world";
+        for algorithm in Compression::ALL {
+            let compressed = compress(data, algorithm);
+            assert_eq!(compressed[0], algorithm.header());
+            let decompressed = decompress(&compressed, None).unwrap();
+            assert_eq!(data, &decompressed[..], "{}", algorithm);
+        }
+    }
+
     #[test]
     fn test_too_large() {
         let data = b"hello world";
-        let compressed = compress(data);
         let max_size = Some(5); // Intentionally smaller than the expected decompressed size
-        assert!(decompress(&compressed, max_size).is_err());
+        for algorithm in Compression::ALL {
+            let compressed = compress(data, algorithm);
+            assert!(decompress(&compressed, max_size).is_err(), "{}", algorithm);
+        }
     }
 
     #[test]
     fn test_incorrect_decompress() {
         let data = b"wt2gh2giojamonguspotion";
         assert!(decompress(data, None).is_err());
+        assert!(decompress(&[], None).is_err());
+    }
+
+    #[test]
+    fn test_from_str() {
+        for algorithm in Compression::ALL {
+            assert_eq!(Compression::from_str(&algorithm.to_string()).unwrap(), algorithm);
+        }
+        assert_eq!(Compression::from_str("zstd").unwrap(), Compression::Zstd(ZSTD_LEVEL));
+        assert_eq!(Compression::from_str("zstd:19").unwrap(), Compression::Zstd(19));
+        assert!(Compression::from_str("zstd:1000").is_err());
+        assert!(Compression::from_str("lz4:1").is_err());
+        assert!(Compression::from_str("gzip").is_err());
     }
 }
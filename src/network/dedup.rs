@@ -0,0 +1,124 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use crate::{error, keys::Hash, util::Error};
+
+pub const MIN_DEDUP_WINDOW: Duration = Duration::from_millis(100);
+pub const MAX_DEDUP_WINDOW: Duration = Duration::from_secs(600);
+pub const MIN_DEDUP_CAPACITY: usize = 1_000;
+pub const MAX_DEDUP_CAPACITY: usize = 10_000_000;
+
+/// Remembers the hashes of recently broadcast notes so they aren't re-broadcast.
+/// Hashes are kept in two generations which rotate every `window`, or early when
+/// the current generation reaches `capacity`, so a hash is remembered for
+/// between one and two windows.
+pub struct DedupWindow {
+    window: Duration,
+    capacity: usize,
+    rotated_at: Instant,
+    current: HashSet<Hash>,
+    previous: HashSet<Hash>,
+}
+
+impl DedupWindow {
+    pub fn new(window: Duration, capacity: usize, now: Instant) -> Result<Self, Error> {
+        if !(MIN_DEDUP_WINDOW..=MAX_DEDUP_WINDOW).contains(&window) {
+            return Err(error!(
+                "dedup window must be between {:?} and {:?}",
+                MIN_DEDUP_WINDOW,
+                MAX_DEDUP_WINDOW
+            ));
+        }
+        if !(MIN_DEDUP_CAPACITY..=MAX_DEDUP_CAPACITY).contains(&capacity) {
+            return Err(error!(
+                "dedup capacity must be between {} and {}",
+                MIN_DEDUP_CAPACITY,
+                MAX_DEDUP_CAPACITY
+            ));
+        }
+        Ok(Self {
+            window,
+            capacity,
+            rotated_at: now,
+            current: HashSet::new(),
+            previous: HashSet::new(),
+        })
+    }
+
+    fn rotate(&mut self, now: Instant) {
+        self.previous = std::mem::take(&mut self.current);
+        self.rotated_at = now;
+    }
+
+    /// Record `hash` as seen at `now`, returning whether it is new.
+    pub fn insert(&mut self, hash: Hash, now: Instant) -> bool {
+        if now.duration_since(self.rotated_at) >= self.window {
+            self.rotate(now);
+            // everything in `previous` is also older than the window
+            if now.duration_since(self.rotated_at) >= self.window {
+                self.previous.clear();
+            }
+        }
+        if self.current.contains(&hash) || self.previous.contains(&hash) {
+            return false;
+        }
+        if self.current.len() >= self.capacity {
+            self.rotate(now);
+        }
+        self.current.insert(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_within_window() {
+        let start = Instant::now();
+        let mut dedup = DedupWindow::new(WINDOW, MIN_DEDUP_CAPACITY, start).unwrap();
+        let hash = Hash::random();
+        assert!(dedup.insert(hash, start));
+        assert!(!dedup.insert(hash, start + WINDOW - Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn test_after_rotation() {
+        let start = Instant::now();
+        let mut dedup = DedupWindow::new(WINDOW, MIN_DEDUP_CAPACITY, start).unwrap();
+        let hash = Hash::random();
+        assert!(dedup.insert(hash, start));
+        // the first rotation moves the hash to the previous generation
+        assert!(dedup.insert(Hash::random(), start + WINDOW));
+        assert!(!dedup.insert(hash, start + WINDOW));
+        // the second rotation forgets it
+        assert!(dedup.insert(hash, start + 2 * WINDOW));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let start = Instant::now();
+        let mut dedup = DedupWindow::new(WINDOW, MIN_DEDUP_CAPACITY, start).unwrap();
+        let first = Hash::random();
+        assert!(dedup.insert(first, start));
+        for _ in 0..2 * MIN_DEDUP_CAPACITY {
+            assert!(dedup.insert(Hash::random(), start));
+        }
+        assert!(dedup.current.len() <= MIN_DEDUP_CAPACITY);
+        assert!(dedup.insert(first, start));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let now = Instant::now();
+        assert!(DedupWindow::new(Duration::from_millis(1), MIN_DEDUP_CAPACITY, now).is_err());
+        assert!(DedupWindow::new(Duration::from_secs(3600), MIN_DEDUP_CAPACITY, now).is_err());
+        assert!(DedupWindow::new(WINDOW, 1, now).is_err());
+        assert!(DedupWindow::new(WINDOW, usize::MAX, now).is_err());
+        assert!(DedupWindow::new(MIN_DEDUP_WINDOW, MAX_DEDUP_CAPACITY, now).is_ok());
+    }
+}
@@ -24,7 +24,18 @@ impl Endpoint {
             && !addr.is_documentation()
             && !addr.is_broadcast()
     }
-    pub fn to_bytes(&self) -> [u8; 6] {
+    /// Checks that the endpoint has a port a peer could be reached on.
+    pub fn check_port(&self) -> Result<(), Error> {
+        if self.port == 0 {
+            return Err(error!("endpoint {} has port 0", self));
+        }
+        Ok(())
+    }
+    /// The /24 subnet of the address
+    pub fn subnet(&self) -> [u8; 3] {
+        [self.addr[0], self.addr[1], self.addr[2]]
+    }
+    pub fn to_bytes(self) -> [u8; 6] {
         let mut bytes = [0u8; 6];
         bytes[0..4].copy_from_slice(&self.addr);
         bytes[4..6].copy_from_slice(&self.port.to_le_bytes());
@@ -66,14 +77,16 @@ impl From<std::net::SocketAddrV4> for Endpoint {
 impl FromStr for Endpoint {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.split('/').last().unwrap();
+        let s = s.split('/').next_back().unwrap();
         let mut parts = s.split(':');
         let addr = parts.next().ok_or(error!("no ip"))?;
         let port = parts.next().ok_or(error!("no port"))?.parse()?;
-        Ok(Self {
+        let endpoint = Self {
             addr: addr.parse::<std::net::Ipv4Addr>()?.octets(),
             port,
-        })
+        };
+        endpoint.check_port()?;
+        Ok(endpoint)
     }
 }
 
@@ -93,3 +106,23 @@ impl DeJson for Endpoint {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reject_port_zero() {
+        assert!(Endpoint::from_str("1.2.3.4:0").is_err());
+        assert!(Endpoint::from_str("udp://1.2.3.4:0").is_err());
+        let endpoint = Endpoint::from_str("1.2.3.4:41594").unwrap();
+        assert_eq!(endpoint.addr, [1, 2, 3, 4]);
+        assert_eq!(endpoint.port, 41594);
+    }
+
+    #[test]
+    fn test_reject_port_zero_in_config() {
+        assert!(Endpoint::deserialize_json("\"1.2.3.4:0\"").is_err());
+        assert!(Endpoint::deserialize_json("\"1.2.3.4:41594\"").is_ok());
+    }
+}
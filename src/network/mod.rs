@@ -7,15 +7,23 @@ mod receiver;
 mod shred;
 mod assembler;
 mod broadcaster;
+mod dedup;
+mod shred_cache;
+mod peer_snapshot;
 
-use center_map::{CenterMap, CenterMapValue};
-use compress::{compress, decompress};
+use center_map::CenterMap;
+pub use compress::compress;
+#[cfg(test)]
+pub use compress::decompress;
 use models::{Peer, Note};
-use shred::Shred;
+pub use shred::{Shred, ShredParams, MAX_SHRED_DATA_SIZE};
+use shred_cache::ShredCache;
 
+pub use compress::Compression;
 pub use endpoint::Endpoint;
-pub use transmitter::{Transmitter, MTU};
+pub use transmitter::{Transmitter, TransmitterConfig, MTU};
 pub use receiver::Receiver;
-pub use models::{ShredNote, TelemetryNote};
-pub use assembler::Assembler;
-pub use broadcaster::Broadcaster;
\ No newline at end of file
+pub use models::{ShredNote, ShredRequest, SyncRequest, SyncResponse, TelemetryNote, SYNC_RESPONSE_HEADER_LEN};
+pub use assembler::{Assembler, AssemblerConfig};
+pub use broadcaster::Broadcaster;
+pub use dedup::DedupWindow;
\ No newline at end of file
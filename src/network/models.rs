@@ -1,9 +1,12 @@
+use std::time::{Duration, SystemTime};
+
 use bincode::{Decode, Encode};
 
 use crate::{
     error,
-    keys::{Hash, HashBuilder, Private, Public, Signature},
-    protocol::{Amount, Open, Slot, Tx},
+    keys::{Domain, Hash, HashBuilder, Private, Public, Signature},
+    protocol::{Amount, Open, Slot, Tx, Vote},
+    state::Block,
     util::{self, Error, Version},
 };
 
@@ -16,11 +19,18 @@ pub struct Peer {
     pub last_contact: Slot,
     pub endpoint: Endpoint,
     pub version: Version,
+    /// Raised by the peer's useful contributions and lowered by its invalid data and timeouts
+    pub score: i32,
+    /// The number of blocks the peer last reported finalizing after genesis
+    pub height: u64,
 }
 impl CenterMapValue<Amount> for Peer {
     fn priority(&self) -> Amount {
         self.weight
     }
+    fn score(&self) -> i32 {
+        self.score
+    }
 }
 
 #[derive(Encode, Decode, Clone, Copy)]
@@ -31,36 +41,48 @@ pub struct TelemetryNote {
     pub slot: Slot,
     pub ep: Endpoint,
     pub version: Version,
+    /// The number of blocks the sender has finalized after genesis
+    pub height: u64,
 }
 impl TelemetryNote {
-    fn hash_pieces(slot: Slot, ep: Endpoint, version: Version) -> Hash {
-        let mut buf = [0u8; 20];
+    fn hash_pieces(slot: Slot, ep: Endpoint, version: Version, height: u64) -> Hash {
+        let mut buf = [0u8; 28];
         buf[0..8].copy_from_slice(&slot.to_bytes());
         buf[8..14].copy_from_slice(&ep.to_bytes());
         buf[14..20].copy_from_slice(&version.to_bytes());
-        Hash::digest(&buf)
+        buf[20..28].copy_from_slice(&height.to_le_bytes());
+        Hash::digest_in(Domain::Telemetry, &buf)
     }
-    pub fn new(private: Private, slot: Slot, ep: Endpoint, version: Version) -> Self {
+    pub fn new(private: Private, slot: Slot, ep: Endpoint, version: Version, height: u64) -> Self {
         let mut tel_note = Self {
             from: private.to_public(),
             signature: Signature::zero(),
             slot,
             ep,
             version,
+            height,
         };
-        let bytes = util::view_as_bytes(&tel_note);
-        let hash = Hash::digest(&bytes[96..]);
-        let signature = private.sign(&hash);
-        tel_note.signature = signature;
+        tel_note.signature = private.sign(&tel_note.hash());
         tel_note
     }
     pub fn hash(&self) -> Hash {
-        Self::hash_pieces(self.slot, self.ep, self.version)
+        Self::hash_pieces(self.slot, self.ep, self.version, self.height)
+    }
+    /// The hash of this note including its sender, which identifies it among
+    /// the notes of all nodes
+    pub fn id(&self) -> Hash {
+        let mut hb = HashBuilder::new();
+        hb.update(self.from.as_bytes());
+        hb.update(self.hash().as_bytes());
+        hb.finish()
     }
     pub fn verify(&self) -> Result<(), Error> {
         let hash = self.hash();
         self.from.verify(&hash, &self.signature)
     }
+    pub fn check_skew(&self, now: SystemTime, max_skew: Duration) -> Result<(), Error> {
+        self.slot.check_skew(now, max_skew)
+    }
 }
 
 #[derive(Encode, Decode, Clone)]
@@ -72,8 +94,19 @@ pub struct ShredNote {
     pub shred: Shred,
 }
 impl ShredNote {
+    #[cfg(test)]
+    pub fn new(private: Private, slot: Slot, shred: Shred) -> Self {
+        let mut shred_note = Self {
+            from: private.to_public(),
+            signature: Signature::zero(),
+            slot,
+            shred,
+        };
+        shred_note.signature = private.sign(&shred_note.hash());
+        shred_note
+    }
     pub fn hash(&self) -> Hash {
-        let mut hb = HashBuilder::new();
+        let mut hb = HashBuilder::with_domain(Domain::Shred);
         hb.update(&self.slot.to_bytes());
         self.shred.hash_into(&mut hb);
         hb.finish()
@@ -84,14 +117,149 @@ impl ShredNote {
     }
 }
 
+/// The maximum number of bytes in the bitmap of a `ShredRequest`,
+/// enough for the largest possible batch
+pub const MAX_SHRED_REQUEST_BITMAP_LEN: usize = 32;
+
+/// A request from `from` for the shreds of a batch of `slot` which it is missing.
+/// Bit `i` of `bitmap` is set if shred `i` of the batch is needed.
+#[derive(Encode, Decode, Clone)]
+pub struct ShredRequest {
+    pub from: Public,
+    pub signature: Signature,
+    pub slot: Slot,
+    pub batch_index: u32,
+    pub bitmap: Vec<u8>,
+}
+impl ShredRequest {
+    pub fn new(private: Private, slot: Slot, batch_index: u32, bitmap: Vec<u8>) -> Self {
+        let mut shred_request = Self {
+            from: private.to_public(),
+            signature: Signature::zero(),
+            slot,
+            batch_index,
+            bitmap,
+        };
+        shred_request.signature = private.sign(&shred_request.hash());
+        shred_request
+    }
+    pub fn hash(&self) -> Hash {
+        let mut hb = HashBuilder::with_domain(Domain::ShredRequest);
+        hb.update(&self.slot.to_bytes());
+        hb.update(&self.batch_index.to_le_bytes());
+        hb.update(&self.bitmap);
+        hb.finish()
+    }
+    /// The hash of this request including its sender, which identifies it among
+    /// the requests of all nodes
+    pub fn id(&self) -> Hash {
+        let mut hb = HashBuilder::new();
+        hb.update(self.from.as_bytes());
+        hb.update(self.hash().as_bytes());
+        hb.finish()
+    }
+    pub fn verify(&self) -> Result<(), Error> {
+        if self.bitmap.len() > MAX_SHRED_REQUEST_BITMAP_LEN {
+            return Err(error!("shred request bitmap too large"));
+        }
+        let hash = self.hash();
+        self.from.verify(&hash, &self.signature)
+    }
+    /// Is shred `shred_index` of the batch requested?
+    pub fn is_requested(&self, shred_index: usize) -> bool {
+        self.bitmap
+            .get(shred_index / 8)
+            .is_some_and(|x| x & (1 << (shred_index % 8)) != 0)
+    }
+}
+
+/// A request from `from` to `to` for the finalized blocks after `after`
+#[derive(Encode, Decode, Clone)]
+pub struct SyncRequest {
+    pub from: Public,
+    pub to: Public,
+    pub signature: Signature,
+    pub after: Slot,
+}
+impl SyncRequest {
+    pub fn new(private: Private, to: Public, after: Slot) -> Self {
+        let mut sync_request = Self {
+            from: private.to_public(),
+            to,
+            signature: Signature::zero(),
+            after,
+        };
+        sync_request.signature = private.sign(&sync_request.hash());
+        sync_request
+    }
+    pub fn hash(&self) -> Hash {
+        let mut buf = [0u8; 72];
+        buf[0..32].copy_from_slice(self.from.as_bytes());
+        buf[32..64].copy_from_slice(self.to.as_bytes());
+        buf[64..72].copy_from_slice(&self.after.to_bytes());
+        Hash::digest_in(Domain::SyncRequest, &buf)
+    }
+    pub fn verify(&self) -> Result<(), Error> {
+        let hash = self.hash();
+        self.from.verify(&hash, &self.signature)
+    }
+}
+
+/// Finalized blocks from `from` answering the `SyncRequest` with hash `request`, in order,
+/// with the votes of a quorum of representatives for the last of them
+#[derive(Encode, Decode, Clone)]
+pub struct SyncResponse {
+    pub from: Public,
+    pub signature: Signature,
+    pub request: Hash,
+    pub blocks: Vec<Block>,
+    pub votes: Vec<Vote>,
+}
+impl SyncResponse {
+    pub fn new(private: Private, request: Hash, blocks: Vec<Block>, votes: Vec<Vote>) -> Self {
+        let mut sync_response = Self {
+            from: private.to_public(),
+            signature: Signature::zero(),
+            request,
+            blocks,
+            votes,
+        };
+        sync_response.signature = private.sign(&sync_response.hash());
+        sync_response
+    }
+    /// The hash of the request and blocks, which are each signed by their leader,
+    /// as are the votes by their representative
+    pub fn hash(&self) -> Hash {
+        let mut hb = HashBuilder::with_domain(Domain::SyncResponse);
+        hb.update(self.request.as_bytes());
+        for block in self.blocks.iter() {
+            hb.update(block.hash.as_bytes());
+        }
+        hb.finish()
+    }
+    pub fn verify(&self) -> Result<(), Error> {
+        let hash = self.hash();
+        self.from.verify(&hash, &self.signature)
+    }
+}
+
 const MAGIC_NUMBER: [u8; 7] = [0x3f, 0xd1, 0x0f, 0xe2, 0x5e, 0x76, 0xfa];
+/// The length of a `Note::SyncResponse` without its blocks and votes: the magic number,
+/// the variant tag, the responder and its signature, the request,
+/// and the length prefixes of the blocks and votes
+pub const SYNC_RESPONSE_HEADER_LEN: usize = MAGIC_NUMBER.len() + 4 + 32 + 64 + 32 + 8 + 8;
 
 #[derive(Encode, Decode, Clone)]
 pub enum Note {
-    TelemetryNote(Box<TelemetryNote>),
-    ShredNote(Box<ShredNote>),
+    Telemetry(Box<TelemetryNote>),
+    Shred(Box<ShredNote>),
+    ShredRequest(Box<ShredRequest>),
+    /// Only the signed transaction is sent: receivers verify it into a `TxHalf`, as its
+    /// memoized hash can't be trusted from the wire, and `TxFiller` provides the account indices
     Transaction(Box<Tx>),
-    Open(Box<Open>)
+    Open(Box<Open>),
+    SyncRequest(Box<SyncRequest>),
+    SyncResponse(Box<SyncResponse>)
 }
 impl Note {
     pub fn serialize(&self, mtu: usize) -> Vec<u8> {
@@ -101,17 +269,245 @@ impl Note {
         bytes
     }
     pub fn deserialize(bytes: &[u8], mtu: usize) -> Result<Self, Error> {
-        if bytes.len() < 8 {
+        if bytes.len() <= MAGIC_NUMBER.len() {
             return Err(error!("message too small"));
         }
-        if bytes[0..8] != MAGIC_NUMBER {
+        if bytes[..MAGIC_NUMBER.len()] != MAGIC_NUMBER {
             return Err(error!("wrong magic number"));
         }
         if bytes.len() > mtu {
             return Err(error!("message too large"));
         }
-        util::decode_from_slice(&bytes[8..]).or_else(|_| {
-            return Err(error!("invalid message"));
-        })
+        util::decode_from_slice(&bytes[MAGIC_NUMBER.len()..]).map_err(|_| error!("invalid message"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{work_fixtures, Identity, Seed, Work};
+    use crate::network::MTU;
+    use crate::state::BlockBody;
+    use crate::protocol::{Pair, TxEmpty, TxKind};
+    use std::str::FromStr;
+
+    const MAX_SKEW: Duration = Duration::from_secs(2);
+
+    fn tel_note_at(time: SystemTime) -> TelemetryNote {
+        TelemetryNote::new(
+            Seed([3; 32]).derive(0),
+            Slot::from_system_time(time),
+            Endpoint::from_str("127.0.0.1:41594").unwrap(),
+            Version::new(0, 1, 0),
+            0,
+        )
+    }
+
+    #[test]
+    fn test_tel_note_verify() {
+        let note = tel_note_at(SystemTime::now());
+        assert!(note.verify().is_ok());
+        let mut tampered = note;
+        tampered.ep = Endpoint::from_str("127.0.0.1:41595").unwrap();
+        assert!(tampered.verify().is_err());
+        let mut inflated = note;
+        inflated.height = 1_000;
+        assert!(inflated.verify().is_err());
+    }
+
+    #[test]
+    fn test_tel_note_within_skew() {
+        let now = SystemTime::now();
+        assert!(tel_note_at(now).check_skew(now, MAX_SKEW).is_ok());
+        assert!(tel_note_at(now + MAX_SKEW / 2).check_skew(now, MAX_SKEW).is_ok());
+        assert!(tel_note_at(now - Duration::from_secs(60)).check_skew(now, MAX_SKEW).is_ok());
+    }
+
+    #[test]
+    fn test_tel_note_beyond_skew() {
+        let now = SystemTime::now();
+        let note = tel_note_at(now + MAX_SKEW + Duration::from_secs(1));
+        assert!(note.check_skew(now, MAX_SKEW).is_err());
+        let mut note = tel_note_at(now);
+        note.slot = Slot::max();
+        assert!(note.check_skew(now, MAX_SKEW).is_err());
+    }
+
+    #[test]
+    fn test_transaction_note_to_tx_half() {
+        let (id, work) = (work_fixtures::identity(0), work_fixtures::first_tx_work());
+        let tx = Tx::sign(&id, 0, Public::random(), TxKind::Transfer(Amount::from_raw(1)), work);
+        let bytes = Note::Transaction(Box::new(tx)).serialize(MTU);
+        // neither the hash nor the account indices are sent
+        assert_eq!(bytes.len(), MAGIC_NUMBER.len() + NOTE_TAG_LEN + TX_LEN);
+        let tx_half = match Note::deserialize(&bytes, MTU).unwrap() {
+            Note::Transaction(tx) => TxEmpty::boxed(*tx).verify().unwrap(),
+            _ => panic!("expected a transaction"),
+        };
+        assert_eq!(tx_half.hash(), tx.hash());
+        assert!(tx_half.tx().from == id.public && tx_half.tx().to == tx.to && tx_half.tx().amount == tx.amount);
+    }
+
+    #[test]
+    fn test_reject_malformed_transaction_notes() {
+        let private = Seed([0; 32]).derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let tx = Tx::sign(&id, 0, Public::random(), TxKind::Transfer(Amount::from_raw(1)), Work::zero());
+        let bytes = Note::Transaction(Box::new(tx)).serialize(MTU);
+
+        // an unknown note kind
+        let mut unknown = bytes.clone();
+        unknown[MAGIC_NUMBER.len()..MAGIC_NUMBER.len() + 4].copy_from_slice(&200u32.to_le_bytes());
+        assert!(Note::deserialize(&unknown, MTU).is_err());
+
+        // a transaction amount beyond the supply doesn't decode
+        let amount_offset = MAGIC_NUMBER.len() + 4 + 8 + 32;
+        for amount in [Amount::initial_supply().to_raw() + 1, u64::MAX] {
+            let mut oversized = bytes.clone();
+            oversized[amount_offset..amount_offset + 8].copy_from_slice(&amount.to_le_bytes());
+            assert!(Note::deserialize(&oversized, MTU).is_err());
+        }
+        let mut supply = bytes.clone();
+        supply[amount_offset..amount_offset + 8].copy_from_slice(&Amount::initial_supply().to_raw().to_le_bytes());
+        assert!(matches!(Note::deserialize(&supply, MTU).unwrap(), Note::Transaction(tx) if tx.amount == Amount::initial_supply()));
+
+        // truncated transactions are rejected
+        assert!(Note::deserialize(&bytes[..bytes.len() - 1], MTU).is_err());
+    }
+
+    /// Encode `value`, check it takes exactly `expected_len` bytes on the wire,
+    /// and decode it again, checking it re-encodes to the same bytes.
+    fn round_trip<T: Encode + Decode>(value: &T, expected_len: usize) -> T {
+        let mut bytes = Vec::new();
+        util::encode_into_writer(&mut bytes, value).unwrap();
+        assert_eq!(bytes.len(), expected_len);
+        let decoded: T = util::decode_from_slice(&bytes).unwrap();
+        let mut reencoded = Vec::new();
+        util::encode_into_writer(&mut reencoded, &decoded).unwrap();
+        assert_eq!(bytes, reencoded);
+        decoded
+    }
+
+    // The wire sizes of the models, in bytes
+    const PEER_LEN: usize = 8 + 8 + 6 + 6 + 4 + 8;
+    const TELEMETRY_NOTE_LEN: usize = 32 + 64 + 8 + 6 + 6 + 8;
+    /// Six `u32` headers and the length prefix of the data
+    const EMPTY_SHRED_LEN: usize = 6 * 4 + 8;
+    const SHRED_NOTE_LEN: usize = 32 + 64 + 8 + EMPTY_SHRED_LEN;
+    /// Excluding the bitmap, which is prefixed by its length
+    const SHRED_REQUEST_LEN: usize = 32 + 64 + 8 + 4 + 8;
+    const TX_LEN: usize = 8 + 32 + 8 + 32 + 8 + 64;
+    const OPEN_LEN: usize = 32 + 32 + 8 + 64;
+    const SYNC_REQUEST_LEN: usize = 32 + 32 + 64 + 8;
+    const VOTE_LEN: usize = 32 + 2 * (8 + 32) + 64;
+    /// Excluding the opens, transactions and votes, which are each prefixed by their length
+    const BLOCK_LEN: usize = 32 + 64 + 8 + 32 + 32 + 32;
+    /// The variant tag of a `Note`
+    const NOTE_TAG_LEN: usize = 4;
+
+    #[test]
+    fn test_round_trip_models() {
+        let private = Seed([5; 32]).derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let ep = Endpoint::from_str("10.0.0.1:41594").unwrap();
+        let version = Version::new(1, 2, 3);
+
+        let peer = Peer { weight: Amount::from_raw(7), last_contact: Slot::zero().next(), endpoint: ep, version, score: -3, height: 12 };
+        let decoded = round_trip(&peer, PEER_LEN);
+        assert!(decoded.weight == peer.weight && decoded.last_contact == peer.last_contact && decoded.score == peer.score);
+        assert_eq!(decoded.height, peer.height);
+        assert_eq!(decoded.endpoint.to_bytes(), ep.to_bytes());
+        assert_eq!(decoded.version.to_bytes(), version.to_bytes());
+
+        let tel_note = TelemetryNote::new(private, Slot::zero().next(), ep, version, 12);
+        let decoded = round_trip(&tel_note, TELEMETRY_NOTE_LEN);
+        assert!(decoded.height == tel_note.height && decoded.verify().is_ok());
+        assert!(decoded.from == tel_note.from && decoded.signature == tel_note.signature && decoded.slot == tel_note.slot);
+        assert_eq!(decoded.ep.to_bytes(), ep.to_bytes());
+        assert_eq!(decoded.version.to_bytes(), version.to_bytes());
+        assert!(decoded.verify().is_ok());
+
+        let shred_note = ShredNote::new(private, Slot::zero().next(), Shred::default());
+        let decoded = round_trip(&shred_note, SHRED_NOTE_LEN);
+        assert!(decoded.from == shred_note.from && decoded.signature == shred_note.signature && decoded.slot == shred_note.slot);
+        assert_eq!(decoded.hash(), shred_note.hash());
+
+        let bitmap = vec![0b101, 0b1];
+        let shred_request = ShredRequest::new(private, Slot::zero().next(), 3, bitmap.clone());
+        let decoded = round_trip(&shred_request, SHRED_REQUEST_LEN + bitmap.len());
+        assert!(decoded.from == shred_request.from && decoded.signature == shred_request.signature && decoded.slot == shred_request.slot);
+        assert_eq!(decoded.batch_index, 3);
+        assert_eq!(decoded.bitmap, bitmap);
+
+        let tx = Tx::sign(&id, 4, Public::random(), TxKind::Transfer(Amount::from_raw(9)), Work::zero());
+        let open = Open::sign(&id, Public::random(), Work::zero());
+        let sync_request = SyncRequest::new(private, Public::random(), Slot::zero().next());
+        let block = Block::sign(id, Slot::zero().next(), Hash::random(), BlockBody { transactions: vec![tx], tx_hashes: vec![tx.hash()], ..BlockBody::default() });
+        let decoded = round_trip(&block, BLOCK_LEN + 3 * 8 + TX_LEN);
+        assert!(decoded.hash == block.hash && decoded.signature == block.signature && decoded.previous == block.previous);
+        assert_eq!(decoded.transactions.len(), 1);
+        let vote = Vote::sign(&id, Pair { slot: Slot::zero(), block: Hash::random() }, Pair { slot: block.slot, block: block.hash });
+        let sync_response = SyncResponse::new(private, sync_request.hash(), vec![block.clone(), block], vec![vote]);
+        let notes = [
+            (Note::Telemetry(Box::new(tel_note)), TELEMETRY_NOTE_LEN),
+            (Note::Shred(Box::new(shred_note.clone())), SHRED_NOTE_LEN),
+            (Note::ShredRequest(Box::new(shred_request.clone())), SHRED_REQUEST_LEN + bitmap.len()),
+            (Note::Transaction(Box::new(tx)), TX_LEN),
+            (Note::Open(Box::new(open)), OPEN_LEN),
+            (Note::SyncRequest(Box::new(sync_request)), SYNC_REQUEST_LEN),
+            (Note::SyncResponse(Box::new(sync_response)), SYNC_RESPONSE_HEADER_LEN - MAGIC_NUMBER.len() - NOTE_TAG_LEN + 2 * (BLOCK_LEN + 3 * 8 + TX_LEN) + VOTE_LEN),
+        ];
+        for (note, len) in notes.iter() {
+            match (round_trip(note, NOTE_TAG_LEN + len), note) {
+                (Note::Telemetry(a), Note::Telemetry(b)) => assert!(a.signature == b.signature),
+                (Note::Shred(a), Note::Shred(b)) => assert!(a.signature == b.signature),
+                (Note::ShredRequest(a), Note::ShredRequest(b)) => assert!(a.signature == b.signature),
+                (Note::Transaction(a), Note::Transaction(b)) => {
+                    assert!(a.nonce == b.nonce && a.from == b.from && a.amount == b.amount && a.to == b.to);
+                    assert!(a.work == b.work && a.signature == b.signature);
+                }
+                (Note::Open(a), Note::Open(b)) => {
+                    assert!(a.account == b.account && a.representative == b.representative);
+                    assert!(a.work == b.work && a.signature == b.signature);
+                }
+                (Note::SyncRequest(a), Note::SyncRequest(b)) => {
+                    assert!(a.from == b.from && a.to == b.to && a.after == b.after && a.signature == b.signature);
+                    assert!(a.verify().is_ok());
+                }
+                (Note::SyncResponse(a), Note::SyncResponse(b)) => {
+                    assert!(a.from == b.from && a.request == b.request && a.signature == b.signature);
+                    assert_eq!(a.blocks.len(), b.blocks.len());
+                    assert!(a.blocks.iter().zip(b.blocks.iter()).all(|(a, b)| a.hash == b.hash && a.signature == b.signature));
+                    assert!(a.votes.len() == 1 && a.votes[0].verify_and_hash().is_ok());
+                    assert!(a.verify().is_ok());
+                }
+                _ => panic!("note decoded as a different variant"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sync_notes_are_bound() {
+        let (requester, responder) = (Seed([5; 32]).derive(0), Seed([5; 32]).derive(1));
+        let sync_request = SyncRequest::new(requester, responder.to_public(), Slot::zero().next());
+        assert!(sync_request.verify().is_ok());
+        // neither the target nor the slot can be swapped out
+        let mut redirected = sync_request.clone();
+        redirected.to = Public::random();
+        assert!(redirected.verify().is_err());
+        let mut rewound = sync_request.clone();
+        rewound.after = Slot::zero();
+        assert!(rewound.verify().is_err());
+
+        // nor can a response be attributed to another request or responder
+        let sync_response = SyncResponse::new(responder, sync_request.hash(), vec![], vec![]);
+        assert!(sync_response.verify().is_ok());
+        let mut rebound = sync_response.clone();
+        rebound.request = rewound.hash();
+        assert!(rebound.verify().is_err());
+        let mut impersonated = sync_response.clone();
+        impersonated.from = requester.to_public();
+        assert!(impersonated.verify().is_err());
     }
 }
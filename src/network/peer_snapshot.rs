@@ -0,0 +1,82 @@
+use std::{fs, sync::mpsc::{self, SyncSender}, thread::{self, JoinHandle}, time::SystemTime};
+
+use crate::{keys::Public, log_warn, protocol::Slot, storage, util::{self, Error, Version}};
+
+use super::Endpoint;
+
+/// What is kept of a peer across restarts: its endpoint, version, score and
+/// last contact. Its weight is looked up again.
+pub type PeerSnapshot = Vec<(Public, Endpoint, Version, i32, Slot)>;
+
+/// Write `peers` to `path`, replacing any previous snapshot only once the
+/// new one is complete, so that a restart mid-write keeps the old one
+pub fn save(path: &str, peers: &PeerSnapshot) -> Result<(), Error> {
+    let mut bytes = Vec::new();
+    util::encode_into_writer(&mut bytes, peers)?;
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, bytes).map_err(storage::io_error)?;
+    fs::rename(tmp, path).map_err(storage::io_error)?;
+    Ok(())
+}
+
+/// Read the snapshot at `path`, which is empty if there is none. No peer is
+/// taken to have been heard from after the snapshot was last written, so
+/// peers age by however long the node was down.
+pub fn load(path: &str) -> Result<PeerSnapshot, Error> {
+    let (bytes, written) = match fs::read(path) {
+        Ok(bytes) => (bytes, fs::metadata(path).and_then(|m| m.modified()).map_err(storage::io_error)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(storage::io_error(e)),
+    };
+    let written = Slot::from_system_time(written.min(SystemTime::now()));
+    let mut peers: PeerSnapshot = util::decode_from_slice(&bytes)?;
+    for peer in peers.iter_mut() {
+        peer.4 = peer.4.min(written);
+    }
+    Ok(peers)
+}
+
+/// Saves snapshots to a path on a thread of its own, keeping disk writes off
+/// the caller's thread. A snapshot is dropped if the previous one is still
+/// waiting to be written.
+pub struct Writer {
+    path: String,
+    snapshots: Option<SyncSender<PeerSnapshot>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    pub fn new(path: String) -> Self {
+        let (snapshots, receiver) = mpsc::sync_channel::<PeerSnapshot>(1);
+        let thread_path = path.clone();
+        let thread = thread::spawn(move || {
+            for snapshot in receiver {
+                if let Err(e) = save(&thread_path, &snapshot) {
+                    log_warn!("Failed to snapshot peers to {}: {}", thread_path, e);
+                }
+            }
+        });
+        Self { path, snapshots: Some(snapshots), thread: Some(thread) }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Queue `snapshot` to be written
+    pub fn write(&self, snapshot: PeerSnapshot) {
+        if let Some(snapshots) = &self.snapshots {
+            let _ = snapshots.try_send(snapshot);
+        }
+    }
+}
+
+impl Drop for Writer {
+    /// Finish writing any queued snapshot
+    fn drop(&mut self) {
+        drop(self.snapshots.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
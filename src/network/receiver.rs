@@ -1,33 +1,49 @@
-use std::{sync::Arc, net::UdpSocket};
+use std::{sync::Arc, net::{SocketAddr, UdpSocket}, time::{Duration, Instant}};
 use crate::{process::{Handle, Mailbox, Message, Process}, protocol::TxEmpty, util::{DefaultInitVec, Error}};
-use super::{models::Note, MTU};
+use super::{models::Note, Endpoint, MTU};
 use rand::seq::SliceRandom;
 
 pub struct Receiver {
     socket: Arc<UdpSocket>,
+    /// How long to wait for a packet before doing maintenance
+    read_timeout: Duration,
+    last_maintenance: Instant,
     transmitter: Handle,
     assembler: Handle,
     tx_pools: Vec<Handle>,
     open_pool: Handle,
+    state: Handle,
 }
 
 impl Receiver {
     // Create a new instance of the Receiver struct
     pub fn new(
-        socket: Arc<UdpSocket>, 
+        socket: Arc<UdpSocket>,
+        read_timeout: Duration,
         transmitter: Handle,
         assembler: Handle,
         tx_pools: Vec<Handle>,
         open_pool: Handle,
+        state: Handle,
     ) -> Self {
         Self {
             socket,
+            read_timeout,
+            last_maintenance: Instant::now(),
             transmitter,
             assembler,
             tx_pools,
             open_pool,
+            state,
         }
     }
+
+    // Do periodic maintenance while the socket is idle or at least every `read_timeout`
+    fn on_maintenance_tick(&mut self) {
+        self.last_maintenance = Instant::now();
+        self.transmitter.send(Message::PrunePeers);
+        self.state.send(Message::SyncTick(Box::new(self.transmitter.clone())));
+    }
 }
 
 impl Process for Receiver {
@@ -37,30 +53,45 @@ impl Process for Receiver {
     // Run the receiver
     fn run(&mut self, _: Mailbox, _: Handle) -> Result<(), Error> {
         let socket = self.socket.clone();
+        socket.set_read_timeout(Some(self.read_timeout))?;
 
-        // Spawn a task to receive notes from the socket
+        // Receive notes from the socket
         let mut buf = Vec::default_init(MTU);
         loop {
-            let n = match socket.recv_from(&mut buf) {
-                Ok((n, _)) => n,
+            if self.last_maintenance.elapsed() >= self.read_timeout {
+                self.on_maintenance_tick();
+            }
+            let (n, from) = match socket.recv_from(&mut buf) {
+                Ok((n, SocketAddr::V4(from))) => (n, Endpoint::from(from)),
+                // Peers are only reachable over ipv4
+                Ok((_, SocketAddr::V6(_))) => continue,
                 Err(e) => match e.kind() {
-                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => {
+                    // The read timed out without a packet arriving
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                        self.on_maintenance_tick();
                         continue
                     }
+                    std::io::ErrorKind::Interrupted => continue,
                     _ => return Err::<(), Error>(Error::from(e)),
                 },
             };
             let bytes = &buf[..n];
             let note = match Note::deserialize(bytes, MTU) {
                 Ok(note) => note,
-                Err(e) => continue
+                Err(_) => {
+                    self.transmitter.send(Message::PeerInvalid(Box::new(from)));
+                    continue
+                }
             };
             match note {
-                Note::TelemetryNote(tel_note) => {
+                Note::Telemetry(tel_note) => {
                     self.transmitter.send(Message::TelemetryNote(tel_note));
                 }
-                Note::ShredNote(shred_note) => {
-                    self.assembler.send(Message::ShredNote(shred_note));
+                Note::Shred(shred_note) => {
+                    self.assembler.send(Message::PeerShredNote(Box::new((from, *shred_note))));
+                }
+                Note::ShredRequest(shred_request) => {
+                    self.assembler.send(Message::ShredRequest(shred_request));
                 }
                 Note::Transaction(tx) => {
                     self.tx_pools.choose(&mut rand::thread_rng()).unwrap().send(
@@ -70,7 +101,46 @@ impl Process for Receiver {
                 Note::Open(open) => {
                     self.open_pool.send(Message::Open(open));
                 }
+                Note::SyncRequest(sync_request) => {
+                    self.state.send(Message::SyncRequest(Box::new((self.transmitter.clone(), *sync_request))));
+                }
+                Note::SyncResponse(sync_response) => {
+                    self.transmitter.send(Message::PeerUseful(Box::new(from)));
+                    self.state.send(Message::SyncBlocks(Box::new((self.transmitter.clone(), *sync_response))));
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process;
+
+    #[test]
+    fn test_maintenance_tick_when_idle() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let (transmitter, mut mailbox) = process::channel();
+        let (assembler, _) = process::channel();
+        let (open_pool, _) = process::channel();
+        let (tx_pool, _) = process::channel();
+        let (state, _) = process::channel();
+        process::spawn(Receiver::new(
+            socket,
+            Duration::from_millis(20),
+            transmitter,
+            assembler,
+            vec![tx_pool],
+            open_pool,
+            state,
+        ));
+        // no packets arrive, so the receiver prunes peers after each timeout
+        for _ in 0..2 {
+            assert!(matches!(
+                mailbox.recv_timeout(Duration::from_secs(1)),
+                Some(Message::PrunePeers)
+            ));
+        }
+    }
+}
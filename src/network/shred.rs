@@ -5,14 +5,16 @@ use std::{
 
 use bincode::{Decode, Encode};
 use bitvec::vec::BitVec;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use reed_solomon_erasure::{
     galois_8::{Field, ReedSolomon},
     ReconstructShard,
 };
 
 use crate::{
+    error,
     keys::HashBuilder,
-    util::{UninitBitVec, UninitVec},
+    util::{Error, UninitBitVec, UninitVec},
 };
 
 // Maps number of data shreds to the optimal erasure batch size which has the
@@ -25,7 +27,73 @@ const DATA_TO_TOTAL: [usize; 33] = [
 ];
 
 const DATA_SHREDS_PER_FULL_BATCH: usize = 32;
-const TOTAL_SHREDS_PER_FULL_BATCH: usize = DATA_TO_TOTAL[DATA_SHREDS_PER_FULL_BATCH];
+
+// The maximum number of shreds in a batch supported by GF(2^8) Reed-Solomon
+pub const MAX_TOTAL_SHREDS_PER_BATCH: usize = 256;
+
+// The most data shredded at once, well above the size of any block, so that
+// pathological inputs are refused rather than shredded into millions of shreds
+pub const MAX_SHRED_DATA_SIZE: usize = 64 * 1024 * 1024;
+
+// How data is laid out into shreds, as computed by `Shred::shred_count`
+#[derive(Debug, PartialEq)]
+struct ShredLayout {
+    chunk_len: u32,
+    n_batches: usize,
+    n_data_shreds_for_last_batch: usize,
+    n_total_shreds_for_last_batch: usize,
+    shred_count: usize,
+}
+
+/// The Reed-Solomon parameters used to shred data: the number of data shreds
+/// in a full batch, and the total number of shreds in a batch with a given
+/// number of data shreds.
+#[derive(Clone, Debug)]
+pub struct ShredParams {
+    data_shreds_per_full_batch: usize,
+    data_to_total: Vec<usize>,
+}
+
+impl ShredParams {
+    /// Create `ShredParams` where a full batch has `data_shreds_per_full_batch`
+    /// data shreds, and a batch with `n` data shreds has `data_to_total[n]`
+    /// shreds in total.
+    pub fn new(data_shreds_per_full_batch: usize, data_to_total: Vec<usize>) -> Result<Self, Error> {
+        if data_shreds_per_full_batch == 0 {
+            return Err(error!("a batch must have at least one data shred"));
+        }
+        if data_to_total.len() != data_shreds_per_full_batch + 1 {
+            return Err(error!(
+                "data_to_total must have {} entries",
+                data_shreds_per_full_batch + 1
+            ));
+        }
+        for (n_data, &n_total) in data_to_total.iter().enumerate().skip(1) {
+            if n_total <= n_data || n_total > MAX_TOTAL_SHREDS_PER_BATCH {
+                return Err(error!(
+                    "a batch with {} data shreds must have between {} and {} shreds",
+                    n_data,
+                    n_data + 1,
+                    MAX_TOTAL_SHREDS_PER_BATCH
+                ));
+            }
+        }
+        Ok(Self {
+            data_shreds_per_full_batch,
+            data_to_total,
+        })
+    }
+
+    fn total_shreds_per_full_batch(&self) -> usize {
+        self.data_to_total[self.data_shreds_per_full_batch]
+    }
+}
+
+impl Default for ShredParams {
+    fn default() -> Self {
+        Self::new(DATA_SHREDS_PER_FULL_BATCH, DATA_TO_TOTAL.to_vec()).unwrap()
+    }
+}
 
 struct ReedSolomonCache {
     cache: Mutex<HashMap<(usize, usize), Arc<ReedSolomon>>>,
@@ -69,8 +137,10 @@ impl AsMut<[u8]> for Shred {
 pub struct Shred {
     // The total number of batches in the shredded data
     n_batches: u32,
-    // The number of data shreds in each batch
+    // The number of data shreds in the batch this shred belongs to
     n_data_shreds: u32,
+    // The total number of shreds in the batch this shred belongs to
+    n_total_shreds: u32,
     // The overall size of the original data
     overall_data_size: u32,
     // The index of the batch this shred belongs to
@@ -88,6 +158,8 @@ impl Shred {
         hb.update(&self.n_batches.to_le_bytes());
         // Update the HashBuilder with the number of data shreds (in little-endian bytes)
         hb.update(&self.n_data_shreds.to_le_bytes());
+        // Update the HashBuilder with the total number of shreds (in little-endian bytes)
+        hb.update(&self.n_total_shreds.to_le_bytes());
         // Update the HashBuilder with the overall data size (in little-endian bytes)
         hb.update(&self.overall_data_size.to_le_bytes());
         // Update the HashBuilder with the batch index (in little-endian bytes)
@@ -98,34 +170,51 @@ impl Shred {
         hb.update(&self.data);
     }
 
-    // Shreds the input data into multiple Shred instances
-    pub fn shred(data: &[u8], chunk_len: u32) -> Vec<Self> {
-        // If the input data is empty or the chunk length is zero, return an empty vector
-        if data.len() == 0 || chunk_len == 0 {
-            return Vec::new();
+    // Returns how `data_len` bytes are laid out into shreds, or `None` if there are
+    // none or more than `MAX_SHRED_DATA_SIZE` of them, or `chunk_len` is zero
+    fn shred_count(data_len: usize, chunk_len: u32, params: &ShredParams) -> Option<ShredLayout> {
+        if data_len == 0 || data_len > MAX_SHRED_DATA_SIZE || chunk_len == 0 {
+            return None;
         }
 
         // If the chunk length is greater than the data length, set them equal to each other
-        let chunk_len = std::cmp::min(chunk_len, data.len() as u32);
+        let chunk_len = std::cmp::min(chunk_len as usize, data_len);
 
         // Calculate the number of data shreds based on the data length and chunk length
-        let n_data_shreds = data.len().div_ceil(chunk_len as usize);
+        let n_data_shreds = data_len.div_ceil(chunk_len);
 
         // Calculate the number of batches based on the number of data shreds
-        let n_batches = n_data_shreds.div_ceil(DATA_SHREDS_PER_FULL_BATCH);
-
-        // Calculate the number of data shreds for the last batch
-        let n_data_shreds_for_last_batch = n_data_shreds % DATA_SHREDS_PER_FULL_BATCH;
+        let n_batches = n_data_shreds.div_ceil(params.data_shreds_per_full_batch);
 
-        // Calculate the total number of shreds for the last batch
-        let n_total_shreds_for_last_batch = DATA_TO_TOTAL[n_data_shreds_for_last_batch];
+        // Calculate the number of data shreds and total shreds for the last batch
+        let n_data_shreds_for_last_batch = n_data_shreds % params.data_shreds_per_full_batch;
+        let n_total_shreds_for_last_batch = params.data_to_total[n_data_shreds_for_last_batch];
 
         // Calculate the total number of shreds
-        let shred_count = {
-            let n_full_batches = n_data_shreds / DATA_SHREDS_PER_FULL_BATCH;
-            let n_shreds_for_last_batch = DATA_TO_TOTAL[n_data_shreds_for_last_batch];
-            (n_full_batches * TOTAL_SHREDS_PER_FULL_BATCH) + n_shreds_for_last_batch
+        let n_full_batches = n_data_shreds / params.data_shreds_per_full_batch;
+        let shred_count = n_full_batches * params.total_shreds_per_full_batch() + n_total_shreds_for_last_batch;
+        Some(ShredLayout {
+            chunk_len: chunk_len as u32,
+            n_batches,
+            n_data_shreds_for_last_batch,
+            n_total_shreds_for_last_batch,
+            shred_count,
+        })
+    }
+
+    // Shreds the input data into multiple Shred instances, with the redundancy given by `params`
+    pub fn from_data(data: &[u8], chunk_len: u32, params: &ShredParams) -> Vec<Self> {
+        // If the input data is empty or too large, or the chunk length is zero, return an empty vector
+        let Some(layout) = Self::shred_count(data.len(), chunk_len, params) else {
+            return Vec::new();
         };
+        let ShredLayout {
+            chunk_len,
+            n_batches,
+            n_data_shreds_for_last_batch,
+            n_total_shreds_for_last_batch,
+            shred_count,
+        } = layout;
 
         // Create an iterator to chunk the data into shreds
         let mut chunks = data.chunks(chunk_len as usize);
@@ -133,20 +222,23 @@ impl Shred {
         // Create a vector to store the shreds with the calculated length
         let mut shreds = Vec::with_capacity(shred_count);
 
+        // The number of data and coding shreds of each batch, to encode once all are laid out
+        let mut batches = Vec::with_capacity(n_batches);
+
         // Initialize variables for batch index and start index
         let mut batch_index = 0;
         let mut start_index = 0;
 
-        // Iterate over the shreds and process them in batches
+        // Iterate over the shreds and lay them out in batches
         while start_index < shred_count {
             // Calculate the tentative end total index for the current batch
-            let end_total_index_tentative = start_index + TOTAL_SHREDS_PER_FULL_BATCH;
+            let end_total_index_tentative = start_index + params.total_shreds_per_full_batch();
 
             // Determine the number of total and data shreds for the current batch
             let (n_total, n_data) = if end_total_index_tentative > shred_count {
                 (n_total_shreds_for_last_batch, n_data_shreds_for_last_batch)
             } else {
-                (TOTAL_SHREDS_PER_FULL_BATCH, DATA_SHREDS_PER_FULL_BATCH)
+                (params.total_shreds_per_full_batch(), params.data_shreds_per_full_batch)
             };
 
             // Calculate the number of coding shreds for the current batch
@@ -161,13 +253,14 @@ impl Shred {
                 let shred = Self {
                     n_batches: n_batches as u32,
                     n_data_shreds: n_data as u32,
+                    n_total_shreds: n_total as u32,
                     overall_data_size: data.len() as u32,
                     batch_index: batch_index as u32,
                     shred_index: (i - start_index) as u32,
                     data: {
                         let mut v = Vec::with_capacity(chunk_len as usize);
                         v.extend_from_slice(chunks.next().unwrap());
-                        v.extend(std::iter::repeat(0).take(v.capacity() - v.len()));
+                        v.extend(std::iter::repeat_n(0, v.capacity() - v.len()));
                         v
                     },
                 };
@@ -179,6 +272,7 @@ impl Shred {
                 let shred = Self {
                     n_batches: n_batches as u32,
                     n_data_shreds: n_data as u32,
+                    n_total_shreds: n_total as u32,
                     overall_data_size: data.len() as u32,
                     batch_index: batch_index as u32,
                     shred_index: (i - start_index) as u32,
@@ -188,20 +282,31 @@ impl Shred {
                 shreds.push(shred);
             }
 
-            // Get the Reed-Solomon encoder from the cache based on the number of data and coding shreds
-            let reed_solomon = REED_SOLOMON_CACHE.get(n_data, n_coding);
-
-            // Encode the shreds using the Reed-Solomon encoder
-            reed_solomon
-                .encode(&mut shreds[start_index..end_total_index])
-                .unwrap();
+            batches.push((n_data, n_coding));
 
             // Update the start index and batch index for the next iteration
             start_index = end_total_index;
             batch_index += 1;
         }
 
-        assert_eq!(shreds.len(), shred_count as usize);
+        assert_eq!(shreds.len(), shred_count);
+
+        // Split the shreds into the disjoint slices of each batch, and encode
+        // the batches in parallel, as each is independent of the others
+        let mut slices = Vec::with_capacity(batches.len());
+        let mut rest = shreds.as_mut_slice();
+        for &(n_data, n_coding) in batches.iter() {
+            let (batch, tail) = rest.split_at_mut(n_data + n_coding);
+            slices.push((batch, n_data, n_coding));
+            rest = tail;
+        }
+        slices.into_par_iter().for_each(|(batch, n_data, n_coding)| {
+            // Get the Reed-Solomon encoder from the cache based on the number of data and coding shreds
+            let reed_solomon = REED_SOLOMON_CACHE.get(n_data, n_coding);
+
+            // Encode the shreds using the Reed-Solomon encoder
+            reed_solomon.encode(batch).unwrap();
+        });
 
         // Return the shredded data
         shreds
@@ -264,6 +369,8 @@ struct Batch {
     n_provided: u32,
     // The number of data shreds in the batch
     n_data: u32,
+    // The total number of shreds in the batch
+    n_total: u32,
     // The length of each shred in the batch
     chunk_len: u32,
     // The list of shreds in the batch
@@ -277,6 +384,7 @@ impl Batch {
             initialized: false,
             n_provided: 0,
             n_data: u32::MAX,
+            n_total: u32::MAX,
             chunk_len: u32::MAX,
             shreds: Vec::new(),
         }
@@ -303,17 +411,25 @@ impl Batch {
 
         // If the batch is not initialized, initialize it with the shred's information
         if !self.initialized {
+            let (n_data, n_total) = (shred.n_data_shreds as usize, shred.n_total_shreds as usize);
+            // Reject parameters which Reed-Solomon can't reconstruct with
+            if n_data == 0 || n_total <= n_data || n_total > MAX_TOTAL_SHREDS_PER_BATCH {
+                return false;
+            }
             self.n_data = shred.n_data_shreds;
+            self.n_total = shred.n_total_shreds;
             self.chunk_len = chunk_len;
-            let n_total = DATA_TO_TOTAL[self.n_data as usize];
             self.shreds.reserve(n_total);
             self.shreds
                 .extend((0..n_total).map(|_| BatchItem(Vec::new())));
             self.initialized = true;
         }
 
-        // Check if the shred's chunk length matches the batch's chunk length
-        if chunk_len != self.chunk_len {
+        // Check if the shred's parameters match the batch's parameters
+        if chunk_len != self.chunk_len
+            || shred.n_data_shreds != self.n_data
+            || shred.n_total_shreds != self.n_total
+        {
             return false;
         }
 
@@ -355,7 +471,7 @@ impl Batch {
         // Get the Reed-Solomon encoder from the cache based on the number of data and coding shreds
         let reed_solomon = REED_SOLOMON_CACHE.get(
             self.n_data as usize,
-            DATA_TO_TOTAL[self.n_data as usize] - self.n_data as usize,
+            (self.n_total - self.n_data) as usize,
         );
 
         // Reconstruct the data shreds using the Reed-Solomon encoder
@@ -406,6 +522,7 @@ impl ShredList {
 
         // Check if the shred is valid and within the allowed data size bounds
         if data_size_bound > self.max_data_size as usize
+            || claimed_data_size > self.max_data_size
             || (n_batches == 0 || n_data == 0 || chunk_len == 0)
         {
             return false;
@@ -459,7 +576,7 @@ impl ShredList {
         }
 
         // If there are no batches, return an empty vector
-        if self.batches.len() == 0 {
+        if self.batches.is_empty() {
             return Some(Vec::new());
         }
 
@@ -468,7 +585,7 @@ impl ShredList {
 
         // Iterate over the batches and reconstruct the data for each batch
         for batch in self.batches.iter_mut() {
-            assert_eq!(batch.try_reconstruct(&mut data), true);
+            assert!(batch.try_reconstruct(&mut data));
         }
 
         // Truncate the reconstructed data to the claimed data size
@@ -478,6 +595,11 @@ impl ShredList {
         Some(data)
     }
 
+    // Returns the number of batches, or 0 if the ShredList is not initialized
+    pub fn n_batches(&self) -> usize {
+        self.batches.len()
+    }
+
     // Checks if a specific shred is needed for reconstruction
     pub fn need_shred(&self, batch_index: usize, shred_index: usize) -> bool {
         // If the ShredList is not initialized, always return true (shred is needed)
@@ -518,7 +640,7 @@ mod tests {
         let chunk_len = 2;
 
         // Shred the data
-        let shreds = Shred::shred(&data, chunk_len);
+        let shreds = Shred::from_data(&data, chunk_len, &ShredParams::default());
 
         // Create a ShredList and provide the shreds
         let mut shred_list = ShredList::new(MAX_DATA_SIZE);
@@ -539,7 +661,7 @@ mod tests {
         let chunk_len = 2;
 
         // Shred the data
-        let mut shreds = Shred::shred(&data, chunk_len);
+        let mut shreds = Shred::from_data(&data, chunk_len, &ShredParams::default());
         assert_eq!(shreds.len(), DATA_TO_TOTAL[5]);
 
         // Remove some shreds to simulate missing shreds
@@ -568,7 +690,7 @@ mod tests {
         let chunk_len = 2;
 
         // Shred the data
-        let mut shreds = Shred::shred(&data, chunk_len);
+        let mut shreds = Shred::from_data(&data, chunk_len, &ShredParams::default());
 
         // Remove too many shreds to make reconstruction impossible
         shreds.truncate(2);
@@ -596,7 +718,7 @@ mod tests {
         let chunk_len = 12;
 
         // Shred the large data
-        let shreds = Shred::shred(&data, chunk_len);
+        let shreds = Shred::from_data(&data, chunk_len, &ShredParams::default());
         assert!(shreds.len() >= (data.len() / chunk_len as usize));
 
         // Create a ShredList and provide the shreds
@@ -611,4 +733,108 @@ mod tests {
         // Verify the reconstructed data matches the original data
         assert_eq!(data, reconstructed_data);
     }
-}
+
+    #[test]
+    fn test_parallel_shred_matches_single_thread() {
+        let mut data = vec![0; 256 * 1024];
+        rand::thread_rng().fill_bytes(&mut data);
+        let shred_with = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let shreds = pool.install(|| Shred::from_data(&data, 1000, &ShredParams::default()));
+            let mut bytes = Vec::new();
+            crate::util::encode_into_writer(&mut bytes, &shreds).unwrap();
+            bytes
+        };
+        assert!(shred_with(1) == shred_with(4));
+    }
+
+    #[test]
+    fn test_shred_and_reconstruct_with_custom_params() {
+        // 4 data shreds per batch, each batch with as many coding shreds as data shreds
+        let params = ShredParams::new(4, vec![0, 2, 4, 6, 8]).unwrap();
+        let data: Vec<u8> = (0..20).collect();
+        let chunk_len = 2;
+
+        // 10 data shreds in batches of 4, 4 and 2
+        let shreds = Shred::from_data(&data, chunk_len, &params);
+        assert_eq!(shreds.len(), 8 + 8 + 4);
+
+        // Drop the first half of each batch, which is all of its data shreds
+        let mut shred_list = ShredList::new(MAX_DATA_SIZE);
+        for shred in shreds {
+            let n_data = shred.n_data_shreds as usize;
+            if shred.get_shred_index() < n_data {
+                continue;
+            }
+            assert!(shred_list.try_provide(shred));
+        }
+        assert_eq!(shred_list.try_reconstruct().unwrap(), data);
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        assert!(ShredParams::new(0, vec![0]).is_err());
+        assert!(ShredParams::new(2, vec![0, 2]).is_err());
+        assert!(ShredParams::new(2, vec![0, 2, 2]).is_err());
+        assert!(ShredParams::new(2, vec![0, 2, 300]).is_err());
+        assert!(ShredParams::new(2, vec![0, 2, 4]).is_ok());
+    }
+
+    #[test]
+    fn test_reject_invalid_shred_params() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let mut shreds = Shred::from_data(&data, 2, &ShredParams::default());
+        let mut shred_list = ShredList::new(MAX_DATA_SIZE);
+        let mut shred = shreds.remove(0);
+        shred.n_total_shreds = shred.n_data_shreds;
+        assert!(!shred_list.try_provide(shred.clone()));
+        shred.n_data_shreds = 1000;
+        shred.n_total_shreds = 2000;
+        assert!(!shred_list.try_provide(shred));
+    }
+
+    #[test]
+    fn test_shred_count_bounds() {
+        let params = ShredParams::default();
+        let counts = |data_len, chunk_len| Shred::shred_count(data_len, chunk_len, &params)
+            .map(|layout| (layout.chunk_len, layout.shred_count));
+        assert_eq!(counts(1, 1), Some((1, DATA_TO_TOTAL[1])));
+        assert_eq!(counts(100, 1000), Some((100, DATA_TO_TOTAL[1])));
+        let n_full_batches = MAX_SHRED_DATA_SIZE / DATA_SHREDS_PER_FULL_BATCH;
+        assert_eq!(counts(MAX_SHRED_DATA_SIZE, 1), Some((1, n_full_batches * DATA_TO_TOTAL[32])));
+        // sizes above the maximum are refused, however large
+        assert_eq!(counts(MAX_SHRED_DATA_SIZE + 1, 1), None);
+        assert_eq!(counts(usize::MAX, u32::MAX), None);
+        assert_eq!(counts(0, 1), None);
+        assert_eq!(counts(1, 0), None);
+        // and `from_data` lays data out as counted
+        let data = vec![7; 5000];
+        assert_eq!(Shred::from_data(&data, 100, &params).len(), counts(data.len(), 100).unwrap().1);
+    }
+
+    #[test]
+    fn test_reject_claimed_size_above_max() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let mut shred = Shred::from_data(&data, 2, &ShredParams::default()).remove(0);
+        let mut shred_list = ShredList::new(10);
+        shred.overall_data_size = 11;
+        assert!(!shred_list.try_provide(shred.clone()));
+        shred.overall_data_size = 10;
+        assert!(shred_list.try_provide(shred));
+    }
+
+    #[test]
+    fn test_reject_oversized_batch() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        let mut shred = Shred::from_data(&data, 2, &ShredParams::default()).remove(0);
+        // more data shreds than the batch has in total
+        shred.n_data_shreds = 200;
+        let mut batch = Batch::new();
+        assert!(!batch.try_provide(shred.clone()));
+        assert!(!batch.initialized);
+        // more shreds than GF(2^8) Reed-Solomon supports
+        shred.n_total_shreds = MAX_TOTAL_SHREDS_PER_BATCH as u32 + 1;
+        assert!(!batch.try_provide(shred));
+        assert!(!batch.initialized);
+    }
+}
\ No newline at end of file
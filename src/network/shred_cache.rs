@@ -0,0 +1,72 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::protocol::Slot;
+
+use super::{ShredNote, ShredRequest};
+
+/// The recently broadcast shred notes of the latest `max_slots` slots,
+/// kept to serve `ShredRequest`s from peers missing them.
+pub struct ShredCache {
+    max_slots: usize,
+    slots: BTreeMap<Slot, HashMap<(usize, usize), ShredNote>>,
+}
+
+impl ShredCache {
+    pub fn new(max_slots: usize) -> Self {
+        Self {
+            max_slots,
+            slots: BTreeMap::new(),
+        }
+    }
+
+    /// Cache `shred_note`, evicting the oldest slot if there are too many.
+    pub fn insert(&mut self, shred_note: ShredNote) {
+        if !self.slots.contains_key(&shred_note.slot) && self.slots.len() == self.max_slots {
+            let (&oldest, _) = self.slots.first_key_value().unwrap();
+            if shred_note.slot < oldest {
+                return;
+            }
+            self.slots.remove(&oldest);
+        }
+        let key = (shred_note.shred.get_batch_index(), shred_note.shred.get_shred_index());
+        self.slots.entry(shred_note.slot).or_default().insert(key, shred_note);
+    }
+
+    /// Get the cached shred notes requested by `shred_request`.
+    pub fn get(&self, shred_request: &ShredRequest) -> Vec<ShredNote> {
+        let shred_notes = match self.slots.get(&shred_request.slot) {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+        let batch_index = shred_request.batch_index as usize;
+        (0..shred_request.bitmap.len() * 8)
+            .filter(|&i| shred_request.is_requested(i))
+            .filter_map(|i| shred_notes.get(&(batch_index, i)).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::Seed, network::shred::{Shred, ShredParams}};
+
+    #[test]
+    fn test_evict_oldest_slot() {
+        let private = Seed([4; 32]).derive(0);
+        let mut cache = ShredCache::new(2);
+        let mut slot = Slot::zero();
+        for _ in 0..3 {
+            slot = slot.next();
+            for shred in Shred::from_data(&[1, 2, 3, 4], 2, &ShredParams::default()) {
+                cache.insert(ShredNote::new(private, slot, shred));
+            }
+        }
+        let request = |slot| ShredRequest::new(private, slot, 0, vec![0xff]);
+        assert!(cache.get(&request(Slot::zero().next())).is_empty());
+        assert_eq!(cache.get(&request(slot)).len(), 8);
+        // older than every cached slot, so not cached
+        cache.insert(ShredNote::new(private, Slot::zero(), Shred::from_data(&[1], 1, &ShredParams::default()).remove(0)));
+        assert!(cache.get(&request(Slot::zero())).is_empty());
+    }
+}
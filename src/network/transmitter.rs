@@ -1,16 +1,24 @@
-use std::{net::UdpSocket, sync::Arc, time::Duration};
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc}, time::{Duration, Instant, SystemTime}};
 
-use rand::Rng;
+use rand::seq::SliceRandom;
 
 use crate::{
-    error, keys::{Identity, Private, Public, Signature}, process::{self, Handle, Mailbox, Message, Process, ProcessEndless}, protocol::{Amount, Slot, Tx}, util::{self, DefaultInitVec, Error, Interval, UninitVec, Version}
+    keys::{Hash, Identity, Public}, log_error, log_warn, process::{self, Handle, Mailbox, Message, ProcessEndless}, protocol::{Amount, Slot}, rpc::{Page, PeerInfo, RpcEnvelope, RpcResponse}, util::{Interval, Version}
 };
 
-use super::{models::TelemetryNote, CenterMap, Endpoint, Note, Peer, Shred, ShredNote};
+use super::{models::TelemetryNote, peer_snapshot, CenterMap, DedupWindow, Endpoint, Note, Peer, ShredCache, ShredNote};
 
 pub const MTU: usize = 1280;
 const PEER_UPDATE_INTERVAL: u64 = 15;
 const PEER_TIMEOUT_INTERVAL: u64 = 3 * PEER_UPDATE_INTERVAL;
+/// The number of our own most recently sent telemetry notes which are remembered
+const MAX_SENT_TELEMETRY: usize = 8;
+/// The number of most recent slots whose broadcast shreds are cached for retransmission
+const MAX_CACHED_SHRED_SLOTS: usize = 32;
+/// The bound on the magnitude of a peer's score, so that neither a long good nor bad history is permanent
+const MAX_PEER_SCORE: i32 = 1_000;
+/// The maximum number of peers listed by a single `peers` RPC
+const MAX_PEER_INFOS: u32 = 1024;
 fn fanout(n: usize) -> usize {
     if n < 8 {
         n
@@ -25,69 +33,207 @@ fn fanout(n: usize) -> usize {
     }
 }
 
+/// Take up to `n` of `endpoints` in order, first those in a /24 subnet not yet
+/// taken, then the rest, so that a sample spans as many subnets as it can
+fn diverse_sample(endpoints: impl Iterator<Item = Endpoint>, n: usize) -> Vec<Endpoint> {
+    let mut subnets = HashSet::new();
+    let (mut sample, mut rest) = (Vec::with_capacity(n), Vec::new());
+    for endpoint in endpoints {
+        if sample.len() < n && subnets.insert(endpoint.subnet()) {
+            sample.push(endpoint);
+        } else {
+            rest.push(endpoint);
+        }
+    }
+    let left = n - sample.len();
+    sample.extend(rest.into_iter().take(left));
+    sample
+}
+
+/// Get the height reached by the peers holding half of the total weight of those
+/// given as `(height, weight)`, counting each the same if none has any weight
+fn weighted_median(mut heights: Vec<(u64, Amount)>) -> Option<u64> {
+    heights.sort_unstable_by_key(|&(height, _)| height);
+    let weighted = heights.iter().any(|&(_, weight)| weight > Amount::zero());
+    let weigh = |weight: Amount| if weighted { weight.to_raw() as u128 } else { 1 };
+    let total: u128 = heights.iter().map(|&(_, weight)| weigh(weight)).sum();
+    let mut seen = 0;
+    for (height, weight) in heights {
+        seen += weigh(weight);
+        if 2 * seen >= total {
+            return Some(height);
+        }
+    }
+    None
+}
+
+/// The settings of a `Transmitter`
+pub struct TransmitterConfig {
+    /// The endpoint we advertise to peers
+    pub visible_ep: Endpoint,
+    /// The peers contacted first on discovery
+    pub initial_peers: Arc<Vec<Endpoint>>,
+    /// Secondary seeds, added to `initial_peers` if none of them lead to a peer in time
+    pub fallback_peers: Arc<Vec<Endpoint>>,
+    /// How long discovery waits on `initial_peers` alone before adding `fallback_peers`
+    pub discovery_timeout: Duration,
+    /// The most peers kept with less weight than ours
+    pub max_less: usize,
+    /// The most peers kept with more weight than ours
+    pub max_greater: usize,
+    /// The number of highest-weight peers included in every fanout
+    pub priority_peers: usize,
+    pub version: Version,
+    pub allow_peers_with_private_ip_addresses: bool,
+    pub allow_peers_with_node_external_ip_address: bool,
+    pub max_clock_skew: Duration,
+    /// Drops broadcast notes already seen
+    pub dedup: DedupWindow,
+    /// How much a peer's score is raised for each useful contribution
+    pub score_reward: i32,
+    /// How much a peer's score is lowered for each invalid note or missed telemetry update
+    pub score_penalty: i32,
+    /// The path the peer table is snapshotted to and restored from, if any
+    pub peer_snapshot: Option<String>
+}
+
 pub struct Transmitter {
-    socket: Arc<UdpSocket>,
     visible_ep: Endpoint,
     id: Identity,
     initial_peers: Arc<Vec<Endpoint>>,
-    max_less: usize,
-    max_greater: usize,
+    /// Secondary seeds, added to `initial_peers` if none of them lead to a peer in time
+    fallback_peers: Arc<Vec<Endpoint>>,
+    /// When discovery gives up on `initial_peers` alone, until any peer is found
+    discovery_deadline: Option<Instant>,
+    /// The number of highest-weight peers included in every fanout
+    priority_peers: usize,
     get_weight: Box<dyn Fn(&Public) -> Amount + Send>,
     version: Version,
     allow_peers_with_private_ip_addresses: bool,
     allow_peers_with_node_external_ip_address: bool,
+    max_clock_skew: Duration,
+    dedup: DedupWindow,
+    /// How much a peer's score is raised for each useful contribution
+    score_reward: i32,
+    /// How much a peer's score is lowered for each invalid note or missed telemetry update
+    score_penalty: i32,
+    /// Snapshots the peer table every interval to the path it's restored from
+    /// on start, so a restart doesn't rediscover peers from scratch
+    peer_snapshot: Option<peer_snapshot::Writer>,
+    /// The ids of our own most recently sent telemetry notes
+    sent_telemetry: VecDeque<Hash>,
+    /// Recently broadcast shred notes, to serve retransmit requests
+    shred_cache: ShredCache,
     peers: CenterMap<Public, Amount, Peer>,
-    broadcaster: Handle
+    /// The peer last seen at each endpoint, to score peers by the endpoint their notes
+    /// arrive from. Entries of peers since evicted or moved are checked on lookup and
+    /// dropped on pruning
+    peers_by_endpoint: HashMap<[u8; 6], Public>,
+    broadcaster: Handle,
+    /// Shared with the `State`, which reports readiness based on it
+    peer_count: Arc<AtomicUsize>,
+    /// Our finalized height, kept up to date by the `State` and reported in our telemetry
+    finalized_height: Arc<AtomicU64>,
+    /// The weighted median of the finalized heights our peers report, shared with the `State`
+    sync_target: Arc<AtomicU64>
 }
 
 impl Transmitter {
     pub fn new(
-        socket: Arc<UdpSocket>,
-        visible_ep: Endpoint,
         id: Identity,
-        initial_peers: Arc<Vec<Endpoint>>,
-        max_less: usize,
-        max_greater: usize,
+        config: TransmitterConfig,
         get_weight: Box<dyn Fn(&Public) -> Amount + Send>,
-        version: Version,
-        allow_peers_with_private_ip_addresses: bool,
-        allow_peers_with_node_external_ip_address: bool,
-        broadcaster: Handle
+        broadcaster: Handle,
+        peer_count: Arc<AtomicUsize>,
+        finalized_height: Arc<AtomicU64>,
+        sync_target: Arc<AtomicU64>
     ) -> Self {
         let weight = get_weight(&id.public);
-        Self {
-            socket,
-            visible_ep,
+        let mut transmitter = Self {
+            visible_ep: config.visible_ep,
             id,
-            initial_peers,
-            max_less,
-            max_greater,
+            initial_peers: config.initial_peers,
+            fallback_peers: config.fallback_peers,
+            discovery_deadline: Some(Instant::now() + config.discovery_timeout),
+            priority_peers: config.priority_peers,
             get_weight,
-            version,
-            allow_peers_with_private_ip_addresses,
-            allow_peers_with_node_external_ip_address,
-            peers: CenterMap::new(weight, max_less, max_greater),
-            broadcaster
+            version: config.version,
+            allow_peers_with_private_ip_addresses: config.allow_peers_with_private_ip_addresses,
+            allow_peers_with_node_external_ip_address: config.allow_peers_with_node_external_ip_address,
+            max_clock_skew: config.max_clock_skew,
+            dedup: config.dedup,
+            score_reward: config.score_reward,
+            score_penalty: config.score_penalty,
+            peer_snapshot: config.peer_snapshot.map(peer_snapshot::Writer::new),
+            sent_telemetry: VecDeque::with_capacity(MAX_SENT_TELEMETRY),
+            shred_cache: ShredCache::new(MAX_CACHED_SHRED_SLOTS),
+            peers: CenterMap::new(weight, config.max_less, config.max_greater),
+            peers_by_endpoint: HashMap::new(),
+            broadcaster,
+            peer_count,
+            finalized_height,
+            sync_target
+        };
+        transmitter.restore_peers();
+        transmitter
+    }
+
+    // Restore the peers of the last snapshot which haven't timed out since
+    fn restore_peers(&mut self) {
+        let Some(path) = self.peer_snapshot.as_ref().map(|writer| writer.path()) else {
+            return;
+        };
+        let snapshot = match peer_snapshot::load(path) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                log_warn!("Failed to restore peers from {}: {}", path, e);
+                return;
+            }
+        };
+        let now = Slot::now();
+        for (public, endpoint, version, score, last_contact) in snapshot {
+            if public == self.id.public
+                || !version.is_compatible(self.version)
+                || now.saturating_sub(last_contact) >= PEER_TIMEOUT_INTERVAL
+            {
+                continue;
+            }
+            let weight = (self.get_weight)(&public);
+            self.insert_peer(public, Peer { weight, last_contact, endpoint, version, score, height: 0 });
         }
+        self.peer_count.store(self.peers.len(), Ordering::Relaxed);
     }
 
-    // Broadcast a message to a subset of peers using fanout
+    // Snapshot the peers, to be restored on the next start
+    fn snapshot_peers(&self) {
+        let Some(writer) = &self.peer_snapshot else {
+            return;
+        };
+        writer.write(self.peers.iter()
+            .map(|(&public, peer)| (public, peer.endpoint, peer.version, peer.score, peer.last_contact))
+            .collect());
+    }
+
+    // Broadcast a message to a random subset of peers using fanout, spread
+    // across distinct /24 subnets where possible, and always including the
+    // `priority_peers` highest-weight live peers
     fn broadcast_fanout(&mut self, msg: Vec<u8>) {
-        let mut peer_count = self.peers.len();
-        let mut broadcast_left = fanout(peer_count);
-        let mut endpoints = Vec::with_capacity(broadcast_left);
-        let mut rng = rand::thread_rng();
         let now = Slot::now();
-        while broadcast_left > 0 && peer_count > 0 {
-            let i = rng.gen_range(0..peer_count);
+        let broadcast_count = fanout(self.peers.len());
+        self.prune_peers(now);
+        let mut order: Vec<usize> = (0..self.peers.len()).collect();
+        order.shuffle(&mut rand::thread_rng());
+        let mut endpoints = diverse_sample(order.into_iter().map(|i| self.peers[i].endpoint), broadcast_count);
+        for i in self.peers.top_indices(self.priority_peers) {
             let peer = &self.peers[i];
-            if now.saturating_sub(peer.last_contact) >= PEER_TIMEOUT_INTERVAL {
-                self.peers.remove_index(i);
-                peer_count -= 1;
-                continue;
+            if !endpoints.iter().any(|ep| ep.to_bytes() == peer.endpoint.to_bytes()) {
+                endpoints.push(peer.endpoint);
             }
-            endpoints.push(peer.endpoint);
-            broadcast_left -= 1;
+        }
+        // If no live peers remain, fall back to initial peers to recover connectivity
+        if endpoints.is_empty() {
+            self.broadcast_initial_peers(msg);
+            return;
         }
         self.broadcaster.send(Message::Broadcast(
             Box::new((Arc::new(endpoints), msg))
@@ -96,26 +242,75 @@ impl Transmitter {
 
     // Broadcast a message to initial peers
     fn broadcast_initial_peers(&self, bytes: Vec<u8>) {
-        let socket = self.socket.clone();
         let initial_peers = self.initial_peers.clone();
         self.broadcaster.send(Message::Broadcast(
             Box::new((initial_peers, bytes))
         ));
     }
 
+    // If no peer has been found by the discovery deadline, say so loudly
+    // and start broadcasting to the fallback peers as well
+    fn check_discovery(&mut self, now: Instant) {
+        let Some(deadline) = self.discovery_deadline else {
+            return;
+        };
+        if !self.peers.is_empty() {
+            self.discovery_deadline = None;
+            return;
+        }
+        if now < deadline {
+            return;
+        }
+        self.discovery_deadline = None;
+        if self.fallback_peers.is_empty() {
+            log_error!("No peers found through {} initial peers; this node is isolated from the network", self.initial_peers.len());
+            return;
+        }
+        log_error!(
+            "No peers found through {} initial peers; falling back to {} secondary peers",
+            self.initial_peers.len(),
+            self.fallback_peers.len()
+        );
+        let seeds = self.initial_peers.iter().chain(self.fallback_peers.iter()).copied().collect();
+        self.initial_peers = Arc::new(seeds);
+    }
+
     // Send telemetry messages at regular intervals
     fn on_interval(&mut self) {
+        self.check_discovery(Instant::now());
+
+        // Penalize peers which have missed a telemetry update
+        let now = Slot::now();
+        for i in 0..self.peers.len() {
+            if now.saturating_sub(self.peers[i].last_contact) >= 2 * PEER_UPDATE_INTERVAL {
+                self.rescore(i, -self.score_penalty);
+            }
+        }
+
         // Update my personal weight
         self.peers.update_center((self.get_weight)(&self.id.public));
 
+        // Sync towards the height most of the weight of our peers has reached
+        let heights = self.peers.iter()
+            .filter(|(_, peer)| peer.height > 0)
+            .map(|(_, peer)| (peer.height, peer.weight))
+            .collect();
+        self.sync_target.store(weighted_median(heights).unwrap_or(0), Ordering::Relaxed);
+
         // Create a new telemetry message
         let tel_note = Box::new(TelemetryNote::new(
             self.id.private,
             Slot::now(),
             self.visible_ep,
             self.version,
+            self.finalized_height.load(Ordering::Relaxed),
         ));
-        let msg = Note::TelemetryNote(tel_note);
+        // Remember it, so that it's never re-broadcast if reflected back to us
+        if self.sent_telemetry.len() == MAX_SENT_TELEMETRY {
+            self.sent_telemetry.pop_front();
+        }
+        self.sent_telemetry.push_back(tel_note.id());
+        let msg = Note::Telemetry(tel_note);
         let bytes = msg.serialize(MTU);
 
         // Broadcast the telemetry message to initial peers or a subset of peers
@@ -124,14 +319,23 @@ impl Transmitter {
         } else {
             self.broadcast_fanout(bytes);
         }
+        self.snapshot_peers();
     }
 
     // Handle incoming telemetry messages
     fn on_tel_note(&mut self, tel_note: Box<TelemetryNote>) {
+        // Never re-broadcast telemetry we originated
+        if self.sent_telemetry.contains(&tel_note.id()) {
+            return;
+        }
         // Filter out invalids
         if tel_note.verify().is_err() {
             return;
         }
+        // Reject telemetry from peers whose clock is too far ahead
+        if tel_note.check_skew(SystemTime::now(), self.max_clock_skew).is_err() {
+            return;
+        }
         // Don't accept telemetry from myself :)
         if tel_note.from == self.id.public {
             return;
@@ -140,6 +344,10 @@ impl Transmitter {
         if !tel_note.version.is_compatible(self.version) {
             return;
         }
+        // A peer can't be reached on port 0
+        if tel_note.ep.check_port().is_err() {
+            return;
+        }
         // if we aren't allowed to contact private IPs
         if !self.allow_peers_with_private_ip_addresses && !tel_note.ep.is_external() {
             return;
@@ -158,7 +366,9 @@ impl Transmitter {
                 if now.saturating_sub(peer.last_contact) >= PEER_UPDATE_INTERVAL {
                     peer.version = tel_note.version;
                     peer.endpoint = tel_note.ep;
+                    peer.height = tel_note.height;
                     peer.last_contact = now;
+                    self.peers_by_endpoint.insert(tel_note.ep.to_bytes(), tel_note.from);
                     true
                 } else {
                     false
@@ -166,39 +376,179 @@ impl Transmitter {
             }
             None => {
                 // Insert a new peer if it doesn't exist
-                self.peers.insert(
+                self.insert_peer(
                     tel_note.from,
                     Peer {
                         version: tel_note.version,
                         endpoint: tel_note.ep,
                         weight: (self.get_weight)(&tel_note.from),
                         last_contact: now,
+                        score: 0,
+                        height: tel_note.height,
                     },
                 )
             }
         };
 
         // Broadcast the telemetry message to other peers if necessary
-        if should_broadcast {
-            let note = Note::TelemetryNote(tel_note);
+        if should_broadcast && self.dedup.insert(tel_note.id(), Instant::now()) {
+            let note = Note::Telemetry(tel_note);
             let bytes = note.serialize(MTU);
             self.broadcast_fanout(bytes);
         }
     }
 
-    async fn on_msg(&mut self, msg: Message) {
+    // Insert a peer, returning whether it was kept
+    fn insert_peer(&mut self, public: Public, peer: Peer) -> bool {
+        let endpoint = peer.endpoint.to_bytes();
+        let inserted = self.peers.insert(public, peer);
+        if inserted {
+            self.peers_by_endpoint.insert(endpoint, public);
+        }
+        inserted
+    }
+
+    // Get the index of the peer at `endpoint`, if it's one of our peers
+    fn peer_at(&self, endpoint: &Endpoint) -> Option<usize> {
+        let bytes = endpoint.to_bytes();
+        let index = self.peers.index_of(self.peers_by_endpoint.get(&bytes)?)?;
+        (self.peers[index].endpoint.to_bytes() == bytes).then_some(index)
+    }
+
+    // Remove peers which haven't been heard from within `PEER_TIMEOUT_INTERVAL`
+    fn prune_peers(&mut self, now: Slot) {
+        let mut i = 0;
+        while i < self.peers.len() {
+            if now.saturating_sub(self.peers[i].last_contact) >= PEER_TIMEOUT_INTERVAL {
+                self.peers.remove_index(i);
+            } else {
+                i += 1;
+            }
+        }
+        let peers = &self.peers;
+        self.peers_by_endpoint.retain(|bytes, public| {
+            peers.get(public).is_some_and(|peer| peer.endpoint.to_bytes() == *bytes)
+        });
+    }
+
+    // Adjust the score of the peer at `index` by `delta`, within `MAX_PEER_SCORE`
+    fn rescore(&mut self, index: usize, delta: i32) {
+        let score = &mut self.peers[index].score;
+        *score = score.saturating_add(delta).clamp(-MAX_PEER_SCORE, MAX_PEER_SCORE);
+        self.peers.update_index(index);
+    }
+
+    // Adjust the score of the peer at `endpoint`, if it's one of our peers
+    fn rescore_endpoint(&mut self, endpoint: Endpoint, delta: i32) {
+        if let Some(index) = self.peer_at(&endpoint) {
+            self.rescore(index, delta);
+        }
+    }
+
+    // Send notes only to the peer `to`
+    fn send_notes(&self, to: &Public, notes: impl Iterator<Item = Note>) {
+        let endpoint = match self.peers.get(to) {
+            Some(peer) => peer.endpoint,
+            None => return
+        };
+        let endpoints = Arc::new(vec![endpoint]);
+        for note in notes {
+            let bytes = note.serialize(MTU);
+            self.broadcaster.send(Message::Broadcast(Box::new((endpoints.clone(), bytes))));
+        }
+    }
+
+    // Send shred notes only to the peer `to`
+    fn send_shred_notes(&self, to: &Public, shred_notes: Vec<ShredNote>) {
+        self.send_notes(to, shred_notes.into_iter().map(|shred_note| Note::Shred(Box::new(shred_note))));
+    }
+
+    /// Describe `page` of the peers, highest weight first, of at most `MAX_PEER_INFOS` peers
+    fn peer_infos(&self, page: Page) -> RpcResponse {
+        let total = self.peers.len() as u64;
+        let range = page.range(total, MAX_PEER_INFOS);
+        let peers = self.peers
+            .iter_by_priority()
+            .skip(range.start as usize)
+            .take((range.end - range.start) as usize)
+            .map(|(address, peer)| PeerInfo {
+                address: *address,
+                endpoint: peer.endpoint,
+                weight: peer.weight,
+                version: peer.version,
+                last_contact: peer.last_contact,
+            })
+            .collect();
+        RpcResponse::Peers { peers, total, has_more: range.end < total }
+    }
+
+    fn on_msg(&mut self, msg: Message) {
         match msg {
             // Shred notes sent back from `Restorer`
             Message::ShredNote(shred_note) => {
+                // Don't re-broadcast shreds we've recently broadcast
+                if !self.dedup.insert(shred_note.hash(), Instant::now()) {
+                    return;
+                }
+                self.shred_cache.insert((*shred_note).clone());
                 // Broadcast the shred message to a subset of peers
-                let bytes = Note::ShredNote(shred_note).serialize(MTU);
+                let bytes = Note::Shred(shred_note).serialize(MTU);
                 self.broadcast_fanout(bytes);
             },
+            // Our own shred requests for missing shreds from `Assembler`
+            Message::ShredRequest(shred_request) if shred_request.from == self.id.public => {
+                let bytes = Note::ShredRequest(shred_request).serialize(MTU);
+                self.broadcast_fanout(bytes);
+            },
+            // Peers' shred requests of slots the `Assembler` holds nothing of,
+            // served from the shreds we recently broadcast
+            Message::ShredRequest(shred_request) => {
+                if shred_request.verify().is_err() {
+                    return;
+                }
+                let shred_notes = self.shred_cache.get(&shred_request);
+                self.send_shred_notes(&shred_request.from, shred_notes);
+            },
+            // Requested shreds from `Assembler`, sent only to the requester
+            Message::ShredResponse(v) => {
+                let (to, shred_notes) = *v;
+                self.send_shred_notes(&to, shred_notes);
+            },
             Message::TelemetryNote(tel_note) => {
                 self.on_tel_note(tel_note);
             },
+            // Ticks from our `Interval`
+            Message::Tick => {
+                self.on_interval();
+            },
+            Message::PrunePeers => {
+                self.prune_peers(Slot::now());
+            },
+            Message::PeerUseful(endpoint) => {
+                self.rescore_endpoint(*endpoint, self.score_reward);
+            },
+            Message::PeerInvalid(endpoint) => {
+                self.rescore_endpoint(*endpoint, -self.score_penalty);
+            },
+            // Our own requests for finalized blocks from `State`
+            Message::SendSyncRequest(sync_request) => {
+                let to = sync_request.to;
+                self.send_notes(&to, std::iter::once(Note::SyncRequest(sync_request)));
+            },
+            // Finalized blocks from `State`, sent only to the requester
+            Message::SyncResponse(v) => {
+                let (to, sync_response) = *v;
+                self.send_notes(&to, std::iter::once(Note::SyncResponse(Box::new(sync_response))));
+            },
+            // A `peers` RPC, answered straight to the client
+            Message::PeersRequest(v) => {
+                let (rpc_sender, id, page) = *v;
+                let peers = self.peer_infos(page);
+                rpc_sender.send(Message::RpcResponse(Box::new(RpcEnvelope::ok(id, peers))));
+            },
             _ => {}
         }
+        self.peer_count.store(self.peers.len(), Ordering::Relaxed);
     }
 }
 
@@ -213,3 +563,444 @@ impl ProcessEndless for Transmitter {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::Seed, network::{Shred, ShredParams, ShredRequest}};
+    use std::str::FromStr;
+
+    fn transmitter(broadcaster: Handle) -> Transmitter {
+        transmitter_with_snapshot(broadcaster, None)
+    }
+
+    fn transmitter_with_snapshot(broadcaster: Handle, peer_snapshot: Option<String>) -> Transmitter {
+        transmitter_with_fallback(broadcaster, peer_snapshot, vec![], Duration::from_secs(60))
+    }
+
+    fn transmitter_with_fallback(
+        broadcaster: Handle,
+        peer_snapshot: Option<String>,
+        fallback_peers: Vec<Endpoint>,
+        discovery_timeout: Duration,
+    ) -> Transmitter {
+        let private = Seed([5; 32]).derive(0);
+        let config = TransmitterConfig {
+            visible_ep: Endpoint::from_str("127.0.0.1:41594").unwrap(),
+            initial_peers: Arc::new(vec![Endpoint::from_str("127.0.0.1:41600").unwrap()]),
+            fallback_peers: Arc::new(fallback_peers),
+            discovery_timeout,
+            max_less: 8,
+            max_greater: 8,
+            priority_peers: 2,
+            version: Version::new(0, 1, 0),
+            allow_peers_with_private_ip_addresses: true,
+            allow_peers_with_node_external_ip_address: true,
+            max_clock_skew: Duration::from_secs(2),
+            dedup: DedupWindow::new(Duration::from_secs(5), 1_000, Instant::now()).unwrap(),
+            score_reward: 1,
+            score_penalty: 4,
+            peer_snapshot
+        };
+        Transmitter::new(
+            Identity { private, public: private.to_public() },
+            config,
+            Box::new(|_| Amount::from_raw(1)),
+            broadcaster,
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(AtomicU64::new(0)),
+            Arc::new(AtomicU64::new(0)),
+        )
+    }
+
+    fn insert_peers(transmitter: &mut Transmitter, last_contact: Slot) {
+        for i in 0..4 {
+            transmitter.insert_peer(Public::random(), Peer {
+                weight: Amount::from_raw(2),
+                last_contact,
+                endpoint: Endpoint::from_str(&format!("127.0.0.1:{}", 41700 + i)).unwrap(),
+                version: Version::new(0, 1, 0),
+                score: 0,
+                height: 0,
+            });
+        }
+    }
+
+    #[test]
+    fn test_broadcast_fanout_all_peers_stale() {
+        let (broadcaster, mut mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        insert_peers(&mut transmitter, Slot::zero());
+        transmitter.broadcast_fanout(vec![1, 2, 3]);
+        assert!(transmitter.peers.is_empty());
+        match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::Broadcast(v)) => {
+                let (endpoints, bytes) = *v;
+                assert!(Arc::ptr_eq(&endpoints, &transmitter.initial_peers));
+                assert_eq!(bytes, vec![1, 2, 3]);
+            }
+            _ => panic!("expected a broadcast to initial peers"),
+        }
+        assert!(mailbox.recv_timeout(Duration::from_millis(10)).is_none());
+    }
+
+    #[test]
+    fn test_reflected_telemetry() {
+        let (broadcaster, mut mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        insert_peers(&mut transmitter, Slot::now());
+        transmitter.on_interval();
+        let bytes = match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::Broadcast(v)) => v.1,
+            _ => panic!("expected our telemetry to be broadcast"),
+        };
+        let tel_note = match Note::deserialize(&bytes, MTU).unwrap() {
+            Note::Telemetry(tel_note) => tel_note,
+            _ => panic!("expected a telemetry note"),
+        };
+        assert!(tel_note.verify().is_ok());
+        transmitter.on_tel_note(tel_note);
+        assert!(mailbox.recv_timeout(Duration::from_millis(10)).is_none());
+    }
+
+    #[test]
+    fn test_reject_telemetry_port_zero() {
+        let (broadcaster, _mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        let peer = Seed([7; 32]).derive(0);
+        let ep = Endpoint { addr: [1, 2, 3, 4], port: 0 };
+        let version = Version::new(0, 1, 0);
+        transmitter.on_tel_note(Box::new(TelemetryNote::new(peer, Slot::now(), ep, version, 0)));
+        assert!(transmitter.peers.is_empty());
+        let ep = Endpoint { addr: [1, 2, 3, 4], port: 41594 };
+        transmitter.on_tel_note(Box::new(TelemetryNote::new(peer, Slot::now(), ep, version, 0)));
+        assert!(transmitter.peers.contains(&peer.to_public()));
+    }
+
+    #[test]
+    fn test_serve_cached_shreds() {
+        let (broadcaster, mut mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        let requester = Seed([6; 32]).derive(0);
+        let requester_endpoint = Endpoint::from_str("127.0.0.1:41800").unwrap();
+        transmitter.peers.insert(requester.to_public(), Peer {
+            weight: Amount::from_raw(2),
+            last_contact: Slot::now(),
+            endpoint: requester_endpoint,
+            version: Version::new(0, 1, 0),
+            score: 0,
+            height: 0,
+        });
+
+        // broadcast the shreds of a slot
+        let slot = Slot::now();
+        let shreds = Shred::from_data(&[1, 2, 3, 4, 5, 6], 2, &ShredParams::default());
+        let n_shreds = shreds.len();
+        for shred in shreds {
+            let shred_note = ShredNote::new(transmitter.id.private, slot, shred);
+            transmitter.on_msg(Message::ShredNote(Box::new(shred_note)));
+        }
+        for _ in 0..n_shreds {
+            assert!(matches!(mailbox.recv_timeout(Duration::from_secs(1)), Some(Message::Broadcast(_))));
+        }
+
+        // request shreds 1 and 2 of the only batch
+        let request = ShredRequest::new(requester, slot, 0, vec![0b110]);
+        transmitter.on_msg(Message::ShredRequest(Box::new(request)));
+        let mut served = Vec::new();
+        while let Some(Message::Broadcast(v)) = mailbox.recv_timeout(Duration::from_millis(50)) {
+            let (endpoints, bytes) = *v;
+            assert_eq!(endpoints.len(), 1);
+            assert_eq!(endpoints[0].to_socket_addr(), requester_endpoint.to_socket_addr());
+            match Note::deserialize(&bytes, MTU).unwrap() {
+                Note::Shred(shred_note) => served.push(shred_note.shred.get_shred_index()),
+                _ => panic!("expected a shred note"),
+            }
+        }
+        served.sort();
+        assert_eq!(served, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_broadcast_fanout_priority_peers() {
+        let (broadcaster, mut mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        // enough low-weight peers that a random sample often misses a given one
+        for i in 0..6 {
+            transmitter.peers.insert(Public::random(), Peer {
+                weight: Amount::from_raw(i),
+                last_contact: Slot::now(),
+                endpoint: Endpoint::from_str(&format!("127.0.0.1:{}", 41700 + i)).unwrap(),
+                version: Version::new(0, 1, 0),
+                score: 0,
+                height: 0,
+            });
+        }
+        for weight in [1_000, 2_000] {
+            transmitter.peers.insert(Public::random(), Peer {
+                weight: Amount::from_raw(weight),
+                last_contact: Slot::now(),
+                endpoint: Endpoint::from_str(&format!("127.0.0.1:{}", 41900 + weight / 1_000)).unwrap(),
+                version: Version::new(0, 1, 0),
+                score: 0,
+                height: 0,
+            });
+        }
+        let ports = |endpoints: &[Endpoint]| endpoints.iter().map(|ep| ep.port).collect::<Vec<_>>();
+        for _ in 0..20 {
+            transmitter.broadcast_fanout(vec![1]);
+            let endpoints = match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::Broadcast(v)) => v.0,
+                _ => panic!("expected a broadcast"),
+            };
+            let ports = ports(&endpoints);
+            assert!(ports.contains(&41901) && ports.contains(&41902));
+            // the random sample is still sent to
+            assert!(ports.len() >= fanout(8));
+        }
+    }
+
+    #[test]
+    fn test_evict_lowest_scored_peer() {
+        let (broadcaster, _mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        // fill the peers of greater weight than ours, all of equal weight
+        let keys: Vec<Public> = (0..8).map(|_| Public::random()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            transmitter.insert_peer(*key, Peer {
+                weight: Amount::from_raw(2),
+                last_contact: Slot::now(),
+                endpoint: Endpoint::from_str(&format!("127.0.0.1:{}", 41700 + i)).unwrap(),
+                version: Version::new(0, 1, 0),
+                score: 0,
+                height: 0,
+            });
+        }
+        // one peer sends shreds, another sends garbage
+        transmitter.on_msg(Message::PeerUseful(Box::new(Endpoint::from_str("127.0.0.1:41700").unwrap())));
+        transmitter.on_msg(Message::PeerInvalid(Box::new(Endpoint::from_str("127.0.0.1:41701").unwrap())));
+        assert_eq!(transmitter.peers.get(&keys[0]).unwrap().score, 1);
+        assert_eq!(transmitter.peers.get(&keys[1]).unwrap().score, -4);
+
+        // a new peer of the same weight replaces the lowest-scored one
+        let newcomer = Public::random();
+        assert!(transmitter.insert_peer(newcomer, Peer {
+            weight: Amount::from_raw(2),
+            last_contact: Slot::now(),
+            endpoint: Endpoint::from_str("127.0.0.1:41800").unwrap(),
+            version: Version::new(0, 1, 0),
+            score: 0,
+            height: 0,
+        }));
+        assert!(transmitter.peers.contains(&newcomer) && transmitter.peers.contains(&keys[0]));
+        assert!(!transmitter.peers.contains(&keys[1]));
+        assert_eq!(transmitter.peers.len(), 8);
+
+        // the evicted peer's endpoint no longer scores anyone
+        transmitter.on_msg(Message::PeerUseful(Box::new(Endpoint::from_str("127.0.0.1:41701").unwrap())));
+        assert!(transmitter.peers.iter().all(|(key, peer)| peer.score == 0 || *key == keys[0]));
+        transmitter.prune_peers(Slot::now());
+        assert_eq!(transmitter.peers_by_endpoint.len(), 8);
+    }
+
+    #[test]
+    fn test_tick_broadcasts_telemetry() {
+        let (broadcaster, mut mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        transmitter.on_msg(Message::Tick);
+        assert!(matches!(mailbox.recv_timeout(Duration::from_secs(1)), Some(Message::Broadcast(_))));
+    }
+
+    #[test]
+    fn test_restore_peers_after_restart() {
+        let dir = "test_restore_peers";
+        std::fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/peers", dir);
+        let (broadcaster, _mailbox) = process::channel();
+        let mut before = transmitter_with_snapshot(broadcaster.clone(), Some(path.clone()));
+        assert!(before.peers.is_empty());
+        let last_contact = Slot::from_system_time(SystemTime::now() - Duration::from_secs(5));
+        insert_peers(&mut before, last_contact);
+        before.rescore(0, 7);
+        before.on_interval();
+        // dropping the transmitter finishes writing the snapshot
+        drop(before);
+
+        // restarting restores the peer table, endpoints, scores and last contacts
+        let after = transmitter_with_snapshot(broadcaster.clone(), Some(path.clone()));
+        assert_eq!(after.peers.len(), 4);
+        assert_eq!(after.peer_count.load(Ordering::Relaxed), 4);
+        let mut ports: Vec<u16> = after.peers.iter().map(|(_, peer)| peer.endpoint.port).collect();
+        ports.sort();
+        assert_eq!(ports, vec![41700, 41701, 41702, 41703]);
+        assert_eq!(after.peers.iter().map(|(_, peer)| peer.score).sum::<i32>(), 7);
+        assert!(after.peers.iter().all(|(_, peer)| peer.last_contact == last_contact));
+        drop(after);
+
+        // peers age by how long ago the snapshot was written, and are dropped once timed out
+        let written = SystemTime::now() - Duration::from_secs(60);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(written).unwrap();
+        let after_downtime = transmitter_with_snapshot(broadcaster, Some(path.clone()));
+        assert!(after_downtime.peers.is_empty());
+        assert!(peer_snapshot::load(&path).unwrap().iter().all(|peer| peer.4 == Slot::from_system_time(written)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_diverse_sample() {
+        let ep = |s: &str| Endpoint::from_str(s).unwrap();
+        let endpoints = [ep("10.0.1.1:1"), ep("10.0.1.2:1"), ep("10.0.1.3:1"), ep("10.0.2.1:1"), ep("10.0.2.2:1")];
+        let addrs = |sample: Vec<Endpoint>| sample.iter().map(|ep| ep.addr[3] + 10 * ep.addr[2]).collect::<Vec<_>>();
+        assert_eq!(addrs(diverse_sample(endpoints.into_iter(), 2)), vec![11, 21]);
+        // once every subnet is taken, the rest are taken in order
+        assert_eq!(addrs(diverse_sample(endpoints.into_iter(), 4)), vec![11, 21, 12, 13]);
+        assert_eq!(diverse_sample(endpoints.into_iter(), 8).len(), 5);
+        // diversity that can't be met doesn't shrink the sample
+        assert_eq!(addrs(diverse_sample(endpoints[..3].iter().copied(), 2)), vec![11, 12]);
+    }
+
+    #[test]
+    fn test_broadcast_fanout_spans_subnets() {
+        let (broadcaster, mut mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        transmitter.priority_peers = 0;
+        // most peers crowd one subnet, so a uniform sample of 4 of 8 often misses the other
+        for i in 0..8u8 {
+            let subnet = if i < 6 { 1 } else { 2 };
+            transmitter.peers.insert(Public::random(), Peer {
+                weight: Amount::from_raw(2),
+                last_contact: Slot::now(),
+                endpoint: Endpoint { addr: [10, 0, subnet, i], port: 41700 },
+                version: Version::new(0, 1, 0),
+                score: 0,
+                height: 0,
+            });
+        }
+        for _ in 0..20 {
+            transmitter.broadcast_fanout(vec![1]);
+            let endpoints = match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::Broadcast(v)) => v.0,
+                _ => panic!("expected a broadcast"),
+            };
+            assert_eq!(endpoints.len(), fanout(8));
+            let subnets: HashSet<[u8; 3]> = endpoints.iter().map(|ep| ep.subnet()).collect();
+            assert_eq!(subnets.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_peers_request() {
+        let (broadcaster, _mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        let (rpc_sender, mut responses) = process::channel();
+        let mut request = |transmitter: &mut Transmitter, id| {
+            transmitter.on_msg(Message::PeersRequest(Box::new((rpc_sender.clone(), id, Page::default()))));
+            match responses.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::RpcResponse(envelope)) => match envelope.result {
+                    Ok(RpcResponse::Peers { peers, total, has_more }) if envelope.id == id => {
+                        assert!(total == peers.len() as u64 && !has_more);
+                        peers
+                    }
+                    _ => panic!("expected peers"),
+                },
+                _ => panic!("expected a response"),
+            }
+        };
+        // no peers yet is an empty list, not an error
+        assert!(request(&mut transmitter, 1).is_empty());
+
+        let slot = Slot::now();
+        for (i, weight) in [3, 7, 5].into_iter().enumerate() {
+            transmitter.peers.insert(Public::random(), Peer {
+                weight: Amount::from_raw(weight),
+                last_contact: slot,
+                endpoint: Endpoint::from_str(&format!("127.0.0.1:{}", 41700 + i)).unwrap(),
+                version: Version::new(0, 1, 0),
+                score: 0,
+                height: 0,
+            });
+        }
+        let peers = request(&mut transmitter, 2);
+        let weights: Vec<u64> = peers.iter().map(|peer| peer.weight.to_raw()).collect();
+        assert_eq!(weights, vec![7, 5, 3]);
+        assert_eq!(peers[0].endpoint.port, 41701);
+        assert!(peers[0].last_contact == slot);
+        assert!(peers.iter().all(|peer| transmitter.peers.get(&peer.address).is_some_and(|p| p.endpoint.port == peer.endpoint.port)));
+
+        // a page of the peers starts after the higher-weight ones
+        match transmitter.peer_infos(Page { offset: 1, count: Some(1) }) {
+            RpcResponse::Peers { peers, total, has_more } => {
+                assert_eq!(peers.iter().map(|peer| peer.weight.to_raw()).collect::<Vec<_>>(), vec![5]);
+                assert!(total == 3 && has_more);
+            }
+            _ => panic!("expected peers"),
+        }
+    }
+
+    #[test]
+    fn test_fallback_peers_after_discovery_timeout() {
+        let (broadcaster, mut mailbox) = process::channel();
+        let fallback = Endpoint::from_str("127.0.0.1:41650").unwrap();
+        let mut transmitter = transmitter_with_fallback(broadcaster, None, vec![fallback], Duration::from_millis(50));
+        let mut telemetry_ports = |transmitter: &mut Transmitter| {
+            transmitter.on_interval();
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::Broadcast(v)) => v.0.iter().map(|ep| ep.port).collect::<Vec<u16>>(),
+                _ => panic!("expected a broadcast"),
+            }
+        };
+        // the unreachable initial peer is tried alone until the timeout
+        assert_eq!(telemetry_ports(&mut transmitter), vec![41600]);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(telemetry_ports(&mut transmitter), vec![41600, 41650]);
+        assert!(transmitter.discovery_deadline.is_none());
+    }
+
+    #[test]
+    fn test_no_fallback_once_peers_found() {
+        let (broadcaster, _mailbox) = process::channel();
+        let fallback = Endpoint::from_str("127.0.0.1:41650").unwrap();
+        let mut transmitter = transmitter_with_fallback(broadcaster, None, vec![fallback], Duration::ZERO);
+        insert_peers(&mut transmitter, Slot::now());
+        transmitter.check_discovery(Instant::now());
+        assert!(transmitter.discovery_deadline.is_none());
+        // losing the peers later doesn't bring in the fallback
+        transmitter.peers.clear();
+        transmitter.check_discovery(Instant::now());
+        assert_eq!(transmitter.initial_peers.len(), 1);
+    }
+
+    #[test]
+    fn test_weighted_median() {
+        let amount = Amount::from_raw;
+        assert_eq!(weighted_median(vec![]), None);
+        // a heavy peer outweighs several light ones claiming to be far ahead
+        assert_eq!(weighted_median(vec![(100, amount(1)), (10, amount(10)), (90, amount(1)), (80, amount(1))]), Some(10));
+        // without weights, every peer counts the same
+        assert_eq!(weighted_median(vec![(3, Amount::zero()), (1, Amount::zero()), (2, Amount::zero())]), Some(2));
+    }
+
+    #[test]
+    fn test_sync_target_follows_peers() {
+        let (broadcaster, _mailbox) = process::channel();
+        let mut transmitter = transmitter(broadcaster);
+        let version = Version::new(0, 1, 0);
+        let report = |transmitter: &mut Transmitter, index, height| {
+            let ep = Endpoint { addr: [1, 2, 3, 4], port: 41600 + index as u16 };
+            transmitter.on_tel_note(Box::new(TelemetryNote::new(Seed([7; 32]).derive(index), Slot::now(), ep, version, height)));
+        };
+        for (index, height) in [(0, 40), (1, 50), (2, 60)] {
+            report(&mut transmitter, index, height);
+        }
+        transmitter.on_interval();
+        assert_eq!(transmitter.sync_target.load(Ordering::Relaxed), 50);
+
+        // the target goes down when the peers reporting higher are gone
+        let gone: Vec<Public> = (1..3).map(|index| Seed([7; 32]).derive(index).to_public()).collect();
+        for public in gone.iter() {
+            let index = transmitter.peers.iter().position(|(p, _)| p == public).unwrap();
+            transmitter.peers.remove_index(index);
+        }
+        transmitter.on_interval();
+        assert_eq!(transmitter.sync_target.load(Ordering::Relaxed), 40);
+    }
+}
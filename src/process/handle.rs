@@ -1,4 +1,4 @@
-use std::{collections::HashMap, sync::{atomic::AtomicU64, Mutex}};
+use std::{collections::HashMap, sync::Mutex};
 
 use bincode::{de::{BorrowDecoder, Decoder}, enc::Encoder, error::{DecodeError, EncodeError}, BorrowDecode, Decode, Encode};
 use kanal::Sender;
@@ -15,6 +15,14 @@ impl Handle {
         // we don't ever want to block when sending!
         _ = self.0.try_send(msg);
     }
+    /// Do both handles send to the same process?
+    pub fn same_process(&self, other: &Handle) -> bool {
+        self.to_usize() == other.to_usize()
+    }
+    /// Has the process behind this handle stopped receiving messages?
+    pub fn is_disconnected(&self) -> bool {
+        self.0.is_disconnected()
+    }
 }
 
 static_assert!(std::mem::size_of::<Handle>() == std::mem::size_of::<usize>());
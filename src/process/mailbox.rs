@@ -16,7 +16,74 @@ impl Mailbox {
             }
         }
     }
+    /// Receive a message, waiting at most `timeout` for one. Returns `None` both
+    /// on timeout and once every `Handle` is dropped, which `is_disconnected` tells apart.
     pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Message> {
         self.0.recv_timeout(timeout).ok()
     }
-}
\ No newline at end of file
+    /// Receive a message if one is waiting, without blocking. Returns `None` both
+    /// when empty and once every `Handle` is dropped, which `is_disconnected` tells apart.
+    #[cfg(test)]
+    pub fn try_recv(&mut self) -> Option<Message> {
+        self.0.try_recv().ok().flatten()
+    }
+    /// Has every `Handle` to this mailbox been dropped? Once it has, and the
+    /// messages already sent are received, no message will ever arrive again.
+    #[cfg(test)]
+    pub fn is_disconnected(&self) -> bool {
+        self.0.is_disconnected()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process::{self, Handle, Process}, util::Error};
+
+    /// Sends a `Tick` to `out` whenever its mailbox has been idle for a while
+    struct Ticker {
+        out: Handle,
+    }
+
+    impl Process for Ticker {
+        const NAME: &'static str = "Ticker";
+        const RESTART_ON_CRASH: bool = false;
+
+        fn run(&mut self, mut mailbox: Mailbox, _: Handle) -> Result<(), Error> {
+            loop {
+                match mailbox.recv_timeout(Duration::from_millis(5)) {
+                    Some(Message::EndLeaderMode) => return Ok(()),
+                    Some(_) => {}
+                    None => self.out.send(Message::Tick),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_recv_timeout_tick_loop() {
+        let (out, mut ticks) = process::channel();
+        let ticker = process::spawn(Ticker { out });
+        // ticks keep coming while the ticker is idle
+        for _ in 0..3 {
+            assert!(matches!(ticks.recv_timeout(Duration::from_secs(1)), Some(Message::Tick)));
+        }
+        ticker.send(Message::EndLeaderMode);
+        // once the ticker returns, its handle to us is dropped
+        while ticks.recv_timeout(Duration::from_secs(1)).is_some() {}
+        assert!(ticks.is_disconnected());
+    }
+
+    #[test]
+    fn test_try_recv() {
+        let (handle, mut mailbox) = process::channel();
+        assert!(mailbox.try_recv().is_none());
+        assert!(!mailbox.is_disconnected());
+        handle.send(Message::Tick);
+        drop(handle);
+        // messages sent before disconnecting are still received
+        assert!(matches!(mailbox.try_recv(), Some(Message::Tick)));
+        assert!(mailbox.try_recv().is_none());
+        assert!(mailbox.is_disconnected());
+    }
+}
@@ -2,10 +2,12 @@ use std::sync::Arc;
 
 use bincode::{Decode, Encode};
 
-use crate::{keys::Public, network::{Endpoint, ShredNote, TelemetryNote}, protocol::{Open, OpenFull, Slot, Tx, TxEmpty, TxFull}, rpc::{RpcRequest, RpcResponse}, static_assert};
+use crate::{keys::Public, network::{Endpoint, ShredNote, ShredRequest, SyncRequest, SyncResponse, TelemetryNote}, protocol::{Open, OpenFull, Slot, TxEmpty, TxFull}, rpc::{Page, RpcEnvelope, RpcRequest}, state::Block, static_assert};
 
 use super::Handle;
 
+// Payloads are boxed even when already on the heap, so that a `Message` stays 16 bytes
+#[allow(clippy::box_collection, clippy::vec_box)]
 #[derive(Encode, Decode)]
 pub enum Message {
     // Leader mode messages
@@ -16,13 +18,42 @@ pub enum Message {
     // Transaction messages
     TxEmpty(Box<TxEmpty>),
     TxFullList(Box<Vec<Box<TxFull>>>),
+    /// The accounts written by finalized transactions, as `(public, index)`
+    FinalizedAccounts(Box<Vec<(Public, u64)>>),
+    /// The transaction pools, to be sent the accounts written by finalized transactions
+    TxPools(Box<Vec<Handle>>),
 
     // Shred note messages
     ShredNote(Box<ShredNote>),
+    /// A shred note received from the peer at the given endpoint, to be assembled
+    PeerShredNote(Box<(Endpoint, ShredNote)>),
+    ShredRequest(Box<ShredRequest>),
+    /// Shred notes to send to the peer with the given public key
+    ShredResponse(Box<(Public, Vec<ShredNote>)>),
+    /// A block assembled from the shreds of its slot's scheduled leader
+    Block(Box<Block>),
 
     // Telemetry messages
     TelemetryNote(Box<TelemetryNote>),
     TelemetryInterval,
+    /// Remove peers which haven't been heard from recently
+    PrunePeers,
+    /// A peer at the given endpoint sent us shreds or finalized blocks
+    PeerUseful(Box<Endpoint>),
+    /// A peer at the given endpoint sent us data which couldn't be decoded
+    PeerInvalid(Box<Endpoint>),
+
+    // Sync messages
+    /// A peer's request for finalized blocks, and the `Transmitter` to answer it through
+    SyncRequest(Box<(Handle, SyncRequest)>),
+    /// Finalized blocks to send to the peer with the given public key
+    SyncResponse(Box<(Public, SyncResponse)>),
+    /// Finalized blocks from a peer, and the `Transmitter` to request more through
+    SyncBlocks(Box<(Handle, SyncResponse)>),
+    /// Our own request for finalized blocks, to send to the peer it is addressed to
+    SendSyncRequest(Box<SyncRequest>),
+    /// Check whether we're behind, requesting blocks through the given `Transmitter` if so
+    SyncTick(Box<Handle>),
 
     // Open messages
     Open(Box<Open>),
@@ -30,7 +61,11 @@ pub enum Message {
     
     // RPC
     RpcRequest(Box<(Handle, u64, RpcRequest)>),
-    RpcResponse(Box<(u64, RpcResponse)>),
+    RpcResponse(Box<RpcEnvelope>),
+    /// The client accepts compressed responses, to be acknowledged with the given request id
+    RpcAcceptCompression(u64),
+    /// A `peers` RPC for a page of the peers, to be answered by the `Transmitter` through the given `RpcSender`
+    PeersRequest(Box<(Handle, u64, Page)>),
 
     // Broadcast
     Broadcast(Box<(Arc<Vec<Endpoint>>, Vec<u8>)>),
@@ -42,7 +77,16 @@ pub enum Message {
     BatchedTryInsertResponse(Box<Vec<bool>>),
 
     // Interval
-    Tick
+    Tick,
+
+    // Shutdown
+    /// Stop the process once what it holds is persisted, replying `Stopped` through the given `Handle`
+    Stop(Box<Handle>),
+    Stopped,
+
+    // Remote
+    /// Sent to a remote process when there is nothing else to send, so that a dead connection is noticed
+    Heartbeat
 }
 
 static_assert!(std::mem::size_of::<Message>() == 16);
\ No newline at end of file
@@ -8,14 +8,21 @@ pub use mailbox::Mailbox;
 pub use oncebox::Oncebox;
 
 use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 use std::thread;
 use std::time::Duration;
+use kanal::ReceiveErrorTimeout;
 use crate::network::Endpoint;
-use crate::util::{self, Error, UninitVec};
+use crate::util::{self, Backoff, Error, UninitVec};
 use crate::{log_error, log_warn};
 
 const SLEEP_MS_BEFORE_RETRY: u64 = 20;
+/// The wait before the first attempt to reconnect to a remote process
+const REMOTE_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// How long a connection to a remote process may be idle before a heartbeat is sent
+const REMOTE_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long connecting or writing to a remote process may block before the connection is given up
+const REMOTE_IO_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub fn sleep(dur: Duration) {
     std::thread::sleep(dur);
@@ -27,17 +34,22 @@ pub trait Process {
     fn run(&mut self, mailbox: Mailbox, handle: Handle) -> Result<(), Error>;
 }
 
+/// Creates a `Handle` and the `Mailbox` which receives the messages sent to it,
+/// without spawning a process.
+pub fn channel() -> (Handle, Mailbox) {
+    let (tx, rx) = kanal::unbounded();
+    (Handle(tx), Mailbox(rx))
+}
+
 /// Spawns a `Process`. Processes are the building blocks upon which
 /// the program is built. They are single-threaded, and communicate
 /// via message-passing: anyone with a `Handle` to a given `Process`
 /// can send messages to it, which the process can receive by calling
 /// `recv` on its `Mailbox`.
 pub fn spawn<P: Process + Send + 'static>(mut process: P) -> Handle {
-    let (tx, rx) = kanal::unbounded();
-    let handle = Handle(tx.clone());
+    let (handle, mailbox) = channel();
+    let handle_clone = handle.clone();
     thread::spawn(move || {
-        let handle = Handle(tx);
-        let mailbox = Mailbox(rx);
         loop {
             match process.run(mailbox.clone(), handle.clone()) {
                 Ok(_) => break,
@@ -53,7 +65,7 @@ pub fn spawn<P: Process + Send + 'static>(mut process: P) -> Handle {
         }
         handle.deactivate();
     });
-    handle
+    handle_clone
 }
 
 pub trait ProcessSolitary {
@@ -87,14 +99,10 @@ pub trait ProcessEndless {
 
 /// Spawns an endless process, which never finishes or errors.
 pub fn spawn_endless<P: ProcessEndless + Send + 'static>(mut process: P) -> Handle {
-    let (tx, rx) = kanal::unbounded();
-    let handle = Handle(tx.clone());
-    thread::spawn(move || {
-        let handle = Handle(tx);
-        let mailbox = Mailbox(rx);
-        process.run(mailbox, handle);
-    });
-    handle
+    let (handle, mailbox) = channel();
+    let handle_clone = handle.clone();
+    thread::spawn(move || process.run(mailbox, handle));
+    handle_clone
 }
 
 pub trait ProcessSolitaryEndless {
@@ -124,38 +132,132 @@ fn send_message(socket: &mut TcpStream, msg: &Message) -> Result<(), Error> {
     }
     let len = buf.len() as u32;
     buf[0..4].copy_from_slice(&len.to_le_bytes());
-    socket.write_all(&mut buf)?;
+    socket.write_all(&buf)?;
+    Ok(())
+}
+
+/// Check that a remote process is reachable at the given TCP `Endpoint`
+/// by connecting to it within `timeout`.
+pub fn check_remote(ep: Endpoint, timeout: Duration) -> Result<(), Error> {
+    TcpStream::connect_timeout(&ep.to_socket_addr(), timeout)?;
     Ok(())
 }
 
 /// Connect to a remote process, specified by the given TCP `Endpoint`,
 /// and returns a handle to it.
-/// In case of network error, delivery of messages is not guaranteed.
-pub fn connect_remote(ep: Endpoint) -> Handle {
+/// Lost connections are retried with a jittered backoff doubling up to `max_backoff`,
+/// and heartbeats are sent while idle so that a dead connection is noticed.
+/// In case of network error, delivery of messages is not guaranteed: the
+/// message whose write failed is resent on reconnection, but any before it
+/// which were written but never reached the remote process are lost.
+pub fn connect_remote(ep: Endpoint, max_backoff: Duration) -> Handle {
     let (tx, rx) = kanal::unbounded();
-    let handle = Handle(tx.clone());
+    let handle = Handle(tx);
     thread::spawn(move || {
+        let mut backoff = Backoff::new(REMOTE_INITIAL_BACKOFF, max_backoff);
         let mut last_msg = None;
         loop {
-            if let Ok(mut socket) = TcpStream::connect(ep.to_socket_addr()) {
-                if let Ok(mut socket_clone) = socket.try_clone() {
-                    thread::spawn(move || {
-                        while let Ok(msg_maybe) = recv_message(&mut socket_clone) {
-                            if let Some((handle, msg)) = msg_maybe {
-                                handle.send(msg);
-                            }
-                        }
-                    });
-                    for msg in last_msg.take().into_iter().chain(rx.clone()) {
-                        if send_message(&mut socket, &msg).is_err() {
-                            last_msg = Some(msg);
-                            break;
-                        }
+            if let Ok(socket) = TcpStream::connect_timeout(&ep.to_socket_addr(), REMOTE_IO_TIMEOUT) {
+                backoff.reset();
+                match serve_remote(socket, &rx, &mut last_msg) {
+                    // every handle was dropped
+                    Ok(()) => return,
+                    Err(e) => {
+                        log_warn!("Lost connection to remote process at tcp://{}: {}", ep, e);
                     }
                 }
             }
-            thread::sleep(Duration::from_millis(100));
+            thread::sleep(backoff.next_wait());
         }
     });
     handle
-}
\ No newline at end of file
+}
+
+/// Send the messages received on `rx` to `socket` until it fails, keeping the
+/// message whose write failed in `last_msg`, and forward its replies.
+/// Returns `Ok` once every handle to the remote process is dropped.
+fn serve_remote(
+    mut socket: TcpStream,
+    rx: &kanal::Receiver<Message>,
+    last_msg: &mut Option<Message>,
+) -> Result<(), Error> {
+    socket.set_write_timeout(Some(REMOTE_IO_TIMEOUT))?;
+    let mut socket_clone = socket.try_clone()?;
+    thread::spawn(move || {
+        while let Ok(msg_maybe) = recv_message(&mut socket_clone) {
+            if let Some((handle, msg)) = msg_maybe {
+                handle.send(msg);
+            }
+        }
+        // the remote process closed the connection, so fail the next write
+        let _ = socket_clone.shutdown(Shutdown::Both);
+    });
+    let result = loop {
+        let msg = match last_msg.take() {
+            Some(msg) => msg,
+            None => match rx.recv_timeout(REMOTE_HEARTBEAT_INTERVAL) {
+                Ok(msg) => msg,
+                Err(ReceiveErrorTimeout::Timeout) => Message::Heartbeat,
+                Err(_) => break Ok(()),
+            }
+        };
+        if let Err(e) = send_message(&mut socket, &msg) {
+            if !matches!(msg, Message::Heartbeat) {
+                *last_msg = Some(msg);
+            }
+            break Err(e);
+        }
+    };
+    let _ = socket.shutdown(Shutdown::Both);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_check_remote() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ep = Endpoint::from_str(&listener.local_addr().unwrap().to_string()).unwrap();
+        assert!(check_remote(ep, Duration::from_secs(1)).is_ok());
+
+        // nothing listens on the port once the listener is closed
+        drop(listener);
+        assert!(check_remote(ep, Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn test_connect_remote_heartbeat_and_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ep = Endpoint::from_str(&listener.local_addr().unwrap().to_string()).unwrap();
+        let handle = connect_remote(ep, Duration::from_millis(200));
+
+        // an idle connection is kept alive with heartbeats
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut len = [0u8; 4];
+        socket.read_exact(&mut len).unwrap();
+        let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+        socket.read_exact(&mut buf).unwrap();
+        assert!(matches!(util::decode_from_slice(&buf).unwrap(), Message::Heartbeat));
+
+        // once the connection is closed, messages are delivered over a new one
+        drop(socket);
+        let (mut socket, _) = listener.accept().unwrap();
+        handle.send(Message::Tick);
+        loop {
+            socket.read_exact(&mut len).unwrap();
+            let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+            socket.read_exact(&mut buf).unwrap();
+            match util::decode_from_slice(&buf).unwrap() {
+                Message::Heartbeat => continue,
+                msg => {
+                    assert!(matches!(msg, Message::Tick));
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -1,13 +1,18 @@
 use std::fmt;
+use std::str::FromStr;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
 
-use bincode::{Encode, Decode};
+use bincode::{de::{BorrowDecoder, Decoder}, error::DecodeError, BorrowDecode, Decode, Encode};
 
-use crate::util::ArchivableTo;
+use crate::{error, util::{ArchivableTo, Error}};
 
 const RAW_PER_UNIT: u64 = 10_000_000_000;
+/// The number of decimal places in a unit
+const UNIT_DECIMALS: usize = 10;
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
+/// Decoding rejects amounts above the initial supply, so no amount from the
+/// network can exceed it
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode)]
 pub struct Amount(u64);
 
 impl ArchivableTo<u64> for Amount {
@@ -27,28 +32,80 @@ impl Amount {
     pub const fn from_raw(value: u64) -> Self {
         Amount(value)
     }
+    /// Get the amount of `value` raw, or `None` if it's above the initial supply
+    pub const fn from_raw_checked(value: u64) -> Option<Self> {
+        match value <= Self::initial_supply().0 {
+            true => Some(Amount(value)),
+            false => None,
+        }
+    }
+    #[cfg(test)]
+    /// Parse a raw amount, as an integer such as `125000000000`. Amounts above
+    /// the initial supply are rejected.
+    pub fn from_raw_str(s: &str) -> Result<Self, Error> {
+        if !digits(s) {
+            return Err(error!("invalid raw amount: {}", s));
+        }
+        let raw = s.parse::<u64>().map_err(|_| error!("amount too large: {}", s))?;
+        Amount::from_raw_checked(raw).ok_or_else(|| error!("amount exceeds the supply: {}", s))
+    }
     pub const fn to_raw(self) -> u64 {
         self.0
     }
+    #[cfg(test)]
     pub fn to_unit(self) -> f32 {
         self.0 as f32 / RAW_PER_UNIT as f32
     }
+    #[cfg(test)]
     pub fn from_unit(value: f32) -> Self {
         Amount((value * RAW_PER_UNIT as f32) as u64)
     }
     pub const fn initial_supply() -> Self {
         Amount(i64::MAX as u64)
     }
+    #[cfg(test)]
     pub const fn max() -> Self {
         Amount(u64::MAX)
     }
+    pub const fn checked_add(self, other: Self) -> Option<Self> {
+        match self.0.checked_add(other.0) {
+            Some(value) => Some(Amount(value)),
+            None => None,
+        }
+    }
+    pub const fn checked_sub(self, other: Self) -> Option<Self> {
+        match self.0.checked_sub(other.0) {
+            Some(value) => Some(Amount(value)),
+            None => None,
+        }
+    }
+    pub const fn checked_mul(self, other: Self) -> Option<Self> {
+        match self.0.checked_mul(other.0) {
+            Some(value) => Some(Amount(value)),
+            None => None,
+        }
+    }
+    #[cfg(test)]
+    pub const fn saturating_add(self, other: Self) -> Self {
+        Amount(self.0.saturating_add(other.0))
+    }
+    #[cfg(test)]
+    pub const fn saturating_sub(self, other: Self) -> Self {
+        Amount(self.0.saturating_sub(other.0))
+    }
+    #[cfg(test)]
+    pub const fn saturating_mul(self, other: Self) -> Self {
+        Amount(self.0.saturating_mul(other.0))
+    }
 }
 
+// The operators panic rather than wrap on overflow, as a wrapped amount would
+// silently corrupt balances. Amounts from untrusted sources use `checked_*`.
 impl Add for Amount {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
-        Amount(self.0 + other.0)
+        self.checked_add(other).expect("amount addition overflowed")
     }
 }
 
@@ -56,7 +113,7 @@ impl Sub for Amount {
     type Output = Self;
 
     fn sub(self, other: Self) -> Self {
-        Amount(self.0 - other.0)
+        self.checked_sub(other).expect("amount subtraction underflowed")
     }
 }
 
@@ -64,7 +121,7 @@ impl Mul for Amount {
     type Output = Self;
 
     fn mul(self, other: Self) -> Self {
-        Amount(self.0 * other.0)
+        self.checked_mul(other).expect("amount multiplication overflowed")
     }
 }
 
@@ -78,19 +135,19 @@ impl Div for Amount {
 
 impl AddAssign for Amount {
     fn add_assign(&mut self, other: Self) {
-        self.0 += other.0;
+        *self = *self + other;
     }
 }
 
 impl SubAssign for Amount {
     fn sub_assign(&mut self, other: Self) {
-        self.0 -= other.0;
+        *self = *self - other;
     }
 }
 
 impl MulAssign for Amount {
     fn mul_assign(&mut self, other: Self) {
-        self.0 *= other.0;
+        *self = *self * other;
     }
 }
 
@@ -100,6 +157,61 @@ impl DivAssign for Amount {
     }
 }
 
+impl Decode for Amount {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Amount::from_raw_checked(u64::decode(decoder)?)
+            .ok_or(DecodeError::Other("amount exceeds the supply"))
+    }
+}
+
+impl<'de> BorrowDecode<'de> for Amount {
+    fn borrow_decode<D: BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Amount::from_raw_checked(u64::borrow_decode(decoder)?)
+            .ok_or(DecodeError::Other("amount exceeds the supply"))
+    }
+}
+
+/// Formats the amount in units, exactly, always with a decimal point so that
+/// it parses back as units: `12.5`, `12.0`, `0.0000000001`
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (whole, fraction) = (self.0 / RAW_PER_UNIT, self.0 % RAW_PER_UNIT);
+        let fraction = format!("{:0width$}", fraction, width = UNIT_DECIMALS);
+        let fraction = fraction.trim_end_matches('0');
+        match fraction.is_empty() {
+            true => write!(f, "{}.0", whole),
+            false => write!(f, "{}.{}", whole, fraction),
+        }
+    }
+}
+
+/// Whether `s` is a non-empty string of ASCII digits
+fn digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Parses units, such as `12.5` or `12`; raw amounts are parsed by
+/// `Amount::from_raw_str`. Amounts above the initial supply are rejected.
+impl FromStr for Amount {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, "0"));
+        if !digits(whole) || !digits(fraction) {
+            return Err(error!("invalid amount: {}", s));
+        }
+        if fraction.len() > UNIT_DECIMALS {
+            return Err(error!("amount has more than {} decimal places: {}", UNIT_DECIMALS, s));
+        }
+        let fraction = format!("{:0<width$}", fraction, width = UNIT_DECIMALS);
+        let raw = whole.parse::<u64>().ok()
+            .and_then(|whole| whole.checked_mul(RAW_PER_UNIT))
+            .and_then(|whole| whole.checked_add(fraction.parse().ok()?))
+            .ok_or_else(|| error!("amount too large: {}", s))?;
+        Amount::from_raw_checked(raw).ok_or_else(|| error!("amount exceeds the supply: {}", s))
+    }
+}
+
 impl fmt::Debug for Amount {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Amount({})", self.0)
@@ -161,4 +273,88 @@ mod tests {
         let result = amount1 / amount2;
         assert_eq!(result.to_raw(), 20);
     }
+
+    #[test]
+    fn test_from_raw_checked() {
+        let supply = Amount::initial_supply().to_raw();
+        assert_eq!(Amount::from_raw_checked(0), Some(Amount::zero()));
+        assert_eq!(Amount::from_raw_checked(supply), Some(Amount::initial_supply()));
+        assert_eq!(Amount::from_raw_checked(supply + 1), None);
+        assert_eq!(Amount::from_raw_checked(u64::MAX), None);
+    }
+
+    #[test]
+    fn test_decode_above_supply() {
+        let decode = |raw: u64| crate::util::decode_from_slice::<Amount>(&raw.to_le_bytes());
+        assert_eq!(decode(7).unwrap(), Amount::from_raw(7));
+        assert!(decode(Amount::initial_supply().to_raw() + 1).is_err());
+    }
+
+    #[test]
+    fn test_checked_and_saturating_at_bounds() {
+        let (max, one, two) = (Amount::max(), Amount::from_raw(1), Amount::from_raw(2));
+        assert_eq!(max.checked_add(Amount::zero()), Some(max));
+        assert_eq!(max.checked_add(one), None);
+        assert_eq!(max.saturating_add(one), max);
+        assert_eq!(Amount::zero().checked_sub(one), None);
+        assert_eq!(max.checked_sub(max), Some(Amount::zero()));
+        assert_eq!(Amount::zero().saturating_sub(one), Amount::zero());
+        assert_eq!(max.checked_mul(one), Some(max));
+        assert_eq!(max.checked_mul(two), None);
+        assert_eq!(max.saturating_mul(two), max);
+        assert_eq!(Amount::from_raw(u64::MAX / 2).checked_mul(two), Some(Amount::from_raw(u64::MAX - 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "amount addition overflowed")]
+    fn test_add_overflow_panics() {
+        let _ = Amount::max() + Amount::from_raw(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "amount subtraction underflowed")]
+    fn test_sub_underflow_panics() {
+        let _ = Amount::zero() - Amount::from_raw(1);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Amount::from_raw(125_000_000_000).to_string(), "12.5");
+        assert_eq!(Amount::from_raw(120_000_000_000).to_string(), "12.0");
+        assert_eq!(Amount::from_raw(1).to_string(), "0.0000000001");
+        assert_eq!(Amount::zero().to_string(), "0.0");
+        assert_eq!(Amount::initial_supply().to_string(), "922337203.6854775807");
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("12.5".parse::<Amount>().unwrap(), Amount::from_raw(125_000_000_000));
+        assert_eq!("12.50000".parse::<Amount>().unwrap(), Amount::from_raw(125_000_000_000));
+        assert_eq!("125".parse::<Amount>().unwrap(), Amount::from_raw(1_250_000_000_000));
+        assert_eq!("5".parse::<Amount>().unwrap(), "5.0".parse::<Amount>().unwrap());
+        assert_eq!("0.0000000001".parse::<Amount>().unwrap(), Amount::from_raw(1));
+        assert!("0.00000000001".parse::<Amount>().is_err());
+        assert_eq!("922337203.6854775807".parse::<Amount>().unwrap(), Amount::initial_supply());
+        assert!("922337203.6854775808".parse::<Amount>().is_err());
+        assert!("922337204".parse::<Amount>().is_err());
+        assert!("99999999999.0".parse::<Amount>().is_err());
+        for bad in ["", ".", "1.", ".5", "-1", "+1", "1.2.3", "1e5", " 1"] {
+            assert!(bad.parse::<Amount>().is_err(), "{}", bad);
+        }
+        for raw in [0, 1, 10_000_000_000, 123_456_789_012_345, i64::MAX as u64] {
+            let amount = Amount::from_raw(raw);
+            assert_eq!(amount.to_string().parse::<Amount>().unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn test_from_raw_str() {
+        assert_eq!(Amount::from_raw_str("125").unwrap(), Amount::from_raw(125));
+        assert_eq!(Amount::from_raw_str("9223372036854775807").unwrap(), Amount::initial_supply());
+        assert!(Amount::from_raw_str("9223372036854775808").is_err());
+        assert!(Amount::from_raw_str("99999999999999999999").is_err());
+        for bad in ["", "12.5", "-1", "+1", "1e5", " 1"] {
+            assert!(Amount::from_raw_str(bad).is_err(), "{}", bad);
+        }
+    }
 }
@@ -0,0 +1,30 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::keys::{Domain, HashBuilder};
+
+pub const DEFAULT_CHAIN_ID: u32 = 0;
+
+/// The version of the layout of chain-bound hash preimages, to be bumped whenever it changes
+const PREIMAGE_VERSION: u8 = 1;
+
+/// The id of the network, such as mainnet or a testnet, which transactions and blocks are bound to
+static CHAIN_ID: AtomicU32 = AtomicU32::new(DEFAULT_CHAIN_ID);
+
+/// Get the id of the network this node is on.
+pub fn chain_id() -> u32 {
+    CHAIN_ID.load(Ordering::Relaxed)
+}
+
+/// Set the id of the network this node is on, before anything is signed or verified.
+pub fn set_chain_id(chain_id: u32) {
+    CHAIN_ID.store(chain_id, Ordering::Relaxed);
+}
+
+/// A `HashBuilder` for a structure of `domain` on the network `chain_id`, so that
+/// its signature can't be replayed on another network
+pub fn chain_hash_builder(domain: Domain, chain_id: u32) -> HashBuilder {
+    let mut hb = HashBuilder::with_domain(domain);
+    hb.update(&[PREIMAGE_VERSION]);
+    hb.update(&chain_id.to_le_bytes());
+    hb
+}
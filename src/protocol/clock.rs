@@ -2,26 +2,39 @@ use std::time::SystemTime;
 
 use crate::process;
 
-use super::Slot;
+use super::{slot, Slot};
 
 /// Represents a clock that manages and emits the current slot number based on system time.
 pub struct Clock {
+    /// The unix time in milliseconds at which slot 0 starts.
+    genesis_time_ms: u64,
     /// The last slot number that was emitted by the clock.
     last_emitted: Slot,
 }
 
 impl Clock {
-    /// Constructs a new `Clock` instance.
+    /// Constructs a new `Clock` instance using the network's genesis time.
     pub fn new() -> Self {
+        Self::with_genesis(slot::genesis_time_ms())
+    }
+
+    /// Constructs a new `Clock` instance whose slot 0 starts at `genesis_time_ms`.
+    pub fn with_genesis(genesis_time_ms: u64) -> Self {
         Self {
+            genesis_time_ms,
             last_emitted: Slot::zero(),
         }
     }
 
+    /// Returns the slot number at `time`.
+    pub fn slot_at(&self, time: SystemTime) -> Slot {
+        Slot::from_system_time_since(self.genesis_time_ms, time)
+    }
+
     /// Returns the current slot number, never returning the same slot twice.
     pub fn tick(&mut self) -> Slot {
         let now = SystemTime::now();
-        let cur_slot = Slot::from_system_time(now);
+        let cur_slot = self.slot_at(now);
 
         // If the current slot is greater than the last emitted, update and return it.
         if self.last_emitted < cur_slot {
@@ -31,7 +44,7 @@ impl Clock {
 
         // Calculate the next slot and the time duration until it starts.
         let next_slot = cur_slot.next();
-        let til_next = next_slot.to_system_time().duration_since(now).unwrap();
+        let til_next = next_slot.to_system_time_since(self.genesis_time_ms).duration_since(now).unwrap();
 
         // Sleep until the next slot time is reached.
         process::sleep(til_next);
@@ -41,3 +54,33 @@ impl Clock {
         next_slot
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_same_genesis_same_slots() {
+        let genesis_time_ms = 1_700_000_000_000;
+        let a = Clock::with_genesis(genesis_time_ms);
+        let b = Clock::with_genesis(genesis_time_ms);
+        let genesis = SystemTime::UNIX_EPOCH + Duration::from_millis(genesis_time_ms);
+        for offset_ms in [0, 499, 500, 1_234_567] {
+            let time = genesis + Duration::from_millis(offset_ms);
+            assert_eq!(a.slot_at(time), b.slot_at(time));
+        }
+        assert_eq!(a.slot_at(genesis), Slot::zero());
+        assert_eq!(a.slot_at(genesis + Duration::from_millis(500)), Slot::zero().next());
+        let later = Clock::with_genesis(genesis_time_ms + 500);
+        assert_eq!(later.slot_at(genesis + Duration::from_millis(500)), Slot::zero());
+    }
+
+    #[test]
+    fn test_reject_future_genesis() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_millis(slot::DEFAULT_GENESIS_TIME_MS);
+        let future = slot::DEFAULT_GENESIS_TIME_MS + 1;
+        assert!(slot::set_genesis_time_ms(future, now).is_err());
+        assert_eq!(slot::genesis_time_ms(), slot::DEFAULT_GENESIS_TIME_MS);
+    }
+}
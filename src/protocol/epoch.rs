@@ -2,11 +2,12 @@ use bincode::{Encode, Decode};
 
 use super::Slot;
 
-#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Epoch(pub(super) u64);
 
 impl Epoch {
     pub const LEN: usize = 86400;
+    #[cfg(test)]
     pub const fn zero() -> Self {
         Self(0)
     }
@@ -30,9 +31,4 @@ impl Epoch {
             None
         }
     }
-    pub fn iter(&self) -> impl Iterator<Item = Slot> {
-        (self.0 * Self::LEN as u64..(self.0 + 1) * Self::LEN as u64)
-            .into_iter()
-            .map(|i| Slot(i))
-    }
 }
@@ -1,6 +1,6 @@
 use rand::{
     distributions::{Distribution, WeightedIndex},
-    SeedableRng,
+    RngCore, SeedableRng,
 };
 use rand_chacha::ChaChaRng;
 
@@ -9,7 +9,37 @@ use crate::{keys::Public, static_assert, util::DefaultInitVec};
 use super::{Amount, Epoch, Slot};
 
 const NUM_CONSECUTIVE_LEADER_SLOTS: usize = 4;
-static_assert!((Epoch::LEN % NUM_CONSECUTIVE_LEADER_SLOTS) == 0);
+static_assert!(Epoch::LEN.is_multiple_of(NUM_CONSECUTIVE_LEADER_SLOTS));
+/// Mixed into the seed of every schedule, so that schedules are specific to this network's protocol
+const SCHEDULE_SEED_DOMAIN: [u8; 24] = *b"starlight-leader-sched-1";
+
+/// A reproducible rng for sampling leader schedules, seeded only by the epoch and
+/// `SCHEDULE_SEED_DOMAIN`, so that all honest nodes sample identical schedules
+pub struct ScheduleRng(ChaChaRng);
+
+impl ScheduleRng {
+    pub fn new(epoch: Epoch) -> Self {
+        let mut seed = [0u8; 32];
+        seed[0..8].copy_from_slice(&epoch.to_bytes());
+        seed[8..32].copy_from_slice(&SCHEDULE_SEED_DOMAIN);
+        Self(ChaChaRng::from_seed(seed))
+    }
+}
+
+impl RngCore for ScheduleRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
 
 pub struct LeaderSchedule {
     pub epoch: Epoch,
@@ -29,10 +59,9 @@ impl LeaderSchedule {
         get_weight: impl Fn(&Public) -> Amount,
         epoch: Epoch,
     ) {
+        // Sort the voters, so that the schedule doesn't depend on the order they were found in
         voters.sort();
-        let mut seed = [0u8; 32];
-        seed[0..8].copy_from_slice(&epoch.to_bytes());
-        let mut rng = ChaChaRng::from_seed(seed);
+        let mut rng = ScheduleRng::new(epoch);
         let weighted_index =
             WeightedIndex::new(voters.iter().map(|x| get_weight(x).to_raw())).unwrap();
         for i in 0..self.leaders.len() {
@@ -40,11 +69,97 @@ impl LeaderSchedule {
         }
         self.epoch = epoch;
     }
-    pub fn get(&self, slot: Slot) -> Option<Public> {
+    pub fn leader_for(&self, slot: Slot) -> Option<Public> {
         if self.epoch == Epoch::max() {
             return None;
         }
         let idx = self.epoch.index_of(slot)?;
-        Some(self.leaders[idx / 4])
+        Some(self.leaders[idx / NUM_CONSECUTIVE_LEADER_SLOTS])
+    }
+    /// Get the leaders of up to `limit` slots of the epoch, starting at `from`
+    /// or at the start of the epoch, whichever is later.
+    pub fn upcoming(&self, from: Slot, limit: usize) -> Vec<(Slot, Public)> {
+        if self.epoch == Epoch::max() {
+            return vec![];
+        }
+        let start = match self.epoch.index_of(from) {
+            Some(i) => i,
+            None if from < self.epoch.get(0).unwrap() => 0,
+            None => return vec![],
+        };
+        (start..Epoch::LEN)
+            .take(limit)
+            .map(|i| (self.epoch.get(i).unwrap(), self.leaders[i / NUM_CONSECUTIVE_LEADER_SLOTS]))
+            .collect()
+    }
+    /// Get the first slot of the epoch at or after `from` which is led by `leader`.
+    pub fn next_slot_led_by(&self, leader: &Public, from: Slot) -> Option<Slot> {
+        self.upcoming(from, Epoch::LEN)
+            .into_iter()
+            .find(|(_, x)| x == leader)
+            .map(|(slot, _)| slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Seed;
+
+    fn weighted_schedule(epoch: Epoch) -> (LeaderSchedule, Vec<Public>) {
+        let seed = Seed([7; 32]);
+        let voters: Vec<Public> = (0..4).map(|i| seed.derive(i).to_public()).collect();
+        let weights = voters.clone();
+        let mut schedule = LeaderSchedule::empty();
+        schedule.fill(voters.clone(), |public| {
+            let i = weights.iter().position(|x| x == public).unwrap();
+            Amount::from_raw(i as u64 + 1)
+        }, epoch);
+        (schedule, voters)
+    }
+
+    #[test]
+    fn test_upcoming_matches_leader_for() {
+        let epoch = Epoch(5);
+        let (schedule, voters) = weighted_schedule(epoch);
+        let start = epoch.get(0).unwrap();
+        let upcoming = schedule.upcoming(start, 64);
+        assert_eq!(upcoming.len(), 64);
+        for (i, &(slot, leader)) in upcoming.iter().enumerate() {
+            assert!(slot == epoch.get(i).unwrap());
+            assert!(Some(leader) == schedule.leader_for(slot));
+            assert!(voters.contains(&leader));
+        }
+    }
+
+    #[test]
+    fn test_upcoming_bounds() {
+        let epoch = Epoch(5);
+        let (schedule, _) = weighted_schedule(epoch);
+        let from = epoch.get(Epoch::LEN - 10).unwrap();
+        let upcoming = schedule.upcoming(from, 64);
+        assert_eq!(upcoming.len(), 10);
+        assert!(upcoming[0].0 == from);
+        assert!(schedule.upcoming(Epoch(6).get(0).unwrap(), 64).is_empty());
+        assert!(LeaderSchedule::empty().upcoming(from, 64).is_empty());
+    }
+
+    #[test]
+    fn test_schedule_is_deterministic() {
+        let epoch = Epoch(5);
+        let (schedule, mut voters) = weighted_schedule(epoch);
+        // built independently, from the voters in another order
+        voters.reverse();
+        let weights = voters.clone();
+        let mut other = LeaderSchedule::empty();
+        other.fill(voters, |public| {
+            let i = weights.iter().rev().position(|x| x == public).unwrap();
+            Amount::from_raw(i as u64 + 1)
+        }, epoch);
+        let bytes = |schedule: &LeaderSchedule| heed::bytemuck::cast_slice::<Public, u8>(&schedule.leaders).to_vec();
+        assert_eq!(bytes(&schedule), bytes(&other));
+        // but another epoch samples another schedule
+        let (next, _) = weighted_schedule(Epoch(6));
+        assert_ne!(bytes(&schedule), bytes(&next));
     }
 }
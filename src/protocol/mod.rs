@@ -1,4 +1,5 @@
 mod amount;
+mod chain;
 mod clock;
 mod epoch;
 mod leader_schedule;
@@ -13,12 +14,13 @@ mod task;
 mod scheduler;
 
 pub use amount::Amount;
+pub use chain::{chain_hash_builder, chain_id, set_chain_id, DEFAULT_CHAIN_ID};
 pub use clock::Clock;
 pub use epoch::Epoch;
 pub use leader_schedule::LeaderSchedule;
 pub use pair::Pair;
-pub use slot::Slot;
-pub use tx::Tx;
+pub use slot::{set_genesis_time_ms, Slot, DEFAULT_GENESIS_TIME_MS};
+pub use tx::{Tx, TxKind};
 pub use tx_stages::{TxEmpty, TxHalf, TxFull};
 pub use open::Open;
 pub use open_full::OpenFull;
@@ -1,6 +1,8 @@
 use bincode::{Encode, Decode};
 
-use crate::{keys::{Difficulty, Hash, Public, Signature, Work}, util::{self, Error}};
+use crate::{keys::{Difficulty, Domain, Hash, Public, Signature, Work}, util::Error};
+#[cfg(test)]
+use crate::keys::Identity;
 
 
 #[repr(C)]
@@ -13,15 +15,97 @@ pub struct Open {
 }
 
 impl Open {
+    #[cfg(test)]
+    /// Build and sign an open of `id` with `representative`.
+    /// `work` must be valid for `Open::work_hash(&id.public, &representative)`.
+    pub fn sign(id: &Identity, representative: Public, work: Work) -> Self {
+        let mut open = Self {
+            account: id.public,
+            representative,
+            work,
+            signature: Signature::zero()
+        };
+        open.signature = id.private.sign(&open.hash());
+        open
+    }
+    /// Does this open make `account` its own representative?
+    pub fn is_self_delegating(&self) -> bool {
+        self.account == self.representative
+    }
+    /// The hash that `work` is computed over, which includes `account` and `representative`
+    pub fn work_hash(account: &Public, representative: &Public) -> Hash {
+        let mut buf = [0u8; 64];
+        buf[0..32].copy_from_slice(account.as_bytes());
+        buf[32..64].copy_from_slice(representative.as_bytes());
+        Hash::digest(&buf)
+    }
+    /// The hash signed by `account`, which includes everything up to `signature`
+    pub fn hash(&self) -> Hash {
+        let mut buf = [0u8; 72];
+        buf[0..32].copy_from_slice(self.account.as_bytes());
+        buf[32..64].copy_from_slice(self.representative.as_bytes());
+        buf[64..72].copy_from_slice(self.work.as_bytes());
+        Hash::digest_in(Domain::Open, &buf)
+    }
+    /// Fully validate the work and self-signature of this open, without
+    /// touching the `Bank`, so that malformed opens can be rejected early.
     pub fn verify_and_hash(&self) -> Result<Hash, Error> {
-        let bytes = util::view_as_bytes(self);
-        // include `account` and `representative`
-        let work_hash = Hash::digest(&bytes[0..64]);
-        // include everything up to `signature`
-        let tx_hash = Hash::digest(&bytes[0..70]);
+        let work_hash = Self::work_hash(&self.account, &self.representative);
+        let open_hash = self.hash();
         self.work.verify(&work_hash, Difficulty::BASE)?;
-        self.account.verify(&tx_hash, &self.signature)?;
-        Ok(tx_hash)
+        self.account.verify(&open_hash, &self.signature)?;
+        Ok(open_hash)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::work_fixtures;
+    use std::str::FromStr;
+
+    fn fixture() -> (Identity, Public, Work) {
+        (work_fixtures::identity(0), work_fixtures::identity(1).public, work_fixtures::open_work(0))
+    }
+
+    #[test]
+    fn test_valid_open() {
+        let (id, representative, work) = fixture();
+        let open = Open::sign(&id, representative, work);
+        assert_eq!(open.verify_and_hash().unwrap(), open.hash());
+    }
+
+    #[test]
+    fn test_self_delegating_open() {
+        let (id, representative, _) = fixture();
+        let work = Work::from_str("0000000000000000").unwrap();
+        let open = Open::sign(&id, id.public, work);
+        assert!(open.is_self_delegating());
+        assert!(!Open::sign(&id, representative, work).is_self_delegating());
+    }
+
+    #[test]
+    fn test_zero_signature() {
+        let (id, representative, work) = fixture();
+        let mut open = Open::sign(&id, representative, work);
+        open.signature = Signature::zero();
+        assert!(open.verify_and_hash().is_err());
+    }
+
+    #[test]
+    fn test_mismatched_hash() {
+        let (id, representative, work) = fixture();
+        // signed by a different account than the one claimed
+        let mut open = Open::sign(&id, representative, work);
+        open.account = representative;
+        assert!(open.verify_and_hash().is_err());
+        // the signature doesn't cover the claimed representative
+        let mut open = Open::sign(&id, representative, work);
+        open.representative = id.public;
+        assert!(open.verify_and_hash().is_err());
+        // the signature doesn't cover the claimed work
+        let mut open = Open::sign(&id, representative, work);
+        open.work = Work::zero();
+        assert!(open.verify_and_hash().is_err());
+    }
+}
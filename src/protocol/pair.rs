@@ -1,6 +1,9 @@
+use bincode::{Decode, Encode};
+
 use super::Slot;
 use crate::keys::Hash;
 
+#[derive(Encode, Decode, Clone, Copy)]
 pub struct Pair {
     pub slot: Slot,
     pub block: Hash,
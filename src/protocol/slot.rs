@@ -1,34 +1,56 @@
-use std::time::{Duration, Instant, SystemTime};
+use std::{sync::atomic::{AtomicU64, Ordering}, time::{Duration, SystemTime}};
 
 use bincode::{Encode, Decode};
 
+use crate::{error, util::Error};
+
 use super::Epoch;
 
-const GENESIS_TIME_MS: u64 = 1710290840 * 1000;
+pub const DEFAULT_GENESIS_TIME_MS: u64 = 1710290840 * 1000;
 const SLOT_TIME_MS: u64 = 500;
 
+/// The unix time in milliseconds at which slot 0 starts, shared by the whole network
+static GENESIS_TIME_MS: AtomicU64 = AtomicU64::new(DEFAULT_GENESIS_TIME_MS);
+
+/// Get the unix time in milliseconds at which slot 0 starts.
+pub fn genesis_time_ms() -> u64 {
+    GENESIS_TIME_MS.load(Ordering::Relaxed)
+}
+
+/// Set the unix time in milliseconds at which slot 0 starts, which can't be after `now`.
+pub fn set_genesis_time_ms(genesis_time_ms: u64, now: SystemTime) -> Result<(), Error> {
+    let now_ms = now.duration_since(SystemTime::UNIX_EPOCH)?.as_millis() as u64;
+    if genesis_time_ms > now_ms {
+        return Err(error!("genesis timestamp {} is in the future", genesis_time_ms));
+    }
+    GENESIS_TIME_MS.store(genesis_time_ms, Ordering::Relaxed);
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, Encode, Decode, Default)]
 pub struct Slot(pub(super) u64);
 impl Slot {
     pub fn zero() -> Slot {
         Slot(0)
     }
-    pub fn to_system_time(self) -> SystemTime {
-        SystemTime::UNIX_EPOCH + Duration::from_millis(GENESIS_TIME_MS + self.0 * SLOT_TIME_MS)
+    /// Get the start of this slot when slot 0 starts at `genesis_time_ms`.
+    pub fn to_system_time_since(self, genesis_time_ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(genesis_time_ms + self.0 * SLOT_TIME_MS)
     }
     pub fn from_system_time(time: SystemTime) -> Slot {
+        Self::from_system_time_since(genesis_time_ms(), time)
+    }
+    /// Get the slot at `time` when slot 0 starts at `genesis_time_ms`.
+    pub fn from_system_time_since(genesis_time_ms: u64, time: SystemTime) -> Slot {
         let now_ms = time
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_millis() as u64;
-        Slot((now_ms - GENESIS_TIME_MS) / SLOT_TIME_MS)
+        Slot(now_ms.saturating_sub(genesis_time_ms) / SLOT_TIME_MS)
     }
     pub fn now() -> Slot {
         Self::from_system_time(SystemTime::now())
     }
-    pub fn prev(self) -> Self {
-        Self(self.0.saturating_sub(1))
-    }
     pub fn next(self) -> Self {
         Self(self.0.saturating_add(1))
     }
@@ -38,15 +60,26 @@ impl Slot {
     pub const fn to_bytes(self) -> [u8; 8] {
         self.0.to_le_bytes()
     }
+    #[cfg(test)]
     pub const fn from_bytes(bytes: [u8; 8]) -> Self {
         Self(u64::from_le_bytes(bytes))
     }
+    #[cfg(test)]
     pub fn max() -> Self {
         Self(u64::MAX)
     }
     pub fn epoch(self) -> Epoch {
         Epoch(self.0 / Epoch::LEN as u64)
     }
+    /// Reject slots more than `max_skew` ahead of `now`, as produced by
+    /// peers with a wildly wrong clock.
+    pub fn check_skew(self, now: SystemTime, max_skew: Duration) -> Result<(), Error> {
+        let latest = Self::from_system_time(now + max_skew);
+        if self > latest {
+            return Err(error!("slot {} is more than {:?} ahead of local time", self.0, max_skew));
+        }
+        Ok(())
+    }
 }
 
 impl std::ops::Sub for Slot {
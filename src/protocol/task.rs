@@ -6,12 +6,16 @@ pub struct Task {
     pub nonce: u64,
     pub from_index: u64,
     pub amount: Amount,
-    pub to_index: u64
+    pub to_index: u64,
+    /// The part of `amount` burned as the open fee of `to_index` instead of being received
+    pub burn: Amount,
+    /// Whether the task opens the account `from_index`, represented by `to_index`
+    pub open: bool
 }
 
 impl Task {
     pub fn is_change_representative(&self) -> bool {
-        self.amount == Amount::zero()
+        !self.open && self.amount == Amount::zero()
     }
 }
 
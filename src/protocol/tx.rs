@@ -1,15 +1,37 @@
 use crate::{
     error,
-    keys::{Difficulty, Hash, Public, Signature, Work},
-    util::{self, Archived, Error},
+    keys::{Difficulty, Domain, Hash, Public, Signature, Work},
+    util::Error,
 };
+#[cfg(test)]
+use crate::keys::Identity;
 
-use super::Amount;
+use super::{chain_hash_builder, chain_id, Amount};
 use bincode::{Encode, Decode};
 
+/// What a transaction does
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxKind {
+    /// Transfer the given amount from `from` to `to`
+    Transfer(Amount),
+    /// Change the representative of `from` to `to`
+    ChangeRepresentative,
+}
+
+impl TxKind {
+    #[cfg(test)]
+    fn amount(self) -> Amount {
+        match self {
+            TxKind::Transfer(amount) => amount,
+            TxKind::ChangeRepresentative => Amount::zero(),
+        }
+    }
+}
+
 /// A transaction, either a normal or change representative transaction.
 /// When `amount` != `Amount::zero()`:
 /// - Funds equal to `amount` are transferred from `from` to `to`.
+///
 /// Else:
 /// - The representative of `from` is changed to `to`.
 #[derive(Encode, Decode, Clone, Copy, Debug)]
@@ -24,17 +46,113 @@ pub struct Tx {
 }
 
 impl Tx {
+    #[cfg(test)]
+    /// Build and sign a transaction from `id`.
+    /// `work` must be valid for `Tx::work_hash(nonce, &id.public)`.
+    pub fn sign(id: &Identity, nonce: u64, to: Public, kind: TxKind, work: Work) -> Self {
+        let mut tx = Self {
+            nonce,
+            from: id.public,
+            amount: kind.amount(),
+            to,
+            work,
+            signature: Signature::zero(),
+        };
+        tx.signature = id.private.sign(&tx.hash());
+        tx
+    }
     pub fn is_change_representative(&self) -> bool {
         self.amount == Amount::zero()
     }
+    /// Does the transaction transfer at least `min`? Changing representative
+    /// transfers nothing, so it always does.
+    pub fn meets_min_amount(&self, min: Amount) -> bool {
+        self.is_change_representative() || self.amount >= min
+    }
+    pub fn kind(&self) -> TxKind {
+        match self.is_change_representative() {
+            true => TxKind::ChangeRepresentative,
+            false => TxKind::Transfer(self.amount),
+        }
+    }
+    /// The hash that `work` is computed over, which includes `nonce` and `from`
+    pub fn work_hash(nonce: u64, from: &Public) -> Hash {
+        let mut buf = [0u8; 40];
+        buf[0..8].copy_from_slice(&nonce.to_le_bytes());
+        buf[8..40].copy_from_slice(from.as_bytes());
+        Hash::digest(&buf)
+    }
+    #[cfg(test)]
+    /// The hash signed by `from`, which includes everything up to `signature`
+    /// and the id of the network
+    pub fn hash(&self) -> Hash {
+        self.hash_on(chain_id())
+    }
+    /// The hash of this transaction on the network `chain_id`
+    pub fn hash_on(&self, chain_id: u32) -> Hash {
+        let mut buf = [0u8; 88];
+        buf[0..8].copy_from_slice(&self.nonce.to_le_bytes());
+        buf[8..40].copy_from_slice(self.from.as_bytes());
+        buf[40..48].copy_from_slice(&self.amount.to_raw().to_le_bytes());
+        buf[48..80].copy_from_slice(self.to.as_bytes());
+        buf[80..88].copy_from_slice(self.work.as_bytes());
+        let mut hb = chain_hash_builder(Domain::Tx, chain_id);
+        hb.update(&buf);
+        hb.finish()
+    }
+    /// Cheaply reject transactions which can never be valid, such as those
+    /// transferring more than the supply, before checking work and signature.
+    pub fn check_bounds(&self) -> Result<(), Error> {
+        if self.amount > Amount::initial_supply() {
+            return Err(error!("transaction amount exceeds the supply"));
+        }
+        Ok(())
+    }
     pub fn verify_and_hash(&self) -> Result<Hash, Error> {
-        let bytes = util::view_as_bytes(self);
-        // include `nonce` and `from`
-        let work_hash = Hash::digest(&bytes[0..40]);
-        // include everything up to `signature`
-        let tx_hash = Hash::digest(&bytes[0..96]);
+        self.verify_and_hash_on(chain_id())
+    }
+    /// Verify the transaction as signed for the network `chain_id`
+    pub fn verify_and_hash_on(&self, chain_id: u32) -> Result<Hash, Error> {
+        self.check_bounds()?;
+        let work_hash = Self::work_hash(self.nonce, &self.from);
+        let tx_hash = self.hash_on(chain_id);
         self.work.verify(&work_hash, Difficulty::BASE)?;
         self.from.verify(&tx_hash, &self.signature)?;
         Ok(tx_hash)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::work_fixtures;
+
+    fn fixture() -> (Identity, Work) {
+        (work_fixtures::identity(0), work_fixtures::first_tx_work())
+    }
+
+    #[test]
+    fn test_oversized_amount() {
+        let (id, work) = fixture();
+        let supply = Tx::sign(&id, 0, Public::random(), TxKind::Transfer(Amount::initial_supply()), work);
+        assert!(supply.verify_and_hash().is_ok());
+        for amount in [Amount::initial_supply() + Amount::from_raw(1), Amount::max()] {
+            // even when correctly signed
+            let tx = Tx::sign(&id, 0, Public::random(), TxKind::Transfer(amount), work);
+            let e = tx.verify_and_hash().unwrap_err();
+            assert!(e.as_str().contains("exceeds the supply"));
+        }
+    }
+
+    #[test]
+    fn test_replay_on_other_chain() {
+        let (id, work) = fixture();
+        let tx = Tx::sign(&id, 0, Public::random(), TxKind::Transfer(Amount::from_raw(1)), work);
+        assert!(tx.hash_on(chain_id()) == tx.hash());
+        assert!(tx.verify_and_hash_on(chain_id()).is_ok());
+        // the signature is only valid on the network it was made for
+        let other = chain_id().wrapping_add(1);
+        assert!(tx.hash_on(other) != tx.hash());
+        assert!(tx.verify_and_hash_on(other).is_err());
+    }
+}
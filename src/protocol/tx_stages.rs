@@ -1,35 +1,152 @@
-use bincode::{Decode, Encode};
-use crate::keys::Hash;
+use bincode::{
+    de::{BorrowDecoder, Decoder},
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+    BorrowDecode, Decode, Encode,
+};
+use crate::{keys::Hash, util::Error};
+#[cfg(test)]
+use crate::keys::{Identity, Public, Work};
 
 use super::Tx;
+#[cfg(test)]
+use super::TxKind;
+
+// The hash of a transaction is memoized as it moves through these stages.
+// It is only ever computed by `TxEmpty::verify`, `TxFull::sign` or on decoding,
+// and `tx` is never exposed mutably, so downstream code can reuse it without rehashing.
+// The hash is not encoded, so that a decoded hash is always one we verified.
+
+/// Verify a decoded `tx`, giving the hash to memoize
+fn decoded_hash(tx: &Tx) -> Result<Hash, DecodeError> {
+    tx.verify_and_hash().map_err(|_| DecodeError::Other("transaction failed verification"))
+}
 
-#[derive(Encode, Decode)]
 #[repr(C)]
 pub struct TxFull {
-    pub tx: Tx,
-    pub hash: Hash,
+    tx: Tx,
+    hash: Hash,
     pub from_index: u64,
     pub to_index: u64
 }
+impl TxFull {
+    #[cfg(test)]
+    /// Builds, hashes and signs a transaction from `id`, mirroring `Block::sign`.
+    /// `work` must be valid for `Tx::work_hash(nonce, &id.public)`.
+    pub fn sign(
+        id: &Identity,
+        from_index: u64,
+        to: Public,
+        to_index: u64,
+        kind: TxKind,
+        nonce: u64,
+        work: Work,
+    ) -> Self {
+        let tx = Tx::sign(id, nonce, to, kind, work);
+        Self {
+            hash: tx.hash(),
+            tx,
+            from_index,
+            to_index
+        }
+    }
+    #[cfg(test)]
+    pub fn tx(&self) -> &Tx {
+        &self.tx
+    }
+    #[cfg(test)]
+    pub fn hash(&self) -> Hash {
+        self.hash
+    }
+}
+
+impl Encode for TxFull {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.tx.encode(encoder)?;
+        self.from_index.encode(encoder)?;
+        self.to_index.encode(encoder)
+    }
+}
+
+impl Decode for TxFull {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let tx = Tx::decode(decoder)?;
+        Ok(Self {
+            hash: decoded_hash(&tx)?,
+            tx,
+            from_index: u64::decode(decoder)?,
+            to_index: u64::decode(decoder)?
+        })
+    }
+}
+
+impl<'de> BorrowDecode<'de> for TxFull {
+    fn borrow_decode<D: BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let tx = Tx::borrow_decode(decoder)?;
+        Ok(Self {
+            hash: decoded_hash(&tx)?,
+            tx,
+            from_index: u64::borrow_decode(decoder)?,
+            to_index: u64::borrow_decode(decoder)?
+        })
+    }
+}
 
-#[derive(Encode, Decode)]
 #[repr(C)]
 pub struct TxHalf {
-    pub tx: Tx,
-    pub hash: Hash,
+    tx: Tx,
+    hash: Hash,
     from_index: u64,
     to_index: u64
 }
 impl TxHalf {
+    pub fn tx(&self) -> &Tx {
+        &self.tx
+    }
+    pub fn hash(&self) -> Hash {
+        self.hash
+    }
     pub fn provide(mut self: Box<Self>, from_index: u64, to_index: u64) -> Box<TxFull> {
         self.from_index = from_index;
         self.to_index = to_index;
         unsafe {
-            std::mem::transmute(self)
+            std::mem::transmute::<Box<TxHalf>, Box<TxFull>>(self)
         }
     }
 }
 
+impl Encode for TxHalf {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.tx.encode(encoder)?;
+        self.from_index.encode(encoder)?;
+        self.to_index.encode(encoder)
+    }
+}
+
+impl Decode for TxHalf {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let tx = Tx::decode(decoder)?;
+        Ok(Self {
+            hash: decoded_hash(&tx)?,
+            tx,
+            from_index: u64::decode(decoder)?,
+            to_index: u64::decode(decoder)?
+        })
+    }
+}
+
+impl<'de> BorrowDecode<'de> for TxHalf {
+    fn borrow_decode<D: BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let tx = Tx::borrow_decode(decoder)?;
+        Ok(Self {
+            hash: decoded_hash(&tx)?,
+            tx,
+            from_index: u64::borrow_decode(decoder)?,
+            to_index: u64::borrow_decode(decoder)?
+        })
+    }
+}
+
 #[derive(Encode, Decode)]
 #[repr(C)]
 pub struct TxEmpty {
@@ -47,10 +164,96 @@ impl TxEmpty {
             to_index: 0
         })
     }
-    pub fn provide(mut self: Box<Self>, hash: Hash) -> Box<TxHalf> {
-        self.hash = hash;
+    /// Verifies the transaction, memoizing its hash.
+    pub fn verify(mut self: Box<Self>) -> Result<Box<TxHalf>, Error> {
+        self.hash = self.tx.verify_and_hash()?;
+        Ok(unsafe {
+            std::mem::transmute::<Box<TxEmpty>, Box<TxHalf>>(self)
+        })
+    }
+    /// Memoizes the hash of the transaction without verifying it, for tests
+    /// whose transactions lack valid work.
+    #[cfg(test)]
+    pub fn assume_verified(mut self: Box<Self>) -> Box<TxHalf> {
+        self.hash = self.tx.hash();
         unsafe {
-            std::mem::transmute(self)
+            std::mem::transmute::<Box<TxEmpty>, Box<TxHalf>>(self)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::work_fixtures, protocol::Amount, util};
+
+    fn fixture() -> (Identity, Work) {
+        (work_fixtures::identity(0), work_fixtures::first_tx_work())
+    }
+
+    #[test]
+    fn test_sign() {
+        let (id, work) = fixture();
+        let to = Public::random();
+        for kind in [TxKind::Transfer(Amount::from_raw(42)), TxKind::ChangeRepresentative] {
+            let tx_full = TxFull::sign(&id, 3, to, 7, kind, 0, work);
+            assert_eq!(tx_full.tx().verify_and_hash().unwrap(), tx_full.hash());
+            assert_eq!(tx_full.tx().from, id.public);
+            assert_eq!(tx_full.tx().to, to);
+            assert_eq!(tx_full.tx().kind(), kind);
+            assert_eq!((tx_full.from_index, tx_full.to_index), (3, 7));
+        }
+    }
+
+    #[test]
+    fn test_sign_tampered() {
+        let (id, work) = fixture();
+        let mut tx_full = TxFull::sign(&id, 0, Public::random(), 1, TxKind::Transfer(Amount::from_raw(42)), 0, work);
+        tx_full.tx.amount = Amount::from_raw(43);
+        assert!(tx_full.tx.verify_and_hash().is_err());
+        let tx_full = TxFull::sign(&id, 0, Public::random(), 1, TxKind::ChangeRepresentative, 1, work);
+        assert!(tx_full.tx.verify_and_hash().is_err());
+    }
+
+    #[test]
+    fn test_verify_memoizes_hash() {
+        let (id, work) = fixture();
+        let tx = Tx::sign(&id, 0, Public::random(), TxKind::Transfer(Amount::from_raw(42)), work);
+        let tx_half = TxEmpty::boxed(tx).verify().unwrap();
+        assert_eq!(tx_half.hash(), tx.hash());
+        assert_eq!(tx_half.hash(), tx.verify_and_hash().unwrap());
+        let tx_full = tx_half.provide(3, 7);
+        assert_eq!(tx_full.hash(), tx.hash());
+        assert_eq!((tx_full.from_index, tx_full.to_index), (3, 7));
+
+        let mut tampered = tx;
+        tampered.amount = Amount::from_raw(43);
+        assert!(TxEmpty::boxed(tampered).verify().is_err());
+    }
+
+    #[test]
+    fn test_decode_recomputes_hash() {
+        let (id, work) = fixture();
+        let tx_full = TxFull::sign(&id, 3, Public::random(), 7, TxKind::Transfer(Amount::from_raw(42)), 0, work);
+        // the hash isn't encoded
+        let mut encoded = Vec::new();
+        util::encode_into_writer(&mut encoded, &tx_full).unwrap();
+        let mut expected = Vec::new();
+        util::encode_into_writer(&mut expected, &(*tx_full.tx(), 3u64, 7u64)).unwrap();
+        assert_eq!(encoded, expected);
+
+        let decoded: TxFull = util::decode_from_slice(&encoded).unwrap();
+        assert_eq!(decoded.hash(), tx_full.hash());
+        assert_eq!((decoded.from_index, decoded.to_index), (3, 7));
+        let decoded: TxHalf = util::decode_from_slice(&encoded).unwrap();
+        assert_eq!(decoded.hash(), tx_full.hash());
+
+        // a transaction which fails verification can't be decoded
+        let mut tampered = *tx_full.tx();
+        tampered.amount = Amount::from_raw(43);
+        let mut encoded = Vec::new();
+        util::encode_into_writer(&mut encoded, &(tampered, 3u64, 7u64)).unwrap();
+        assert!(util::decode_from_slice::<TxFull>(&encoded).is_err());
+        assert!(util::decode_from_slice::<TxHalf>(&encoded).is_err());
+    }
+}
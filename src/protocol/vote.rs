@@ -1,9 +1,14 @@
+use bincode::{Decode, Encode};
+
 use super::Pair;
 use crate::{
-    keys::{Hash, Public, Signature},
+    keys::{Domain, Hash, Public, Signature},
     util::Error,
 };
+#[cfg(test)]
+use crate::keys::Identity;
 
+#[derive(Encode, Decode, Clone)]
 pub struct Vote {
     pub from: Public,
     pub left: Pair,
@@ -12,14 +17,29 @@ pub struct Vote {
 }
 
 impl Vote {
-    pub fn verify_and_hash(&self) -> Result<Hash, Error> {
+    #[cfg(test)]
+    /// Build and sign a vote from `id` for the blocks from `left` to `right`
+    pub fn sign(id: &Identity, left: Pair, right: Pair) -> Self {
+        let mut vote = Self {
+            from: id.public,
+            left,
+            right,
+            signature: Signature::zero(),
+        };
+        vote.signature = id.private.sign(&vote.hash());
+        vote
+    }
+    fn hash(&self) -> Hash {
         let mut bytes = [0u8; 112];
         bytes[0..32].copy_from_slice(self.from.as_bytes());
         bytes[32..40].copy_from_slice(&self.left.slot.to_bytes());
         bytes[40..72].copy_from_slice(self.left.block.as_bytes());
         bytes[72..80].copy_from_slice(&self.right.slot.to_bytes());
         bytes[80..112].copy_from_slice(self.right.block.as_bytes());
-        let vote_hash = Hash::digest(&bytes);
+        Hash::digest_in(Domain::Vote, &bytes)
+    }
+    pub fn verify_and_hash(&self) -> Result<Hash, Error> {
+        let vote_hash = self.hash();
         self.from.verify(&vote_hash, &self.signature)?;
         Ok(vote_hash)
     }
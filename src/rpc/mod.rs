@@ -3,9 +3,19 @@ mod rpc_receiver;
 mod rpc_sender;
 mod rpc_request;
 mod rpc_response;
+mod subscriptions;
+mod rate_limiter;
+#[cfg(unix)]
+mod unix_rpc_server;
 
-pub use rpc_server::RpcServer;
+pub use rpc_server::{bind_listener, RpcServer};
 pub use rpc_receiver::RpcReceiver;
 pub use rpc_sender::RpcSender;
-pub use rpc_request::RpcRequest;
-pub use rpc_response::RpcResponse;
\ No newline at end of file
+pub use rpc_request::{Page, RpcRequest, RpcRequestError, MAX_REQUEST_LEN};
+pub use rpc_response::{DagNode, PeerInfo, RpcEnvelope, RpcErrorCode, RpcResponse};
+pub use subscriptions::{Confirmation, Subscriptions};
+#[cfg(test)]
+pub use subscriptions::ConfirmationKind;
+pub use rate_limiter::RateLimiter;
+#[cfg(unix)]
+pub use unix_rpc_server::UnixRpcServer;
\ No newline at end of file
@@ -0,0 +1,135 @@
+use std::{collections::HashMap, net::{IpAddr, Ipv6Addr}, sync::Mutex, time::{Duration, Instant}};
+
+use crate::{error, util::Error};
+
+use super::RpcRequest;
+
+/// How often the buckets of idle clients are dropped. A bucket untouched for
+/// this long has refilled, so is as good as new.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Limits how often each client may make each limited action, with a token
+/// bucket per action and client that refills at `per_minute` a minute.
+/// Clients are IPv4 addresses or IPv6 /64 prefixes, as a single IPv6 host
+/// is commonly handed a whole /64. Actions without a limit are unlimited.
+pub struct RateLimiter {
+    /// The requests per minute allowed of each action, by index in `RpcRequest::ACTIONS`
+    limits: Vec<Option<u32>>,
+    buckets: Mutex<Buckets>
+}
+
+struct Buckets {
+    /// The tokens left, and when they were last counted, of each action and client
+    by_client: HashMap<(usize, IpAddr), (f64, Instant)>,
+    last_pruned: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a `RateLimiter` from the requests per minute allowed of each named action
+    pub fn new(limits: &[(String, u32)]) -> Result<Self, Error> {
+        let mut by_action = vec![None; RpcRequest::ACTIONS.len()];
+        for (action, per_minute) in limits {
+            let index = RpcRequest::ACTIONS.iter().position(|a| a == action)
+                .ok_or_else(|| error!("unknown RPC action: {}", action))?;
+            by_action[index] = Some(*per_minute);
+        }
+        let buckets = Buckets { by_client: HashMap::new(), last_pruned: Instant::now() };
+        Ok(Self { limits: by_action, buckets: Mutex::new(buckets) })
+    }
+
+    /// Take a token for `request` from `client`, returning whether it's within the limit
+    pub fn allow(&self, request: &RpcRequest, client: IpAddr) -> bool {
+        self.allow_at(request.action_index(), client, Instant::now())
+    }
+
+    fn allow_at(&self, action: usize, client: IpAddr, now: Instant) -> bool {
+        let Some(per_minute) = self.limits[action] else {
+            return true;
+        };
+        let capacity = per_minute as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.last_pruned) >= PRUNE_INTERVAL {
+            buckets.by_client.retain(|_, (_, last)| now.saturating_duration_since(*last) < PRUNE_INTERVAL);
+            buckets.last_pruned = now;
+        }
+        let (tokens, last) = buckets.by_client.entry((action, client_key(client))).or_insert((capacity, now));
+        let refilled = now.saturating_duration_since(*last).as_secs_f64() * capacity / 60.0;
+        *tokens = (*tokens + refilled).min(capacity);
+        *last = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/// The client `addr` is counted as: IPv4 addresses as they are, and IPv6
+/// addresses by their /64 prefix
+fn client_key(addr: IpAddr) -> IpAddr {
+    match addr.to_canonical() {
+        IpAddr::V4(v4) => IpAddr::V4(v4),
+        IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !(u64::MAX as u128))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_and_refill() {
+        let limiter = RateLimiter::new(&[("work_generate".to_string(), 6)]).unwrap();
+        let work_generate = RpcRequest::WorkGenerate(crate::keys::Hash::zero(), None).action_index();
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+        for _ in 0..6 {
+            assert!(limiter.allow_at(work_generate, a, start));
+        }
+        assert!(!limiter.allow_at(work_generate, a, start));
+        // other clients and actions have their own limits
+        assert!(limiter.allow_at(work_generate, b, start));
+        assert!(limiter.allow(&RpcRequest::IsLeader, a));
+        // a token refills every 10 seconds
+        assert!(!limiter.allow_at(work_generate, a, start + Duration::from_secs(9)));
+        assert!(limiter.allow_at(work_generate, a, start + Duration::from_secs(15)));
+        assert!(!limiter.allow_at(work_generate, a, start + Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn test_ipv6_by_prefix() {
+        let limiter = RateLimiter::new(&[("work_generate".to_string(), 1)]).unwrap();
+        let work_generate = RpcRequest::WorkGenerate(crate::keys::Hash::zero(), None).action_index();
+        let start = Instant::now();
+        assert!(limiter.allow_at(work_generate, "2001:db8::1".parse().unwrap(), start));
+        // the rest of the /64 shares the limit, but not the next /64
+        assert!(!limiter.allow_at(work_generate, "2001:db8::ffff:1:2:3".parse().unwrap(), start));
+        assert!(limiter.allow_at(work_generate, "2001:db8:0:1::1".parse().unwrap(), start));
+        // IPv4-mapped addresses count as their IPv4 address
+        assert!(limiter.allow_at(work_generate, "10.0.0.1".parse().unwrap(), start));
+        assert!(!limiter.allow_at(work_generate, "::ffff:10.0.0.1".parse().unwrap(), start));
+    }
+
+    #[test]
+    fn test_prune_idle_buckets() {
+        let limiter = RateLimiter::new(&[("work_generate".to_string(), 6)]).unwrap();
+        let work_generate = RpcRequest::WorkGenerate(crate::keys::Hash::zero(), None).action_index();
+        let start = Instant::now();
+        for i in 0..100u32 {
+            assert!(limiter.allow_at(work_generate, IpAddr::from(i.to_be_bytes()), start));
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().by_client.len(), 100);
+        // only buckets idle for a whole interval are pruned, once an interval has passed
+        let later = start + PRUNE_INTERVAL / 2;
+        assert!(limiter.allow_at(work_generate, IpAddr::from([0, 0, 0, 1]), later));
+        assert!(limiter.allow_at(work_generate, IpAddr::from([0, 0, 0, 2]), start + PRUNE_INTERVAL));
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_client.len(), 2);
+        assert_eq!(buckets.last_pruned, start + PRUNE_INTERVAL);
+    }
+
+    #[test]
+    fn test_unknown_action() {
+        assert!(RateLimiter::new(&[("work_generat".to_string(), 6)]).is_err());
+    }
+}
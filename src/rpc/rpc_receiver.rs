@@ -1,42 +1,85 @@
-use std::{io::Read, net::TcpStream};
+use std::{io::{self, Read}, net::IpAddr, sync::Arc};
 
-use crate::{error, process::{Handle, Mailbox, Message, Process}, util::{self, DefaultInitVec, Error}};
+use crate::{process::{Handle, Mailbox, Message, Process}, util::{DefaultInitVec, Error}};
 
-use super::RpcRequest;
+use super::{RateLimiter, RpcEnvelope, RpcErrorCode, RpcRequest, RpcRequestError, MAX_REQUEST_LEN};
 
-pub struct RpcReceiver {
+/// Reads requests from a stream, such as a `TcpStream` or `UnixStream`
+pub struct RpcReceiver<S: Read> {
     destination: Handle,
+    /// Answers requests about peers
+    transmitter: Handle,
     rpc_sender: Handle,
-    stream: TcpStream
+    stream: S,
+    /// Is the client allowlisted for privileged requests?
+    privileged: bool,
+    /// The rate limiter and address of the client, if its requests are limited
+    rate_limit: Option<(Arc<RateLimiter>, IpAddr)>
 }
-impl RpcReceiver {
-    pub fn new(destination: Handle, rpc_sender: Handle, stream: TcpStream) -> Self {
-        Self { destination, rpc_sender, stream }
+impl<S: Read> RpcReceiver<S> {
+    pub fn new(
+        destination: Handle,
+        transmitter: Handle,
+        rpc_sender: Handle,
+        stream: S,
+        privileged: bool,
+        rate_limit: Option<(Arc<RateLimiter>, IpAddr)>
+    ) -> Self {
+        Self { destination, transmitter, rpc_sender, stream, privileged, rate_limit }
+    }
+    fn respond_err(&self, id: u64, code: RpcErrorCode, message: String) {
+        self.rpc_sender.send(Message::RpcResponse(Box::new(RpcEnvelope::err(id, code, message))));
     }
 }
 
-impl Process for RpcReceiver {
+impl<S: Read> Process for RpcReceiver<S> {
     const NAME: &'static str = "RpcReceiver";
     const RESTART_ON_CRASH: bool = false;
     
-    fn run(&mut self, mailbox: Mailbox, _: Handle) -> Result<(), Error> {
-        let mut buf = Vec::default_init(4096);
+    fn run(&mut self, _: Mailbox, _: Handle) -> Result<(), Error> {
+        let mut buf = Vec::default_init(MAX_REQUEST_LEN);
         loop {
             let mut len = [0u8; 4];
             self.stream.read_exact(&mut len)?;
             let len = u32::from_le_bytes(len) as usize;
-            if len > buf.len() {
-                return Err(error!("too big"));
+            if len > MAX_REQUEST_LEN {
+                // read the id and skip the rest of the body, so the next request can still be read
+                self.stream.read_exact(&mut buf[..8])?;
+                io::copy(&mut (&mut self.stream).take(len as u64 - 8), &mut io::sink())?;
+                let e = RpcRequestError::BodyTooLarge(len);
+                self.respond_err(RpcRequest::parse_id(&buf[..8]), RpcErrorCode::InvalidRequest(e.clone()), e.to_string());
+                continue;
             }
             self.stream.read_exact(&mut buf[..len])?;
-            let stream = self.stream.try_clone()?;
-            let (id, cmd): (u64, RpcRequest) = match util::decode_from_slice(&buf[..len]) {
+            let (id, cmd) = match RpcRequest::parse(&buf[..len]) {
                 Ok(v) => v,
-                Err(_) => continue
+                Err(e) => {
+                    self.respond_err(RpcRequest::parse_id(&buf[..len]), RpcErrorCode::InvalidRequest(e.clone()), e.to_string());
+                    continue;
+                }
             };
-            self.destination.send(Message::RpcRequest(
-                Box::new((self.rpc_sender.clone(), id, cmd))
-            ));
+            if matches!(cmd, RpcRequest::AcceptCompression) {
+                self.rpc_sender.send(Message::RpcAcceptCompression(id));
+                continue;
+            }
+            if cmd.is_privileged() && !self.privileged {
+                self.respond_err(id, RpcErrorCode::NotPermitted, "request not permitted from this address".to_string());
+                continue;
+            }
+            if let Some((rate_limiter, client)) = &self.rate_limit {
+                if !rate_limiter.allow(&cmd, *client) {
+                    self.respond_err(id, RpcErrorCode::RateLimited, format!("rate limit exceeded for {}", cmd.action()));
+                    continue;
+                }
+            }
+            match cmd {
+                RpcRequest::Peers(page) => self.transmitter.send(Message::PeersRequest(
+                    Box::new((self.rpc_sender.clone(), id, page))
+                )),
+                cmd => self.destination.send(Message::RpcRequest(
+                    Box::new((self.rpc_sender.clone(), id, cmd))
+                ))
+            }
         }
     }
 }
\ No newline at end of file
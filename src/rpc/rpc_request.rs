@@ -1,9 +1,250 @@
-use bincode::{Decode, Encode};
+use std::{fmt, ops::Range};
 
-use crate::keys::{Difficulty, Hash, Public};
+use bincode::{error::DecodeError, Decode, Encode};
+
+use crate::{keys::{Difficulty, Hash, Public}, protocol::{Epoch, Vote}, util};
+
+/// The maximum length in bytes of a request frame
+pub const MAX_REQUEST_LEN: usize = 4096;
+/// The length of the request id and action tag which start every frame
+const HEADER_LEN: usize = 8 + 4;
+
+/// Why a request frame could not be parsed
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+pub enum RpcRequestError {
+    /// The frame ends before the request id and action
+    MissingAction,
+    /// The action tag is not a known request
+    UnknownAction(u32),
+    /// The parameters of the action with this tag are malformed
+    BadParams(u32),
+    /// The frame is longer than `MAX_REQUEST_LEN`
+    BodyTooLarge(usize),
+}
+
+impl fmt::Display for RpcRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcRequestError::MissingAction => write!(f, "missing action"),
+            RpcRequestError::UnknownAction(action) => write!(f, "unknown action {}", action),
+            RpcRequestError::BadParams(action) => write!(f, "bad params for action {}", action),
+            RpcRequestError::BodyTooLarge(len) => write!(f, "body of {} bytes exceeds the maximum of {}", len, MAX_REQUEST_LEN),
+        }
+    }
+}
+
+/// Which items of a list to respond with: up to `count` of them, or a default
+/// number if `None`, starting at `offset`
+#[derive(Encode, Decode, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub offset: u64,
+    pub count: Option<u32>
+}
+
+impl Page {
+    /// The number of items in a page which doesn't specify a count
+    pub const DEFAULT_COUNT: u32 = 256;
+
+    /// Get the range of indices of this page in a list of `total` items,
+    /// holding at most `max_count` of them
+    pub fn range(self, total: u64, max_count: u32) -> Range<u64> {
+        let count = self.count.unwrap_or(Self::DEFAULT_COUNT).min(max_count);
+        let start = self.offset.min(total);
+        start..start.saturating_add(count as u64).min(total)
+    }
+}
 
 #[derive(Encode, Decode)]
 pub enum RpcRequest {
     AccountBalance(Public),
-    WorkGenerate(Hash, Option<Difficulty>)
-}
\ No newline at end of file
+    WorkGenerate(Hash, Option<Difficulty>),
+    /// The leaders of a page of the slots of the epoch, by default the current one
+    LeaderSchedule(Option<Epoch>, Page),
+    IsLeader,
+    WorkThreshold,
+    AccountIndex(Public),
+    AccountPublic(u64),
+    BlockConfirmed(Hash),
+    /// Submit a signed vote for inclusion in the next block led by this node
+    Vote(Box<Vote>),
+    /// Receive a `Confirmation` for every finalized transfer, or only for
+    /// those from or to the given accounts
+    Subscribe(Option<Vec<Public>>),
+    Telemetry,
+    /// Answered whenever the node is running
+    Health,
+    /// Answered with an error until the node has peers and has caught up
+    Ready,
+    SyncStatus,
+    /// Advertise that the client can `decompress` responses. Responses after
+    /// the acknowledgement are compressed if the node is configured to.
+    AcceptCompression,
+    /// Describe a page of the blocks which are not yet finalized, nearest the root
+    /// first, for debugging forks
+    DagDump(Page),
+    /// List a page of the peers this node is connected to, highest weight first
+    Peers(Page),
+    /// Count the finalized and active blocks, and get the head of the longest chain
+    BlockCount,
+    /// Verify the chain export at this path on the node's host without importing it
+    VerifyImport(String)
+}
+
+impl RpcRequest {
+    /// The name of each action, indexed by its tag in a frame
+    pub const ACTIONS: [&'static str; 19] = [
+        "account_balance",
+        "work_generate",
+        "leader_schedule",
+        "is_leader",
+        "work_threshold",
+        "account_index",
+        "account_public",
+        "block_confirmed",
+        "vote",
+        "subscribe",
+        "telemetry",
+        "health",
+        "ready",
+        "sync_status",
+        "accept_compression",
+        "dag_dump",
+        "peers",
+        "block_count",
+        "verify_import",
+    ];
+    /// Get the index of this request's action in `ACTIONS`
+    pub fn action_index(&self) -> usize {
+        match self {
+            RpcRequest::AccountBalance(_) => 0,
+            RpcRequest::WorkGenerate(..) => 1,
+            RpcRequest::LeaderSchedule(..) => 2,
+            RpcRequest::IsLeader => 3,
+            RpcRequest::WorkThreshold => 4,
+            RpcRequest::AccountIndex(_) => 5,
+            RpcRequest::AccountPublic(_) => 6,
+            RpcRequest::BlockConfirmed(_) => 7,
+            RpcRequest::Vote(_) => 8,
+            RpcRequest::Subscribe(_) => 9,
+            RpcRequest::Telemetry => 10,
+            RpcRequest::Health => 11,
+            RpcRequest::Ready => 12,
+            RpcRequest::SyncStatus => 13,
+            RpcRequest::AcceptCompression => 14,
+            RpcRequest::DagDump(_) => 15,
+            RpcRequest::Peers(_) => 16,
+            RpcRequest::BlockCount => 17,
+            RpcRequest::VerifyImport(_) => 18,
+        }
+    }
+    /// Get the name of this request's action, as used to configure rate limits
+    pub fn action(&self) -> &'static str {
+        Self::ACTIONS[self.action_index()]
+    }
+    /// Parse a frame holding a request id followed by a request
+    pub fn parse(frame: &[u8]) -> Result<(u64, Self), RpcRequestError> {
+        if frame.len() > MAX_REQUEST_LEN {
+            return Err(RpcRequestError::BodyTooLarge(frame.len()));
+        }
+        if frame.len() < HEADER_LEN {
+            return Err(RpcRequestError::MissingAction);
+        }
+        let action = u32::from_le_bytes(frame[8..HEADER_LEN].try_into().unwrap());
+        util::decode_from_slice(frame).map_err(|e| match e {
+            DecodeError::UnexpectedVariant { type_name: "RpcRequest", .. } => RpcRequestError::UnknownAction(action),
+            _ => RpcRequestError::BadParams(action),
+        })
+    }
+    /// Get the request id a frame starts with, or 0 if it's too short to hold
+    /// one, so that errors can be sent in response to unparseable requests
+    pub fn parse_id(frame: &[u8]) -> u64 {
+        match frame.get(..8) {
+            Some(id) => u64::from_le_bytes(id.try_into().unwrap()),
+            None => 0,
+        }
+    }
+    /// Is this request only permitted from allowlisted addresses?
+    pub fn is_privileged(&self) -> bool {
+        matches!(self, RpcRequest::Vote(_) | RpcRequest::DagDump(_) | RpcRequest::VerifyImport(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(id: u64, request: &RpcRequest) -> Vec<u8> {
+        let mut frame = Vec::new();
+        util::encode_into_writer(&mut frame, &(id, request)).unwrap();
+        frame
+    }
+
+    #[test]
+    fn test_parse() {
+        let public = Public::random();
+        match RpcRequest::parse(&frame(7, &RpcRequest::AccountIndex(public))) {
+            Ok((7, RpcRequest::AccountIndex(p))) => assert!(p == public),
+            _ => panic!("expected the request to parse"),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(RpcRequest::parse(&[0; HEADER_LEN - 1]).err(), Some(RpcRequestError::MissingAction));
+
+        let mut unknown = frame(7, &RpcRequest::IsLeader);
+        unknown[8..HEADER_LEN].copy_from_slice(&200u32.to_le_bytes());
+        assert_eq!(RpcRequest::parse(&unknown).err(), Some(RpcRequestError::UnknownAction(200)));
+
+        // an `AccountIndex` missing most of its public key
+        let mut truncated = frame(7, &RpcRequest::AccountIndex(Public::random()));
+        truncated.truncate(HEADER_LEN + 4);
+        assert_eq!(RpcRequest::parse(&truncated).err(), Some(RpcRequestError::BadParams(5)));
+
+        // a `LeaderSchedule` whose epoch is neither `None` nor `Some`
+        let mut bad_option = frame(7, &RpcRequest::LeaderSchedule(None, Page::default()));
+        bad_option[HEADER_LEN] = 9;
+        assert_eq!(RpcRequest::parse(&bad_option).err(), Some(RpcRequestError::BadParams(2)));
+
+        let oversized = vec![0; MAX_REQUEST_LEN + 1];
+        assert_eq!(RpcRequest::parse(&oversized).err(), Some(RpcRequestError::BodyTooLarge(MAX_REQUEST_LEN + 1)));
+
+        // the id of an unparseable request is still read, when it's there
+        assert_eq!(RpcRequest::parse_id(&truncated), 7);
+        assert_eq!(RpcRequest::parse_id(&[7; 7]), 0);
+    }
+
+    #[test]
+    fn test_action_index_is_tag() {
+        let requests = [
+            RpcRequest::WorkGenerate(Hash::zero(), None),
+            RpcRequest::AccountBalance(Public::random()),
+            RpcRequest::SyncStatus,
+            RpcRequest::AcceptCompression,
+            RpcRequest::DagDump(Page::default()),
+            RpcRequest::Peers(Page::default()),
+            RpcRequest::BlockCount,
+            RpcRequest::VerifyImport(String::new()),
+        ];
+        for request in requests {
+            let frame = frame(7, &request);
+            assert_eq!(u32::from_le_bytes(frame[8..HEADER_LEN].try_into().unwrap()) as usize, request.action_index());
+        }
+        assert_eq!(RpcRequest::WorkGenerate(Hash::zero(), None).action(), "work_generate");
+    }
+
+    #[test]
+    fn test_page_range() {
+        let page = |offset, count| Page { offset, count };
+        // first, middle and last pages
+        assert_eq!(page(0, Some(10)).range(25, 100), 0..10);
+        assert_eq!(page(10, Some(10)).range(25, 100), 10..20);
+        assert_eq!(page(20, Some(10)).range(25, 100), 20..25);
+        // beyond the end
+        assert_eq!(page(25, Some(10)).range(25, 100), 25..25);
+        assert_eq!(page(u64::MAX, Some(10)).range(25, 100), 25..25);
+        // the default count, and counts above the maximum
+        assert_eq!(Page::default().range(1000, 1000), 0..Page::DEFAULT_COUNT as u64);
+        assert_eq!(page(0, Some(50)).range(1000, 20), 0..20);
+    }
+}
@@ -1,9 +1,211 @@
 use bincode::{Decode, Encode};
 
-use crate::keys::Work;
+use super::{Confirmation, RpcRequestError};
+
+use crate::{keys::{Difficulty, Hash, Public, Work}, network::Endpoint, protocol::{Amount, Slot}, util::Version};
 
 #[derive(Encode, Decode)]
 pub enum RpcResponse {
     AccountBalance(u64),
-    WorkGenerate(Work)
+    WorkGenerate(Work),
+    LeaderSchedule {
+        slots: Vec<(Slot, Public)>,
+        /// The number of slots in the epoch, or zero if its schedule is unknown
+        total: u64,
+        /// Whether there are slots after this page
+        has_more: bool
+    },
+    IsLeader {
+        leader: bool,
+        current_slot: Slot,
+        next_leader_slot: Option<Slot>
+    },
+    WorkThreshold {
+        difficulty: Difficulty,
+        multiplier: f64
+    },
+    AccountIndex(Option<u64>),
+    AccountPublic(Option<Public>),
+    /// Whether the block is finalized, or `None` if it is unknown
+    BlockConfirmed(Option<bool>),
+    /// The hash of the accepted vote
+    Vote(Hash),
+    /// The subscription was registered; confirmations follow with the same id
+    Subscribed,
+    Confirmation(Confirmation),
+    Telemetry {
+        version: Version,
+        current_slot: Slot,
+        /// The unix time in milliseconds at which the node started
+        start_time_ms: u64,
+        uptime_ms: u64,
+        /// The number of slots whose shreds were dropped before they could be assembled
+        evicted_shred_lists: u64,
+        /// The number of outbound datagrams which failed to send
+        broadcast_send_errors: u64,
+        /// The number of outbound datagrams dropped because the send queue was full
+        broadcast_dropped: u64
+    },
+    Health,
+    Ready {
+        peers: usize,
+        /// The slot of the last finalized block
+        finalized_slot: Slot
+    },
+    SyncStatus {
+        /// Whether peers have finalized more blocks than this node
+        syncing: bool,
+        /// The number of blocks finalized after genesis
+        current: u64,
+        /// The most blocks peers have reported finalizing after genesis
+        target: u64,
+        percent: f64
+    },
+    /// Whether the responses that follow are compressed; each frame then
+    /// starts with a `Compression` header byte
+    AcceptCompression(bool),
+    DagDump {
+        /// The last finalized block, from which all others follow
+        root: Hash,
+        /// The head of the longest chain
+        longest_chain: Hash,
+        /// The number of blocks, which may be more than are listed
+        total: u64,
+        /// The blocks of the page, nearest the root first
+        nodes: Vec<DagNode>,
+        /// Whether there are blocks after this page
+        has_more: bool
+    },
+    /// The peers this node is connected to, highest weight first; empty until any are found
+    Peers {
+        peers: Vec<PeerInfo>,
+        /// The number of peers, which may be more than are listed
+        total: u64,
+        /// Whether there are peers after this page
+        has_more: bool
+    },
+    /// The numbers of finalized and active blocks, which together are every block the node holds
+    BlockCount {
+        /// The number of finalized blocks, including genesis
+        finalized: u64,
+        /// The number of blocks which are not yet finalized
+        active: u64,
+        /// The head of the longest chain, which is the last finalized block if none are active
+        head: Hash,
+        /// The slot of `head`
+        slot: Slot
+    },
+    /// The outcome of verifying a chain export; nothing was imported
+    VerifyImport {
+        /// The number of blocks verified before the first failure, or in all
+        verified: u64,
+        /// The index of the first block which failed verification, and why
+        failure: Option<(u64, String)>
+    }
+}
+
+/// A block which is not yet finalized, as listed by `RpcResponse::DagDump`
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+pub struct DagNode {
+    pub hash: Hash,
+    pub previous: Hash,
+    pub slot: Slot,
+    pub leader: Public,
+    /// The number of blocks before this one since the node started, so the
+    /// root has the lowest height and each block is one above its previous
+    pub height: u64
+}
+
+/// A peer this node is connected to, as listed by `RpcResponse::Peers`
+#[derive(Encode, Decode, Clone)]
+pub struct PeerInfo {
+    pub address: Public,
+    pub endpoint: Endpoint,
+    pub weight: Amount,
+    pub version: Version,
+    /// The slot of the peer's most recent telemetry
+    pub last_contact: Slot
+}
+
+/// The reason a request failed
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+pub enum RpcErrorCode {
+    /// The request could not be parsed
+    InvalidRequest(RpcRequestError),
+    /// The request is not permitted from the client's address
+    NotPermitted,
+    /// The client or the node has the maximum number of subscriptions
+    TooManySubscriptions,
+    /// The node has no peers, or hasn't caught up to the network
+    NotReady,
+    /// The request is valid, but could not be completed
+    Failed,
+    /// The client has made too many requests of this action recently
+    RateLimited,
+    /// The node is running as many requests of this action as it allows at once
+    Busy
+}
+
+/// The envelope every response is sent in: the id of the request it answers,
+/// followed by either the response or an error code and message
+#[derive(Encode, Decode)]
+pub struct RpcEnvelope {
+    pub id: u64,
+    pub result: Result<RpcResponse, (RpcErrorCode, String)>
+}
+
+impl RpcEnvelope {
+    pub fn ok(id: u64, response: RpcResponse) -> Self {
+        Self { id, result: Ok(response) }
+    }
+    pub fn err(id: u64, code: RpcErrorCode, message: impl Into<String>) -> Self {
+        Self { id, result: Err((code, message.into())) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    fn encode(envelope: &RpcEnvelope) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        util::encode_into_writer(&mut bytes, envelope).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_ok_envelope() {
+        // id, `Ok` tag, `AccountIndex` tag, `Some` tag, index
+        let bytes = encode(&RpcEnvelope::ok(7, RpcResponse::AccountIndex(Some(3))));
+        assert_eq!(bytes.len(), 8 + 4 + 4 + 1 + 8);
+        assert_eq!(bytes[0..8], 7u64.to_le_bytes());
+        assert_eq!(bytes[8..12], 0u32.to_le_bytes());
+        let envelope: RpcEnvelope = util::decode_from_slice(&bytes).unwrap();
+        assert_eq!(envelope.id, 7);
+        assert!(matches!(envelope.result, Ok(RpcResponse::AccountIndex(Some(3)))));
+    }
+
+    #[test]
+    fn test_err_envelope() {
+        // id, `Err` tag, code tag, message length, message
+        let bytes = encode(&RpcEnvelope::err(7, RpcErrorCode::NotPermitted, "denied"));
+        assert_eq!(bytes.len(), 8 + 4 + 4 + 8 + 6);
+        assert_eq!(bytes[0..8], 7u64.to_le_bytes());
+        assert_eq!(bytes[8..12], 1u32.to_le_bytes());
+        assert_eq!(&bytes[24..], b"denied");
+
+        let e = RpcRequestError::UnknownAction(200);
+        let code = RpcErrorCode::InvalidRequest(e.clone());
+        let bytes = encode(&RpcEnvelope::err(0, code.clone(), e.to_string()));
+        let envelope: RpcEnvelope = util::decode_from_slice(&bytes).unwrap();
+        assert_eq!(envelope.id, 0);
+        match envelope.result {
+            Err((decoded, message)) => {
+                assert_eq!(decoded, code);
+                assert_eq!(message, e.to_string());
+            }
+            Ok(_) => panic!("expected an error"),
+        }
+    }
 }
\ No newline at end of file
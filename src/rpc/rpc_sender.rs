@@ -1,38 +1,89 @@
-use std::{io::Write, net::TcpStream};
+use std::io::Write;
 
-use crate::{process::{Handle, Mailbox, Message, Process}, util::{self, Error}};
+use crate::{network::{self, Compression}, process::{Handle, Mailbox, Message, Process}, util::{self, Error}};
 
-use super::RpcResponse;
+use super::{RpcEnvelope, RpcResponse};
 
-pub struct RpcSender {
-    stream: TcpStream
+/// Responses shorter than this are sent uncompressed even to clients accepting
+/// compression, behind a `Compression::None` header
+const MIN_COMPRESS_LEN: usize = 256;
+
+/// Writes responses to a stream, such as a `TcpStream` or `UnixStream`
+pub struct RpcSender<S: Write> {
+    stream: S,
+    /// How responses are compressed for clients which accept compression
+    compression: Compression,
+    /// Has the client accepted compressed responses?
+    compressing: bool
 }
 
-impl RpcSender {
-    pub fn new(stream: TcpStream) -> Self {
-        Self { stream }
+impl<S: Write> RpcSender<S> {
+    pub fn new(stream: S, compression: Compression) -> Self {
+        Self { stream, compression, compressing: false }
+    }
+    /// Write `envelope` as a frame prefixed by its length, compressing it if
+    /// the client accepted compression
+    fn write(&mut self, buf: &mut Vec<u8>, envelope: &RpcEnvelope) -> Result<(), Error> {
+        buf.clear();
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        util::encode_into_writer(buf, envelope).unwrap();
+        if self.compressing {
+            let compression = match buf.len() - 4 < MIN_COMPRESS_LEN {
+                true => Compression::None,
+                false => self.compression,
+            };
+            let compressed = network::compress(&buf[4..], compression);
+            buf.truncate(4);
+            buf.extend_from_slice(&compressed);
+        }
+        let len_bytes = (buf.len() as u32 - 4).to_le_bytes();
+        buf[0..4].copy_from_slice(&len_bytes);
+        self.stream.write_all(buf)?;
+        Ok(())
     }
 }
 
-impl Process for RpcSender {
+impl<S: Write> Process for RpcSender<S> {
     const NAME: &'static str = "RpcSender";
     const RESTART_ON_CRASH: bool = false;
-    
-    fn run(&mut self, mut mailbox: Mailbox, handle: Handle) -> Result<(), Error> {
+
+    fn run(&mut self, mut mailbox: Mailbox, _: Handle) -> Result<(), Error> {
         let mut buf = Vec::with_capacity(4096);
         loop {
-            let rpc_response = match mailbox.recv() {
-                Message::RpcResponse(v) => v,
+            match mailbox.recv() {
+                Message::RpcResponse(envelope) => self.write(&mut buf, &envelope)?,
+                Message::RpcAcceptCompression(id) => {
+                    // the acknowledgement itself is never compressed
+                    let compressing = self.compression != Compression::None;
+                    self.write(&mut buf, &RpcEnvelope::ok(id, RpcResponse::AcceptCompression(compressing)))?;
+                    self.compressing = compressing;
+                }
                 _ => continue
-            };
-            let (id, response): (u64, RpcResponse) = *rpc_response;
-            buf.extend_from_slice(&0u32.to_le_bytes());
-            util::encode_into_writer(&mut buf, &response).unwrap();
-            let len_bytes = (buf.len() as u32 - 4).to_le_bytes();
-            buf[0..4].copy_from_slice(&len_bytes);
-            self.stream.write_all(&buf)?;
+            }
         }
     }
-    
+
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_one_frame_per_response() {
+        let mut sender = RpcSender::new(Vec::new(), Compression::None);
+        let mut buf = Vec::new();
+        for id in 1..=3 {
+            sender.write(&mut buf, &RpcEnvelope::ok(id, RpcResponse::Health)).unwrap();
+        }
+        // the buffer is reused, so each frame holds only its own response
+        let mut stream = &sender.stream[..];
+        for id in 1..=3 {
+            let len = u32::from_le_bytes(stream[..4].try_into().unwrap()) as usize;
+            let envelope: RpcEnvelope = util::decode_from_slice(&stream[4..4 + len]).unwrap();
+            assert_eq!(envelope.id, id);
+            stream = &stream[4 + len..];
+        }
+        assert!(stream.is_empty());
+    }
+}
@@ -1,20 +1,49 @@
-use std::{io::ErrorKind, net::TcpListener};
+use std::{io::ErrorKind, net::{IpAddr, TcpListener}, sync::Arc};
 
-use crate::{error, process::{self, Handle, Mailbox, Process, ProcessSolitary}, util::Error};
+use socket2::{Domain, Protocol, Socket, Type};
 
-use super::{RpcReceiver, RpcSender};
+use crate::{error, network::{Compression, Endpoint}, process::{self, Handle, ProcessSolitary}, util::Error};
+
+use super::{RateLimiter, RpcReceiver, RpcSender};
 
 pub struct RpcServer {
     destination: Handle,
-    listener: TcpListener
+    /// Answers requests about peers
+    transmitter: Handle,
+    listener: TcpListener,
+    /// The addresses permitted to make privileged requests
+    allowlist: Vec<IpAddr>,
+    /// How responses are compressed for clients which accept compression
+    compression: Compression,
+    /// Limits the requests of each client address
+    rate_limiter: Arc<RateLimiter>
 }
 
 impl RpcServer {
-    pub fn new(destination: Handle, listener: TcpListener) -> Self {
-        Self { destination, listener }
+    pub fn new(
+        destination: Handle,
+        transmitter: Handle,
+        listener: TcpListener,
+        allowlist: Vec<IpAddr>,
+        compression: Compression,
+        rate_limiter: Arc<RateLimiter>
+    ) -> Self {
+        Self { destination, transmitter, listener, allowlist, compression, rate_limiter }
     }
 }
 
+/// Bind a TCP listener to `ep`, queueing up to `backlog` connections waiting to be accepted
+pub fn bind_listener(ep: Endpoint, backlog: u32) -> Result<TcpListener, Error> {
+    let addr = ep.to_socket_addr();
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    // like `TcpListener::bind`, allow rebinding while old connections linger
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
+    Ok(socket.into())
+}
+
 /// Is this `ErrorKind` fatal for the TCP listening socket?
 fn is_tcp_fatal(kind: ErrorKind) -> bool {
     // For reliable operation the application should detect the network
@@ -59,17 +88,169 @@ impl ProcessSolitary for RpcServer {
                 Err(e) if is_tcp_fatal(e.kind()) => return Err(e.into()),
                 _ => continue
             };
+            let Ok(client) = stream.peer_addr().map(|addr| addr.ip()) else {
+                continue;
+            };
+            let privileged = self.allowlist.contains(&client);
             let rpc_sender = process::spawn(RpcSender::new(match stream.try_clone() {
                 Ok(stream) => stream,
                 Err(_) => continue
-            }));
+            }, self.compression));
             process::spawn(RpcReceiver::new(
                 self.destination.clone(),
+                self.transmitter.clone(),
                 rpc_sender,
-                stream
+                stream,
+                privileged,
+                Some((self.rate_limiter.clone(), client))
             ));
         }
         Err(error!("tcp listener finished"))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::{Read, Write}, net::TcpStream, time::Duration};
+    use crate::{keys::{Hash, Public}, network, process::Message, protocol::Slot, rpc::{Page, RpcEnvelope, RpcErrorCode, RpcRequest, RpcRequestError, RpcResponse, MAX_REQUEST_LEN}, util};
+
+    fn send(client: &mut TcpStream, id: u64, request: RpcRequest) {
+        let mut frame = vec![0u8; 4];
+        util::encode_into_writer(&mut frame, &(id, request)).unwrap();
+        let len = (frame.len() as u32 - 4).to_le_bytes();
+        frame[0..4].copy_from_slice(&len);
+        client.write_all(&frame).unwrap();
+    }
+
+    fn recv(client: &mut TcpStream) -> Vec<u8> {
+        let mut len = [0u8; 4];
+        client.read_exact(&mut len).unwrap();
+        let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+        client.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_bind_listener_with_backlog() {
+        // port 0 binds any free port
+        let listener = bind_listener(Endpoint { addr: [127, 0, 0, 1], port: 0 }, 4096).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).unwrap();
+        assert!(listener.accept().is_ok());
+    }
+
+    #[test]
+    fn test_compress_responses() {
+        let listener = bind_listener(Endpoint { addr: [127, 0, 0, 1], port: 0 }, 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (destination, mut mailbox) = process::channel();
+        let (transmitter, _peers) = process::channel();
+        process::spawn_solitary(RpcServer::new(destination, transmitter, listener, vec![], Compression::default(), Arc::new(RateLimiter::new(&[]).unwrap())));
+        let schedule: Vec<(Slot, Public)> = vec![(Slot::zero(), Public::random()); 100];
+        let mut answer = |client: &mut TcpStream, id: u64| {
+            send(client, id, RpcRequest::LeaderSchedule(None, Page::default()));
+            // answer the request in place of the `State`
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::RpcRequest(v)) => {
+                    let (rpc_sender, id, _) = *v;
+                    rpc_sender.send(Message::RpcResponse(Box::new(RpcEnvelope::ok(id, RpcResponse::LeaderSchedule { slots: schedule.clone(), total: 100, has_more: false }))));
+                }
+                _ => panic!("expected the request to be forwarded"),
+            }
+            recv(client)
+        };
+
+        // clients which don't accept compression get plain responses
+        let mut plain = TcpStream::connect(addr).unwrap();
+        let plain_frame = answer(&mut plain, 1);
+        let envelope: RpcEnvelope = util::decode_from_slice(&plain_frame).unwrap();
+        assert!(matches!(envelope.result, Ok(RpcResponse::LeaderSchedule { slots, .. }) if slots.len() == 100));
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        send(&mut client, 2, RpcRequest::AcceptCompression);
+        let envelope: RpcEnvelope = util::decode_from_slice(&recv(&mut client)).unwrap();
+        assert_eq!(envelope.id, 2);
+        assert!(matches!(envelope.result, Ok(RpcResponse::AcceptCompression(true))));
+        let frame = answer(&mut client, 3);
+        assert!(frame.len() < plain_frame.len());
+        let envelope: RpcEnvelope = util::decode_from_slice(&network::decompress(&frame, None).unwrap()).unwrap();
+        assert_eq!(envelope.id, 3);
+        assert!(matches!(envelope.result, Ok(RpcResponse::LeaderSchedule { slots, .. }) if slots == schedule));
+    }
+
+    #[test]
+    fn test_rate_limit_action() {
+        let listener = bind_listener(Endpoint { addr: [127, 0, 0, 1], port: 0 }, 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (destination, mut mailbox) = process::channel();
+        let rate_limiter = Arc::new(RateLimiter::new(&[("work_generate".to_string(), 3)]).unwrap());
+        let (transmitter, _peers) = process::channel();
+        process::spawn_solitary(RpcServer::new(destination, transmitter, listener, vec![], Compression::None, rate_limiter));
+        let mut client = TcpStream::connect(addr).unwrap();
+        for id in 0..5 {
+            send(&mut client, id, RpcRequest::WorkGenerate(Hash::zero(), None));
+            send(&mut client, 100 + id, RpcRequest::AccountBalance(Public::random()));
+        }
+        // all balance requests and the first 3 work requests are forwarded
+        let mut forwarded = Vec::new();
+        while let Some(Message::RpcRequest(v)) = mailbox.recv_timeout(Duration::from_millis(200)) {
+            forwarded.push(v.1);
+        }
+        assert_eq!(forwarded, vec![0, 100, 1, 101, 2, 102, 103, 104]);
+        for id in [3, 4] {
+            let envelope: RpcEnvelope = util::decode_from_slice(&recv(&mut client)).unwrap();
+            assert_eq!(envelope.id, id);
+            assert!(matches!(envelope.result, Err((RpcErrorCode::RateLimited, _))));
+        }
+    }
+
+    #[test]
+    fn test_invalid_request_errors_echo_id() {
+        let listener = bind_listener(Endpoint { addr: [127, 0, 0, 1], port: 0 }, 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (destination, _mailbox) = process::channel();
+        let (transmitter, _peers) = process::channel();
+        process::spawn_solitary(RpcServer::new(destination, transmitter, listener, vec![], Compression::None, Arc::new(RateLimiter::new(&[]).unwrap())));
+        let mut client = TcpStream::connect(addr).unwrap();
+        let raw_frame = |id: u64, len: usize| {
+            let mut frame = (len as u32).to_le_bytes().to_vec();
+            frame.extend_from_slice(&id.to_le_bytes());
+            frame.resize(4 + len, 0xff);
+            frame
+        };
+        // an unknown action, then a body too large to read
+        client.write_all(&raw_frame(9, 16)).unwrap();
+        client.write_all(&raw_frame(10, MAX_REQUEST_LEN + 1)).unwrap();
+        for (id, error) in [(9, RpcRequestError::UnknownAction(u32::MAX)), (10, RpcRequestError::BodyTooLarge(MAX_REQUEST_LEN + 1))] {
+            let envelope: RpcEnvelope = util::decode_from_slice(&recv(&mut client)).unwrap();
+            assert_eq!(envelope.id, id);
+            assert!(matches!(envelope.result, Err((RpcErrorCode::InvalidRequest(e), _)) if e == error));
+        }
+    }
+
+    #[test]
+    fn test_route_peers_to_transmitter() {
+        let listener = bind_listener(Endpoint { addr: [127, 0, 0, 1], port: 0 }, 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (destination, mut mailbox) = process::channel();
+        let (transmitter, mut peers) = process::channel();
+        process::spawn_solitary(RpcServer::new(destination, transmitter, listener, vec![], Compression::None, Arc::new(RateLimiter::new(&[]).unwrap())));
+        let mut client = TcpStream::connect(addr).unwrap();
+        send(&mut client, 7, RpcRequest::Peers(Page::default()));
+        // answer the request in place of the `Transmitter`
+        match peers.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::PeersRequest(v)) => {
+                let (rpc_sender, id, page) = *v;
+                assert!(id == 7 && page == Page::default());
+                let peers = RpcResponse::Peers { peers: vec![], total: 0, has_more: false };
+                rpc_sender.send(Message::RpcResponse(Box::new(RpcEnvelope::ok(id, peers))));
+            }
+            _ => panic!("expected the request to be routed to the transmitter"),
+        }
+        let envelope: RpcEnvelope = util::decode_from_slice(&recv(&mut client)).unwrap();
+        assert_eq!(envelope.id, 7);
+        assert!(matches!(envelope.result, Ok(RpcResponse::Peers { peers, .. }) if peers.is_empty()));
+        assert!(mailbox.recv_timeout(Duration::from_millis(50)).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+
+use bincode::{Decode, Encode};
+
+use crate::{error, keys::{Hash, Public}, process::{Handle, Message}, protocol::{Amount, Task}, util::Error};
+
+use super::{RpcEnvelope, RpcResponse};
+
+/// What a finalized task did
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConfirmationKind {
+    /// `amount` was transferred from `from` to `to`
+    Transfer,
+    /// `from` changed its representative to `to`, transferring nothing
+    ChangeRepresentative,
+    /// `from` was opened, represented by `to`
+    Open
+}
+
+/// A finalized task, as sent to subscribers
+#[derive(Encode, Decode, Clone)]
+pub struct Confirmation {
+    /// The block the task was finalized in
+    pub block: Hash,
+    pub kind: ConfirmationKind,
+    pub from: Public,
+    pub to: Public,
+    /// The amount `to` received, after the burn
+    pub amount: Amount,
+    /// The amount burned as the open fee of `to`, which `from` sent on top of `amount`
+    pub burn: Amount
+}
+
+impl Confirmation {
+    /// The confirmation of `task`, from `from` to `to`, finalized in `block`
+    pub fn of_task(block: Hash, from: Public, to: Public, task: &Task) -> Self {
+        let kind = match (task.open, task.is_change_representative()) {
+            (true, _) => ConfirmationKind::Open,
+            (false, true) => ConfirmationKind::ChangeRepresentative,
+            (false, false) => ConfirmationKind::Transfer,
+        };
+        let amount = task.amount.checked_sub(task.burn).unwrap_or(Amount::zero());
+        Self { block, kind, from, to, amount, burn: task.burn }
+    }
+}
+
+struct Subscriber {
+    rpc_sender: Handle,
+    /// The id of the subscribe request, which confirmations are sent as responses to
+    id: u64,
+    /// The accounts whose confirmations are sent, or `None` for all accounts
+    accounts: Option<HashSet<Public>>
+}
+
+impl Subscriber {
+    fn wants(&self, confirmation: &Confirmation) -> bool {
+        self.accounts.as_ref().is_none_or(|accounts| {
+            accounts.contains(&confirmation.from) || accounts.contains(&confirmation.to)
+        })
+    }
+}
+
+/// The clients subscribed to confirmations, each optionally filtered to a set of accounts
+pub struct Subscriptions {
+    subscribers: Vec<Subscriber>,
+    /// The maximum number of subscriptions of a single connection
+    max_per_connection: usize,
+    /// The maximum number of subscriptions of all connections
+    max_total: usize
+}
+
+impl Subscriptions {
+    pub fn new(max_per_connection: usize, max_total: usize) -> Self {
+        Self { subscribers: Vec::new(), max_per_connection, max_total }
+    }
+    /// Send confirmations to `rpc_sender` as responses to request `id`,
+    /// only for transfers from or to `accounts` if given.
+    /// Fails if the connection or the node has too many subscriptions.
+    pub fn subscribe(&mut self, rpc_sender: Handle, id: u64, accounts: Option<Vec<Public>>) -> Result<(), Error> {
+        self.subscribers.retain(|subscriber| !subscriber.rpc_sender.is_disconnected());
+        if self.subscribers.len() >= self.max_total {
+            return Err(error!("the node has the maximum of {} subscriptions", self.max_total));
+        }
+        let of_connection = self.subscribers
+            .iter()
+            .filter(|subscriber| subscriber.rpc_sender.same_process(&rpc_sender))
+            .count();
+        if of_connection >= self.max_per_connection {
+            return Err(error!("the connection has the maximum of {} subscriptions", self.max_per_connection));
+        }
+        self.subscribers.push(Subscriber {
+            rpc_sender,
+            id,
+            accounts: accounts.map(|accounts| accounts.into_iter().collect())
+        });
+        Ok(())
+    }
+    /// Send `confirmation` to the subscribers wanting it, dropping those which have disconnected
+    pub fn dispatch(&mut self, confirmation: &Confirmation) {
+        self.subscribers.retain(|subscriber| !subscriber.rpc_sender.is_disconnected());
+        for subscriber in self.subscribers.iter().filter(|subscriber| subscriber.wants(confirmation)) {
+            subscriber.rpc_sender.send(Message::RpcResponse(Box::new(
+                RpcEnvelope::ok(subscriber.id, RpcResponse::Confirmation(confirmation.clone()))
+            )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process;
+    use std::time::Duration;
+
+    fn transfer(amount: u64, burn: u64) -> Task {
+        Task { nonce: 0, from_index: 1, amount: Amount::from_raw(amount), to_index: 2, burn: Amount::from_raw(burn), open: false }
+    }
+
+    #[test]
+    fn test_confirmation_of_task() {
+        let (from, to) = (Public::random(), Public::random());
+        let confirmation = Confirmation::of_task(Hash::random(), from, to, &transfer(10, 3));
+        assert_eq!(confirmation.kind, ConfirmationKind::Transfer);
+        assert!(confirmation.amount == Amount::from_raw(7) && confirmation.burn == Amount::from_raw(3));
+        let confirmation = Confirmation::of_task(Hash::random(), from, to, &transfer(0, 0));
+        assert_eq!(confirmation.kind, ConfirmationKind::ChangeRepresentative);
+        assert!(confirmation.amount == Amount::zero() && confirmation.burn == Amount::zero());
+        let confirmation = Confirmation::of_task(Hash::random(), from, to, &Task { open: true, ..transfer(0, 0) });
+        assert_eq!(confirmation.kind, ConfirmationKind::Open);
+    }
+
+    #[test]
+    fn test_filter_by_account() {
+        let (alice, bob, carol) = (Public::random(), Public::random(), Public::random());
+        let (alice_sender, mut alice_mailbox) = process::channel();
+        let (bob_sender, mut bob_mailbox) = process::channel();
+        let mut subscriptions = Subscriptions::new(4, 16);
+        subscriptions.subscribe(alice_sender, 1, Some(vec![alice])).unwrap();
+        subscriptions.subscribe(bob_sender, 2, Some(vec![bob])).unwrap();
+
+        let confirmation = |from, to| Confirmation::of_task(Hash::random(), from, to, &transfer(1, 0));
+        subscriptions.dispatch(&confirmation(alice, carol));
+        subscriptions.dispatch(&confirmation(carol, bob));
+        subscriptions.dispatch(&confirmation(carol, carol));
+
+        for (mailbox, id, account) in [(&mut alice_mailbox, 1, alice), (&mut bob_mailbox, 2, bob)] {
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::RpcResponse(envelope)) => {
+                    assert_eq!(envelope.id, id);
+                    match envelope.result {
+                        Ok(RpcResponse::Confirmation(c)) => assert!(c.from == account || c.to == account),
+                        _ => panic!("expected a confirmation"),
+                    }
+                }
+                _ => panic!("expected a confirmation"),
+            }
+            assert!(mailbox.recv_timeout(Duration::from_millis(10)).is_none());
+        }
+    }
+
+    #[test]
+    fn test_unfiltered_and_disconnected() {
+        let (sender, mut mailbox) = process::channel();
+        let (gone, gone_mailbox) = process::channel();
+        drop(gone_mailbox);
+        let mut subscriptions = Subscriptions::new(4, 16);
+        subscriptions.subscribe(sender, 1, None).unwrap();
+        subscriptions.subscribe(gone, 2, None).unwrap();
+        let confirmation = Confirmation::of_task(Hash::random(), Public::random(), Public::random(), &transfer(1, 0));
+        subscriptions.dispatch(&confirmation);
+        assert!(matches!(mailbox.recv_timeout(Duration::from_secs(1)), Some(Message::RpcResponse(_))));
+        assert_eq!(subscriptions.subscribers.len(), 1);
+    }
+
+    #[test]
+    fn test_subscription_caps() {
+        let (a, _a_mailbox) = process::channel();
+        let (b, _b_mailbox) = process::channel();
+        let (c, _c_mailbox) = process::channel();
+        let mut subscriptions = Subscriptions::new(2, 3);
+        subscriptions.subscribe(a.clone(), 1, None).unwrap();
+        subscriptions.subscribe(a.clone(), 2, Some(vec![Public::random()])).unwrap();
+        let e = subscriptions.subscribe(a, 3, None).unwrap_err();
+        assert!(e.as_str().contains("connection has the maximum of 2"));
+
+        // other connections are unaffected, up to the global cap
+        subscriptions.subscribe(b, 1, None).unwrap();
+        let e = subscriptions.subscribe(c, 1, None).unwrap_err();
+        assert!(e.as_str().contains("node has the maximum of 3"));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs,
+    os::unix::{fs::{DirBuilderExt, FileTypeExt, PermissionsExt}, net::UnixListener},
+    path::Path,
+};
+
+use crate::{error, network::Compression, process::{self, Handle, ProcessSolitary}, util::Error};
+
+use super::{RpcReceiver, RpcSender};
+
+/// Serves RPC over a Unix domain socket, for clients on the same host.
+/// Access is restricted to the node's user by the permissions of the
+/// socket file, so all requests are privileged and none are rate limited.
+pub struct UnixRpcServer {
+    destination: Handle,
+    /// Answers requests about peers
+    transmitter: Handle,
+    listener: UnixListener,
+    /// How responses are compressed for clients which accept compression
+    compression: Compression
+}
+
+impl UnixRpcServer {
+    /// Listen on a socket at `path`, replacing any socket left there by a previous run
+    pub fn bind(destination: Handle, transmitter: Handle, path: &str, compression: Compression) -> Result<Self, Error> {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+            Ok(_) => return Err(error!("{} exists and is not a socket", path)),
+            Err(_) => {}
+        }
+        // bind inside a directory only we can enter, so the socket can't be
+        // connected to before its permissions are restricted
+        let staging = format!("{}.bind", path);
+        let _ = fs::remove_dir_all(&staging);
+        fs::DirBuilder::new().mode(0o700).create(&staging)?;
+        let result = Self::bind_staged(&staging, path);
+        let _ = fs::remove_dir_all(&staging);
+        Ok(Self { destination, transmitter, listener: result?, compression })
+    }
+
+    /// Bind a socket in `staging`, restrict it to our user, then move it to `path`
+    fn bind_staged(staging: &str, path: &str) -> Result<UnixListener, Error> {
+        let staged = Path::new(staging).join("socket");
+        let listener = UnixListener::bind(&staged)?;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    }
+}
+
+impl ProcessSolitary for UnixRpcServer {
+    const NAME: &'static str = "UnixRpcServer";
+    const RESTART_ON_CRASH: bool = true;
+
+    fn run(&mut self) -> Result<(), Error> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue
+            };
+            let rpc_sender = process::spawn(RpcSender::new(match stream.try_clone() {
+                Ok(stream) => stream,
+                Err(_) => continue
+            }, self.compression));
+            process::spawn(RpcReceiver::new(
+                self.destination.clone(),
+                self.transmitter.clone(),
+                rpc_sender,
+                stream,
+                true,
+                None
+            ));
+        }
+        Err(error!("unix listener finished"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::Hash, process::Message, rpc::{RpcEnvelope, RpcRequest, RpcResponse}, util};
+    use std::{io::{Read, Write}, os::unix::net::UnixStream, time::Duration};
+
+    #[test]
+    fn test_serve_over_unix_socket() {
+        let path = format!("{}/starlight_rpc_{}.sock", std::env::temp_dir().display(), Hash::random());
+        let (destination, mut mailbox) = process::channel();
+        let (transmitter, _peers) = process::channel();
+        process::spawn_solitary(UnixRpcServer::bind(destination, transmitter, &path, Compression::None).unwrap());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(fs::metadata(format!("{}.bind", path)).is_err());
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        let mut frame = vec![0u8; 4];
+        util::encode_into_writer(&mut frame, &(7u64, RpcRequest::IsLeader)).unwrap();
+        let len = (frame.len() as u32 - 4).to_le_bytes();
+        frame[0..4].copy_from_slice(&len);
+        client.write_all(&frame).unwrap();
+
+        // answer the request in place of the `State`
+        match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::RpcRequest(v)) => {
+                let (rpc_sender, id, request) = *v;
+                assert_eq!(id, 7);
+                assert!(matches!(request, RpcRequest::IsLeader));
+                rpc_sender.send(Message::RpcResponse(Box::new(RpcEnvelope::ok(id, RpcResponse::AccountIndex(None)))));
+            }
+            _ => panic!("expected the request to be forwarded"),
+        }
+
+        let mut len = [0u8; 4];
+        client.read_exact(&mut len).unwrap();
+        let mut buf = vec![0u8; u32::from_le_bytes(len) as usize];
+        client.read_exact(&mut buf).unwrap();
+        let envelope: RpcEnvelope = util::decode_from_slice(&buf).unwrap();
+        assert_eq!(envelope.id, 7);
+        assert!(matches!(envelope.result, Ok(RpcResponse::AccountIndex(None))));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -2,17 +2,25 @@ use std::sync::atomic::AtomicU64;
 
 use crate::{
     keys::Public,
-    protocol::{Amount, Slot}, util::Atomic,
+    protocol::Amount, static_assert, util::Atomic,
 };
 
 use super::Batch;
 
+/// The version of the layout of `Account`, as stored raw in the `Bank`.
+/// Must be incremented whenever the fields of `Account` change.
+pub const ACCOUNT_LAYOUT_VERSION: u32 = 2;
+
 #[derive(Debug)]
+#[repr(C)]
 pub struct Account {
     pub batch: Atomic<Batch>,
     pub latest_balance: Atomic<Amount>,
     pub finalized_balance: Atomic<Amount>,
     pub weight: Atomic<Amount>,
     pub nonce: AtomicU64,
-    pub rep_index: AtomicU64
+    pub rep_index: AtomicU64,
+    pub public: Public
 }
+
+static_assert!(std::mem::size_of::<Account>() == 80);
@@ -0,0 +1,114 @@
+use crate::{keys::Public, storage::{self, Database, Flush}, util::Error};
+
+/// Translates between the public keys of accounts and their indices in the `Bank`.
+pub struct AccountIndex {
+    /// The index of each account by public key
+    indices: Database<Public, u64>,
+    /// The public key of each account by index
+    publics: Database<u64, Public>,
+    /// The final nonce of each pruned account by public key
+    retired: Database<Public, u64>,
+}
+
+impl AccountIndex {
+    /// Opens the `AccountIndex` of `data_dir`. Data directories written before
+    /// public keys were indexed have their reverse index rebuilt.
+    pub fn open(data_dir: &str) -> Result<Self, Error> {
+        let indices = Database::open(&storage::db_dir(data_dir))?;
+        let publics = Database::open(&storage::publics_dir(data_dir))?;
+        let retired = Database::open(&storage::retired_dir(data_dir))?;
+        if publics.len() < indices.len() {
+            indices.for_each(|public, index| publics.put(&index, &public));
+        }
+        Ok(Self { indices, publics, retired })
+    }
+
+    /// Get the number of indexed accounts
+    pub fn len(&self) -> u64 {
+        self.indices.len()
+    }
+
+    /// Record that the account of `public` is at `index`
+    pub fn insert(&self, public: &Public, index: u64) {
+        self.indices.put(public, &index);
+        self.publics.put(&index, public);
+    }
+
+    /// Get the index of the account of `public`, or `None` if it does not exist
+    pub fn index_of(&self, public: &Public) -> Option<u64> {
+        self.indices.get(public)
+    }
+
+    /// Get the public key of the account at `index`, or `None` if it does not exist
+    pub fn public_of(&self, index: u64) -> Option<Public> {
+        self.publics.get(&index)
+    }
+
+    /// Forget the account at `index`, as when its open is reverted
+    pub fn remove(&self, index: u64) {
+        if let Some(public) = self.publics.get(&index) {
+            self.indices.remove(&public);
+            self.publics.remove(&index);
+        }
+    }
+
+    /// Forget the account at `index`, which was pruned with `nonce`. The nonce is
+    /// kept so that the transactions it signed can't be replayed if it reopens.
+    pub fn retire(&self, index: u64, nonce: u64) {
+        if let Some(public) = self.publics.get(&index) {
+            self.remove(index);
+            self.retired.put(&public, &nonce);
+        }
+    }
+
+    /// Get the nonce the account of `public` had when it was pruned, or `None`
+    /// if it never was
+    pub fn retired_nonce(&self, public: &Public) -> Option<u64> {
+        self.retired.get(public)
+    }
+}
+
+impl Flush for AccountIndex {
+    fn flush(&self) -> Result<(), Error> {
+        self.indices.flush()?;
+        self.publics.flush()?;
+        self.retired.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Hash;
+
+    #[test]
+    fn test_resolve_both_ways() {
+        let dir = format!("{}/starlight_account_index_{}", std::env::temp_dir().display(), Hash::random());
+        let publics: Vec<Public> = (0..5).map(|_| Public::random()).collect();
+        let accounts = AccountIndex::open(&dir).unwrap();
+        for (index, public) in publics.iter().enumerate() {
+            accounts.insert(public, index as u64);
+        }
+        assert_eq!(accounts.len(), 5);
+        for (index, public) in publics.iter().enumerate() {
+            assert_eq!(accounts.index_of(public), Some(index as u64));
+            assert_eq!(accounts.public_of(index as u64), Some(*public));
+        }
+        assert_eq!(accounts.index_of(&Public::random()), None);
+        assert_eq!(accounts.public_of(5), None);
+
+        // a retired account is forgotten, but its nonce is kept
+        accounts.retire(4, 7);
+        assert_eq!(accounts.index_of(&publics[4]), None);
+        assert_eq!(accounts.public_of(4), None);
+        assert_eq!(accounts.retired_nonce(&publics[4]), Some(7));
+        assert_eq!(accounts.retired_nonce(&publics[3]), None);
+        drop(accounts);
+
+        // the reverse index is rebuilt if it is missing
+        std::fs::remove_dir_all(storage::publics_dir(&dir)).unwrap();
+        let accounts = AccountIndex::open(&dir).unwrap();
+        assert_eq!(accounts.public_of(3), Some(publics[3]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
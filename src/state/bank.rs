@@ -1,51 +1,306 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::HashSet;
+use std::sync::{atomic::{AtomicU64, Ordering}, Mutex, RwLock};
 
-use super::{Account, Batch};
+use super::{account::ACCOUNT_LAYOUT_VERSION, Account, Batch, IndexFactory, StateTree};
+use crate::keys::{Hash, Public};
 use crate::protocol::{Amount, Task};
-use crate::storage::ListStore;
+use crate::storage::{Flush, ListStore, ObjectStore};
+use crate::error;
 use crate::util::{Atomic, Error};
 
-pub struct Bank(ListStore<Account>);
+/// The `rep_index` marking a vacant account slot, whose account was pruned or whose
+/// open was reverted, which may be reused
+const VACANT: u64 = u64::MAX;
+
+pub struct Bank {
+    /// The accounts, which are only added and vacated under the write lock
+    accounts: RwLock<ListStore<Account>>,
+    /// The indices of accounts, reusing those of pruned accounts
+    indices: Mutex<IndexFactory>,
+    /// The total amount burned by finalized tasks
+    burned: Mutex<ObjectStore<Amount>>,
+    /// The merkle tree over the hashes of all accounts, updated as they change
+    tree: Mutex<StateTree>,
+}
 
 impl Bank {
-    pub fn open(dir: &str) -> Result<Self, Error> {
-        Ok(Self(ListStore::open(&dir)?))
+    #[cfg(test)]
+    /// Opens the `Bank` in `dir`. If it has no accounts, it is bootstrapped with
+    /// the account of `genesis` at index 0, followed by one account per entry of
+    /// `genesis_allocations`, funded with that amount. The genesis account receives
+    /// the remainder of the initial supply, and represents every bootstrapped account.
+    pub fn open(dir: &str, genesis: Public, genesis_allocations: &[(Public, Amount)]) -> Result<Self, Error> {
+        Self::open_with_representatives(dir, genesis, genesis_allocations, &[])
+    }
+
+    /// Like `open`, but also bootstraps one self-representing account per entry of
+    /// `genesis_representatives` after the allocations, funded with that amount so
+    /// that it's its weight. The genesis account represents the rest of the supply.
+    pub fn open_with_representatives(
+        dir: &str,
+        genesis: Public,
+        genesis_allocations: &[(Public, Amount)],
+        genesis_representatives: &[(Public, Amount)]
+    ) -> Result<Self, Error> {
+        let mut bank = Self {
+            accounts: RwLock::new(ListStore::open(dir)?),
+            indices: Mutex::new(IndexFactory::new(0, [])),
+            burned: Mutex::new(ObjectStore::open(&format!("{}/burned", dir), Amount::zero())?),
+            tree: Mutex::new(StateTree::new(vec![])),
+        };
+        Self::check_layout(dir, bank.len())?;
+        if bank.len() == 0 {
+            bank.bootstrap(genesis, genesis_allocations, genesis_representatives)?;
+        }
+        let vacant = (0..bank.len())
+            .filter(|&i| bank.get(i).unwrap().rep_index.load(Ordering::Relaxed) == VACANT);
+        bank.indices = Mutex::new(IndexFactory::new(bank.len(), vacant));
+        let leaves = (0..bank.len()).map(|i| bank.account_hash(i)).collect();
+        bank.tree = Mutex::new(StateTree::new(leaves));
+        Ok(bank)
+    }
+
+    /// Check that the accounts in `dir` were written with the current `Account` layout,
+    /// recording it if `dir` doesn't have a layout yet. Stores written before layouts
+    /// were recorded have the layout of version 1.
+    fn check_layout(dir: &str, len: u64) -> Result<(), Error> {
+        let path = format!("{}/layout", dir);
+        let current = format!("{} {}", ACCOUNT_LAYOUT_VERSION, std::mem::size_of::<Account>());
+        match std::fs::read_to_string(&path) {
+            Ok(layout) if layout.trim() == current => Ok(()),
+            Ok(layout) => Err(error!(
+                "bank in {} has account layout {}, but this node uses account layout {}",
+                dir,
+                layout.trim(),
+                current
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && len > 0 => Err(error!(
+                "bank in {} has account layout 1, but this node uses account layout {}",
+                dir,
+                current
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                std::fs::write(&path, format!("{}\n", current))?;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn bootstrap(
+        &mut self,
+        genesis: Public,
+        genesis_allocations: &[(Public, Amount)],
+        genesis_representatives: &[(Public, Amount)]
+    ) -> Result<(), Error> {
+        let sum = |allocations: &[(Public, Amount)]| allocations
+            .iter()
+            .try_fold(0u64, |acc, (_, x)| acc.checked_add(x.to_raw()));
+        let represented = sum(genesis_representatives);
+        let allocated = sum(genesis_allocations)
+            .zip(represented)
+            .and_then(|(a, b)| a.checked_add(b))
+            .filter(|&x| x <= Amount::initial_supply().to_raw())
+            .ok_or_else(|| error!("genesis allocations exceed the initial supply"))?;
+        let remainder = Amount::initial_supply() - Amount::from_raw(allocated);
+        let genesis_weight = Amount::initial_supply() - Amount::from_raw(represented.unwrap());
+        let accounts = self.accounts.get_mut().unwrap();
+        accounts.push(new_account(genesis, remainder, genesis_weight, 0));
+        for &(public, amount) in genesis_allocations {
+            accounts.push(new_account(public, amount, Amount::zero(), 0));
+        }
+        for &(public, amount) in genesis_representatives {
+            let index = accounts.len();
+            accounts.push(new_account(public, amount, amount, index));
+        }
+        Ok(())
     }
 
     /// Get the number of accounts in the `Bank`
     pub fn len(&self) -> u64 {
-        self.0.len()
+        self.accounts.read().unwrap().len()
+    }
+
+    /// Get the account at `index`, or `None` if it does not exist
+    pub fn get(&self, index: u64) -> Option<&Account> {
+        let accounts = self.accounts.read().unwrap();
+        // safety: the store only grows by mapping more files, so an account stays where it
+        // is for as long as the `Bank` is open; a slot is only rewritten once it's vacant,
+        // when its account can no longer be found through the `AccountIndex`
+        accounts.get(index).map(|account| unsafe { &*(account as *const Account) })
+    }
+
+    /// Add a new empty account of `public` with representative given by `rep_index`,
+    /// or self-delegating if `None`, and return its index. The lowest vacant slot
+    /// is reused if there is one.
+    pub fn add_account(&self, public: Public, rep_index: Option<u64>) -> Result<u64, Error> {
+        let mut indices = self.indices.lock().unwrap();
+        if let Some(rep_index) = rep_index.filter(|&i| !indices.is_allocated(i)) {
+            return Err(error!("representative {} does not exist", rep_index));
+        }
+        let index = indices.next();
+        let account = new_account(public, Amount::zero(), Amount::zero(), rep_index.unwrap_or(index));
+        let mut accounts = self.accounts.write().unwrap();
+        match accounts.get_mut(index) {
+            Some(vacant) => {
+                *vacant = account;
+                drop(accounts);
+                self.touch(index);
+            }
+            None => {
+                accounts.push(account);
+                drop(accounts);
+                self.tree.lock().unwrap().push(self.account_hash(index));
+            }
+        }
+        Ok(index)
+    }
+
+    /// Vacate the slot of the account at `index`, to be reused. Vacant slots at the end
+    /// are dropped, so that the accounts are the same whatever order they were vacated in.
+    /// The genesis account is never vacated.
+    fn vacate(&self, index: u64) {
+        let mut indices = self.indices.lock().unwrap();
+        let mut accounts = self.accounts.write().unwrap();
+        *accounts.get_mut(index).unwrap() = new_account(Public::zero(), Amount::zero(), Amount::zero(), VACANT);
+        indices.free(index);
+        let mut tree = self.tree.lock().unwrap();
+        while accounts.len() > 1 && accounts.get(accounts.len() - 1).unwrap().rep_index.load(Ordering::Relaxed) == VACANT {
+            accounts.pop();
+            indices.prev();
+            tree.pop();
+        }
+        let len = accounts.len();
+        drop((accounts, tree));
+        if index < len {
+            self.touch(index);
+        }
+    }
+
+    /// Build the task opening an account of `public` represented by `rep_index`, or
+    /// self-delegating if `None`, whose nonce starts at `nonce`. The account is added
+    /// right away, so that the tasks after it can refer to it, and is vacated again
+    /// if the task is reverted.
+    pub fn open_task(&self, public: Public, rep_index: Option<u64>, nonce: u64) -> Result<Task, Error> {
+        let from_index = self.add_account(public, rep_index)?;
+        self.get(from_index).unwrap().nonce.store(nonce, Ordering::Relaxed);
+        self.touch(from_index);
+        let to_index = rep_index.unwrap_or(from_index);
+        Ok(Task { nonce, from_index, amount: Amount::zero(), to_index, burn: Amount::zero(), open: true })
     }
 
-    /// Add a new empty account with representative given by `rep_index`,
-    /// and return its index
-    pub fn add_account(&mut self) -> u64 {
-        let index = self.0.len();
-        self.0.push(Account {
-            latest_balance: Atomic::new(Amount::zero()),
-            finalized_balance: Atomic::new(Amount::zero()),
-            weight: Atomic::new(Amount::zero()),
-            batch: Atomic::new(Batch::null()),
-            nonce: AtomicU64::new(0),
-            rep_index: AtomicU64::new(/*rep_index*/0)
-        });
-        index
+    /// Update the hash of the account at `index` in the state tree, after it changed.
+    /// The account is read under the lock, so the last update always sees the latest account.
+    fn touch(&self, index: u64) {
+        let mut tree = self.tree.lock().unwrap();
+        tree.set(index, self.account_hash(index));
     }
 
-    pub fn pop_account(&mut self) -> Option<Account> {
-        self.0.pop()
+    /// Hash the account at `index` over the fields all nodes agree on: its public key,
+    /// latest balance, nonce, the public key of its representative and its weight.
+    /// The representative is hashed by public key, as its index is how this node stores it.
+    fn account_hash(&self, index: u64) -> Hash {
+        let account = self.get(index).unwrap();
+        let representative = self.get(account.rep_index.load(Ordering::Relaxed))
+            .map_or(Public::zero(), |representative| representative.public);
+        let mut buf = [0u8; 88];
+        buf[0..32].copy_from_slice(account.public.as_bytes());
+        buf[32..40].copy_from_slice(&account.latest_balance.load(Ordering::Relaxed).to_raw().to_le_bytes());
+        buf[40..48].copy_from_slice(&account.nonce.load(Ordering::Relaxed).to_le_bytes());
+        buf[48..80].copy_from_slice(representative.as_bytes());
+        buf[80..88].copy_from_slice(&account.weight.load(Ordering::Relaxed).to_raw().to_le_bytes());
+        Hash::digest(&buf)
+    }
+
+    /// Get the merkle root of the hashes of all accounts in index order, as given by
+    /// `account_hash`, which commits to the public key of each account.
+    pub fn state_root(&self) -> Hash {
+        self.tree.lock().unwrap().root()
+    }
+
+    #[cfg(test)]
+    /// Get the total amount burned by finalized tasks, which together with the
+    /// finalized balances of all accounts makes up the initial supply
+    pub fn burned(&self) -> Amount {
+        self.burned.lock().unwrap().get()
+    }
+
+    /// Build the task transferring `amount` from `from_index` to `to_index`, or `None` if
+    /// `to_index` does not exist. If this is the first funding of `to_index`, which has
+    /// never sent nor held anything, up to `open_fee` of `amount` is burned.
+    pub fn transfer_task(
+        &self,
+        nonce: u64,
+        from_index: u64,
+        amount: Amount,
+        to_index: u64,
+        open_fee: Amount
+    ) -> Option<Task> {
+        let to_account = self.get(to_index)?;
+        let is_first_funding = to_account.nonce.load(Ordering::Relaxed) == 0
+            && to_account.latest_balance.load(Ordering::Relaxed) == Amount::zero();
+        let burn = match is_first_funding {
+            true => open_fee.min(amount),
+            false => Amount::zero()
+        };
+        Some(Task { nonce, from_index, amount, to_index, burn, open: false })
+    }
+
+    /// Prune every account that holds and represents nothing, isn't the
+    /// representative of another account, and has no activity according to
+    /// `is_active`, returning the index and nonce of each pruned account.
+    /// The genesis account is never pruned.
+    pub fn prune(&self, is_active: impl Fn(u64) -> bool) -> Vec<(u64, u64)> {
+        let mut reps = HashSet::new();
+        for i in 0..self.len() {
+            let rep_index = self.get(i).unwrap().rep_index.load(Ordering::Relaxed);
+            if rep_index != i && rep_index != VACANT {
+                reps.insert(rep_index);
+            }
+        }
+        let mut result = vec![];
+        for i in 1..self.len() {
+            let account = self.get(i).unwrap();
+            if account.rep_index.load(Ordering::Relaxed) == VACANT
+            || account.latest_balance.load(Ordering::Relaxed) != Amount::zero()
+            || account.finalized_balance.load(Ordering::Relaxed) != Amount::zero()
+            || account.weight.load(Ordering::Relaxed) != Amount::zero()
+            || reps.contains(&i)
+            || is_active(i) {
+                continue;
+            }
+            result.push((i, account.nonce.load(Ordering::Relaxed)));
+        }
+        for &(i, _) in result.iter().rev() {
+            self.vacate(i);
+        }
+        result
+    }
+
+    /// Get the latest batch any account was queued in, which a reopened `Bank`
+    /// must be queued after, lest a new batch be mistaken for a conflicting one
+    pub fn last_batch(&self) -> Batch {
+        (0..self.len())
+            .map(|i| self.get(i).unwrap().batch.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(Batch::null())
     }
 
     /// Queues a `Task` to prevent conflicts within the same batch.
     /// The queuing mechanism only impacts the validity and behavior of `Task`s within the specified `batch`.
+    /// Tasks whose amounts would overflow a balance when finished are rejected here.
     pub fn queue_task(&self, task: &Task, batch: Batch) -> Result<(), ()> {
         // 1) ensure nonce matches, and balance is sufficient
-        let from = self.0.get(task.from_index).ok_or(())?;
+        let from = self.get(task.from_index).ok_or(())?;
         if from.nonce.load(Ordering::Relaxed) != task.nonce
         || from.latest_balance.load(Ordering::Relaxed) < task.amount {
             return Err(());
         }
-        // 2) ensure one transaction per account per batch!
+        // 2) ensure the receiver can hold what it receives
+        let to = self.get(task.to_index).ok_or(())?;
+        let received = task.amount.checked_sub(task.burn).ok_or(())?;
+        to.latest_balance.load(Ordering::Relaxed).checked_add(received).ok_or(())?;
+        // 3) ensure one transaction per account per batch!
         if from.batch.swap(batch, Ordering::Relaxed) == batch {
             return Err(());
         }
@@ -54,72 +309,417 @@ impl Bank {
 
     /// Finish a created `Task`
     pub fn finish_task(&self, task: &Task) {
+        if task.open {
+            // the account was added when the task was built
+            return;
+        }
         if !task.is_change_representative() {
             // deduct from send half
-            let from_account = self.0.get(task.from_index).unwrap();
+            let from_account = self.get(task.from_index).unwrap();
             from_account.nonce.fetch_add(1, Ordering::Relaxed);
             from_account.latest_balance.fetch_sub(task.amount, Ordering::Relaxed);
-            // add to recv half
-            let to_account = self.0.get(task.to_index).unwrap();
-            to_account.latest_balance.fetch_add(task.amount, Ordering::Relaxed);
+            // add to recv half, less what is burned
+            let to_account = self.get(task.to_index).unwrap();
+            to_account.latest_balance.fetch_add(task.amount - task.burn, Ordering::Relaxed);
+            self.touch(task.to_index);
         } else {
-            let from_account = self.0.get(task.from_index).unwrap();
+            let from_account = self.get(task.from_index).unwrap();
             from_account.rep_index.store(task.to_index, Ordering::Relaxed);
         }
+        self.touch(task.from_index);
     }
 
     /// Revert a task
     pub fn revert_task(&self, task: &Task) {
+        if task.open {
+            self.vacate(task.from_index);
+            return;
+        }
         if !task.is_change_representative() {
-            let from_account = self.0.get(task.from_index).unwrap();
+            let from_account = self.get(task.from_index).unwrap();
             // Decrement the account nonce
             from_account.nonce.fetch_sub(1, Ordering::Relaxed);
             // Add the transaction amount back to the account balance
             from_account.latest_balance.fetch_add(task.amount, Ordering::Relaxed);
 
-            let to_account = self.0.get(task.to_index).unwrap();
-            // Deduct the transaction amount from the account balance
-            to_account.latest_balance.fetch_sub(task.amount, Ordering::Relaxed);
+            let to_account = self.get(task.to_index).unwrap();
+            // Deduct the received amount from the account balance
+            to_account.latest_balance.fetch_sub(task.amount - task.burn, Ordering::Relaxed);
+            self.touch(task.to_index);
         } else {
-            let account = self.0.get(task.from_index).unwrap();
+            let account = self.get(task.from_index).unwrap();
             // Revert the representative change
             account.rep_index.store(task.to_index, Ordering::Relaxed);
         }
+        self.touch(task.from_index);
+    }
+
+    /// Finalize a list of tasks, in order
+    pub fn finalize_tasks(&self, tasks: &[Task]) {
+        for task in tasks {
+            self.finalize_task(task);
+        }
     }
 
     /// Finalize a task
     pub fn finalize_task(&self, task: &Task) {
+        if task.open {
+            // an opened account holds nothing, so no weight moves
+            return;
+        }
         if !task.is_change_representative() {
-            let from_account = self.0.get(task.from_index).unwrap();
+            let from_account = self.get(task.from_index).unwrap();
             // Deduct the transaction amount from the sender's finalized balance
             from_account.finalized_balance.fetch_sub(task.amount, Ordering::Relaxed);
             let from_rep = from_account.rep_index.load(Ordering::Relaxed);
 
-            let to_account = self.0.get(task.to_index).unwrap();
-            // Add the transaction amount to the receiver's finalized balance
-            to_account.finalized_balance.fetch_add(task.amount, Ordering::Relaxed);
+            let to_account = self.get(task.to_index).unwrap();
+            // Add the received amount to the receiver's finalized balance
+            to_account.finalized_balance.fetch_add(task.amount - task.burn, Ordering::Relaxed);
             let to_rep = to_account.rep_index.load(Ordering::Relaxed);
 
-            let from_rep_account = self.0.get(from_rep).unwrap();
+            let from_rep_account = self.get(from_rep).unwrap();
             // Deduct the transaction amount from the representative's weight
             from_rep_account.weight.fetch_sub(task.amount, Ordering::Relaxed);
 
-            let to_rep_account = self.0.get(to_rep).unwrap();
-            // Add the transaction amount to the representative's weight
-            to_rep_account.weight.fetch_add(task.amount, Ordering::Relaxed);
+            let to_rep_account = self.get(to_rep).unwrap();
+            // Add the received amount to the representative's weight
+            to_rep_account.weight.fetch_add(task.amount - task.burn, Ordering::Relaxed);
+            self.touch(from_rep);
+            self.touch(to_rep);
+
+            if task.burn != Amount::zero() {
+                let mut burned = self.burned.lock().unwrap();
+                let total = burned.get() + task.burn;
+                burned.put(total).unwrap();
+            }
         } else {
-            let from_account = self.0.get(task.from_index).unwrap();
+            let from_account = self.get(task.from_index).unwrap();
             // Get the previous representative index
             let prev_rep = from_account.rep_index.swap(task.to_index, Ordering::Relaxed);
             let finalized_balance = from_account.finalized_balance.load(Ordering::Relaxed);
 
-            let prev_rep_account = self.0.get(prev_rep).unwrap();
+            let prev_rep_account = self.get(prev_rep).unwrap();
             // Deduct the finalized balance from the previous representative's weight
             prev_rep_account.weight.fetch_sub(finalized_balance, Ordering::Relaxed);
 
-            let to_rep_account = self.0.get(task.to_index).unwrap();
+            let to_rep_account = self.get(task.to_index).unwrap();
             // Add the finalized balance to the new representative's weight
             to_rep_account.weight.fetch_add(finalized_balance, Ordering::Relaxed);
+            self.touch(task.from_index);
+            self.touch(prev_rep);
+            self.touch(task.to_index);
+        }
+    }
+}
+
+/// Build an account of `public` holding `balance`, with `weight` delegated to it
+fn new_account(public: Public, balance: Amount, weight: Amount, rep_index: u64) -> Account {
+    Account {
+        latest_balance: Atomic::new(balance),
+        finalized_balance: Atomic::new(balance),
+        weight: Atomic::new(weight),
+        batch: Atomic::new(Batch::null()),
+        nonce: AtomicU64::new(0),
+        rep_index: AtomicU64::new(rep_index),
+        public
+    }
+}
+
+impl Flush for Bank {
+    fn flush(&self) -> Result<(), Error> {
+        self.accounts.read().unwrap().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Hash;
+
+    fn test_dir() -> String {
+        format!("{}/starlight_bank_{}", std::env::temp_dir().display(), Hash::random())
+    }
+
+    /// Pair each of `amounts` with a random public key
+    fn allocations(amounts: &[Amount]) -> Vec<(Public, Amount)> {
+        amounts.iter().map(|&amount| (Public::random(), amount)).collect()
+    }
+
+    fn open(dir: &str, amounts: &[Amount]) -> Result<Bank, Error> {
+        Bank::open(dir, Public::random(), &allocations(amounts))
+    }
+
+    fn total_finalized_balance(bank: &Bank) -> u64 {
+        (0..bank.len())
+            .map(|i| bank.get(i).unwrap().finalized_balance.load(Ordering::Relaxed).to_raw())
+            .sum()
+    }
+
+    #[test]
+    fn test_genesis_allocations() {
+        let dir = test_dir();
+        let allocations = [Amount::from_raw(1_000), Amount::from_raw(2_500), Amount::from_raw(7)];
+        let bank = open(&dir, &allocations).unwrap();
+        assert_eq!(bank.len(), 4);
+
+        let genesis = bank.get(0).unwrap();
+        assert_eq!(
+            genesis.latest_balance.load(Ordering::Relaxed),
+            Amount::initial_supply() - Amount::from_raw(3_507)
+        );
+        assert_eq!(genesis.weight.load(Ordering::Relaxed), Amount::initial_supply());
+        for (i, &amount) in allocations.iter().enumerate() {
+            let account = bank.get(i as u64 + 1).unwrap();
+            assert_eq!(account.latest_balance.load(Ordering::Relaxed), amount);
+            assert_eq!(account.finalized_balance.load(Ordering::Relaxed), amount);
+            assert_eq!(account.rep_index.load(Ordering::Relaxed), 0);
+        }
+        assert_eq!(total_finalized_balance(&bank), Amount::initial_supply().to_raw());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = test_dir();
+        let allocations = [Amount::from_raw(1_000), Amount::from_raw(2_500)];
+        let bank = open(&dir, &allocations).unwrap();
+        let root = bank.state_root();
+        assert_eq!(bank.last_batch(), Batch::null());
+        let batch = Batch::null().next().next();
+        let task = Task { nonce: 0, from_index: 1, amount: Amount::from_raw(10), to_index: 2, burn: Amount::zero(), open: false };
+        bank.queue_task(&task, batch).unwrap();
+        drop(bank);
+        // reopening doesn't bootstrap again, even with other allocations
+        let bank = open(&dir, &[]).unwrap();
+        assert_eq!(bank.len(), 3);
+        assert_eq!(bank.get(2).unwrap().latest_balance.load(Ordering::Relaxed), Amount::from_raw(2_500));
+        assert!(bank.state_root() == root);
+        assert_eq!(bank.last_batch(), batch);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_genesis_representatives() {
+        let dir = test_dir();
+        let allocations = [Amount::from_raw(1_000)];
+        let representatives = [Amount::from_raw(300), Amount::from_raw(200)];
+        let bank = Bank::open_with_representatives(&dir, Public::random(), &self::allocations(&allocations), &self::allocations(&representatives)).unwrap();
+        assert_eq!(bank.len(), 4);
+        let genesis = bank.get(0).unwrap();
+        assert_eq!(genesis.latest_balance.load(Ordering::Relaxed), Amount::initial_supply() - Amount::from_raw(1_500));
+        assert_eq!(genesis.weight.load(Ordering::Relaxed), Amount::initial_supply() - Amount::from_raw(500));
+        assert_eq!(bank.get(1).unwrap().rep_index.load(Ordering::Relaxed), 0);
+        for (i, &amount) in representatives.iter().enumerate() {
+            let index = i as u64 + 2;
+            let account = bank.get(index).unwrap();
+            assert_eq!(account.latest_balance.load(Ordering::Relaxed), amount);
+            assert_eq!(account.weight.load(Ordering::Relaxed), amount);
+            assert_eq!(account.rep_index.load(Ordering::Relaxed), index);
         }
+        assert_eq!(total_finalized_balance(&bank), Amount::initial_supply().to_raw());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(Bank::open_with_representatives(&dir, Public::random(), &self::allocations(&[Amount::initial_supply()]), &self::allocations(&[Amount::from_raw(1)])).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_genesis_allocations_exceeding_supply() {
+        let dir = test_dir();
+        let allocations = [Amount::initial_supply(), Amount::from_raw(1)];
+        assert!(open(&dir, &allocations).is_err());
+        let allocations = [Amount::max(), Amount::max()];
+        assert!(open(&dir, &allocations).is_err());
+        let allocations = [Amount::initial_supply()];
+        let bank = open(&dir, &allocations).unwrap();
+        assert_eq!(bank.get(0).unwrap().latest_balance.load(Ordering::Relaxed), Amount::zero());
+        assert_eq!(total_finalized_balance(&bank), Amount::initial_supply().to_raw());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_account_layout_mismatch() {
+        let dir = test_dir();
+        drop(open(&dir, &[]).unwrap());
+        assert!(open(&dir, &[]).is_ok());
+        std::fs::write(format!("{}/layout", dir), format!("{} 48\n", ACCOUNT_LAYOUT_VERSION + 1)).unwrap();
+        let e = open(&dir, &[]).err().unwrap();
+        assert!(e.as_str().contains("account layout"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_self_delegating_account() {
+        let dir = test_dir();
+        let bank = open(&dir, &[]).unwrap();
+        assert!(bank.add_account(Public::random(), Some(5)).is_err());
+        let index = bank.add_account(Public::random(), None).unwrap();
+        let account = bank.get(index).unwrap();
+        assert_eq!(account.rep_index.load(Ordering::Relaxed), index);
+
+        let amount = Amount::from_raw(1_000);
+        let task = Task { nonce: 0, from_index: 0, amount, to_index: index, burn: Amount::zero(), open: false };
+        bank.queue_task(&task, Batch::null().next()).unwrap();
+        bank.finish_task(&task);
+        bank.finalize_task(&task);
+        let account = bank.get(index).unwrap();
+        assert_eq!(account.finalized_balance.load(Ordering::Relaxed), amount);
+        assert_eq!(account.weight.load(Ordering::Relaxed), amount);
+        assert_eq!(
+            bank.get(0).unwrap().weight.load(Ordering::Relaxed),
+            Amount::initial_supply() - amount
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reject_overflowing_task() {
+        let dir = test_dir();
+        let bank = open(&dir, &[Amount::zero()]).unwrap();
+        let amount = Amount::from_raw(1_000);
+        // burning more than is sent
+        let task = Task { nonce: 0, from_index: 0, amount, to_index: 1, burn: amount + Amount::from_raw(1), open: false };
+        assert!(bank.queue_task(&task, Batch::null().next()).is_err());
+        // a receiver whose balance would overflow
+        bank.get(1).unwrap().latest_balance.store(Amount::max() - Amount::from_raw(999), Ordering::Relaxed);
+        let task = Task { nonce: 0, from_index: 0, amount, to_index: 1, burn: Amount::zero(), open: false };
+        assert!(bank.queue_task(&task, Batch::null().next()).is_err());
+        bank.get(1).unwrap().latest_balance.store(Amount::max() - amount, Ordering::Relaxed);
+        assert!(bank.queue_task(&task, Batch::null().next()).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_empty_accounts() {
+        let dir = test_dir();
+        let amount = Amount::from_raw(10);
+        let bank = open(&dir, &[amount, Amount::zero(), Amount::zero()]).unwrap();
+
+        // empty account 1, and have account 3 delegate to account 2
+        let tasks = [
+            Task { nonce: 0, from_index: 1, amount, to_index: 0, burn: Amount::zero(), open: false },
+            Task { nonce: 0, from_index: 3, amount: Amount::zero(), to_index: 2, burn: Amount::zero(), open: false },
+        ];
+        assert!(tasks[1].is_change_representative());
+        for task in tasks.iter() {
+            bank.queue_task(task, Batch::null().next()).unwrap();
+            bank.finish_task(task);
+        }
+        bank.finalize_tasks(&tasks);
+
+        // account 2 represents account 3, which is still active
+        assert_eq!(bank.prune(|i| i == 3), vec![(1, 1)]);
+        assert!(bank.prune(|i| i == 3).is_empty());
+        assert!(bank.add_account(Public::random(), Some(1)).is_err());
+        drop(bank);
+
+        // the pruned slot is still free after reopening, is reused, and starts over
+        let bank = open(&dir, &[]).unwrap();
+        assert_eq!(bank.add_account(Public::random(), None).unwrap(), 1);
+        assert_eq!(bank.get(1).unwrap().nonce.load(Ordering::Relaxed), 0);
+        assert_eq!(bank.get(1).unwrap().rep_index.load(Ordering::Relaxed), 1);
+        assert_eq!(bank.add_account(Public::random(), None).unwrap(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_fee_burn() {
+        let dir = test_dir();
+        let open_fee = Amount::from_raw(10);
+        let bank = open(&dir, &[]).unwrap();
+        let index = bank.add_account(Public::random(), None).unwrap();
+
+        // the first funding of the new account pays the open fee
+        let first = bank.transfer_task(0, 0, Amount::from_raw(100), index, open_fee).unwrap();
+        assert_eq!(first.burn, open_fee);
+        bank.queue_task(&first, Batch::null().next()).unwrap();
+        bank.finish_task(&first);
+        bank.finalize_task(&first);
+        let second = bank.transfer_task(1, 0, Amount::from_raw(100), index, open_fee).unwrap();
+        assert_eq!(second.burn, Amount::zero());
+        bank.queue_task(&second, Batch::null().next().next()).unwrap();
+        bank.finish_task(&second);
+        bank.finalize_task(&second);
+
+        let account = bank.get(index).unwrap();
+        assert_eq!(account.latest_balance.load(Ordering::Relaxed), Amount::from_raw(190));
+        assert_eq!(account.finalized_balance.load(Ordering::Relaxed), Amount::from_raw(190));
+        assert_eq!(account.weight.load(Ordering::Relaxed), Amount::from_raw(190));
+        assert_eq!(bank.burned(), open_fee);
+        // the burned amount leaves circulation, but still counts towards the supply
+        assert_eq!(
+            total_finalized_balance(&bank),
+            Amount::initial_supply().to_raw() - open_fee.to_raw()
+        );
+        drop(bank);
+        let bank = open(&dir, &[]).unwrap();
+        assert_eq!(bank.burned(), open_fee);
+
+        // a first funding smaller than the open fee is burned entirely
+        let index = bank.add_account(Public::random(), None).unwrap();
+        let task = bank.transfer_task(2, 0, Amount::from_raw(3), index, open_fee).unwrap();
+        assert_eq!(task.burn, Amount::from_raw(3));
+        assert!(bank.transfer_task(2, 0, Amount::from_raw(3), index + 1, open_fee).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_state_root() {
+        let dir = test_dir();
+        let bank = open(&dir, &[Amount::from_raw(1_000)]).unwrap();
+        let index = bank.add_account(Public::random(), None).unwrap();
+        let root = bank.state_root();
+        assert!(root == bank.state_root());
+        // the same accounts under other public keys have another root
+        let other_dir = test_dir();
+        let other = open(&other_dir, &[Amount::from_raw(1_000)]).unwrap();
+        other.add_account(Public::random(), None).unwrap();
+        assert!(other.state_root() != root);
+        std::fs::remove_dir_all(other_dir).unwrap();
+
+        let task = bank.transfer_task(0, 0, Amount::from_raw(100), index, Amount::zero()).unwrap();
+        bank.queue_task(&task, Batch::null().next()).unwrap();
+        bank.finish_task(&task);
+        let applied = bank.state_root();
+        assert!(applied != root && applied == bank.state_root());
+        // finalizing moves weight, which is committed to as well
+        bank.finalize_task(&task);
+        assert!(bank.state_root() != applied);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_incremental_state_root() {
+        let dir = test_dir();
+        let bank = open(&dir, &[Amount::from_raw(1_000), Amount::from_raw(500)]).unwrap();
+        let full_root = |bank: &Bank| crate::util::merkle_root_direct((0..bank.len()).map(|i| bank.account_hash(i)).collect());
+        assert!(bank.state_root() == full_root(&bank));
+        let index = bank.add_account(Public::random(), None).unwrap();
+        assert!(bank.state_root() == full_root(&bank));
+
+        let transfer = bank.transfer_task(0, 1, Amount::from_raw(100), index, Amount::from_raw(10)).unwrap();
+        let change_rep = Task { nonce: 0, from_index: 2, amount: Amount::zero(), to_index: index, burn: Amount::zero(), open: false };
+        let mut batch = Batch::null();
+        for task in [transfer, change_rep] {
+            batch = batch.next();
+            bank.queue_task(&task, batch).unwrap();
+            bank.finish_task(&task);
+            assert!(bank.state_root() == full_root(&bank));
+            bank.finalize_task(&task);
+            assert!(bank.state_root() == full_root(&bank));
+        }
+        let task = bank.transfer_task(0, index, Amount::from_raw(20), 0, Amount::zero()).unwrap();
+        bank.queue_task(&task, batch.next()).unwrap();
+        bank.finish_task(&task);
+        bank.revert_task(&task);
+        assert!(bank.state_root() == full_root(&bank));
+
+        // an open being reverted, of an account no other account delegates to yet
+        let root = bank.state_root();
+        let open = bank.open_task(Public::random(), None, 0).unwrap();
+        assert!(bank.state_root() == full_root(&bank));
+        bank.revert_task(&open);
+        assert!(bank.state_root() == full_root(&bank) && bank.state_root() == root);
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
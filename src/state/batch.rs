@@ -2,7 +2,7 @@
 
 use crate::util::ArchivableTo;
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub struct Batch(u64);
 
 impl ArchivableTo<u64> for Batch {
@@ -1,10 +1,15 @@
+use std::time::{Duration, SystemTime};
+
+use bincode::{Decode, Encode};
+
 use crate::{
     error,
-    keys::{Hash, Identity, Private, Public, Signature},
-    protocol::{Open, Slot, Tx, Vote},
+    keys::{Domain, Hash, Identity, Public, Signature},
+    protocol::{self, Open, Slot, Tx, Vote},
     util::{self, Error},
 };
 
+#[derive(Encode, Decode, Clone)]
 #[repr(C)]
 pub struct Block {
     /// The leader of the current block
@@ -30,40 +35,59 @@ pub struct Block {
     pub votes: Vec<Vote>,
 }
 
+/// Hash the contents of a block on the network `chain_id`. A zero `state_hash`
+/// is left out, so that blocks without a state hash hash as they did before it was signed.
 fn hash_block(
+    chain_id: u32,
     slot: Slot,
     previous: &Hash,
+    state_hash: &Hash,
     opens_hash: &Hash,
     transactions_hash: &Hash,
     vote_hash: &Hash,
 ) -> Hash {
-    let mut buf = [0u8; 136];
+    let mut buf = [0u8; 168];
     buf[0..8].copy_from_slice(&slot.to_bytes());
-    buf[8..40].copy_from_slice(&previous.as_bytes());
-    buf[40..72].copy_from_slice(&opens_hash.as_bytes());
-    buf[72..104].copy_from_slice(&transactions_hash.as_bytes());
-    buf[104..136].copy_from_slice(&vote_hash.as_bytes());
-    Hash::digest(&buf)
+    buf[8..40].copy_from_slice(previous.as_bytes());
+    buf[40..72].copy_from_slice(opens_hash.as_bytes());
+    buf[72..104].copy_from_slice(transactions_hash.as_bytes());
+    buf[104..136].copy_from_slice(vote_hash.as_bytes());
+    buf[136..168].copy_from_slice(state_hash.as_bytes());
+    let mut hb = protocol::chain_hash_builder(Domain::Block, chain_id);
+    hb.update(&buf);
+    hb.finish()
+}
+
+#[cfg(test)]
+/// The contents of a block to be signed, each with the hashes it's committed to by
+#[derive(Default)]
+pub struct BlockBody {
+    pub opens: Vec<Open>,
+    pub open_hashes: Vec<Hash>,
+    pub transactions: Vec<Tx>,
+    pub tx_hashes: Vec<Hash>,
+    pub votes: Vec<Vote>,
+    pub vote_hashes: Vec<Hash>,
 }
 
 impl Block {
-    pub fn sign(
-        id: Identity,
-        slot: Slot,
-        previous: Hash,
-        opens: Vec<Open>,
-        open_hashes: Vec<Hash>,
-        transactions: Vec<Tx>,
-        tx_hashes: Vec<Hash>,
-        votes: Vec<Vote>,
-        vote_hashes: Vec<Hash>,
-    ) -> Self {
+    #[cfg(test)]
+    pub fn sign(id: Identity, slot: Slot, previous: Hash, body: BlockBody) -> Self {
+        Self::sign_with_state_hash(id, slot, previous, Hash::zero(), body)
+    }
+    #[cfg(test)]
+    /// Sign a block committing to `state_hash`, the merkle root of all accounts
+    /// after applying the block, as given by `Bank::state_root`
+    pub fn sign_with_state_hash(id: Identity, slot: Slot, previous: Hash, state_hash: Hash, body: BlockBody) -> Self {
+        let BlockBody { opens, open_hashes, transactions, tx_hashes, votes, vote_hashes } = body;
         let opens_hash = util::merkle_root_direct(open_hashes);
         let transactions_hash = util::merkle_root_direct(tx_hashes);
         let vote_hash = util::merkle_root_direct(vote_hashes);
         let hash = hash_block(
+            protocol::chain_id(),
             slot,
             &previous,
+            &state_hash,
             &opens_hash,
             &transactions_hash,
             &vote_hash,
@@ -75,7 +99,7 @@ impl Block {
             slot,
             previous,
             hash,
-            state_hash: Hash::zero(),
+            state_hash,
             opens,
             transactions,
             votes,
@@ -84,11 +108,13 @@ impl Block {
     pub fn genesis(id: Identity) -> Self {
         let zero_hash = Hash::zero();
         let hash = hash_block(
+            protocol::chain_id(),
             Slot::zero(),
             &zero_hash,
             &zero_hash,
             &zero_hash,
             &zero_hash,
+            &zero_hash,
         );
         let signature = id.private.sign(&hash);
         Self {
@@ -111,7 +137,12 @@ impl Block {
             && self.previous == Hash::zero()
             && self.state_hash == Hash::zero()
     }
-    pub fn verify_and_hash(&self) -> Result<Hash, Error> {
+    /// Verify the block and its contents, rejecting blocks with more than
+    /// `max_votes` votes before verifying any signatures.
+    pub fn verify_and_hash(&self, max_votes: usize) -> Result<Hash, Error> {
+        if self.votes.len() > max_votes {
+            return Err(error!("block has {} votes, more than the maximum of {}", self.votes.len(), max_votes));
+        }
         let opens_hash = util::merkle_root(
             &self.opens,
             |open| open.verify_and_hash(),
@@ -125,8 +156,10 @@ impl Block {
             |vote| vote.verify_and_hash(),
         )?;
         let block_hash = hash_block(
+            protocol::chain_id(),
             self.slot,
             &self.previous,
+            &self.state_hash,
             &opens_hash,
             &transactions_hash,
             &vote_hash,
@@ -137,4 +170,47 @@ impl Block {
         self.leader.verify(&block_hash, &self.signature)?;
         Ok(block_hash)
     }
+    /// Reject blocks whose slot is more than `max_skew` ahead of `now`
+    pub fn check_skew(&self, now: SystemTime, max_skew: Duration) -> Result<(), Error> {
+        self.slot.check_skew(now, max_skew)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::Seed, protocol::Pair};
+
+    #[test]
+    fn test_check_skew() {
+        let private = Seed([4; 32]).derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let max_skew = Duration::from_secs(2);
+        let now = SystemTime::now();
+        let block_at = |time| Block::sign(id, Slot::from_system_time(time), Hash::zero(), BlockBody::default());
+        assert!(block_at(now).check_skew(now, max_skew).is_ok());
+        assert!(block_at(now - Duration::from_secs(60)).check_skew(now, max_skew).is_ok());
+        assert!(block_at(now + max_skew + Duration::from_secs(1)).check_skew(now, max_skew).is_err());
+    }
+
+    #[test]
+    fn test_max_votes_per_block() {
+        let private = Seed([4; 32]).derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let genesis = Block::genesis(id);
+        assert_eq!(genesis.verify_and_hash(0).unwrap(), genesis.hash);
+        // votes with invalid signatures, which fail verification if it is reached
+        let votes = (0..3).map(|_| Vote {
+            from: Public::random(),
+            left: Pair { slot: Slot::zero(), block: Hash::zero() },
+            right: Pair { slot: Slot::zero(), block: Hash::zero() },
+            signature: Signature::from_bytes([0; 64]),
+        }).collect();
+        let block = Block::sign(id, Slot::zero().next(), Hash::zero(), BlockBody { votes, ..BlockBody::default() });
+
+        let e = block.verify_and_hash(2).unwrap_err();
+        assert!(e.as_str().contains("more than the maximum of 2"));
+        let e = block.verify_and_hash(3).unwrap_err();
+        assert!(!e.as_str().contains("more than the maximum"));
+    }
 }
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{error, keys::Hash, util::{Error, Semaphore}};
+
+use super::Block;
+
+/// Verifies blocks with at most a fixed number of verifications running at once,
+/// so that a flood of blocks during catch-up can't starve other work of CPU.
+#[derive(Clone)]
+pub struct BlockVerifier {
+    semaphore: Arc<Semaphore>,
+    /// The maximum number of votes in a valid block
+    max_votes_per_block: usize,
+}
+
+impl BlockVerifier {
+    pub fn new(max_concurrent: usize, max_votes_per_block: usize) -> Result<Self, Error> {
+        if max_concurrent == 0 {
+            return Err(error!("at least one concurrent block verification must be allowed"));
+        }
+        Ok(Self { semaphore: Arc::new(Semaphore::new(max_concurrent)), max_votes_per_block })
+    }
+
+    /// Run `f` once fewer than the maximum number of verifications are running.
+    fn throttle<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _permit = self.semaphore.acquire();
+        f()
+    }
+
+    /// Verify `block`, waiting for other verifications to finish if too many are running.
+    pub fn verify_and_hash(&self, block: &Block) -> Result<Hash, Error> {
+        self.throttle(|| block.verify_and_hash(self.max_votes_per_block))
+    }
+
+    /// Verify `blocks` in parallel, at most the maximum number at once, and get
+    /// the hash of each in order, or an error if any is invalid.
+    pub fn verify_all(&self, blocks: &[Block]) -> Result<Vec<Hash>, Error> {
+        blocks.par_iter().map(|block| self.verify_and_hash(block)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{Identity, Seed};
+    use crate::protocol::Slot;
+    use crate::state::BlockBody;
+    use std::{sync::atomic::{AtomicUsize, Ordering}, thread, time::Duration};
+
+    #[test]
+    fn test_concurrency_limit() {
+        const LIMIT: usize = 3;
+        let verifier = BlockVerifier::new(LIMIT, 16).unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..16).map(|_| {
+            let (verifier, running, max_running) = (verifier.clone(), running.clone(), max_running.clone());
+            thread::spawn(move || verifier.throttle(|| {
+                let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(n, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+            }))
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), LIMIT);
+    }
+
+    #[test]
+    fn test_verify_all() {
+        let private = Seed([4; 32]).derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let (mut previous, mut slot) = (Hash::zero(), Slot::zero());
+        let mut blocks = vec![];
+        for _ in 0..32 {
+            slot = slot.next();
+            let block = Block::sign(id, slot, previous, BlockBody::default());
+            previous = block.hash;
+            blocks.push(block);
+        }
+        let verifier = BlockVerifier::new(2, 16).unwrap();
+        let hashes = verifier.verify_all(&blocks).unwrap();
+        assert!(hashes.iter().zip(blocks.iter()).all(|(hash, block)| *hash == block.hash));
+
+        // a single invalid block fails them all
+        blocks[20].hash = Hash::zero();
+        assert!(verifier.verify_all(&blocks).is_err());
+        assert!(BlockVerifier::new(0, 16).is_err());
+    }
+}
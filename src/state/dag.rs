@@ -1,7 +1,8 @@
+#[cfg(test)]
 use petgraph::algo::astar;
 use petgraph::{
     graphmap::GraphMap,
-    visit::{Dfs, Visitable, Walker},
+    visit::{Dfs, Walker},
     Directed, Incoming,
 };
 use std::{
@@ -15,6 +16,8 @@ use crate::{error, util::Error};
 struct Node<V> {
     /// How many direct predecessors this node has
     height: u64,
+    /// The order in which the node was inserted
+    seq: u64,
     /// The value of the node
     value: V,
 }
@@ -31,19 +34,54 @@ pub struct Dag<K: Hash + Copy + Ord, V> {
     nodes: HashMap<K, Node<V>>,
     /// The root node from which all others follow
     root: K,
+    /// How ties between chains of equal height are broken
+    tie_break: TieBreak,
+    /// The `seq` of the next inserted node
+    next_seq: u64,
+}
+
+/// The tie-break used by every node of the network for fork choice
+pub const FORK_CHOICE_TIE_BREAK: TieBreak = TieBreak::HigherKey;
+
+/// Decides which of two chains of equal height is the longest chain.
+/// This is consensus-critical: nodes using different policies may follow different forks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The chain whose head has the greater key wins
+    HigherKey,
+    /// The chain whose head has the lesser key wins
+    #[allow(dead_code)] // an alternative to `FORK_CHOICE_TIE_BREAK`
+    LowerKey,
+    /// The chain whose head was inserted first wins. This depends on the order in
+    /// which blocks arrive, so different nodes may disagree.
+    #[allow(dead_code)] // an alternative to `FORK_CHOICE_TIE_BREAK`
+    FirstSeen,
 }
 
-fn is_a_taller_than_b<K: Ord>(a: (u64, &K), b: (u64, &K)) -> bool {
-    match a.0.cmp(&b.0) {
-        Ordering::Greater => true,
-        Ordering::Equal => a.1 > b.1,
-        Ordering::Less => false,
+impl TieBreak {
+    /// Is the chain with head `a` taller than the one with head `b`?
+    /// Chains are given as `(height, seq, head)`.
+    fn is_a_taller_than_b<K: Ord>(self, a: (u64, u64, &K), b: (u64, u64, &K)) -> bool {
+        match a.0.cmp(&b.0) {
+            Ordering::Greater => true,
+            Ordering::Equal => match self {
+                TieBreak::HigherKey => a.2 > b.2,
+                TieBreak::LowerKey => a.2 < b.2,
+                TieBreak::FirstSeen => a.1 < b.1,
+            },
+            Ordering::Less => false,
+        }
     }
 }
 
 impl<K: Hash + Copy + Ord, V> Dag<K, V> {
-    /// Creates a new Dag.
+    /// Creates a new Dag, breaking ties between chains with `FORK_CHOICE_TIE_BREAK`.
     pub fn new(root_k: K, root_v: V) -> Self {
+        Self::with_tie_break(root_k, root_v, FORK_CHOICE_TIE_BREAK)
+    }
+
+    /// Creates a new Dag, breaking ties between chains of equal height with `tie_break`.
+    pub fn with_tie_break(root_k: K, root_v: V, tie_break: TieBreak) -> Self {
         let mut graph = GraphMap::new();
         graph.add_node(root_k);
         let mut heads = HashSet::new();
@@ -53,6 +91,7 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
             root_k,
             Node {
                 height: 0,
+                seq: 0,
                 value: root_v,
             },
         );
@@ -62,14 +101,46 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
             longest_chain: root_k,
             nodes,
             root: root_k,
+            tie_break,
+            next_seq: 1,
         }
     }
 
+    /// Is the chain with head `a` taller than the one with head `b`?
+    fn is_a_taller_than_b(&self, a: &K, b: &K) -> bool {
+        let (node_a, node_b) = (&self.nodes[a], &self.nodes[b]);
+        self.tie_break.is_a_taller_than_b(
+            (node_a.height, node_a.seq, a),
+            (node_b.height, node_b.seq, b),
+        )
+    }
+
     /// Get the head of the longest chain of nodes
     pub fn get_longest_chain(&self) -> (&K, &V) {
         (&self.longest_chain, &self.nodes[&self.longest_chain].value)
     }
 
+    /// Get the number of nodes, including the root
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Iterate over all nodes, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.nodes.iter().map(|(k, node)| (k, &node.value))
+    }
+
+    /// Get the number of predecessors a node had when it was inserted, or `None` if it isn't in the DAG
+    pub fn height(&self, k: &K) -> Option<u64> {
+        self.nodes.get(k).map(|node| node.height)
+    }
+
+    #[cfg(test)]
+    /// Get the heads of all chains of nodes, i.e. all nodes without children
+    pub fn heads(&self) -> impl Iterator<Item = &K> {
+        self.heads.iter()
+    }
+
     /// Insert a node into the DAG. If it already exists, an error is returned.
     /// `prev` must reference a valid node in the DAG.
     /// Updates `self.longest_chain` if necessary.
@@ -78,7 +149,7 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
             return Err(error!("node already exists in DAG"));
         }
 
-        self.graph.add_node(key.clone());
+        self.graph.add_node(key);
         let height = {
             let prev_height = self
                 .nodes
@@ -90,13 +161,12 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
             prev_height + 1
         };
 
-        self.nodes.insert(key.clone(), Node { height, value });
-        self.heads.insert(key.clone());
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.nodes.insert(key, Node { height, seq, value });
+        self.heads.insert(key);
 
-        if is_a_taller_than_b(
-            (height, &key),
-            (self.nodes[&self.longest_chain].height, &self.longest_chain),
-        ) {
+        if self.is_a_taller_than_b(&key, &self.longest_chain) {
             self.longest_chain = key;
         }
 
@@ -106,18 +176,19 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
     /// Removes a node from the DAG.
     /// The node cannot be the root.
     pub fn remove(&mut self, key: K) -> Result<(), Error> {
-        if !self.graph.contains_node(key.clone()) {
+        if !self.graph.contains_node(key) {
             return Err(error!("can't find node in DAG"));
         }
         if self.root == key {
             return Err(error!("can't remove root node"));
         }
-        self.graph.remove_node(key.clone());
+        self.graph.remove_node(key);
         self.nodes.remove(&key);
         self.heads.remove(&key);
         Ok(())
     }
 
+    #[cfg(test)]
     /// Iterate over the node denoted by `key`, and all its ancestors.
     /// Starts at `key` and works backwards.
     /// Returns `None` if the node denoted by `key` does not exist.
@@ -133,14 +204,15 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
         }
     }
 
+    #[cfg(test)]
     /// Iterate over the node denoted by `key`, and all its descendants.
     /// Starts at `key` and works forwards.
     /// Returns `None` if the node denoted by `key` does not exist.
     pub fn iter_node_and_descendants(&self, key: K) -> Option<impl Iterator<Item = (&K, &V)>> {
-        if !self.graph.contains_node(key.clone()) {
+        if !self.graph.contains_node(key) {
             return None;
         }
-        let descendants = Dfs::new(&self.graph, key.clone())
+        let descendants = Dfs::new(&self.graph, key)
             .iter(&self.graph)
             .filter_map(move |k| self.nodes.get_key_value(&k).map(|(k, v)| (k, &v.value)));
         Some(descendants)
@@ -160,11 +232,18 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
         self.nodes.get_key_value(&k1).map(|(k, v)| (k, &v.value))
     }
 
+    #[cfg(test)]
+    /// Get the order in which the node denoted by `k` was inserted, starting at 0 for the root.
+    pub fn seq(&self, k: &K) -> Option<u64> {
+        self.nodes.get(k).map(|n| n.seq)
+    }
+
     /// Get the value of the node denoted by `k`.
     pub fn get(&self, k: &K) -> Option<&V> {
         self.nodes.get(k).map(|n| &n.value)
     }
 
+    #[cfg(test)]
     /// Get a path of keys starting with `from` and ending with `to`, or `None` if one does not exist.
     pub fn get_path(&self, from: K, to: K) -> Option<Vec<K>> {
         astar(
@@ -177,6 +256,20 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
         .map(|(_, path)| path)
     }
 
+    /// Get a path of keys starting with the root and ending with `to`, or `None` if `to` does not exist.
+    /// Unlike `get_path`, this walks the ancestors of `to` directly instead of searching the graph.
+    pub fn get_path_from_root(&self, to: K) -> Option<Vec<K>> {
+        if !self.graph.contains_node(to) {
+            return None;
+        }
+        let mut path: Vec<K> = std::iter::successors(Some(to), |&x| {
+            self.graph.neighbors_directed(x, Incoming).next()
+        })
+        .collect();
+        path.reverse();
+        Some(path)
+    }
+
     /// Get the root node
     pub fn get_root(&self) -> (&K, &V) {
         (&self.root, &self.nodes[&self.root].value)
@@ -185,11 +278,11 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
     /// If the node denoted by `key` exists in the DAG, set the corresponding node as the "root" node.
     /// This removes all nodes that are not descendants of the node, or the node itself.
     pub fn set_root(&mut self, key: K) -> Result<(), Error> {
-        if !self.graph.contains_node(key.clone()) {
+        if !self.graph.contains_node(key) {
             return Err(error!("can't find node in DAG"));
         }
 
-        let descendants: HashSet<K> = Dfs::new(&self.graph, key.clone())
+        let descendants: HashSet<K> = Dfs::new(&self.graph, key)
             .iter(&self.graph)
             .collect();
         let to_remove: Vec<K> = self
@@ -208,21 +301,30 @@ impl<K: Hash + Copy + Ord, V> Dag<K, V> {
                 .heads
                 .iter()
                 .max_by(|key1, key2| {
-                    match is_a_taller_than_b(
-                        (self.nodes.get(key1).unwrap().height, key1),
-                        (self.nodes.get(key2).unwrap().height, key2),
-                    ) {
+                    match self.is_a_taller_than_b(key1, key2) {
                         true => Ordering::Greater,
                         false => Ordering::Less,
                     }
                 })
-                .map(|k| k.clone())
+                .copied()
                 .unwrap();
         }
 
         self.root = key;
         Ok(())
     }
+
+    /// Removes all nodes but the root.
+    pub fn truncate(&mut self) {
+        let root = self.root;
+        let to_remove: Vec<K> = self.graph.nodes().filter(|&x| x != root).collect();
+        for node in to_remove {
+            self.graph.remove_node(node);
+        }
+        self.nodes.retain(|&k, _| k == root);
+        self.heads = HashSet::from([root]);
+        self.longest_chain = root;
+    }
 }
 
 #[cfg(test)]
@@ -265,6 +367,60 @@ mod tests {
         assert_eq!(dag.get_longest_chain(), (&'C', &3));
     }
 
+    #[test]
+    fn test_tie_break() {
+        // 'C' and 'B' are competing heads of equal height, 'C' inserted last
+        let tied = |tie_break| {
+            let mut dag = Dag::with_tie_break('A', 1, tie_break);
+            assert!(dag.insert('C', 3, 'A').is_ok());
+            assert!(dag.insert('B', 2, 'A').is_ok());
+            dag
+        };
+        assert_eq!(tied(TieBreak::HigherKey).get_longest_chain(), (&'C', &3));
+        assert_eq!(tied(TieBreak::LowerKey).get_longest_chain(), (&'B', &2));
+        assert_eq!(tied(TieBreak::FirstSeen).get_longest_chain(), (&'C', &3));
+        assert_eq!(Dag::new('A', 1).tie_break, FORK_CHOICE_TIE_BREAK);
+
+        // the policy also applies when the longest chain is pruned
+        let mut dag = Dag::with_tie_break('A', 1, TieBreak::LowerKey);
+        assert!(dag.insert('X', 0, 'A').is_ok());
+        assert!(dag.insert('E', 5, 'X').is_ok());
+        assert!(dag.insert('D', 4, 'X').is_ok());
+        assert!(dag.insert('Y', 0, 'A').is_ok());
+        assert!(dag.insert('Z', 0, 'Y').is_ok());
+        assert!(dag.insert('F', 6, 'Z').is_ok());
+        assert_eq!(dag.get_longest_chain(), (&'F', &6));
+        assert!(dag.set_root('X').is_ok());
+        assert_eq!(dag.get_longest_chain(), (&'D', &4));
+    }
+
+    #[test]
+    fn test_seq() {
+        let mut dag = Dag::new('A', 1);
+        assert!(dag.insert('B', 2, 'A').is_ok());
+        assert!(dag.insert('C', 3, 'A').is_ok());
+        assert_eq!(dag.seq(&'A'), Some(0));
+        assert_eq!(dag.seq(&'B'), Some(1));
+        assert_eq!(dag.seq(&'C'), Some(2));
+        assert_eq!(dag.seq(&'D'), None);
+        // the default policy still prefers the higher key over the first seen
+        assert_eq!(dag.get_longest_chain(), (&'C', &3));
+    }
+
+    #[test]
+    fn test_heads() {
+        let mut dag = Dag::new('A', 1);
+        assert_eq!(dag.heads().collect::<Vec<_>>(), vec![&'A']);
+
+        assert!(dag.insert('B', 2, 'A').is_ok());
+        assert!(dag.insert('C', 3, 'B').is_ok());
+        assert!(dag.insert('D', 4, 'A').is_ok());
+
+        let mut heads: Vec<char> = dag.heads().copied().collect();
+        heads.sort();
+        assert_eq!(heads, vec!['C', 'D']);
+    }
+
     #[test]
     fn test_iter_node_and_ancestors() {
         let mut dag = Dag::new('A', 1);
@@ -314,6 +470,21 @@ mod tests {
         assert!(dag.get_common_ancestor('E', 'A').is_none());
     }
 
+    #[test]
+    fn test_get_path_from_root() {
+        let mut dag = Dag::new('A', 1);
+
+        assert!(dag.insert('B', 2, 'A').is_ok());
+        assert!(dag.insert('C', 3, 'B').is_ok());
+        assert!(dag.insert('D', 4, 'A').is_ok());
+
+        assert_eq!(dag.get_path_from_root('C'), Some(vec!['A', 'B', 'C']));
+        assert_eq!(dag.get_path_from_root('C'), dag.get_path('A', 'C'));
+        assert_eq!(dag.get_path_from_root('D'), Some(vec!['A', 'D']));
+        assert_eq!(dag.get_path_from_root('A'), Some(vec!['A']));
+        assert!(dag.get_path_from_root('E').is_none());
+    }
+
     #[test]
     fn test_set_root() {
         let mut dag = Dag::new('A', 1);
@@ -328,4 +499,18 @@ mod tests {
         assert_eq!(dag.get_longest_chain(), (&'C', &3));
         assert!(dag.set_root('E').is_err());
     }
+
+    #[test]
+    fn test_truncate() {
+        let mut dag: Dag<char, i32> = Dag::new('A', 1);
+        dag.insert('B', 2, 'A').unwrap();
+        dag.insert('C', 3, 'B').unwrap();
+        dag.insert('D', 4, 'A').unwrap();
+        dag.truncate();
+        assert_eq!(dag.len(), 1);
+        assert_eq!(dag.get_longest_chain(), (&'A', &1));
+        assert_eq!(dag.heads().collect::<Vec<_>>(), vec![&'A']);
+        dag.insert('E', 5, 'A').unwrap();
+        assert_eq!(dag.get_longest_chain(), (&'E', &5));
+    }
 }
@@ -0,0 +1,184 @@
+use std::{collections::{BTreeMap, HashMap, VecDeque}, sync::Arc};
+
+use crate::{error, keys::Hash, log_warn, protocol::Vote, storage::{self, RecordLog}, util::Error};
+
+use super::Block;
+
+/// All finalized blocks, in order, along with the votes proving blocks finalized,
+/// so that they can be served to syncing peers. Every block and proof is written
+/// to a `RecordLog`, so that the chain is restored on the next start. Only the most
+/// recent `max_in_memory` blocks are kept in memory; older ones are read back on demand.
+/// The index of every block is kept in memory by hash.
+pub struct Finalized {
+    log: RecordLog<Block>,
+    proof_log: RecordLog<(u64, Vec<Vote>)>,
+    recent: VecDeque<Arc<Block>>,
+    indices: HashMap<Hash, usize>,
+    proofs: BTreeMap<usize, Vec<Vote>>,
+    max_in_memory: usize,
+}
+
+impl Finalized {
+    /// Opens the `Finalized` of `data_dir`, restoring the blocks and proofs
+    /// written by a previous run, or holding only `genesis` if there are none
+    pub fn open(data_dir: &str, genesis: Arc<Block>, max_in_memory: usize) -> Result<Self, Error> {
+        let mut finalized = Self {
+            log: RecordLog::open(&storage::finalized_path(data_dir))?,
+            proof_log: RecordLog::open(&storage::proofs_path(data_dir))?,
+            recent: VecDeque::new(),
+            indices: HashMap::new(),
+            proofs: BTreeMap::new(),
+            max_in_memory: max_in_memory.max(1),
+        };
+        for index in 0..finalized.log.len() {
+            let block = finalized.log.get(index)?;
+            if index == 0 && (block.hash != genesis.hash || block.leader != genesis.leader) {
+                return Err(error!("finalized blocks in {} follow another genesis block", data_dir));
+            }
+            finalized.indices.insert(block.hash, index);
+        }
+        for index in 0..finalized.proof_log.len() {
+            let (block_index, votes) = finalized.proof_log.get(index)?;
+            finalized.proofs.insert(block_index as usize, votes);
+        }
+        match finalized.log.len() {
+            0 => finalized.push(genesis),
+            len => finalized.recent.push_back(Arc::new(finalized.log.get(len - 1)?)),
+        }
+        Ok(finalized)
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// The index of the oldest block in memory
+    fn first_recent(&self) -> usize {
+        self.len() - self.recent.len()
+    }
+
+    /// Get the `index`th finalized block, reading it from disk if it isn't in memory
+    pub fn get(&self, index: usize) -> Result<Arc<Block>, Error> {
+        match index.checked_sub(self.first_recent()) {
+            Some(i) => self.recent.get(i).cloned().ok_or_else(|| error!("block {} not finalized", index)),
+            None => Ok(Arc::new(self.log.get(index)?)),
+        }
+    }
+
+    /// Append `block`, writing it and any blocks which previously failed to be written
+    /// to disk, then dropping the oldest written blocks in memory if over `max_in_memory`
+    pub fn push(&mut self, block: Arc<Block>) {
+        self.indices.insert(block.hash, self.len());
+        self.recent.push_back(block);
+        while self.log.len() < self.len() {
+            let block = self.recent[self.log.len() - self.first_recent()].clone();
+            if let Err(e) = self.log.push(&block) {
+                log_warn!("Failed to write finalized block: {}", e);
+                break;
+            }
+        }
+        while self.recent.len() > self.max_in_memory && self.first_recent() < self.log.len() {
+            self.recent.pop_front();
+        }
+    }
+
+    #[cfg(test)]
+    /// Get the index of the block `hash`, or `None` if it is not finalized
+    pub fn index_of(&self, hash: &Hash) -> Option<usize> {
+        self.indices.get(hash).copied()
+    }
+
+    /// Is the block `hash` finalized?
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.indices.contains_key(hash)
+    }
+
+    /// Record `votes` of a quorum of representatives for the `index`th block
+    pub fn prove(&mut self, index: usize, votes: Vec<Vote>) {
+        let record = (index as u64, votes);
+        if let Err(e) = self.proof_log.push(&record) {
+            log_warn!("Failed to write finality proof: {}", e);
+        }
+        self.proofs.insert(index, record.1);
+    }
+
+    /// Get the votes proving the `index`th block finalized, if they were recorded
+    pub fn proof(&self, index: usize) -> Option<&[Vote]> {
+        self.proofs.get(&index).map(|votes| votes.as_slice())
+    }
+
+    /// Get the index of the first block for which `pred` is false, assuming
+    /// `pred` holds for a prefix of the blocks, like `slice::partition_point`
+    pub fn partition_point(&self, pred: impl Fn(&Block) -> bool) -> Result<usize, Error> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match pred(self.get(mid)?.as_ref()) {
+                true => lo = mid + 1,
+                false => hi = mid,
+            }
+        }
+        Ok(lo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::{Identity, Seed}, protocol::Pair, state::BlockBody};
+
+    #[test]
+    fn test_spill_oldest() {
+        let dir = format!("{}/starlight_finalized_spill_{}", std::env::temp_dir().display(), Hash::random());
+        let private = Seed([0; 32]).derive(0);
+        let leader = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(leader));
+        let mut finalized = Finalized::open(&dir, genesis.clone(), 4).unwrap();
+        let (mut previous, mut slot) = (genesis.hash, genesis.slot);
+        for _ in 0..12 {
+            slot = slot.next();
+            let block = Block::sign(leader, slot, previous, BlockBody::default());
+            previous = block.hash;
+            finalized.push(Arc::new(block));
+            assert!(finalized.recent.len() <= 4);
+        }
+        assert_eq!(finalized.len(), 13);
+        assert_eq!(finalized.log.len(), 13);
+        assert_eq!(finalized.first_recent(), 9);
+        assert!(finalized.get(0).unwrap().hash == genesis.hash);
+        assert!(finalized.contains(&genesis.hash));
+        assert!(finalized.contains(&previous));
+        assert!(!finalized.contains(&Hash::random()));
+        assert_eq!(finalized.index_of(&previous), Some(12));
+        assert!(finalized.get(5).unwrap().hash == finalized.get(6).unwrap().previous);
+        assert_eq!(finalized.partition_point(|block| block.slot <= genesis.slot.next()).unwrap(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = format!("{}/starlight_finalized_restore_{}", std::env::temp_dir().display(), Hash::random());
+        let private = Seed([0; 32]).derive(0);
+        let leader = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(leader));
+        let mut finalized = Finalized::open(&dir, genesis.clone(), 2).unwrap();
+        let block = Block::sign(leader, genesis.slot.next(), genesis.hash, BlockBody::default());
+        let vote = Vote::sign(&leader, Pair { slot: block.slot, block: genesis.hash }, Pair { slot: block.slot, block: block.hash });
+        finalized.push(Arc::new(block.clone()));
+        finalized.prove(1, vec![vote]);
+        drop(finalized);
+
+        // the blocks and proofs are restored
+        let finalized = Finalized::open(&dir, genesis.clone(), 2).unwrap();
+        assert_eq!(finalized.len(), 2);
+        assert_eq!(finalized.index_of(&block.hash), Some(1));
+        assert!(finalized.get(1).unwrap().signature == block.signature);
+        assert!(finalized.proof(1).is_some_and(|votes| votes.len() == 1));
+        drop(finalized);
+
+        // but not for another genesis block
+        let other = Arc::new(Block::genesis(Identity { private: Seed([1; 32]).derive(0), public: Seed([1; 32]).derive(0).to_public() }));
+        assert!(Finalized::open(&dir, other, 2).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
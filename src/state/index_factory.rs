@@ -0,0 +1,66 @@
+use std::collections::BTreeSet;
+
+/// Hands out the indices of accounts in the `Bank`, reusing the lowest
+/// freed index before allocating a new one so the accounts stay dense.
+pub struct IndexFactory {
+    /// The number of indices ever allocated
+    len: u64,
+    /// The freed indices, below `len`
+    free: BTreeSet<u64>,
+}
+
+impl IndexFactory {
+    /// Creates an `IndexFactory` for `len` allocated indices, of which `free` were freed
+    pub fn new(len: u64, free: impl IntoIterator<Item = u64>) -> Self {
+        Self { len, free: free.into_iter().filter(|&i| i < len).collect() }
+    }
+
+    /// Get the next index, which is a freed one if there are any
+    pub fn next(&mut self) -> u64 {
+        match self.free.pop_first() {
+            Some(index) => index,
+            None => {
+                self.len += 1;
+                self.len - 1
+            }
+        }
+    }
+
+    /// Give back the most recently allocated index, as when an open is reverted
+    pub fn prev(&mut self) {
+        self.len = self.len.saturating_sub(1);
+        self.free.remove(&self.len);
+    }
+
+    /// Free `index` to be reused by `next`
+    pub fn free(&mut self, index: u64) {
+        if index < self.len {
+            self.free.insert(index);
+        }
+    }
+
+    /// Is `index` allocated, and not freed?
+    pub fn is_allocated(&self, index: u64) -> bool {
+        index < self.len && !self.free.contains(&index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse_freed_index() {
+        let mut indices = IndexFactory::new(3, [7]);
+        assert_eq!(indices.next(), 3);
+        indices.free(2);
+        indices.free(1);
+        assert!(!indices.is_allocated(1));
+        assert_eq!(indices.next(), 1);
+        assert_eq!(indices.next(), 2);
+        assert_eq!(indices.next(), 4);
+        indices.prev();
+        assert!(!indices.is_allocated(4));
+        assert_eq!(indices.next(), 4);
+    }
+}
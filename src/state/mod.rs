@@ -1,13 +1,28 @@
 mod account;
+mod account_index;
 mod bank;
 mod batch;
 mod block;
+mod block_verifier;
+mod finalized;
+mod index_factory;
+#[allow(clippy::module_inception)]
 mod state;
+mod state_tree;
+mod work_generator;
 mod dag;
 
 pub use account::Account;
+pub use account_index::AccountIndex;
 pub use bank::Bank;
 pub use batch::Batch;
 pub use block::Block;
-pub use state::State;
+#[cfg(test)]
+pub use block::BlockBody;
+pub use block_verifier::BlockVerifier;
+pub use finalized::Finalized;
+pub use index_factory::IndexFactory;
+pub use state::{State, StateConfig};
+pub use state_tree::StateTree;
+pub use work_generator::WorkGenerator;
 pub use dag::Dag;
\ No newline at end of file
@@ -1,12 +1,70 @@
-use std::{sync::{Arc, Mutex}, time::Duration};
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufReader, Read}, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, Instant, SystemTime}};
 
 //use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-use crate::{keys::Public, storage::Database};
+use crate::{keys::Public, storage::{self, Flush}};
 
-use crate::{error, keys::{Hash, Identity, Private}, process::{self, Handle, Mailbox, Message, Process}, protocol::{Amount, Open, OpenFull, Slot, Task, Tx, TxFull}, util::Error};
+use crate::{error, log_error, log_warn, keys::{Difficulty, Hash, Identity}, process::{Handle, Mailbox, Message, Process}, protocol::{Amount, Epoch, LeaderSchedule, Open, Slot, Task, Tx, TxKind, Vote}, rpc::{Confirmation, DagNode, Page, RpcEnvelope, RpcErrorCode, RpcRequest, RpcResponse, Subscriptions}, util::{self, Context, Error, NodeInfo}};
 
-use super::{Bank, Block, Dag};
+use crate::network::{SyncRequest, SyncResponse, MTU, SYNC_RESPONSE_HEADER_LEN};
+use super::{AccountIndex, Bank, Batch, Block, BlockVerifier, Dag, Finalized, WorkGenerator};
+use rand::seq::SliceRandom;
+
+/// How many times opening the persistent stores is attempted while it fails transiently
+const OPEN_ATTEMPTS: u32 = 5;
+/// The wait before the first retry of opening the persistent stores, doubling on each retry
+const OPEN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The maximum number of slots returned by a single `leader_schedule` RPC
+const MAX_LEADER_SCHEDULE_SLOTS: u32 = 1024;
+/// The minimum number of peers for the node to be ready
+const READY_MIN_PEERS: usize = 1;
+/// How many blocks our finalized height may be behind the network's for the node to be ready
+const READY_MAX_HEIGHT_LAG: u64 = 20;
+/// How many slots the last finalized block may be behind the current slot before we sync
+const SYNC_MAX_SLOT_LAG: u64 = 20;
+/// The maximum number of blocks in a response to a `SyncRequest`
+const MAX_SYNC_BLOCKS: usize = 32;
+/// How long a `SyncRequest` awaits its response before another representative is asked
+const SYNC_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+/// The maximum number of blocks listed by a single `dag_dump` RPC
+const MAX_DAG_DUMP_NODES: u32 = 1024;
+/// The longest encoded block accepted by `verify_import`
+const MAX_IMPORT_BLOCK_LEN: usize = 1 << 26;
+
+/// The outcome of `State::verify_import`
+#[derive(Debug)]
+pub struct ImportReport {
+    /// The number of blocks verified before the first failure, or in all
+    pub verified: u64,
+    /// The index of the first block which failed verification, and why
+    pub failure: Option<(u64, Error)>
+}
+
+/// The settings of a `State`
+pub struct StateConfig {
+    /// The directory of the persistent stores
+    pub data_dir: String,
+    /// Whether to prune empty accounts at each epoch boundary of the finalized blocks
+    pub prune_empty_accounts: bool,
+    /// The accounts funded at genesis, besides the genesis leader
+    pub genesis_allocations: Vec<(Public, Amount)>,
+    /// The initial representatives, each allocated an amount which it represents itself
+    pub genesis_representatives: Vec<(Public, Amount)>,
+    /// The amount burned from the first funding of each opened account,
+    /// the same on every node of the network
+    pub genesis_open_fee: Amount,
+    /// The maximum number of representatives with pending votes
+    pub vote_pool_size: usize,
+    /// The most unfinalized blocks of a single leader kept at once
+    pub max_active_blocks_per_leader: usize,
+    /// How far ahead of local time the slot of a block may be
+    pub max_clock_skew: Duration,
+    /// Whether blocks must commit to the state of all accounts after applying them
+    pub verify_state_hash: bool,
+    /// The most finalized blocks kept in memory, older ones being spilled to disk
+    pub max_finalized_in_memory: usize
+}
 
 struct BlockEntry {
     block: Arc<Block>,
@@ -16,43 +74,992 @@ struct BlockEntry {
 pub struct State {
     /// My identity
     id: Identity,
-    /// Are we in leader mode?
-    leader_mode: bool,
-    /// The database of the longest chain
-    db: Database<Public, u64>,
+    /// The directory of the persistent stores
+    data_dir: String,
+    /// The account indices of the longest chain
+    accounts: Arc<AccountIndex>,
     /// The account state of the longest chain
     bank: Arc<Bank>,
-    /// All finalized blocks
-    finalized: Vec<Arc<Block>>,
+    /// All finalized blocks, the oldest of which are spilled to disk
+    finalized: Finalized,
     /// Last finalized block (root) plus all blocks that are not yet finalized
     active: Dag<Hash, Box<BlockEntry>>,
-    /// The slot of the block-in-construction
-    cur_slot: Option<Slot>,
-    /// The transactions of the block-in-construction
-    cur_txs: Option<Vec<Box<TxFull>>>,
-    /// The opens of the block-in-construction
-    cur_opens: Option<Vec<Box<OpenFull>>>
+    /// The latest verified vote of each representative, until the block it votes for is finalized
+    pending_votes: HashMap<Public, Box<Vote>>,
+    /// The maximum number of representatives with pending votes
+    vote_pool_size: usize,
+    /// The RPC clients notified of finalized transfers
+    subscriptions: Subscriptions,
+    /// The leader schedule of the current epoch
+    leader_schedule: LeaderSchedule,
+    /// The leader schedule of the epoch of the last block verified outside the current epoch
+    past_leader_schedule: LeaderSchedule,
+    /// The genesis leader and representatives, from whose weights leader schedules are drawn
+    representatives: Vec<Public>,
+    /// The minimum work difficulty of transactions and opens
+    work_threshold: Difficulty,
+    /// Generates work for `work_generate` RPCs, a limited number at once
+    work_generator: WorkGenerator,
+    /// The version and start time of this node
+    node_info: NodeInfo,
+    /// Throttles the verification of incoming blocks
+    block_verifier: BlockVerifier,
+    /// The amount burned from the first funding of each opened account
+    open_fee: Amount,
+    /// Whether to prune empty accounts at each epoch boundary of the finalized blocks
+    prune_empty_accounts: bool,
+    /// The indices of the accounts touched by finalized tasks since the last epoch boundary
+    epoch_activity: HashSet<u64>,
+    /// The transaction pools, which batch the accounts written by finalized
+    /// transactions to the db; empty until they're spawned
+    tx_pools: Vec<Handle>,
+    /// The number of peers, kept up to date by the `Transmitter`
+    peer_count: Arc<AtomicUsize>,
+    /// The number of slots evicted before being assembled, kept up to date by the `Assembler`
+    evicted_shred_lists: Arc<AtomicU64>,
+    /// The number of datagrams which failed to send, kept up to date by the `Broadcaster`
+    broadcast_send_errors: Arc<AtomicU64>,
+    /// The number of datagrams dropped by a full send queue, kept up to date by the `Broadcaster`
+    broadcast_dropped: Arc<AtomicU64>,
+    /// The batch the tasks of the last synced block were queued in
+    batch: Batch,
+    /// The most unfinalized blocks of a single leader kept in `active`
+    max_active_blocks_per_leader: usize,
+    /// The number of unfinalized blocks in `active`, by leader
+    active_per_leader: HashMap<Public, usize>,
+    /// How far ahead of local time the slot of a block may be
+    max_clock_skew: Duration,
+    /// Whether blocks must commit to the state of all accounts after applying them
+    verify_state_hash: bool,
+    /// The number of blocks finalized after genesis, shared with the `Transmitter` to report to peers
+    finalized_height: Arc<AtomicU64>,
+    /// The weighted median of the finalized heights reported by peers, kept up to date by the `Transmitter`
+    sync_target: Arc<AtomicU64>,
+    /// The hash of our `SyncRequest` awaiting a response, the representative it was sent to, and when
+    sync_request: Option<(Hash, Public, Instant)>,
+    /// The number of `SyncRequest`s sent, rotating them through the representatives
+    sync_requests_sent: usize
 }
 
 impl State {
-    pub fn new(identity: Identity, data_dir: &str, genesis_block: Arc<Block>) -> Result<Self, Error> {
+    /// Creates the `State` in `config.data_dir`, bootstrapping the accounts of
+    /// `genesis_block.leader`, `config.genesis_allocations` and `config.genesis_representatives`
+    /// if it is empty. The leader schedule is drawn from the genesis leader and
+    /// the genesis representatives, in proportion to their weights.
+    pub fn new(
+        identity: Identity,
+        genesis_block: Arc<Block>,
+        config: StateConfig,
+        work_generator: WorkGenerator,
+        node_info: NodeInfo,
+        block_verifier: BlockVerifier,
+        subscriptions: Subscriptions
+    ) -> Result<Self, Error> {
+        let (data_dir, genesis_allocations, genesis_representatives) = (
+            config.data_dir.as_str(),
+            config.genesis_allocations.as_slice(),
+            config.genesis_representatives.as_slice()
+        );
         if !genesis_block.is_genesis() {
             return Err(error!("invalid genesis block"));
         }
-        Ok(Self {
+        let mut genesis_accounts = HashSet::with_capacity(genesis_allocations.len() + 1);
+        genesis_accounts.insert(genesis_block.leader);
+        for (public, _) in genesis_allocations.iter().chain(genesis_representatives) {
+            if !genesis_accounts.insert(*public) {
+                return Err(error!("duplicate genesis allocation for {}", public.to_address()));
+            }
+        }
+        util::retry(OPEN_ATTEMPTS, OPEN_BACKOFF, || storage::prepare_data_dir(data_dir))?;
+        let finalized = Finalized::open(data_dir, genesis_block.clone(), config.max_finalized_in_memory)
+            .context("failed to open finalized blocks")?;
+        let open_accounts = || util::retry(OPEN_ATTEMPTS, OPEN_BACKOFF, || {
+            let bank = Bank::open_with_representatives(&storage::bank_dir(data_dir), genesis_block.leader, genesis_allocations, genesis_representatives)
+                .context("failed to open bank")?;
+            Ok((bank, AccountIndex::open(data_dir).context("failed to open account index")?))
+        });
+        // the accounts are only reopened if they were checkpointed at a height we have the
+        // blocks up to; otherwise they may disagree with the chain after a crash between
+        // flushes, so they're rebuilt from the finalized blocks
+        let reopened = match storage::take_checkpoint(data_dir)? {
+            Some(height) if height < finalized.len() as u64 => match open_accounts() {
+                Ok(stores) => Some((stores, height as usize)),
+                Err(e) => {
+                    log_warn!("Failed to reopen the accounts, rebuilding them: {}", e);
+                    None
+                }
+            },
+            _ => None
+        };
+        let ((bank, accounts), height) = match reopened {
+            Some(reopened) => reopened,
+            None => {
+                storage::remove_accounts(data_dir)?;
+                (open_accounts()?, 0)
+            }
+        };
+        let batch = bank.last_batch();
+        if accounts.len() == 0 {
+            accounts.insert(&genesis_block.leader, 0);
+            for (i, (public, _)) in genesis_allocations.iter().chain(genesis_representatives).enumerate() {
+                accounts.insert(public, i as u64 + 1);
+            }
+        }
+        // genesis accounts are represented by the genesis leader, unless they represent themselves
+        let representatives: Vec<Public> = Some(genesis_block.leader).into_iter()
+            .chain(genesis_representatives.iter().map(|&(public, _)| public))
+            .collect();
+        let mut state = Self {
             id: identity,
-            leader_mode: false,
+            data_dir: data_dir.to_string(),
+            accounts: Arc::new(accounts),
             active: Dag::new(genesis_block.hash, Box::new(BlockEntry {
                 block: genesis_block.clone(),
                 tasks: vec![],
             })),
-            bank: Arc::new(Bank::open(data_dir, genesis_block.leader)?),
-            finalized: vec![genesis_block],
-            cur_slot: None,
-            cur_txs: None,
-            cur_opens: None
+            bank: Arc::new(bank),
+            finalized,
+            pending_votes: HashMap::new(),
+            vote_pool_size: config.vote_pool_size,
+            subscriptions,
+            leader_schedule: LeaderSchedule::empty(),
+            past_leader_schedule: LeaderSchedule::empty(),
+            representatives,
+            work_threshold: Difficulty::BASE,
+            work_generator,
+            node_info,
+            block_verifier,
+            open_fee: config.genesis_open_fee,
+            prune_empty_accounts: config.prune_empty_accounts,
+            epoch_activity: HashSet::new(),
+            tx_pools: Vec::new(),
+            peer_count: Arc::new(AtomicUsize::new(0)),
+            evicted_shred_lists: Arc::new(AtomicU64::new(0)),
+            broadcast_send_errors: Arc::new(AtomicU64::new(0)),
+            broadcast_dropped: Arc::new(AtomicU64::new(0)),
+            batch,
+            max_active_blocks_per_leader: config.max_active_blocks_per_leader,
+            active_per_leader: HashMap::new(),
+            max_clock_skew: config.max_clock_skew,
+            verify_state_hash: config.verify_state_hash,
+            finalized_height: Arc::new(AtomicU64::new(0)),
+            sync_target: Arc::new(AtomicU64::new(0)),
+            sync_request: None,
+            sync_requests_sent: 0
+        };
+        state.replay_finalized(height)?;
+        state.refresh_leader_schedule();
+        Ok(state)
+    }
+    /// Apply the blocks restored from the last run after the one at `height`
+    /// to the accounts, which hold the state after it
+    fn replay_finalized(&mut self, height: usize) -> Result<(), Error> {
+        if height > 0 {
+            let block = self.finalized.get(height)?;
+            self.active = Dag::new(block.hash, Box::new(BlockEntry { block, tasks: vec![] }));
+            self.restore_epoch_activity(height)?;
+        }
+        for index in height + 1..self.finalized.len() {
+            let block = self.finalized.get(index)?;
+            let previous_epoch = self.active.get_root().1.block.slot.epoch();
+            let tasks = self.apply_tasks(&block).context(format!("failed to replay finalized block {}", index))?;
+            self.bank.finalize_tasks(&tasks);
+            self.active = Dag::new(block.hash, Box::new(BlockEntry { block: block.clone(), tasks: vec![] }));
+            self.on_finalized_tasks(previous_epoch, block.slot.epoch(), &tasks);
+        }
+        self.finalized_height.store(self.height(), Ordering::Relaxed);
+        Ok(())
+    }
+    /// Record the accounts touched by the finalized blocks of the epoch of the one at
+    /// `height`, up to it, as if they had been finalized by this run
+    fn restore_epoch_activity(&mut self, height: usize) -> Result<(), Error> {
+        if !self.prune_empty_accounts {
+            return Ok(());
+        }
+        let epoch = self.finalized.get(height)?.slot.epoch();
+        let start = self.finalized.partition_point(|block| block.slot.epoch() < epoch)?;
+        for index in start..=height {
+            let block = self.finalized.get(index)?;
+            for tx in block.transactions.iter() {
+                self.epoch_activity.extend([&tx.from, &tx.to].into_iter().filter_map(|public| self.accounts.index_of(public)));
+            }
+        }
+        Ok(())
+    }
+    /// Flush the accounts as they stand after the last finalized block, recording
+    /// its height so that the next run can reopen them rather than rebuild them.
+    /// The unfinalized blocks are discarded, since the accounts can't hold them apart.
+    fn checkpoint(&mut self) -> Result<(), Error> {
+        self.discard_active();
+        self.bank.flush()?;
+        self.accounts.flush()?;
+        storage::write_checkpoint(&self.data_dir, self.height())
+    }
+    /// Get the persistent stores of the `State`, to be flushed periodically
+    pub fn stores(&self) -> Vec<Arc<dyn Flush + Send + Sync>> {
+        vec![self.bank.clone(), self.accounts.clone()]
+    }
+    /// Get the index of the account of `public`, or `None` if it does not exist
+    pub fn account_index(&self, public: &Public) -> Option<u64> {
+        self.accounts.index_of(public)
+    }
+    /// Get the public key of the account at `index`, or `None` if it does not exist
+    pub fn account_public(&self, index: u64) -> Option<Public> {
+        self.accounts.public_of(index)
+    }
+    /// Is the block `hash` finalized? Returns `None` if the block is
+    /// neither finalized nor awaiting finalization.
+    pub fn block_confirmed(&self, hash: &Hash) -> Option<bool> {
+        if self.finalized.contains(hash) {
+            Some(true)
+        } else if self.active.get(hash).is_some() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+    /// Verify `vote` and keep it, replacing any pending vote from the same
+    /// representative, then finalize any block the pending votes are a quorum for
+    pub fn submit_vote(&mut self, vote: Box<Vote>) -> Result<Hash, Error> {
+        let hash = vote.verify_and_hash()?;
+        if self.pending_votes.len() >= self.vote_pool_size && !self.pending_votes.contains_key(&vote.from) {
+            return Err(error!("vote pool is full"));
+        }
+        self.pending_votes.insert(vote.from, vote);
+        self.finalize_voted();
+        Ok(hash)
+    }
+    /// Finalize the latest block of the longest chain which the pending votes are
+    /// a quorum for, keeping them as its proof, and drop the votes it settles
+    fn finalize_voted(&mut self) {
+        let (&longest_chain, _) = self.active.get_longest_chain();
+        let path = self.active.get_path_from_root(longest_chain).unwrap();
+        for &hash in path.iter().skip(1).rev() {
+            let slot = self.active.get(&hash).unwrap().block.slot;
+            let votes: Vec<Vote> = self.pending_votes.values()
+                .filter(|vote| vote.right.block == hash)
+                .map(|vote| vote.as_ref().clone())
+                .collect();
+            if votes.is_empty() || self.check_quorum(hash, slot, &votes).is_err() {
+                continue;
+            }
+            if let Err(e) = self.finalize_up_to(hash) {
+                log_warn!("Failed to finalize voted block {}: {}", hash, e);
+                return;
+            }
+            self.finalized.prove(self.finalized.len() - 1, votes);
+            let finalized = &self.finalized;
+            self.pending_votes.retain(|_, vote| !finalized.contains(&vote.right.block));
+            return;
+        }
+    }
+    /// Get a function looking up the representative weight of a public key
+    /// in the live account state, which is zero for unknown keys
+    pub fn weight_fn(&self) -> Box<dyn Fn(&Public) -> Amount + Send> {
+        let (accounts, bank) = (self.accounts.clone(), self.bank.clone());
+        Box::new(move |public| accounts
+            .index_of(public)
+            .and_then(|index| bank.get(index))
+            .map_or(Amount::zero(), |account| account.weight.load(Ordering::Relaxed)))
+    }
+    /// Get a function looking up the scheduled leader of a slot, drawing the
+    /// schedule of its epoch from the live representative weights
+    pub fn leader_fn(&self) -> Box<dyn Fn(Slot) -> Option<Public> + Send> {
+        let (weight_of, representatives) = (self.weight_fn(), self.representatives.clone());
+        let schedule = Mutex::new(LeaderSchedule::empty());
+        Box::new(move |slot| {
+            let mut schedule = schedule.lock().unwrap();
+            if schedule.leader_for(slot).is_none() {
+                fill_leader_schedule(&mut schedule, &representatives, &weight_of, slot.epoch());
+            }
+            schedule.leader_for(slot)
         })
     }
+    /// Get a function which checks whether an account has been opened
+    pub fn account_exists_fn(&self) -> Box<dyn Fn(&Public) -> bool + Send> {
+        let accounts = self.accounts.clone();
+        Box::new(move |public| accounts.index_of(public).is_some())
+    }
+    /// Get the leaders of `page` of the upcoming slots of `epoch`, or of the current
+    /// epoch if `None`, of at most `MAX_LEADER_SCHEDULE_SLOTS` slots. The upcoming
+    /// slots of the current epoch start at `now`, so offsets count from `now`.
+    /// Returns nothing for epochs whose schedule is not known.
+    pub fn leader_schedule(&self, epoch: Option<Epoch>, page: Page, now: Slot) -> RpcResponse {
+        let epoch = epoch.unwrap_or(now.epoch());
+        let first = match epoch.index_of(now) {
+            Some(index) => index,
+            None if epoch.get(0).unwrap() > now => 0,
+            None => Epoch::LEN,
+        };
+        let total = match epoch == self.leader_schedule.epoch {
+            true => (Epoch::LEN - first) as u64,
+            false => 0,
+        };
+        let range = page.range(total, MAX_LEADER_SCHEDULE_SLOTS);
+        let slots = match epoch.get(first + range.start as usize) {
+            Some(start) if !range.is_empty() => self.leader_schedule.upcoming(start, (range.end - range.start) as usize),
+            _ => vec![],
+        };
+        RpcResponse::LeaderSchedule { slots, total, has_more: range.end < total }
+    }
+    /// Is this node the leader of `slot`, and which is the next slot it leads?
+    fn is_leader(&self, slot: Slot) -> RpcResponse {
+        RpcResponse::IsLeader {
+            leader: self.leader_schedule.leader_for(slot) == Some(self.id.public),
+            current_slot: slot,
+            next_leader_slot: self.leader_schedule.next_slot_led_by(&self.id.public, slot.next())
+        }
+    }
+    /// The current work threshold for admission, and its multiplier relative to `Difficulty::BASE`
+    fn work_threshold(&self) -> RpcResponse {
+        RpcResponse::WorkThreshold {
+            difficulty: self.work_threshold,
+            multiplier: self.work_threshold.multiplier(Difficulty::BASE)
+        }
+    }
+    /// Get the number of peers, for the `Transmitter` to keep up to date
+    pub fn peer_count(&self) -> Arc<AtomicUsize> {
+        self.peer_count.clone()
+    }
+    /// Get our finalized height, for the `Transmitter` to report to peers
+    pub fn finalized_height(&self) -> Arc<AtomicU64> {
+        self.finalized_height.clone()
+    }
+    /// Get the height to sync to, for the `Transmitter` to keep up to date
+    pub fn sync_target(&self) -> Arc<AtomicU64> {
+        self.sync_target.clone()
+    }
+    pub fn evicted_shred_lists(&self) -> Arc<AtomicU64> {
+        self.evicted_shred_lists.clone()
+    }
+    /// Get the number of datagrams which failed to send, for the `Broadcaster` to keep up to date
+    pub fn broadcast_send_errors(&self) -> Arc<AtomicU64> {
+        self.broadcast_send_errors.clone()
+    }
+    /// Get the number of datagrams dropped by a full send queue, for the `Broadcaster` to keep up to date
+    pub fn broadcast_dropped(&self) -> Arc<AtomicU64> {
+        self.broadcast_dropped.clone()
+    }
+    /// Is the node ready to serve: connected to peers, and finalized to within
+    /// `READY_MAX_HEIGHT_LAG` blocks of the height reported by the network?
+    fn ready(&self) -> Result<RpcResponse, String> {
+        let peers = self.peer_count.load(Ordering::Relaxed);
+        let finalized_slot = self.active.get_root().1.block.slot;
+        if peers < READY_MIN_PEERS {
+            return Err(format!("{} peers, of at least {}", peers, READY_MIN_PEERS));
+        }
+        let lag = self.sync_target.load(Ordering::Relaxed).saturating_sub(self.height());
+        if lag > READY_MAX_HEIGHT_LAG {
+            return Err(format!("finalized {} blocks behind the network, of at most {}", lag, READY_MAX_HEIGHT_LAG));
+        }
+        Ok(RpcResponse::Ready { peers, finalized_slot })
+    }
+    /// Get how many slots the last finalized block is behind the current slot
+    fn finalized_lag(&self) -> u64 {
+        Slot::now().saturating_sub(self.active.get_root().1.block.slot)
+    }
+    /// Get the finalized blocks after `after`, in order, up to `MAX_SYNC_BLOCKS` of them,
+    /// ending at the last block whose finality we have votes for, and those votes.
+    /// The blocks and votes total at most `max_len` bytes when encoded.
+    pub fn finalized_after(&self, after: Slot, max_len: usize) -> (Vec<Block>, Vec<Vote>) {
+        let mut len = 0;
+        let mut blocks = Vec::new();
+        let mut proven = (0, vec![]);
+        let Ok(start) = self.finalized.partition_point(|block| block.slot <= after) else {
+            return (blocks, vec![]);
+        };
+        for index in (start..self.finalized.len()).take(MAX_SYNC_BLOCKS) {
+            let Ok(block) = self.finalized.get(index) else {
+                break;
+            };
+            len += encoded_len(block.as_ref());
+            if len > max_len {
+                break;
+            }
+            blocks.push(block.as_ref().clone());
+            if let Some(votes) = self.finalized.proof(index) {
+                if len + votes.iter().map(encoded_len).sum::<usize>() <= max_len {
+                    proven = (blocks.len(), votes.to_vec());
+                }
+            }
+        }
+        blocks.truncate(proven.0);
+        (blocks, proven.1)
+    }
+    /// Build the tasks of the opens and then the transactions of `block`. The accounts
+    /// of its opens are added right away, so that its transactions can refer to them,
+    /// and are vacated again if it fails. The open fee is charged once per newly
+    /// funded account, by the first transfer to it in `block`.
+    fn tasks_of(&self, block: &Block) -> Result<Vec<Task>, Error> {
+        let mut tasks = Vec::with_capacity(block.opens.len() + block.transactions.len());
+        for open in block.opens.iter() {
+            match self.open_task(open) {
+                Ok(task) => tasks.push(task),
+                Err(e) => {
+                    self.revert_tasks(&tasks);
+                    return Err(e);
+                }
+            }
+        }
+        let mut funded = HashSet::new();
+        for tx in block.transactions.iter() {
+            match self.tx_task(tx, &mut funded) {
+                Ok(task) => tasks.push(task),
+                Err(e) => {
+                    self.revert_tasks(&tasks[..block.opens.len()]);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(tasks)
+    }
+    /// Build the task of `tx`, charging the open fee if its receiver isn't in `funded` yet
+    fn tx_task(&self, tx: &Tx, funded: &mut HashSet<u64>) -> Result<Task, Error> {
+        let index_of = |public| self.accounts.index_of(public)
+            .ok_or_else(|| error!("unknown account {}", public.to_address()));
+        let (from_index, to_index) = (index_of(&tx.from)?, index_of(&tx.to)?);
+        match tx.kind() {
+            TxKind::ChangeRepresentative => Ok(Task { nonce: tx.nonce, from_index, amount: Amount::zero(), to_index, burn: Amount::zero(), open: false }),
+            TxKind::Transfer(amount) => self.bank
+                .transfer_task(tx.nonce, from_index, amount, to_index, match funded.insert(to_index) {
+                    true => self.open_fee,
+                    false => Amount::zero()
+                })
+                .ok_or_else(|| error!("unknown account {}", tx.to.to_address()))
+        }
+    }
+    /// Build the task of `open`, adding its account. An account which was pruned
+    /// starts at the nonce it was pruned with, so that what it signed before can't
+    /// be replayed.
+    fn open_task(&self, open: &Open) -> Result<Task, Error> {
+        if self.accounts.index_of(&open.account).is_some() {
+            return Err(error!("account {} is already open", open.account.to_address()));
+        }
+        let rep_index = match open.is_self_delegating() {
+            true => None,
+            false => Some(self.accounts.index_of(&open.representative)
+                .ok_or_else(|| error!("unknown representative {}", open.representative.to_address()))?),
+        };
+        let nonce = self.accounts.retired_nonce(&open.account).unwrap_or(0);
+        let task = self.bank.open_task(open.account, rep_index, nonce)?;
+        self.accounts.insert(&open.account, task.from_index);
+        Ok(task)
+    }
+    /// Revert `tasks` in reverse order, forgetting the accounts they opened
+    fn revert_tasks(&self, tasks: &[Task]) {
+        for task in tasks.iter().rev() {
+            if task.open {
+                self.accounts.remove(task.from_index);
+            }
+            self.bank.revert_task(task);
+        }
+    }
+    /// Insert a block after `previous` into the active `Dag`, unless its leader
+    /// already has `max_active_blocks_per_leader` unfinalized blocks in it
+    fn insert_active(&mut self, hash: Hash, entry: Box<BlockEntry>, previous: Hash) -> Result<(), Error> {
+        let leader = entry.block.leader;
+        let count = self.active_per_leader.get(&leader).copied().unwrap_or(0);
+        if count >= self.max_active_blocks_per_leader {
+            return Err(error!("leader {} has too many active blocks", leader.to_address()));
+        }
+        self.active.insert(hash, entry, previous)?;
+        self.active_per_leader.insert(leader, count + 1);
+        Ok(())
+    }
+    /// Recount the unfinalized blocks of each leader, after blocks leave `active`
+    fn recount_active(&mut self) {
+        let (&root, _) = self.active.get_root();
+        self.active_per_leader.clear();
+        for (_, entry) in self.active.iter().filter(|&(&h, _)| h != root) {
+            *self.active_per_leader.entry(entry.block.leader).or_insert(0) += 1;
+        }
+    }
+    /// Draw the leader schedule of the current epoch once it has begun
+    fn refresh_leader_schedule(&mut self) {
+        let epoch = Slot::now().epoch();
+        if self.leader_schedule.epoch != epoch {
+            let weight_of = self.weight_fn();
+            fill_leader_schedule(&mut self.leader_schedule, &self.representatives, weight_of, epoch);
+        }
+    }
+    /// Get the scheduled leader of `slot`, drawing the schedule of its epoch
+    /// from the current weights if it isn't the current epoch
+    fn scheduled_leader(&mut self, slot: Slot) -> Option<Public> {
+        if let Some(leader) = self.leader_schedule.leader_for(slot) {
+            return Some(leader);
+        }
+        if self.past_leader_schedule.leader_for(slot).is_none() {
+            let weight_of = self.weight_fn();
+            fill_leader_schedule(&mut self.past_leader_schedule, &self.representatives, weight_of, slot.epoch());
+        }
+        self.past_leader_schedule.leader_for(slot)
+    }
+    /// Verify `block`, which must be led by the scheduled leader of its slot,
+    /// and whose slot must not be more than `max_clock_skew` ahead of local time
+    fn verify_block(&mut self, block: &Block) -> Result<Hash, Error> {
+        self.check_leader(block)?;
+        self.block_verifier.verify_and_hash(block)
+    }
+    /// Check that `block` is led by the scheduled leader of its slot, and that
+    /// its slot is not more than `max_clock_skew` ahead of local time
+    fn check_leader(&mut self, block: &Block) -> Result<(), Error> {
+        block.check_skew(SystemTime::now(), self.max_clock_skew)?;
+        if self.scheduled_leader(block.slot) != Some(block.leader) {
+            return Err(error!("block isn't from the scheduled leader of its slot"));
+        }
+        Ok(())
+    }
+    /// Check that `votes` are each for the block `hash` at `slot`, and are from
+    /// representatives holding more than two thirds of the supply's weight
+    fn check_quorum(&self, hash: Hash, slot: Slot, votes: &[Vote]) -> Result<(), Error> {
+        let weight_of = self.weight_fn();
+        let mut voters = HashSet::with_capacity(votes.len());
+        let mut weight = 0u128;
+        for vote in votes {
+            if vote.right.block != hash || vote.right.slot != slot {
+                return Err(error!("vote is for another block"));
+            }
+            vote.verify_and_hash()?;
+            if voters.insert(vote.from) {
+                weight += weight_of(&vote.from).to_raw() as u128;
+            }
+        }
+        if weight * 3 <= Amount::initial_supply().to_raw() as u128 * 2 {
+            return Err(error!("votes don't hold a quorum of the weight"));
+        }
+        Ok(())
+    }
+    /// Verify that `blocks` extend the last finalized block, each led by the scheduled
+    /// leader of its slot, and that `votes` are a quorum for the last of them, which
+    /// finalizes it and all before it. Then apply and finalize them in order.
+    pub fn apply_proven(&mut self, blocks: Vec<Block>, votes: Vec<Vote>) -> Result<(), Error> {
+        let (&root, root_entry) = self.active.get_root();
+        let (mut previous, mut slot) = (root, root_entry.block.slot);
+        for block in blocks.iter() {
+            if block.previous != previous || block.slot <= slot {
+                return Err(error!("block doesn't follow the last finalized block"));
+            }
+            self.check_leader(block)?;
+            previous = block.hash;
+            slot = block.slot;
+        }
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        // the blocks are chained by their claimed hashes, which verification checks
+        let hashes = self.block_verifier.verify_all(&blocks)?;
+        self.check_quorum(previous, slot, &votes)?;
+        for (block, hash) in blocks.into_iter().zip(hashes) {
+            self.apply_finalized(block, hash)?;
+        }
+        self.finalized.prove(self.finalized.len() - 1, votes);
+        Ok(())
+    }
+    /// Apply the transactions of `block` to the `Bank`, checking its state hash
+    /// if required, and get their tasks. Nothing is applied if any is invalid.
+    fn apply_tasks(&mut self, block: &Block) -> Result<Vec<Task>, Error> {
+        let tasks = self.tasks_of(block)?;
+        let batch = self.batch.next();
+        for (i, task) in tasks.iter().enumerate() {
+            if self.bank.queue_task(task, batch).is_err() {
+                // the accounts of the opens after `task` were added, but not queued
+                let unqueued: Vec<Task> = tasks[i..].iter().filter(|task| task.open).copied().collect();
+                self.revert_tasks(&unqueued);
+                self.revert_tasks(&tasks[..i]);
+                return Err(error!("block has an invalid transaction"));
+            }
+            self.bank.finish_task(task);
+        }
+        if self.verify_state_hash && self.bank.state_root() != block.state_hash {
+            self.revert_tasks(&tasks);
+            return Err(error!("block state hash doesn't match the state of the accounts"));
+        }
+        self.batch = batch;
+        Ok(tasks)
+    }
+    /// Revert the blocks after the last finalized one, removing them from `active`
+    fn discard_active(&mut self) {
+        let (&longest_chain, _) = self.active.get_longest_chain();
+        let path = self.active.get_path_from_root(longest_chain).unwrap();
+        for h in path.iter().skip(1).rev() {
+            self.revert_tasks(&self.active.get(h).unwrap().tasks);
+        }
+        self.active.truncate();
+        self.active_per_leader.clear();
+    }
+    /// Apply and finalize `block`, which must be verified, have the hash `hash`,
+    /// and directly follow the last finalized block. If it's already active it is
+    /// finalized as is, otherwise the active blocks it competes with are discarded.
+    fn apply_finalized(&mut self, block: Block, hash: Hash) -> Result<(), Error> {
+        let (&root, root_entry) = self.active.get_root();
+        if block.previous != root || block.slot <= root_entry.block.slot {
+            return Err(error!("block doesn't follow the last finalized block"));
+        }
+        if self.active.get(&hash).is_some() {
+            return self.finalize_up_to(hash);
+        }
+        self.discard_active();
+        let tasks = self.apply_tasks(&block)?;
+        let entry = Box::new(BlockEntry { block: Arc::new(block), tasks: tasks.clone() });
+        if let Err(e) = self.insert_active(hash, entry, root) {
+            self.revert_tasks(&tasks);
+            return Err(e);
+        }
+        if let Err(e) = self.finalize_up_to(hash) {
+            self.active.remove(hash)?;
+            self.recount_active();
+            self.revert_tasks(&tasks);
+            return Err(e);
+        }
+        Ok(())
+    }
+    /// Verify a `block` assembled from the shreds of its slot, and apply it
+    /// as the new head of the longest chain, which it must extend. It's
+    /// finalized right away if the pending votes are a quorum for it.
+    pub fn on_block(&mut self, block: Block) -> Result<(), Error> {
+        let (&head, head_entry) = self.active.get_longest_chain();
+        if block.previous != head || block.slot <= head_entry.block.slot {
+            return Err(error!("block doesn't extend the longest chain"));
+        }
+        let hash = self.verify_block(&block)?;
+        let tasks = self.apply_tasks(&block)?;
+        let entry = Box::new(BlockEntry { block: Arc::new(block), tasks: tasks.clone() });
+        if let Err(e) = self.insert_active(hash, entry, head) {
+            self.revert_tasks(&tasks);
+            return Err(e);
+        }
+        self.finalize_voted();
+        Ok(())
+    }
+    /// Verify the chain of blocks read from `reader`, each encoded and prefixed by
+    /// its length as a `u32`, without applying any of them. The first block must be
+    /// one this node has finalized or follow one, and each must follow the one before.
+    pub fn verify_import(&self, mut reader: impl Read) -> ImportReport {
+        let mut report = ImportReport { verified: 0, failure: None };
+        let mut previous: Option<(Hash, Slot)> = None;
+        loop {
+            let block = match read_import_block(&mut reader) {
+                Ok(Some(block)) => block,
+                Ok(None) => return report,
+                Err(e) => {
+                    report.failure = Some((report.verified, e));
+                    return report;
+                }
+            };
+            match self.verify_import_block(&block, previous) {
+                Ok(hash) => {
+                    previous = Some((hash, block.slot));
+                    report.verified += 1;
+                }
+                Err(e) => {
+                    report.failure = Some((report.verified, e));
+                    return report;
+                }
+            }
+        }
+    }
+    fn verify_import_block(&self, block: &Block, previous: Option<(Hash, Slot)>) -> Result<Hash, Error> {
+        let hash = self.block_verifier.verify_and_hash(block)?;
+        match previous {
+            Some((previous, slot)) if block.previous != previous || block.slot <= slot => {
+                Err(error!("block doesn't follow the previous block"))
+            }
+            None if !self.finalized.contains(&hash) && !self.finalized.contains(&block.previous) => {
+                Err(error!("block doesn't follow a finalized block"))
+            }
+            _ => Ok(hash)
+        }
+    }
+    /// Answer a peer's `SyncRequest` to us with the finalized blocks after the requested slot
+    fn on_sync_request(&self, transmitter: Handle, sync_request: SyncRequest) {
+        if sync_request.to != self.id.public || sync_request.from == self.id.public || sync_request.verify().is_err() {
+            return;
+        }
+        let (blocks, votes) = self.finalized_after(sync_request.after, MTU - SYNC_RESPONSE_HEADER_LEN);
+        if !blocks.is_empty() {
+            let sync_response = SyncResponse::new(self.id.private, sync_request.hash(), blocks, votes);
+            transmitter.send(Message::SyncResponse(Box::new((sync_request.from, sync_response))));
+        }
+    }
+    /// Get the number of blocks finalized after genesis
+    fn height(&self) -> u64 {
+        self.finalized.len() as u64 - 1
+    }
+    fn sync_status(&self) -> RpcResponse {
+        let (current, target) = (self.height(), self.sync_target.load(Ordering::Relaxed).max(self.height()));
+        RpcResponse::SyncStatus {
+            syncing: current < target,
+            current,
+            target,
+            percent: match target {
+                0 => 100.0,
+                _ => current as f64 * 100.0 / target as f64
+            }
+        }
+    }
+    /// Apply the finalized blocks answering our outstanding `SyncRequest`, from the
+    /// representative it was sent to, then request the next ones if still behind
+    fn on_sync_blocks(&mut self, transmitter: Handle, sync_response: SyncResponse) {
+        match self.sync_request {
+            Some((request, to, _)) if sync_response.request == request && sync_response.from == to => {}
+            _ => return,
+        }
+        if sync_response.verify().is_err() {
+            return;
+        }
+        self.sync_request = None;
+        if let Err(e) = self.apply_proven(sync_response.blocks, sync_response.votes) {
+            log_warn!("Failed to apply finalized blocks from {}: {}", sync_response.from.to_address(), e);
+            return;
+        }
+        self.on_sync_tick(transmitter);
+    }
+    /// Request the finalized blocks after our last one from the next representative
+    /// if we're behind, unless our last request may still be answered
+    fn on_sync_tick(&mut self, transmitter: Handle) {
+        if self.finalized_lag() <= SYNC_MAX_SLOT_LAG {
+            return;
+        }
+        if matches!(self.sync_request, Some((_, _, sent)) if sent.elapsed() < SYNC_REQUEST_TIMEOUT) {
+            return;
+        }
+        let targets: Vec<Public> = self.representatives.iter().copied().filter(|&public| public != self.id.public).collect();
+        if targets.is_empty() {
+            return;
+        }
+        let to = targets[self.sync_requests_sent % targets.len()];
+        self.sync_requests_sent += 1;
+        let after = self.active.get_root().1.block.slot;
+        let sync_request = SyncRequest::new(self.id.private, to, after);
+        self.sync_request = Some((sync_request.hash(), to, Instant::now()));
+        transmitter.send(Message::SendSyncRequest(Box::new(sync_request)));
+    }
+    /// The version, start time and uptime of this node
+    fn telemetry(&self) -> RpcResponse {
+        let start_time_ms = self.node_info
+            .start_time()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |x| x.as_millis() as u64);
+        RpcResponse::Telemetry {
+            version: self.node_info.version,
+            current_slot: Slot::now(),
+            start_time_ms,
+            uptime_ms: self.node_info.uptime().as_millis() as u64,
+            evicted_shred_lists: self.evicted_shred_lists.load(Ordering::Relaxed),
+            broadcast_send_errors: self.broadcast_send_errors.load(Ordering::Relaxed),
+            broadcast_dropped: self.broadcast_dropped.load(Ordering::Relaxed)
+        }
+    }
+    fn block_count(&self) -> RpcResponse {
+        let (head, entry) = self.active.get_longest_chain();
+        RpcResponse::BlockCount {
+            finalized: self.finalized.len() as u64,
+            // the root of the active blocks is the last finalized block
+            active: self.active.len() as u64 - 1,
+            head: *head,
+            slot: entry.block.slot
+        }
+    }
+    /// Describe `page` of the active blocks, nearest the root first, of at most `MAX_DAG_DUMP_NODES` blocks
+    fn dag_dump(&self, page: Page) -> RpcResponse {
+        let mut nodes: Vec<DagNode> = self.active
+            .iter()
+            .map(|(hash, entry)| DagNode {
+                hash: *hash,
+                previous: entry.block.previous,
+                slot: entry.block.slot,
+                leader: entry.block.leader,
+                height: self.active.height(hash).unwrap()
+            })
+            .collect();
+        let total = nodes.len() as u64;
+        let range = page.range(total, MAX_DAG_DUMP_NODES);
+        nodes.sort_unstable_by_key(|node| (node.height, node.hash));
+        nodes.truncate(range.end as usize);
+        nodes.drain(..range.start as usize);
+        RpcResponse::DagDump {
+            root: *self.active.get_root().0,
+            longest_chain: *self.active.get_longest_chain().0,
+            total,
+            nodes,
+            has_more: range.end < total
+        }
+    }
+    /// Generate work for `subject` without blocking the `State`, responding
+    /// with an error if none is found in time, or too many generations are waiting
+    fn work_generate(&self, rpc_sender: Handle, id: u64, subject: Hash, difficulty: Option<Difficulty>) {
+        let threshold = difficulty.unwrap_or(self.work_threshold);
+        self.work_generator.generate(subject, threshold, move |result| {
+            let result = result.map(RpcResponse::WorkGenerate);
+            rpc_sender.send(Message::RpcResponse(Box::new(RpcEnvelope { id, result })));
+        });
+    }
+    fn on_rpc_request(&mut self, rpc_sender: Handle, id: u64, request: RpcRequest) {
+        let response = match request {
+            RpcRequest::WorkGenerate(subject, difficulty) => {
+                self.work_generate(rpc_sender, id, subject, difficulty);
+                return;
+            }
+            RpcRequest::LeaderSchedule(epoch, page) => self.leader_schedule(epoch, page, Slot::now()),
+            RpcRequest::VerifyImport(path) => match File::open(&path) {
+                Ok(file) => {
+                    let report = self.verify_import(BufReader::new(file));
+                    RpcResponse::VerifyImport {
+                        verified: report.verified,
+                        failure: report.failure.map(|(index, e)| (index, e.to_string())),
+                    }
+                }
+                Err(e) => {
+                    rpc_sender.send(Message::RpcResponse(Box::new(
+                        RpcEnvelope::err(id, RpcErrorCode::Failed, format!("failed to open {}: {}", path, e))
+                    )));
+                    return;
+                }
+            },
+            RpcRequest::IsLeader => self.is_leader(Slot::now()),
+            RpcRequest::WorkThreshold => self.work_threshold(),
+            RpcRequest::AccountIndex(public) => RpcResponse::AccountIndex(self.account_index(&public)),
+            RpcRequest::AccountPublic(index) => RpcResponse::AccountPublic(self.account_public(index)),
+            RpcRequest::BlockConfirmed(hash) => RpcResponse::BlockConfirmed(self.block_confirmed(&hash)),
+            RpcRequest::Vote(vote) => match self.submit_vote(vote) {
+                Ok(hash) => RpcResponse::Vote(hash),
+                Err(e) => {
+                    rpc_sender.send(Message::RpcResponse(Box::new(
+                        RpcEnvelope::err(id, RpcErrorCode::Failed, e.to_string())
+                    )));
+                    return;
+                }
+            },
+            RpcRequest::Subscribe(accounts) => match self.subscriptions.subscribe(rpc_sender.clone(), id, accounts) {
+                Ok(()) => RpcResponse::Subscribed,
+                Err(e) => {
+                    rpc_sender.send(Message::RpcResponse(Box::new(
+                        RpcEnvelope::err(id, RpcErrorCode::TooManySubscriptions, e.to_string())
+                    )));
+                    return;
+                }
+            },
+            RpcRequest::Telemetry => self.telemetry(),
+            RpcRequest::Health => RpcResponse::Health,
+            RpcRequest::SyncStatus => self.sync_status(),
+            RpcRequest::DagDump(page) => self.dag_dump(page),
+            RpcRequest::BlockCount => self.block_count(),
+            RpcRequest::Ready => match self.ready() {
+                Ok(response) => response,
+                Err(e) => {
+                    rpc_sender.send(Message::RpcResponse(Box::new(
+                        RpcEnvelope::err(id, RpcErrorCode::NotReady, e)
+                    )));
+                    return;
+                }
+            },
+            _ => return
+        };
+        rpc_sender.send(Message::RpcResponse(Box::new(RpcEnvelope::ok(id, response))));
+    }
+    /// Finalize all blocks from the current root up to and including `target`
+    /// in a single walk, with a single `set_root` at the end.
+    /// `target` must be on the longest chain, so that all blocks
+    /// being finalized have already been processed by the `Bank`.
+    pub fn finalize_up_to(&mut self, target: Hash) -> Result<(), Error> {
+        let (&longest_chain, _) = self.active.get_longest_chain();
+        match self.active.get_common_ancestor(target, longest_chain) {
+            Some((&h, _)) if h == target => {}
+            Some(_) => return Err(error!("can't batch finalize a block off the longest chain")),
+            None => return Err(error!("can't find block in DAG")),
+        }
+        let path_from_root_to_target = self.active.get_path_from_root(target).unwrap();
+        let mut written = HashMap::new();
+        let mut previous_epoch = self.active.get_root().1.block.slot.epoch();
+        for &h in path_from_root_to_target.iter().skip(1) {
+            let entry = self.active.get(&h).unwrap();
+            let (epoch, tasks) = (entry.block.slot.epoch(), entry.tasks.clone());
+            self.bank.finalize_tasks(&tasks);
+            self.finalized.push(entry.block.clone());
+            for task in tasks.iter() {
+                if let (Some(from), Some(to)) = (self.accounts.public_of(task.from_index), self.accounts.public_of(task.to_index)) {
+                    self.subscriptions.dispatch(&Confirmation::of_task(h, from, to, task));
+                    written.insert(from, task.from_index);
+                    written.insert(to, task.to_index);
+                }
+            }
+            // accounts are pruned right after the first block of an epoch is finalized,
+            // so that every node prunes the same accounts however it batches finalization
+            if self.prune_empty_accounts && epoch != previous_epoch {
+                self.active.set_root(h)?;
+                self.recount_active();
+                self.on_finalized_tasks(previous_epoch, epoch, &tasks);
+                self.finalized_height.store(self.height(), Ordering::Relaxed);
+                self.send_finalized_accounts(written.into_iter().collect());
+                return match h == target {
+                    true => Ok(()),
+                    false => self.finalize_up_to(target)
+                };
+            }
+            self.on_finalized_tasks(previous_epoch, epoch, &tasks);
+            previous_epoch = epoch;
+        }
+        self.active.set_root(target)?;
+        self.recount_active();
+        self.finalized_height.store(self.height(), Ordering::Relaxed);
+        self.send_finalized_accounts(written.into_iter().collect());
+        Ok(())
+    }
+    /// Record the accounts touched by the `tasks` of a finalized block of `epoch`, which
+    /// is the root of `active` and follows a block of `previous_epoch`. If it starts an
+    /// epoch and pruning is enabled, the accounts untouched since the last epoch boundary
+    /// are pruned if they hold and represent nothing.
+    fn on_finalized_tasks(&mut self, previous_epoch: Epoch, epoch: Epoch, tasks: &[Task]) {
+        if !self.prune_empty_accounts {
+            return;
+        }
+        let touched = tasks.iter().flat_map(|task| [task.from_index, task.to_index]);
+        if epoch == previous_epoch {
+            self.epoch_activity.extend(touched);
+            return;
+        }
+        let mut active = std::mem::replace(&mut self.epoch_activity, touched.collect());
+        active.extend(self.epoch_activity.iter());
+        self.prune_accounts(|index| active.contains(&index));
+    }
+    /// Prune the accounts which hold and represent nothing once finalized, and aren't
+    /// `is_active`. The unfinalized blocks are reverted first and applied again after,
+    /// so that they see the pruned accounts as a node applying them later does; those
+    /// which no longer apply are discarded along with the blocks after them.
+    fn prune_accounts(&mut self, is_active: impl Fn(u64) -> bool) {
+        let (&root, _) = self.active.get_root();
+        let (&head, _) = self.active.get_longest_chain();
+        let chain: Vec<Arc<Block>> = self.active.get_path_from_root(head).unwrap().iter().skip(1)
+            .map(|h| self.active.get(h).unwrap().block.clone())
+            .collect();
+        self.discard_active();
+        let pruned = self.bank.prune(is_active);
+        for &(index, nonce) in pruned.iter() {
+            self.accounts.retire(index, nonce);
+        }
+        let mut previous = root;
+        for block in chain {
+            let Ok(tasks) = self.apply_tasks(&block) else {
+                break;
+            };
+            let hash = block.hash;
+            if self.insert_active(hash, Box::new(BlockEntry { block, tasks: tasks.clone() }), previous).is_err() {
+                self.revert_tasks(&tasks);
+                break;
+            }
+            previous = hash;
+        }
+    }
+    /// Send the accounts written by finalized transactions to one of the
+    /// transaction pools, to be batched to the db
+    fn send_finalized_accounts(&self, accounts: Vec<(Public, u64)>) {
+        if accounts.is_empty() {
+            return;
+        }
+        if let Some(tx_pool) = self.tx_pools.choose(&mut rand::thread_rng()) {
+            tx_pool.send(Message::FinalizedAccounts(Box::new(accounts)));
+        }
+    }
     /*pub fn finalize_hash(&mut self, h: Hash) -> Result<(), Error> {
         // find the common ancestor of the longest chain and the block to finalize
         let (&longest_chain, _) = self.active.get_longest_chain();
@@ -256,18 +1263,50 @@ impl State {
             self.id,
             slot,
             *self.active.get_longest_chain().0,
-            opens,
-            open_hashes,
-            txs,
-            tx_hashes,
-            vec![],
-            vec![]
+            BlockBody { opens, open_hashes, transactions: txs, tx_hashes, ..BlockBody::default() }
         );
         self.add_block(Arc::new(block), tasks);
         Ok(())
     }*/
 }
 
+/// Fill `schedule` for `epoch` from those of `representatives` holding any weight,
+/// or from the first of them, the genesis leader, if none do
+fn fill_leader_schedule(schedule: &mut LeaderSchedule, representatives: &[Public], weight_of: impl Fn(&Public) -> Amount, epoch: Epoch) {
+    let voters: Vec<Public> = representatives.iter()
+        .copied()
+        .filter(|public| weight_of(public) > Amount::zero())
+        .collect();
+    match voters.is_empty() {
+        true => schedule.fill(vec![representatives[0]], |_| Amount::initial_supply(), epoch),
+        false => schedule.fill(voters, weight_of, epoch),
+    }
+}
+
+/// Get the length of `value` when encoded
+fn encoded_len(value: &impl bincode::Encode) -> usize {
+    let mut bytes = Vec::new();
+    util::encode_into_writer(&mut bytes, value).unwrap();
+    bytes.len()
+}
+
+/// Read the next length-prefixed block, or `None` at the end of `reader`
+fn read_import_block(reader: &mut impl Read) -> Result<Option<Block>, Error> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_IMPORT_BLOCK_LEN {
+        return Err(error!("block of {} bytes exceeds the maximum of {}", len, MAX_IMPORT_BLOCK_LEN));
+    }
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(util::decode_from_slice(&bytes)?))
+}
+
 impl Process for State {
     const NAME: &'static str = "State";
     const RESTART_ON_CRASH: bool = true;
@@ -299,7 +1338,1234 @@ impl Process for State {
             }
         }*/
         loop {
-            process::sleep(Duration::from_secs(1));
+            match mailbox.recv() {
+                Message::TxPools(tx_pools) => {
+                    self.tx_pools = *tx_pools;
+                }
+                Message::RpcRequest(v) => {
+                    let (rpc_sender, id, request) = *v;
+                    self.on_rpc_request(rpc_sender, id, request);
+                }
+                Message::SyncRequest(v) => {
+                    let (transmitter, sync_request) = *v;
+                    self.on_sync_request(transmitter, sync_request);
+                }
+                Message::SyncBlocks(v) => {
+                    let (transmitter, blocks) = *v;
+                    self.on_sync_blocks(transmitter, blocks);
+                }
+                Message::SyncTick(transmitter) => {
+                    self.refresh_leader_schedule();
+                    self.on_sync_tick(*transmitter);
+                }
+                Message::Block(block) => {
+                    let leader = block.leader;
+                    if let Err(e) = self.on_block(*block) {
+                        log_warn!("Rejected a block from {}: {}", leader.to_address(), e);
+                    }
+                }
+                Message::Stop(stopped) => {
+                    if let Err(e) = self.checkpoint() {
+                        log_error!("Failed to checkpoint the accounts, they'll be rebuilt on restart: {}", e);
+                    }
+                    stopped.send(Message::Stopped);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::{work_fixtures, Seed, Work}, process, protocol::{Pair, TxKind}, rpc::ConfirmationKind, state::BlockBody, util::Version};
+
+    const N_BLOCKS: u64 = 50;
+
+    fn test_dir(name: &str) -> String {
+        format!(
+            "{}/starlight_state_{}_{}",
+            std::env::temp_dir().display(),
+            name,
+            Hash::random()
+        )
+    }
+
+    /// The settings of a test `State` in `data_dir`, funding only the genesis leader
+    fn test_config(data_dir: &str) -> StateConfig {
+        StateConfig {
+            data_dir: data_dir.to_string(),
+            prune_empty_accounts: false,
+            genesis_allocations: vec![],
+            genesis_representatives: vec![],
+            genesis_open_fee: Amount::zero(),
+            vote_pool_size: 1000,
+            max_active_blocks_per_leader: 64,
+            max_clock_skew: Duration::from_secs(10),
+            verify_state_hash: false,
+            max_finalized_in_memory: 1_024
+        }
+    }
+
+    /// Create the `State` of `id` from `config`, with the default components of a test node
+    fn new_state(id: Identity, genesis: Arc<Block>, config: StateConfig) -> Result<State, Error> {
+        State::new(
+            id,
+            genesis,
+            config,
+            WorkGenerator::new(Duration::from_secs(10), 1, 16).unwrap(),
+            NodeInfo::start(Version::new(0, 1, 0)),
+            BlockVerifier::new(4, 1024).unwrap(),
+            Subscriptions::new(16, 1024)
+        )
+    }
+
+    /// Create a `State` with two accounts and a chain of `N_BLOCKS` blocks,
+    /// each containing a single transfer between them.
+    fn state_with_chain(data_dir: &str) -> (State, Vec<Hash>) {
+        let private = Seed::random().derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(id));
+        let allocations = [(Public::random(), Amount::zero())];
+        let mut state = new_state(id, genesis.clone(), StateConfig { genesis_allocations: allocations.to_vec(), ..test_config(data_dir) }).unwrap();
+        let mut hashes = Vec::new();
+        let mut previous = genesis.hash;
+        let mut slot = genesis.slot;
+        for i in 0..N_BLOCKS {
+            slot = slot.next();
+            let block = Arc::new(Block::sign(id, slot, previous, BlockBody::default()));
+            let task = Task { nonce: i, from_index: 0, amount: Amount::from_raw(i + 1), to_index: 1, burn: Amount::zero(), open: false };
+            state.active.insert(block.hash, Box::new(BlockEntry {
+                block: block.clone(),
+                tasks: vec![task],
+            }), previous).unwrap();
+            previous = block.hash;
+            hashes.push(block.hash);
+        }
+        (state, hashes)
+    }
+
+    #[test]
+    fn test_finalize_up_to_matches_individual_finalization() {
+        let (dir_a, dir_b) = (test_dir("batched"), test_dir("individual"));
+        let (mut batched, hashes) = state_with_chain(&dir_a);
+        let (mut individual, individual_hashes) = state_with_chain(&dir_b);
+
+        batched.finalize_up_to(*hashes.last().unwrap()).unwrap();
+        for h in individual_hashes.iter() {
+            individual.finalize_up_to(*h).unwrap();
+        }
+
+        assert_eq!(batched.finalized.len(), N_BLOCKS as usize + 1);
+        assert_eq!(batched.finalized.len(), individual.finalized.len());
+        assert_eq!(batched.active.get_root().0, hashes.last().unwrap());
+        assert_eq!(individual.active.get_root().0, individual_hashes.last().unwrap());
+        for index in 0..2 {
+            let a = batched.bank.get(index).unwrap();
+            let b = individual.bank.get(index).unwrap();
+            assert_eq!(a.finalized_balance.load(Ordering::Relaxed), b.finalized_balance.load(Ordering::Relaxed));
+            assert_eq!(a.weight.load(Ordering::Relaxed), b.weight.load(Ordering::Relaxed));
+        }
+        let transferred = Amount::from_raw(N_BLOCKS * (N_BLOCKS + 1) / 2);
+        assert_eq!(batched.bank.get(1).unwrap().finalized_balance.load(Ordering::Relaxed), transferred);
+
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    /// The case of the retired `State::finalize_hash` where `h` is on the longest chain, as a
+    /// reference for `finalize_up_to`. `Bank::finalize_block` becomes finalizing the tasks of the
+    /// block. Its other case, reorganizing onto a block off the longest chain, can't be ported:
+    /// it relies on `Bank::revert_block` and `Bank::process_block`, which don't exist now that
+    /// the `Bank` works on tasks, and `finalize_up_to` rejects such blocks anyway.
+    fn finalize_hash(state: &mut State, h: Hash) {
+        let (&longest_chain, _) = state.active.get_longest_chain();
+        let common_ancestor = *state.active.get_common_ancestor(h, longest_chain).unwrap().0;
+        assert!(common_ancestor == h);
+        let path_from_root_to_block = state.active.get_path(*state.active.get_root().0, h).unwrap();
+        for h in path_from_root_to_block.iter().skip(1) {
+            let entry = state.active.get(h).unwrap();
+            state.bank.finalize_tasks(&entry.tasks);
+            state.finalized.push(entry.block.clone());
+        }
+        state.active.set_root(h).unwrap();
+    }
+
+    #[test]
+    fn test_finalize_up_to_matches_finalize_hash() {
+        let (dir_a, dir_b) = (test_dir("batched"), test_dir("finalize_hash"));
+        let (mut batched, hashes) = state_with_chain(&dir_a);
+        let (mut reference, reference_hashes) = state_with_chain(&dir_b);
+
+        // finalize in uneven steps, ending on the same block
+        for &end in [7, 8, 30, N_BLOCKS as usize].iter() {
+            batched.finalize_up_to(hashes[end - 1]).unwrap();
+            finalize_hash(&mut reference, reference_hashes[end - 1]);
+            assert_eq!(batched.finalized.len(), end + 1);
+            assert_eq!(batched.finalized.len(), reference.finalized.len());
+            assert_eq!(batched.active.len(), reference.active.len());
+            for index in 0..2 {
+                let (a, b) = (batched.bank.get(index).unwrap(), reference.bank.get(index).unwrap());
+                assert_eq!(a.finalized_balance.load(Ordering::Relaxed), b.finalized_balance.load(Ordering::Relaxed));
+                assert_eq!(a.latest_balance.load(Ordering::Relaxed), b.latest_balance.load(Ordering::Relaxed));
+                assert_eq!(a.weight.load(Ordering::Relaxed), b.weight.load(Ordering::Relaxed));
+            }
+        }
+        for index in 1..=N_BLOCKS as usize {
+            assert!(batched.finalized.get(index).unwrap().hash == hashes[index - 1]);
+            assert!(reference.finalized.get(index).unwrap().hash == reference_hashes[index - 1]);
+        }
+
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_prune_at_epoch_boundary() {
+        let dir = test_dir("prune");
+        let private = Seed::random().derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(id));
+        let amount = Amount::from_raw(10);
+        let (emptied, representative) = (Public::random(), Public::random());
+        let allocations = vec![(emptied, amount), (representative, Amount::zero()), (Public::random(), Amount::zero())];
+        let config = StateConfig { genesis_allocations: allocations, prune_empty_accounts: true, ..test_config(&dir) };
+        let mut state = new_state(id, genesis.clone(), config).unwrap();
+
+        // account 1 sends everything back, and account 3 delegates to account 2, followed
+        // by empty blocks starting the next two epochs
+        let next_epoch = |slot: Slot| slot.epoch().get(Epoch::LEN - 1).unwrap().next();
+        let slots = [genesis.slot.next(), next_epoch(genesis.slot), next_epoch(next_epoch(genesis.slot))];
+        let mut tasks = vec![
+            Task { nonce: 0, from_index: 1, amount, to_index: 0, burn: Amount::zero(), open: false },
+            Task { nonce: 0, from_index: 3, amount: Amount::zero(), to_index: 2, burn: Amount::zero(), open: false },
+        ];
+        let mut previous = genesis.hash;
+        let mut blocks = vec![];
+        for slot in slots {
+            let block = Arc::new(Block::sign(id, slot, previous, BlockBody::default()));
+            let entry = Box::new(BlockEntry { block: block.clone(), tasks: std::mem::take(&mut tasks) });
+            for task in entry.tasks.iter() {
+                state.bank.queue_task(task, Batch::null().next()).unwrap();
+                state.bank.finish_task(task);
+            }
+            state.active.insert(block.hash, entry, previous).unwrap();
+            previous = block.hash;
+            blocks.push(block.hash);
+        }
+
+        // the emptied account survives the epoch it was active in
+        state.finalize_up_to(blocks[1]).unwrap();
+        assert_eq!(state.bank.get(1).unwrap().finalized_balance.load(Ordering::Relaxed), Amount::zero());
+        assert_eq!(state.account_index(&emptied), Some(1));
+        assert_eq!(state.active.get_longest_chain().0, &blocks[2]);
+
+        // and is pruned at the next boundary, while the account delegated to survives
+        state.finalize_up_to(blocks[2]).unwrap();
+        assert_eq!(state.account_index(&emptied), None);
+        assert_eq!(state.accounts.retired_nonce(&emptied), Some(1));
+        assert_eq!(state.account_index(&representative), Some(2));
+        assert_eq!(state.account_index(&id.public), Some(0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_genesis_allocations() {
+        let dir = test_dir("genesis");
+        let private = Seed::random().derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(id));
+        let allocations = [
+            (Public::random(), Amount::from_raw(100)),
+            (Public::random(), Amount::from_raw(200)),
+        ];
+        let state = new_state(id, genesis.clone(), StateConfig { genesis_allocations: allocations.to_vec(), ..test_config(&dir) }).unwrap();
+        assert_eq!(state.account_index(&id.public), Some(0));
+        assert_eq!(state.account_public(0), Some(id.public));
+        for (i, (public, amount)) in allocations.iter().enumerate() {
+            let index = state.account_index(public).unwrap();
+            assert_eq!(index, i as u64 + 1);
+            assert_eq!(state.account_public(index), Some(*public));
+            assert_eq!(state.bank.get(index).unwrap().latest_balance.load(Ordering::Relaxed), *amount);
+        }
+        drop(state);
+
+        let duplicate = [(allocations[0].0, Amount::from_raw(1)), (allocations[0].0, Amount::from_raw(1))];
+        assert!(new_state(id, genesis.clone(), StateConfig { genesis_allocations: duplicate.to_vec(), ..test_config(&dir) }).is_err());
+        let genesis_leader = [(id.public, Amount::from_raw(1))];
+        assert!(new_state(id, genesis, StateConfig { genesis_allocations: genesis_leader.to_vec(), ..test_config(&dir) }).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_genesis_representatives() {
+        let dir = test_dir("genesis_representatives");
+        let private = Seed::random().derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(id));
+        let allocations = [(Public::random(), Amount::from_raw(100))];
+        // the representatives hold and represent nearly all of the supply
+        let third = Amount::from_raw((Amount::initial_supply().to_raw() - 100) / 3);
+        let representatives = [(Public::random(), third), (Public::random(), third), (Public::random(), third)];
+        let state = new_state(id, genesis, StateConfig { genesis_allocations: allocations.to_vec(), genesis_representatives: representatives.to_vec(), ..test_config(&dir) }).unwrap();
+        for (i, &(public, amount)) in representatives.iter().enumerate() {
+            let index = state.account_index(&public).unwrap();
+            assert_eq!(index, i as u64 + 2);
+            assert_eq!(state.bank.get(index).unwrap().weight.load(Ordering::Relaxed), amount);
+        }
+        let leaders: HashSet<Public> = state.leader_schedule.leaders.iter().copied().collect();
+        for (public, _) in representatives.iter() {
+            assert!(leaders.contains(public));
+            assert!(state.leader_schedule.next_slot_led_by(public, Slot::now().next()).is_some());
+        }
+        assert!(!leaders.contains(&allocations[0].0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_future_data_dir_version() {
+        let dir = test_dir("future");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(format!("{}/version", dir), format!("{}", storage::DATA_DIR_VERSION + 1)).unwrap();
+        let private = Seed::random().derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(id));
+        assert!(new_state(id, genesis, test_config(&dir)).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_finalize_up_to_unknown_block() {
+        let dir = test_dir("unknown");
+        let (mut state, _) = state_with_chain(&dir);
+        assert!(state.finalize_up_to(Hash::random()).is_err());
+        assert_eq!(state.finalized.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_send_finalized_accounts() {
+        let dir = test_dir("finalized_accounts");
+        let (mut state, hashes) = state_with_chain(&dir);
+        let (tx_pool, mut mailbox) = process::channel();
+        state.tx_pools = vec![tx_pool];
+        state.finalize_up_to(*hashes.last().unwrap()).unwrap();
+        // both accounts are written, once each however many blocks wrote them
+        match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::FinalizedAccounts(accounts)) => {
+                let mut indices: Vec<u64> = accounts.iter().map(|&(_, index)| index).collect();
+                indices.sort();
+                assert_eq!(indices, vec![0, 1]);
+                assert!(accounts.iter().all(|&(public, index)| state.account_public(index) == Some(public)));
+            }
+            _ => panic!("expected the finalized accounts"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_leader_schedule() {
+        let dir = test_dir("leader_schedule");
+        let (state, _) = state_with_chain(&dir);
+        let epoch = Slot::now().epoch();
+        // the schedule is listed from the current slot on
+        let now = epoch.get(100).unwrap();
+        let schedule = |epoch, offset, count| match state.leader_schedule(epoch, Page { offset, count }, now) {
+            RpcResponse::LeaderSchedule { slots, total, has_more } => (slots, total, has_more),
+            _ => panic!("expected a leader schedule"),
+        };
+        let upcoming = Epoch::LEN - 100;
+        let (slots, total, has_more) = schedule(None, 0, None);
+        assert_eq!((slots.len(), total, has_more), (Page::DEFAULT_COUNT as usize, upcoming as u64, true));
+        assert!(slots[0].0 == now);
+        for &(slot, leader) in slots.iter() {
+            assert_eq!(Some(leader), state.leader_schedule.leader_for(slot));
+            assert_eq!(leader, state.id.public);
+        }
+        // a middle page, capped at the maximum count
+        let (slots, _, has_more) = schedule(Some(epoch), 1000, Some(u32::MAX));
+        assert_eq!((slots.len(), has_more), (MAX_LEADER_SCHEDULE_SLOTS as usize, true));
+        assert!(slots[0].0 == epoch.get(1100).unwrap());
+        // the last page, and beyond the end
+        let (slots, _, has_more) = schedule(None, upcoming as u64 - 10, Some(100));
+        assert_eq!((slots.len(), has_more), (10, false));
+        assert!(slots[9].0 == epoch.get(Epoch::LEN - 1).unwrap());
+        let (slots, _, has_more) = schedule(None, upcoming as u64, None);
+        assert_eq!((slots.len(), has_more), (0, false));
+        assert_eq!(schedule(Some(Epoch::zero()), 0, None), (vec![], 0, false));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_refresh_leader_schedule() {
+        let dir = test_dir("refresh_leader_schedule");
+        let (mut state, _) = state_with_chain(&dir);
+        let epoch = Slot::now().epoch();
+
+        // a schedule left over from a past epoch is replaced by the current one
+        let weight_of = state.weight_fn();
+        fill_leader_schedule(&mut state.leader_schedule, &state.representatives, weight_of, Epoch::zero());
+        assert!(state.leader_schedule.leader_for(Slot::now()).is_none());
+        state.refresh_leader_schedule();
+        assert!(state.leader_schedule.epoch == epoch);
+        assert_eq!(state.leader_schedule.leader_for(Slot::now()), Some(state.id.public));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_leader() {
+        let dir = test_dir("is_leader");
+        let (mut state, _) = state_with_chain(&dir);
+        let other = Public::random();
+        let epoch = Slot::now().epoch();
+        state.leader_schedule.fill(
+            vec![state.id.public, other],
+            |_| Amount::from_raw(1),
+            epoch
+        );
+        // find the last slot led by this node before another leader takes over
+        let boundary = (0..Epoch::LEN - 1)
+            .map(|i| (epoch.get(i).unwrap(), epoch.get(i + 1).unwrap()))
+            .find(|&(a, b)| {
+                state.leader_schedule.leader_for(a) == Some(state.id.public)
+                    && state.leader_schedule.leader_for(b) == Some(other)
+            })
+            .unwrap();
+        match state.is_leader(boundary.0) {
+            RpcResponse::IsLeader { leader, current_slot, next_leader_slot } => {
+                assert!(leader);
+                assert_eq!(current_slot, boundary.0);
+                assert!(next_leader_slot.unwrap() > boundary.1);
+            }
+            _ => panic!("unexpected response"),
+        }
+        match state.is_leader(boundary.1) {
+            RpcResponse::IsLeader { leader, current_slot, next_leader_slot } => {
+                assert!(!leader);
+                assert_eq!(current_slot, boundary.1);
+                let next_leader_slot = next_leader_slot.unwrap();
+                assert_eq!(state.leader_schedule.leader_for(next_leader_slot), Some(state.id.public));
+            }
+            _ => panic!("unexpected response"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_work_threshold() {
+        let dir = test_dir("work_threshold");
+        let (state, _) = state_with_chain(&dir);
+        match state.work_threshold() {
+            RpcResponse::WorkThreshold { difficulty, multiplier } => {
+                assert_eq!(difficulty, Difficulty::BASE);
+                assert_eq!(multiplier, 1.0);
+            }
+            _ => panic!("unexpected response"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_block_confirmed() {
+        let dir = test_dir("block_confirmed");
+        let (mut state, hashes) = state_with_chain(&dir);
+        let genesis = state.finalized.get(0).unwrap().hash;
+        assert_eq!(state.block_confirmed(&genesis), Some(true));
+        assert_eq!(state.block_confirmed(&hashes[0]), Some(false));
+        assert_eq!(state.block_confirmed(&Hash::random()), None);
+
+        state.finalize_up_to(hashes[10]).unwrap();
+        assert_eq!(state.block_confirmed(&genesis), Some(true));
+        assert_eq!(state.block_confirmed(&hashes[5]), Some(true));
+        assert_eq!(state.block_confirmed(&hashes[10]), Some(true));
+        assert_eq!(state.block_confirmed(&hashes[11]), Some(false));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_submit_vote() {
+        let dir = test_dir("submit_vote");
+        let (mut state, hashes) = state_with_chain(&dir);
+        let private = Seed::random().derive(0);
+        let representative = Identity { private, public: private.to_public() };
+        let genesis = state.finalized.get(0).unwrap().hash;
+        let vote = |right: usize| Box::new(Vote::sign(
+            &representative,
+            Pair { slot: Slot::zero(), block: genesis },
+            Pair { slot: Slot::zero().next(), block: hashes[right] },
+        ));
+
+        let accepted = vote(0);
+        let expected = accepted.verify_and_hash().unwrap();
+        assert_eq!(state.submit_vote(accepted).unwrap(), expected);
+        assert_eq!(state.pending_votes.len(), 1);
+
+        // a tampered vote no longer matches its signature
+        let mut malformed = vote(1);
+        malformed.right.block = hashes[2];
+        assert!(state.submit_vote(malformed).is_err());
+        assert_eq!(state.pending_votes.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_weight_fn() {
+        let dir = test_dir("weight_fn");
+        let (state, _) = state_with_chain(&dir);
+        let weight_of = state.weight_fn();
+        let leader = state.finalized.get(0).unwrap().leader;
+        let allocated = state.account_public(1).unwrap();
+        assert!(weight_of(&leader) == Amount::initial_supply());
+        assert!(weight_of(&allocated) == Amount::zero());
+        assert!(weight_of(&Public::random()) == Amount::zero());
+
+        // changes to the live state are reflected
+        state.bank.get(1).unwrap().weight.store(Amount::from_raw(5), Ordering::Relaxed);
+        assert!(weight_of(&allocated) == Amount::from_raw(5));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_subscribe_to_confirmations() {
+        let dir = test_dir("subscribe");
+        let (mut state, hashes) = state_with_chain(&dir);
+        let (rpc_sender, mut mailbox) = crate::process::channel();
+        let receiver = state.account_public(1).unwrap();
+        state.on_rpc_request(rpc_sender.clone(), 3, RpcRequest::Subscribe(Some(vec![receiver])));
+        assert!(matches!(
+            mailbox.recv_timeout(Duration::from_secs(1)),
+            Some(Message::RpcResponse(envelope)) if matches!(envelope.result, Ok(RpcResponse::Subscribed))
+        ));
+        state.on_rpc_request(rpc_sender, 4, RpcRequest::Subscribe(Some(vec![Public::random()])));
+        mailbox.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        // each finalized block holds one transfer to `receiver`
+        state.finalize_up_to(hashes[2]).unwrap();
+        for (i, h) in hashes[..3].iter().enumerate() {
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::RpcResponse(envelope)) => {
+                    assert_eq!(envelope.id, 3);
+                    match envelope.result {
+                        Ok(RpcResponse::Confirmation(c)) => {
+                            assert!(c.block == *h && c.to == receiver && c.kind == ConfirmationKind::Transfer);
+                            assert!(c.amount == Amount::from_raw(i as u64 + 1));
+                        }
+                        _ => panic!("expected a confirmation"),
+                    }
+                }
+                _ => panic!("expected a confirmation"),
+            }
+        }
+        assert!(mailbox.recv_timeout(Duration::from_millis(10)).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_telemetry_reports_broadcast_counters() {
+        let dir = test_dir("telemetry");
+        let (state, _) = state_with_chain(&dir);
+        state.broadcast_send_errors().fetch_add(2, Ordering::Relaxed);
+        state.broadcast_dropped().fetch_add(5, Ordering::Relaxed);
+        match state.telemetry() {
+            RpcResponse::Telemetry { broadcast_send_errors, broadcast_dropped, .. } => {
+                assert_eq!((broadcast_send_errors, broadcast_dropped), (2, 5));
+            }
+            _ => panic!("expected telemetry"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_ready() {
+        let dir = test_dir("ready");
+        let (mut state, hashes) = state_with_chain(&dir);
+        let (rpc_sender, mut mailbox) = crate::process::channel();
+        let mut request = |state: &mut State, request| {
+            state.on_rpc_request(rpc_sender.clone(), 1, request);
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::RpcResponse(envelope)) => envelope.result,
+                _ => panic!("expected a response"),
+            }
+        };
+        assert!(matches!(request(&mut state, RpcRequest::Health), Ok(RpcResponse::Health)));
+
+        // not ready without peers, nor while finalized far behind the network
+        assert!(matches!(request(&mut state, RpcRequest::Ready), Err((RpcErrorCode::NotReady, _))));
+        state.peer_count().store(3, Ordering::Relaxed);
+        state.sync_target().store(N_BLOCKS + 1, Ordering::Relaxed);
+        assert!(matches!(request(&mut state, RpcRequest::Ready), Err((RpcErrorCode::NotReady, _))));
+
+        // ready once finalized close to the network's height, however old the blocks are
+        let block = state.active.get(hashes.last().unwrap()).unwrap().block.clone();
+        state.finalize_up_to(block.hash).unwrap();
+        assert!(matches!(
+            request(&mut state, RpcRequest::Ready),
+            Ok(RpcResponse::Ready { peers: 3, finalized_slot }) if finalized_slot == block.slot
+        ));
+        state.peer_count().store(0, Ordering::Relaxed);
+        assert!(matches!(request(&mut state, RpcRequest::Ready), Err((RpcErrorCode::NotReady, _))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Votes by `id` finalizing `block`
+    fn finality_votes(id: Identity, block: &Block) -> Vec<Vote> {
+        vec![Vote::sign(&id, Pair { slot: block.slot, block: block.previous }, Pair { slot: block.slot, block: block.hash })]
+    }
+
+    /// Request finalized blocks from `ahead` on behalf of `behind`, getting the request and the response
+    fn sync_exchange(ahead: &State, behind: &mut State) -> Option<(SyncRequest, SyncResponse)> {
+        let (transmitter, mut mailbox) = crate::process::channel();
+        behind.sync_request = None;
+        behind.on_sync_tick(transmitter.clone());
+        let Some(Message::SendSyncRequest(sync_request)) = mailbox.recv_timeout(Duration::from_millis(100)) else {
+            return None;
+        };
+        ahead.on_sync_request(transmitter, (*sync_request).clone());
+        match mailbox.recv_timeout(Duration::from_millis(100)) {
+            Some(Message::SyncResponse(v)) => Some((*sync_request, v.1)),
+            _ => None,
+        }
+    }
+
+    /// Create a node which has finalized 12 blocks, the first transferring
+    /// `amount` to account 1, and a node with the same genesis which has not
+    fn ahead_and_behind(dir_a: &str, dir_b: &str, amount: Amount, max_finalized_in_memory: usize) -> (State, State) {
+        let leader = work_fixtures::identity(0);
+        let genesis = Arc::new(Block::genesis(leader));
+        let receiver = Public::random();
+        let allocations = [(receiver, Amount::zero())];
+        let open = |id: Identity, dir: &str| {
+            let config = StateConfig { genesis_allocations: allocations.to_vec(), max_finalized_in_memory, ..test_config(dir) };
+            new_state(id, genesis.clone(), config).unwrap()
+        };
+        let mut ahead = open(leader, dir_a);
+        let behind = open(work_fixtures::identity(1), dir_b);
+
+        // the first block transfers to `receiver`, the rest are empty
+        let tx = Tx::sign(&leader, 0, receiver, TxKind::Transfer(amount), work_fixtures::first_tx_work());
+        let (mut previous, mut slot) = (genesis.hash, genesis.slot);
+        for i in 1..=12 {
+            slot = slot.next();
+            let txs = if i == 1 { vec![tx] } else { vec![] };
+            let tx_hashes = txs.iter().map(|tx| tx.hash()).collect();
+            let block = Block::sign(leader, slot, previous, BlockBody { transactions: txs, tx_hashes, ..BlockBody::default() });
+            previous = block.hash;
+            let votes = finality_votes(leader, &block);
+            ahead.apply_proven(vec![block], votes).unwrap();
+        }
+        let fork = Block::sign(leader, slot.next(), genesis.hash, BlockBody::default());
+        let votes = finality_votes(leader, &fork);
+        assert!(ahead.apply_proven(vec![fork], votes).is_err());
+        (ahead, behind)
+    }
+
+    #[test]
+    fn test_sync_finalized_blocks() {
+        let (dir_a, dir_b) = (test_dir("sync_ahead"), test_dir("sync_behind"));
+        let amount = Amount::from_raw(500);
+        let (ahead, mut behind) = ahead_and_behind(&dir_a, &dir_b, amount, 1_024);
+
+        // the behind node requests pages of blocks until it has caught up
+        let (transmitter, mut mailbox) = crate::process::channel();
+        behind.on_sync_tick(transmitter.clone());
+        let mut pages = 0;
+        while let Some(Message::SendSyncRequest(sync_request)) = mailbox.recv_timeout(Duration::from_millis(100)) {
+            assert!(sync_request.to == ahead.id.public);
+            ahead.on_sync_request(transmitter.clone(), *sync_request);
+            let (to, sync_response) = match mailbox.recv_timeout(Duration::from_millis(100)) {
+                Some(Message::SyncResponse(v)) => *v,
+                _ => break,
+            };
+            assert!(to == behind.id.public);
+            let len: usize = sync_response.blocks.iter().map(encoded_len).sum::<usize>()
+                + sync_response.votes.iter().map(encoded_len).sum::<usize>();
+            assert!(len <= MTU - SYNC_RESPONSE_HEADER_LEN);
+            behind.on_sync_blocks(transmitter.clone(), sync_response);
+            pages += 1;
+        }
+        assert!(pages > 1);
+        assert_eq!(behind.finalized.len(), 13);
+        assert_eq!(behind.active.get_root().0, ahead.active.get_root().0);
+        for index in 0..2 {
+            let (a, b) = (ahead.bank.get(index).unwrap(), behind.bank.get(index).unwrap());
+            assert_eq!(a.finalized_balance.load(Ordering::Relaxed), b.finalized_balance.load(Ordering::Relaxed));
+            assert_eq!(a.nonce.load(Ordering::Relaxed), b.nonce.load(Ordering::Relaxed));
+        }
+        assert_eq!(behind.bank.get(1).unwrap().finalized_balance.load(Ordering::Relaxed), amount);
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_reject_unproven_sync() {
+        let (dir_a, dir_b) = (test_dir("unproven_sync_ahead"), test_dir("unproven_sync_behind"));
+        let (ahead, mut behind) = ahead_and_behind(&dir_a, &dir_b, Amount::from_raw(500), 1_024);
+        let (transmitter, _mailbox) = crate::process::channel();
+        let (blocks, votes) = ahead.finalized_after(Slot::zero(), MTU);
+
+        // responses to no request, to another request, or from another peer are ignored
+        let unsolicited = SyncResponse::new(ahead.id.private, Hash::random(), blocks.clone(), votes.clone());
+        behind.on_sync_blocks(transmitter.clone(), unsolicited);
+        assert_eq!(behind.height(), 0);
+        let (sync_request, _) = sync_exchange(&ahead, &mut behind).unwrap();
+        let other = SyncResponse::new(ahead.id.private, Hash::random(), blocks.clone(), votes.clone());
+        behind.on_sync_blocks(transmitter.clone(), other);
+        let impostor = SyncResponse::new(work_fixtures::identity(3).private, sync_request.hash(), blocks.clone(), votes.clone());
+        behind.on_sync_blocks(transmitter.clone(), impostor);
+        assert_eq!(behind.height(), 0);
+
+        // blocks without a quorum of votes, or not from their slot's leader, aren't applied
+        let e = behind.apply_proven(blocks.clone(), vec![]).unwrap_err();
+        assert!(e.as_str().contains("quorum"));
+        let outsider = work_fixtures::identity(3);
+        let votes = finality_votes(outsider, &blocks[0]);
+        let e = behind.apply_proven(vec![blocks[0].clone()], votes).unwrap_err();
+        assert!(e.as_str().contains("quorum"));
+        let genesis = ahead.finalized.get(0).unwrap();
+        let usurped = Block::sign(outsider, genesis.slot.next(), genesis.hash, BlockBody::default());
+        let votes = finality_votes(ahead.id, &usurped);
+        let e = behind.apply_proven(vec![usurped], votes).unwrap_err();
+        assert!(e.as_str().contains("scheduled leader"));
+        assert_eq!(behind.height(), 0);
+
+        // the response to our request from its target is applied
+        let (transmitter, _mailbox) = crate::process::channel();
+        let (_, sync_response) = sync_exchange(&ahead, &mut behind).unwrap();
+        behind.on_sync_blocks(transmitter, sync_response);
+        assert!(behind.height() > 0);
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_on_block() {
+        let dir = test_dir("on_block");
+        let leader = work_fixtures::identity(0);
+        let genesis = Arc::new(Block::genesis(leader));
+        let receiver = Public::random();
+        let config = StateConfig { genesis_allocations: vec![(receiver, Amount::zero())], ..test_config(&dir) };
+        let mut state = new_state(leader, genesis.clone(), config).unwrap();
+        let block = |id: Identity, slot: Slot, previous: Hash, txs: Vec<Tx>| {
+            let tx_hashes = txs.iter().map(|tx| tx.hash()).collect();
+            Block::sign(id, slot, previous, BlockBody { transactions: txs, tx_hashes, ..BlockBody::default() })
+        };
+        let latest_balance = |state: &State| state.bank.get(1).unwrap().latest_balance.load(Ordering::Relaxed);
+
+        // only the scheduled leader may extend the longest chain
+        let e = state.on_block(block(work_fixtures::identity(3), genesis.slot.next(), genesis.hash, vec![])).unwrap_err();
+        assert!(e.as_str().contains("scheduled leader"));
+        let tx = Tx::sign(&leader, 0, receiver, TxKind::Transfer(Amount::from_raw(500)), work_fixtures::first_tx_work());
+        let first = block(leader, genesis.slot.next(), genesis.hash, vec![tx]);
+        state.on_block(first.clone()).unwrap();
+        assert!(*state.active.get_longest_chain().0 == first.hash);
+        assert_eq!(state.block_confirmed(&first.hash), Some(false));
+        assert_eq!(latest_balance(&state), Amount::from_raw(500));
+        let e = state.on_block(block(leader, first.slot.next(), genesis.hash, vec![])).unwrap_err();
+        assert!(e.as_str().contains("longest chain"));
+
+        // finalizing an active block keeps the blocks after it
+        let second = block(leader, first.slot.next(), first.hash, vec![]);
+        state.on_block(second.clone()).unwrap();
+        let votes = finality_votes(leader, &first);
+        state.apply_proven(vec![first.clone()], votes).unwrap();
+        assert!(*state.active.get_root().0 == first.hash);
+        assert!(*state.active.get_longest_chain().0 == second.hash);
+        assert_eq!(state.bank.get(1).unwrap().finalized_balance.load(Ordering::Relaxed), Amount::from_raw(500));
+
+        // while a finalized block competing with the active ones replaces them
+        let third = block(leader, second.slot.next(), second.hash, vec![]);
+        state.on_block(third.clone()).unwrap();
+        let competing = block(leader, third.slot.next(), first.hash, vec![]);
+        let votes = finality_votes(leader, &competing);
+        state.apply_proven(vec![competing.clone()], votes).unwrap();
+        assert!(*state.active.get_longest_chain().0 == competing.hash);
+        assert_eq!(state.block_confirmed(&second.hash), None);
+        assert_eq!(latest_balance(&state), Amount::from_raw(500));
+
+        // pending votes finalize the block they're a quorum for, whether they arrive before it or after
+        let vote = |block: &Block| Box::new(Vote::sign(&leader, Pair { slot: block.slot, block: block.previous }, Pair { slot: block.slot, block: block.hash }));
+        let voted = block(leader, competing.slot.next(), competing.hash, vec![]);
+        state.on_block(voted.clone()).unwrap();
+        state.submit_vote(vote(&voted)).unwrap();
+        assert!(*state.active.get_root().0 == voted.hash);
+        assert!(state.finalized.proof(state.finalized.len() - 1).is_some());
+        assert!(state.pending_votes.is_empty());
+        let early = block(leader, voted.slot.next(), voted.hash, vec![]);
+        state.submit_vote(vote(&early)).unwrap();
+        assert_eq!(state.pending_votes.len(), 1);
+        state.on_block(early.clone()).unwrap();
+        assert!(*state.active.get_root().0 == early.hash);
+        let competing = early;
+
+        // the leader's active blocks are capped
+        state.max_active_blocks_per_leader = 1;
+        let fourth = block(leader, competing.slot.next(), competing.hash, vec![]);
+        state.on_block(fourth.clone()).unwrap();
+        let e = state.on_block(block(leader, fourth.slot.next(), fourth.hash, vec![])).unwrap_err();
+        assert!(e.as_str().contains("too many active blocks"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_sync_status() {
+        let (dir_a, dir_b) = (test_dir("sync_status_ahead"), test_dir("sync_status_behind"));
+        let (ahead, mut behind) = ahead_and_behind(&dir_a, &dir_b, Amount::from_raw(500), 1_024);
+        let status = |state: &State| match state.sync_status() {
+            RpcResponse::SyncStatus { syncing, current, target, percent } => (syncing, current, target, percent),
+            _ => panic!("expected a sync status"),
+        };
+        assert_eq!(status(&behind), (false, 0, 0, 100.0));
+        assert_eq!(status(&ahead), (false, 12, 12, 100.0));
+
+        // peers report the height of the ahead node; apply only the first three blocks of the first page
+        behind.sync_target().store(ahead.finalized_height().load(Ordering::Relaxed), Ordering::Relaxed);
+        let (sync_request, _) = sync_exchange(&ahead, &mut behind).unwrap();
+        let (mut blocks, _) = ahead.finalized_after(Slot::zero(), MTU);
+        blocks.truncate(3);
+        let votes = ahead.finalized.proof(3).unwrap().to_vec();
+        let sync_response = SyncResponse::new(ahead.id.private, sync_request.hash(), blocks, votes);
+        let (transmitter, _mailbox) = crate::process::channel();
+        behind.on_sync_blocks(transmitter, sync_response);
+        assert_eq!(status(&behind), (true, 3, 12, 25.0));
+
+        // and the target follows them back down
+        behind.sync_target().store(2, Ordering::Relaxed);
+        assert_eq!(status(&behind), (false, 3, 3, 100.0));
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_max_active_blocks_per_leader() {
+        let dir = test_dir("max_active_blocks_per_leader");
+        let (mut state, hashes) = state_with_chain(&dir);
+        state.max_active_blocks_per_leader = 2;
+        let previous = *hashes.last().unwrap();
+        let identity = |i| {
+            let private = Seed([9; 32]).derive(i);
+            Identity { private, public: private.to_public() }
+        };
+        let (spammer, other) = (identity(0), identity(1));
+        let mut slot = Slot::now();
+        let mut insert = |state: &mut State, leader: Identity| {
+            slot = slot.next();
+            let block = Arc::new(Block::sign(leader, slot, previous, BlockBody::default()));
+            state.insert_active(block.hash, Box::new(BlockEntry { block: block.clone(), tasks: vec![] }), previous).map(|_| block.hash)
+        };
+
+        // competing blocks of one leader are rejected past the cap, while another leader's are accepted
+        for _ in 0..2 {
+            insert(&mut state, spammer).unwrap();
+        }
+        assert!(insert(&mut state, spammer).is_err());
+        insert(&mut state, other).unwrap();
+
+        // finalizing one drops the others, making room again
+        let previous = *state.active.get_longest_chain().0;
+        state.finalize_up_to(previous).unwrap();
+        let block = Arc::new(Block::sign(spammer, slot.next(), previous, BlockBody::default()));
+        state.insert_active(block.hash, Box::new(BlockEntry { block: block.clone(), tasks: vec![] }), previous).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reject_future_block() {
+        let dir = test_dir("reject_future_block");
+        let (mut state, hashes) = state_with_chain(&dir);
+        let (id, previous) = (state.id, *hashes.last().unwrap());
+        let block = |slot, previous| Block::sign(id, slot, previous, BlockBody::default());
+        let future = Slot::from_system_time(SystemTime::now() + Duration::from_secs(3600));
+
+        // neither live nor finalized blocks may be from the future
+        let e = state.on_block(block(future, previous)).unwrap_err();
+        assert!(e.as_str().contains("ahead of local time"));
+        let root = *state.active.get_root().0;
+        let finalized = block(future, root);
+        let votes = finality_votes(id, &finalized);
+        let e = state.apply_proven(vec![finalized], votes).unwrap_err();
+        assert!(e.as_str().contains("ahead of local time"));
+        assert!(*state.active.get_longest_chain().0 == previous);
+        state.on_block(block(Slot::now(), previous)).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_state_hash() {
+        let dir = test_dir("verify_state_hash");
+        let (mut state, hashes) = state_with_chain(&dir);
+        state.verify_state_hash = true;
+        let (id, previous) = (state.id, *hashes.last().unwrap());
+        state.finalize_up_to(previous).unwrap();
+        let block = |state_hash| Block::sign_with_state_hash(id, Slot::now(), previous, state_hash, BlockBody::default());
+
+        // the state hash is signed, so it can't be swapped out
+        let mut tampered = block(state.bank.state_root());
+        tampered.state_hash = Hash::random();
+        assert!(tampered.verify_and_hash(1000).is_err());
+
+        let wrong = block(Hash::random());
+        let votes = finality_votes(id, &wrong);
+        let e = state.apply_proven(vec![wrong], votes).unwrap_err();
+        assert!(e.as_str().contains("state hash"));
+        assert!(*state.active.get_root().0 == previous);
+        let correct = block(state.bank.state_root());
+        let (hash, votes) = (correct.hash, finality_votes(id, &correct));
+        state.apply_proven(vec![correct], votes).unwrap();
+        assert!(*state.active.get_root().0 == hash);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_fee_once_per_block() {
+        let dir = test_dir("open_fee");
+        let leader = work_fixtures::identity(0);
+        let genesis = Arc::new(Block::genesis(leader));
+        let (receiver, open_fee) = (Public::random(), Amount::from_raw(10));
+        let config = StateConfig { genesis_allocations: vec![(receiver, Amount::zero())], genesis_open_fee: open_fee, ..test_config(&dir) };
+        let state = new_state(leader, genesis.clone(), config).unwrap();
+
+        // only the first of two transfers to the new account in a block pays the fee
+        let txs: Vec<_> = (0..2).map(|nonce| Tx::sign(&leader, nonce, receiver, TxKind::Transfer(Amount::from_raw(100)), Work::zero())).collect();
+        let tx_hashes = txs.iter().map(|tx| tx.hash()).collect();
+        let block = Block::sign(leader, genesis.slot.next(), genesis.hash, BlockBody { transactions: txs, tx_hashes, ..BlockBody::default() });
+        let tasks = state.tasks_of(&block).unwrap();
+        assert_eq!(tasks[0].burn, open_fee);
+        assert_eq!(tasks[1].burn, Amount::zero());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_open() {
+        let dir = test_dir("apply_open");
+        let leader = work_fixtures::identity(0);
+        let genesis = Arc::new(Block::genesis(leader));
+        let (opened, representative) = (work_fixtures::identity(3), work_fixtures::identity(1).public);
+        let open_fee = Amount::from_raw(10);
+        let config = StateConfig { genesis_allocations: vec![(representative, Amount::zero())], genesis_open_fee: open_fee, ..test_config(&dir) };
+        let mut state = new_state(leader, genesis.clone(), config).unwrap();
+        let open = Open::sign(&opened, representative, work_fixtures::open_work(3));
+        let tx = Tx::sign(&leader, 0, opened.public, TxKind::Transfer(Amount::from_raw(500)), work_fixtures::first_tx_work());
+        let block = |slot: Slot, previous: Hash, opens: Vec<Open>, txs: Vec<Tx>| {
+            let open_hashes = opens.iter().map(|open| open.hash()).collect();
+            let tx_hashes = txs.iter().map(|tx| tx.hash()).collect();
+            Block::sign(leader, slot, previous, BlockBody { opens, open_hashes, transactions: txs, tx_hashes, ..BlockBody::default() })
+        };
+        let len = state.bank.len();
+
+        // the open adds the account, which the transfer after it in the block funds
+        let first = block(genesis.slot.next(), genesis.hash, vec![open], vec![tx]);
+        state.on_block(first.clone()).unwrap();
+        let index = state.account_index(&opened.public).unwrap();
+        let account = state.bank.get(index).unwrap();
+        assert_eq!(account.latest_balance.load(Ordering::Relaxed), Amount::from_raw(490));
+        assert_eq!(account.rep_index.load(Ordering::Relaxed), state.account_index(&representative).unwrap());
+        assert_eq!(state.bank.len(), len + 1);
+
+        // a competing finalized block reverts it, vacating the account
+        let competing = block(first.slot.next(), genesis.hash, vec![], vec![]);
+        state.apply_proven(vec![competing.clone()], finality_votes(leader, &competing)).unwrap();
+        assert_eq!(state.account_index(&opened.public), None);
+        assert_eq!(state.bank.len(), len);
+
+        // once finalized, what the opened account received counts towards its representative's weight
+        let reopened = block(competing.slot.next(), competing.hash, vec![open], vec![tx]);
+        state.on_block(reopened.clone()).unwrap();
+        state.apply_proven(vec![reopened.clone()], finality_votes(leader, &reopened)).unwrap();
+        let index = state.account_index(&opened.public).unwrap();
+        assert_eq!(state.bank.get(index).unwrap().finalized_balance.load(Ordering::Relaxed), Amount::from_raw(490));
+        assert_eq!(state.weight_fn()(&representative), Amount::from_raw(490));
+        assert_eq!(state.bank.burned(), open_fee);
+
+        // an account can't be opened twice
+        let again = block(reopened.slot.next(), reopened.hash, vec![open], vec![]);
+        assert!(state.on_block(again).unwrap_err().as_str().contains("already open"));
+        assert_eq!(state.bank.len(), len + 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_at_retired_nonce() {
+        let dir = test_dir("reopen");
+        let leader = work_fixtures::identity(0);
+        let genesis = Arc::new(Block::genesis(leader));
+        let (opened, representative) = (work_fixtures::identity(3), work_fixtures::identity(1).public);
+        let config = StateConfig { genesis_allocations: vec![(representative, Amount::zero())], ..test_config(&dir) };
+        let state = new_state(leader, genesis.clone(), config).unwrap();
+        state.accounts.insert(&opened.public, 2);
+        state.accounts.retire(2, 7);
+
+        // the account of a pruned key starts over where it left off
+        let open = Open::sign(&opened, representative, work_fixtures::open_work(3));
+        let block = Block::sign(leader, genesis.slot.next(), genesis.hash, BlockBody { opens: vec![open], open_hashes: vec![open.hash()], ..BlockBody::default() });
+        let tasks = state.tasks_of(&block).unwrap();
+        assert!(tasks[0].open && tasks[0].nonce == 7);
+        assert_eq!(state.bank.get(tasks[0].from_index).unwrap().nonce.load(Ordering::Relaxed), 7);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_restart_after_sync() {
+        let (dir_a, dir_b) = (test_dir("restart_ahead"), test_dir("restart_behind"));
+        let amount = Amount::from_raw(500);
+        let (ahead, mut behind) = ahead_and_behind(&dir_a, &dir_b, amount, 4);
+        while behind.height() < 12 {
+            let (_, sync_response) = sync_exchange(&ahead, &mut behind).unwrap();
+            let (transmitter, _mailbox) = crate::process::channel();
+            behind.on_sync_blocks(transmitter, sync_response);
+        }
+        assert_eq!(behind.height(), 12);
+        let (id, genesis, receiver) = (behind.id, behind.finalized.get(0).unwrap(), behind.account_public(1).unwrap());
+        drop(behind);
+
+        // the restarted node resumes from the chain it synced, with the accounts it left
+        let config = StateConfig { genesis_allocations: vec![(receiver, Amount::zero())], max_finalized_in_memory: 4, ..test_config(&dir_b) };
+        let restarted = new_state(id, genesis, config).unwrap();
+        assert_eq!(restarted.height(), 12);
+        assert_eq!(restarted.finalized_height.load(Ordering::Relaxed), 12);
+        assert_eq!(restarted.active.get_root().0, ahead.active.get_root().0);
+        for index in 0..2 {
+            let (a, b) = (ahead.bank.get(index).unwrap(), restarted.bank.get(index).unwrap());
+            assert_eq!(a.finalized_balance.load(Ordering::Relaxed), b.finalized_balance.load(Ordering::Relaxed));
+            assert_eq!(a.nonce.load(Ordering::Relaxed), b.nonce.load(Ordering::Relaxed));
+        }
+        assert_eq!(restarted.bank.get(1).unwrap().finalized_balance.load(Ordering::Relaxed), amount);
+
+        // and still serves its blocks, with their proofs, to syncing peers
+        let genesis_slot = restarted.finalized.get(0).unwrap().slot;
+        let (blocks, votes) = restarted.finalized_after(genesis_slot, MTU - SYNC_RESPONSE_HEADER_LEN);
+        assert!(!blocks.is_empty());
+        assert!(!votes.is_empty());
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_restart_from_checkpoint() {
+        let (dir_a, dir_b) = (test_dir("checkpoint_ahead"), test_dir("checkpoint_behind"));
+        let amount = Amount::from_raw(500);
+        let (ahead, mut behind) = ahead_and_behind(&dir_a, &dir_b, amount, 4);
+        let (_, sync_response) = sync_exchange(&ahead, &mut behind).unwrap();
+        let (transmitter, _mailbox) = crate::process::channel();
+        behind.on_sync_blocks(transmitter, sync_response);
+        let height = behind.height() as usize;
+        assert!(height > 0 && height < 12);
+        let (id, genesis, receiver) = (behind.id, behind.finalized.get(0).unwrap(), behind.account_public(1).unwrap());
+        behind.checkpoint().unwrap();
+        drop(behind);
+
+        // mark the checkpointed accounts, which a rebuild would lose
+        let bank = Bank::open(&storage::bank_dir(&dir_b), genesis.leader, &[]).unwrap();
+        bank.get(1).unwrap().weight.store(Amount::from_raw(7), Ordering::Relaxed);
+        bank.flush().unwrap();
+        drop(bank);
+        // and finalize the rest of the chain past the checkpoint
+        let mut finalized = Finalized::open(&dir_b, genesis.clone(), 4).unwrap();
+        for index in height + 1..=12 {
+            finalized.push(ahead.finalized.get(index).unwrap());
+        }
+        drop(finalized);
+
+        // the restarted node reopens the accounts, replaying only the blocks after the checkpoint
+        let config = || StateConfig { genesis_allocations: vec![(receiver, Amount::zero())], max_finalized_in_memory: 4, ..test_config(&dir_b) };
+        let restarted = new_state(id, genesis.clone(), config()).unwrap();
+        assert_eq!(restarted.height(), 12);
+        assert_eq!(restarted.active.get_root().0, ahead.active.get_root().0);
+        assert_eq!(restarted.bank.get(1).unwrap().weight.load(Ordering::Relaxed), Amount::from_raw(7));
+        for index in 0..2 {
+            let (a, b) = (ahead.bank.get(index).unwrap(), restarted.bank.get(index).unwrap());
+            assert_eq!(a.finalized_balance.load(Ordering::Relaxed), b.finalized_balance.load(Ordering::Relaxed));
+            assert_eq!(a.nonce.load(Ordering::Relaxed), b.nonce.load(Ordering::Relaxed));
+        }
+        drop(restarted);
+
+        // without a checkpoint, as after a crash, the accounts are rebuilt from the chain
+        let restarted = new_state(id, genesis, config()).unwrap();
+        assert_eq!(restarted.height(), 12);
+        assert_eq!(restarted.bank.get(1).unwrap().weight.load(Ordering::Relaxed), Amount::zero());
+        assert_eq!(restarted.bank.get(1).unwrap().finalized_balance.load(Ordering::Relaxed), amount);
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_spill_finalized_blocks() {
+        let (dir_a, dir_b) = (test_dir("spill_ahead"), test_dir("spill_behind"));
+        let (ahead, mut behind) = ahead_and_behind(&dir_a, &dir_b, Amount::from_raw(500), 4);
+        assert_eq!(ahead.finalized.len(), 13);
+        assert!(std::fs::metadata(storage::finalized_path(&dir_a)).unwrap().len() > 0);
+
+        // spilled blocks are still found, in order
+        for index in 0..13 {
+            let block = ahead.finalized.get(index).unwrap();
+            assert_eq!(ahead.block_confirmed(&block.hash), Some(true));
+            if index > 0 {
+                assert!(block.previous == ahead.finalized.get(index - 1).unwrap().hash);
+            }
+        }
+        assert!(ahead.finalized.get(13).is_err());
+
+        // and served to a syncing peer
+        let genesis = ahead.finalized.get(0).unwrap();
+        let (_, sync_response) = sync_exchange(&ahead, &mut behind).unwrap();
+        assert!(sync_response.blocks[0].previous == genesis.hash);
+        let len = sync_response.blocks.len() as u64;
+        let (transmitter, _mailbox) = crate::process::channel();
+        behind.on_sync_blocks(transmitter, sync_response);
+        assert_eq!(behind.height(), len);
+        std::fs::remove_dir_all(dir_a).unwrap();
+        std::fs::remove_dir_all(dir_b).unwrap();
+    }
+
+    #[test]
+    fn test_dag_dump() {
+        let dir = test_dir("dag_dump");
+        let (mut state, hashes) = state_with_chain(&dir);
+        // fork off the chain with a single block
+        let previous = hashes[40];
+        let fork = Arc::new(Block::sign(state.id, Slot::now(), previous, BlockBody::default()));
+        state.active.insert(fork.hash, Box::new(BlockEntry { block: fork.clone(), tasks: vec![] }), previous).unwrap();
+
+        let (rpc_sender, mut mailbox) = crate::process::channel();
+        let mut dag_dump = |page| {
+            state.on_rpc_request(rpc_sender.clone(), 1, RpcRequest::DagDump(page));
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::RpcResponse(envelope)) => match envelope.result {
+                    Ok(RpcResponse::DagDump { root, longest_chain, total, nodes, has_more }) => (root, longest_chain, total, nodes, has_more),
+                    _ => panic!("expected a dag dump"),
+                },
+                _ => panic!("expected a response"),
+            }
+        };
+        let (_, _, _, page, has_more) = dag_dump(Page { offset: 10, count: Some(5) });
+        assert!(page.len() == 5 && page[0].height == 10 && has_more);
+        let (root, longest_chain, total, nodes, has_more) = dag_dump(Page { offset: 0, count: Some(1024) });
+        assert!(!has_more);
+        // the genesis root, the chain and the fork, nearest the root first
+        assert!(root == nodes[0].hash && nodes[0].height == 0);
+        assert_eq!(total, N_BLOCKS + 2);
+        assert_eq!(nodes.len() as u64, total);
+        assert!(nodes.windows(2).all(|pair| pair[0].height <= pair[1].height));
+        // the longer branch is the longest chain
+        assert!(longest_chain == *hashes.last().unwrap());
+        let children: Vec<&DagNode> = nodes.iter().filter(|node| node.previous == previous).collect();
+        assert_eq!(children.len(), 2);
+        assert!(children.iter().any(|node| node.hash == fork.hash && node.slot == fork.slot && node.leader == state.id.public));
+        assert!(children.iter().all(|node| node.height == 42));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_block_count() {
+        let dir = test_dir("block_count");
+        let private = Seed::random().derive(0);
+        let id = Identity { private, public: private.to_public() };
+        let genesis = Arc::new(Block::genesis(id));
+        let mut state = new_state(id, genesis.clone(), test_config(&dir)).unwrap();
+        let (rpc_sender, mut mailbox) = crate::process::channel();
+        let mut block_count = |state: &mut State| {
+            state.on_rpc_request(rpc_sender.clone(), 1, RpcRequest::BlockCount);
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::RpcResponse(envelope)) => match envelope.result {
+                    Ok(RpcResponse::BlockCount { finalized, active, head, slot }) => (finalized, active, head, slot),
+                    _ => panic!("expected a block count"),
+                },
+                _ => panic!("expected a response"),
+            }
+        };
+        // only genesis exists
+        let (finalized, active, head, slot) = block_count(&mut state);
+        assert_eq!((finalized, active), (1, 0));
+        assert!(head == genesis.hash && slot == genesis.slot);
+
+        let mut previous = genesis.hash;
+        let mut blocks = Vec::new();
+        for slot in [Slot::zero().next(), Slot::zero().next().next()] {
+            let block = Arc::new(Block::sign(id, slot, previous, BlockBody::default()));
+            state.active.insert(block.hash, Box::new(BlockEntry { block: block.clone(), tasks: vec![] }), previous).unwrap();
+            previous = block.hash;
+            blocks.push(block);
+        }
+        let (finalized, active, head, slot) = block_count(&mut state);
+        assert_eq!((finalized, active), (1, 2));
+        assert!(head == blocks[1].hash && slot == blocks[1].slot);
+
+        state.finalize_up_to(blocks[0].hash).unwrap();
+        let (finalized, active, head, _) = block_count(&mut state);
+        assert_eq!((finalized, active), (2, 1));
+        assert!(head == blocks[1].hash);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_import() {
+        let dir = test_dir("verify_import");
+        let (mut state, hashes) = state_with_chain(&dir);
+        let genesis = state.finalized.get(0).unwrap();
+        let root = state.bank.state_root();
+        // an export of the chain from genesis, its blocks encoded and length-prefixed
+        let mut blocks = vec![(*genesis).clone()];
+        blocks.extend(hashes.iter().map(|hash| (*state.active.get(hash).unwrap().block).clone()));
+        let export = |blocks: &[Block]| {
+            let mut bytes = Vec::new();
+            for block in blocks {
+                let mut frame = vec![0u8; 4];
+                util::encode_into_writer(&mut frame, block).unwrap();
+                let len = (frame.len() as u32 - 4).to_le_bytes();
+                frame[0..4].copy_from_slice(&len);
+                bytes.extend_from_slice(&frame);
+            }
+            bytes
+        };
+
+        let report = state.verify_import(export(&blocks).as_slice());
+        assert!(report.failure.is_none());
+        assert_eq!(report.verified, N_BLOCKS + 1);
+        // nothing was applied
+        assert!(state.bank.state_root() == root);
+        assert_eq!(state.finalized.len(), 1);
+
+        // a block altered after signing is reported, and nothing after it is verified
+        let mut corrupted = blocks.clone();
+        corrupted[7].slot = corrupted[7].slot.next();
+        let report = state.verify_import(export(&corrupted).as_slice());
+        assert_eq!(report.verified, 7);
+        assert_eq!(report.failure.unwrap().0, 7);
+
+        // as is a chain with a block missing
+        let mut gapped = blocks.clone();
+        gapped.remove(10);
+        let report = state.verify_import(export(&gapped).as_slice());
+        assert_eq!(report.verified, 10);
+        assert!(report.failure.unwrap().1.as_str().contains("doesn't follow"));
+
+        // and an export cut off mid-block
+        let bytes = export(&blocks);
+        let report = state.verify_import(&bytes[..bytes.len() - 1]);
+        assert_eq!(report.verified, N_BLOCKS);
+        assert!(report.failure.is_some());
+
+        // verified over RPC from a file on the node's host
+        let path = format!("{}/export", dir);
+        std::fs::write(&path, export(&corrupted)).unwrap();
+        let (rpc_sender, mut mailbox) = process::channel();
+        state.on_rpc_request(rpc_sender.clone(), 5, RpcRequest::VerifyImport(path));
+        match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::RpcResponse(envelope)) => match envelope.result {
+                Ok(RpcResponse::VerifyImport { verified, failure }) => {
+                    assert_eq!(verified, 7);
+                    assert_eq!(failure.unwrap().0, 7);
+                }
+                _ => panic!("expected an import report"),
+            },
+            _ => panic!("expected a response"),
         }
+        state.on_rpc_request(rpc_sender, 6, RpcRequest::VerifyImport(format!("{}/missing", dir)));
+        assert!(matches!(
+            mailbox.recv_timeout(Duration::from_secs(1)),
+            Some(Message::RpcResponse(envelope)) if matches!(envelope.result, Err((RpcErrorCode::Failed, _)))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
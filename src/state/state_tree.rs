@@ -0,0 +1,112 @@
+use crate::keys::Hash;
+
+/// A merkle tree over the hashes of all accounts, kept up to date as accounts
+/// change so that its root costs O(log n) per changed account rather than O(n).
+/// Its root always equals `util::merkle_root_direct` of its leaves.
+pub struct StateTree {
+    /// Each row of the tree, from the leaves up to the root
+    rows: Vec<Vec<Hash>>,
+}
+
+impl StateTree {
+    /// Creates a `StateTree` over `leaves`
+    pub fn new(leaves: Vec<Hash>) -> Self {
+        let mut rows = vec![leaves];
+        while rows.last().unwrap().len() > 1 {
+            let row = rows.last().unwrap();
+            let next = (0..row.len().div_ceil(2)).map(|i| Self::parent(row, i)).collect();
+            rows.push(next);
+        }
+        Self { rows }
+    }
+
+    /// Get the merkle root of the leaves, or `Hash::zero()` if there are none
+    pub fn root(&self) -> Hash {
+        match self.rows[0].is_empty() {
+            true => Hash::zero(),
+            false => self.rows.last().unwrap()[0],
+        }
+    }
+
+    /// Replace the leaf at `index`
+    pub fn set(&mut self, index: u64, leaf: Hash) {
+        self.rows[0][index as usize] = leaf;
+        self.update_path(index as usize);
+    }
+
+    /// Append a leaf
+    pub fn push(&mut self, leaf: Hash) {
+        self.rows[0].push(leaf);
+        self.update_path(self.rows[0].len() - 1);
+    }
+
+    /// Remove the last leaf
+    pub fn pop(&mut self) {
+        if self.rows[0].pop().is_none() {
+            return;
+        }
+        match self.rows[0].len() {
+            0 => self.rows.truncate(1),
+            len => self.update_path(len - 1),
+        }
+    }
+
+    /// Hash the `i`th pair of `row`, pairing an odd last hash with `Hash::zero()`
+    fn parent(row: &[Hash], i: usize) -> Hash {
+        let mut buf = [0u8; 64];
+        buf[0..32].copy_from_slice(row[2 * i].as_bytes());
+        buf[32..64].copy_from_slice(row.get(2 * i + 1).unwrap_or(&Hash::zero()).as_bytes());
+        Hash::digest(&buf)
+    }
+
+    /// Rehash the ancestors of the leaf at `index`, resizing each row to fit the one below it
+    fn update_path(&mut self, mut index: usize) {
+        let mut level = 0;
+        while self.rows[level].len() > 1 {
+            let len = self.rows[level].len().div_ceil(2);
+            if self.rows.len() == level + 1 {
+                self.rows.push(Vec::with_capacity(len));
+            }
+            self.rows[level + 1].resize(len, Hash::zero());
+            let parent = Self::parent(&self.rows[level], index / 2);
+            index /= 2;
+            level += 1;
+            self.rows[level][index] = parent;
+        }
+        self.rows.truncate(level + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+    use rand::Rng;
+
+    #[test]
+    fn test_matches_direct_root() {
+        let mut rng = rand::thread_rng();
+        let mut leaves: Vec<Hash> = vec![];
+        let mut tree = StateTree::new(vec![]);
+        assert!(tree.root() == Hash::zero());
+        for _ in 0..200 {
+            match rng.gen_range(0..4) {
+                0 if !leaves.is_empty() => {
+                    leaves.pop();
+                    tree.pop();
+                }
+                1 if !leaves.is_empty() => {
+                    let index = rng.gen_range(0..leaves.len());
+                    leaves[index] = Hash::random();
+                    tree.set(index as u64, leaves[index]);
+                }
+                _ => {
+                    leaves.push(Hash::random());
+                    tree.push(*leaves.last().unwrap());
+                }
+            }
+            assert!(tree.root() == util::merkle_root_direct(leaves.clone()));
+            assert!(StateTree::new(leaves.clone()).root() == tree.root());
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::{sync::{mpsc::{self, Receiver, SyncSender, TrySendError}, Arc, Mutex}, thread, time::Duration};
+
+use crate::{error, keys::{Difficulty, Hash, Work}, rpc::RpcErrorCode, util::Error};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Generates work for `work_generate` RPCs on a fixed pool of threads, so that
+/// they can't take every core. Up to `max_queued` generations wait for a free
+/// thread; beyond that, generations are rejected as busy.
+#[derive(Clone)]
+pub struct WorkGenerator {
+    jobs: SyncSender<Job>,
+    /// How long a generation may search for work once it's running
+    timeout: Duration,
+}
+
+impl WorkGenerator {
+    pub fn new(timeout: Duration, max_concurrent: usize, max_queued: usize) -> Result<Self, Error> {
+        if max_concurrent == 0 {
+            return Err(error!("at least one concurrent work generation must be allowed"));
+        }
+        let (jobs, receiver) = mpsc::sync_channel(max_queued);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..max_concurrent {
+            let receiver = receiver.clone();
+            thread::spawn(move || Self::work(receiver));
+        }
+        Ok(Self { jobs, timeout })
+    }
+
+    /// Run jobs until every `WorkGenerator` is dropped
+    fn work(receiver: Arc<Mutex<Receiver<Job>>>) {
+        loop {
+            let job = match receiver.lock().unwrap().recv() {
+                Ok(job) => job,
+                Err(_) => return,
+            };
+            job();
+        }
+    }
+
+    /// Queue `job` to run on the pool, returning `false` if the queue is full.
+    fn submit(&self, job: Job) -> bool {
+        match self.jobs.try_send(job) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Generate work for `subject` at `threshold` on the pool, calling `reply` once
+    /// it's found or `timeout` has elapsed, or straight away if the pool is busy
+    pub fn generate(
+        &self,
+        subject: Hash,
+        threshold: Difficulty,
+        reply: impl FnOnce(Result<Work, (RpcErrorCode, String)>) + Send + 'static,
+    ) {
+        let reply = Arc::new(Mutex::new(Some(reply)));
+        let (job_reply, timeout) = (reply.clone(), self.timeout);
+        let submitted = self.submit(Box::new(move || {
+            let result = Work::generate_timeout(&subject, threshold, timeout)
+                .ok_or_else(|| (RpcErrorCode::Failed, format!("work generation timed out after {:?}", timeout)));
+            if let Some(reply) = job_reply.lock().unwrap().take() {
+                reply(result);
+            }
+        }));
+        if !submitted {
+            if let Some(reply) = reply.lock().unwrap().take() {
+                reply(Err((RpcErrorCode::Busy, "too many work generations running".to_string())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::{AtomicUsize, Ordering}, mpsc::channel};
+
+    #[test]
+    fn test_concurrency_limit() {
+        const LIMIT: usize = 2;
+        let generator = WorkGenerator::new(Duration::from_secs(1), LIMIT, 8).unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let (done, finished) = channel();
+        for _ in 0..8 {
+            let (running, max_running, done) = (running.clone(), max_running.clone(), done.clone());
+            assert!(generator.submit(Box::new(move || {
+                let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(n, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                done.send(()).unwrap();
+            })));
+        }
+        for _ in 0..8 {
+            finished.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), LIMIT);
+    }
+
+    #[test]
+    fn test_reject_beyond_queue() {
+        let generator = WorkGenerator::new(Duration::from_millis(200), 1, 0).unwrap();
+        let impossible = Difficulty::from_le_fixed(&u64::MAX.to_le_bytes());
+        let (results, replies) = channel();
+        let reply = move |results: mpsc::Sender<_>| move |result| results.send(result).unwrap();
+        generator.generate(Hash::random(), impossible, reply(results.clone()));
+        thread::sleep(Duration::from_millis(50));
+        // the only thread is busy and nothing may queue
+        generator.generate(Hash::random(), impossible, reply(results.clone()));
+        assert!(matches!(replies.recv().unwrap(), Err((RpcErrorCode::Busy, _))));
+        assert!(matches!(replies.recv().unwrap(), Err((RpcErrorCode::Failed, _))));
+        // the thread is free again once the generation finishes
+        thread::sleep(Duration::from_millis(50));
+        assert!(generator.submit(Box::new(|| ())));
+        assert!(WorkGenerator::new(Duration::from_secs(1), 0, 0).is_err());
+    }
+}
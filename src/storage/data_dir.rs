@@ -0,0 +1,236 @@
+use std::{fs, path::Path};
+
+use crate::{error, error_with, util::Error};
+
+/// The version of the `data_dir` layout written by this node.
+/// - 0: unversioned; the `Bank` is stored directly in `data_dir`
+/// - 1: the `Bank` is stored in `data_dir/bank`
+pub const DATA_DIR_VERSION: u32 = 1;
+const VERSION_FILE: &str = "version";
+const CHECKPOINT_FILE: &str = "checkpoint";
+
+/// Get the directory of the `Bank` within `data_dir`.
+pub fn bank_dir(data_dir: &str) -> String {
+    format!("{}/bank", data_dir)
+}
+
+/// Get the directory of the account database within `data_dir`.
+pub fn db_dir(data_dir: &str) -> String {
+    format!("{}/db", data_dir)
+}
+
+/// Get the directory of the account index to public key database within `data_dir`.
+pub fn publics_dir(data_dir: &str) -> String {
+    format!("{}/publics", data_dir)
+}
+
+/// Get the path of the finalized blocks within `data_dir`.
+pub fn finalized_path(data_dir: &str) -> String {
+    format!("{}/finalized", data_dir)
+}
+
+/// Get the path of the votes proving blocks finalized within `data_dir`.
+pub fn proofs_path(data_dir: &str) -> String {
+    format!("{}/proofs", data_dir)
+}
+
+/// Get the path of the snapshot of the peer table within `data_dir`.
+pub fn peers_path(data_dir: &str) -> String {
+    format!("{}/peers", data_dir)
+}
+
+/// Get the directory of the final nonces of pruned accounts within `data_dir`.
+pub fn retired_dir(data_dir: &str) -> String {
+    format!("{}/retired", data_dir)
+}
+
+/// Get the version of the layout of `data_dir`, or `None` if it is new.
+fn read_version(data_dir: &str) -> Result<Option<u32>, Error> {
+    let path = Path::new(data_dir).join(VERSION_FILE);
+    if path.exists() {
+        let version = fs::read_to_string(&path)
+            .map_err(|e| error_with!(super::io_error(e), "failed to read {}", path.display()))?;
+        let version = version
+            .trim()
+            .parse()
+            .map_err(|_| error!("invalid data directory version: {}", version.trim()))?;
+        return Ok(Some(version));
+    }
+    match fs::read_dir(data_dir).map(|mut entries| entries.next().is_some()) {
+        Ok(true) => Ok(Some(0)),
+        _ => Ok(None),
+    }
+}
+
+fn write_version(data_dir: &str, version: u32) -> Result<(), Error> {
+    fs::write(Path::new(data_dir).join(VERSION_FILE), format!("{}\n", version))?;
+    Ok(())
+}
+
+/// Migrate from version 0 to version 1 by moving the `Bank` into its own directory.
+fn migrate_0_to_1(data_dir: &str) -> Result<(), Error> {
+    let bank_dir = bank_dir(data_dir);
+    fs::create_dir_all(&bank_dir)?;
+    for entry in fs::read_dir(data_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("memmap_") && name.ends_with(".bin") {
+            fs::rename(entry.path(), Path::new(&bank_dir).join(&*name))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the `Bank` and account index of `data_dir`, so that they can be
+/// rebuilt from the finalized blocks.
+pub fn remove_accounts(data_dir: &str) -> Result<(), Error> {
+    for dir in [bank_dir(data_dir), db_dir(data_dir), publics_dir(data_dir), retired_dir(data_dir)] {
+        match fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(error_with!(super::io_error(e), "failed to remove {}", dir));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Record that the accounts of `data_dir` were flushed holding the state after the
+/// finalized block at `height`, and nothing after it. The record is replaced atomically.
+pub fn write_checkpoint(data_dir: &str, height: u64) -> Result<(), Error> {
+    let path = Path::new(data_dir).join(CHECKPOINT_FILE);
+    let staged = Path::new(data_dir).join(format!("{}.tmp", CHECKPOINT_FILE));
+    fs::write(&staged, format!("{}\n", height))
+        .and_then(|_| fs::rename(&staged, &path))
+        .map_err(|e| error_with!(super::io_error(e), "failed to write {}", path.display()))
+}
+
+/// Remove the checkpoint of `data_dir`, returning the height recorded by `write_checkpoint`,
+/// or `None` if there is no readable one. Once it's taken, the accounts can't be trusted
+/// again until they are checkpointed, since they may be written at any time after.
+pub fn take_checkpoint(data_dir: &str) -> Result<Option<u64>, Error> {
+    let path = Path::new(data_dir).join(CHECKPOINT_FILE);
+    let height = match fs::read_to_string(&path) {
+        Ok(height) => height.trim().parse().ok(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(error_with!(super::io_error(e), "failed to read {}", path.display())),
+    };
+    fs::remove_file(&path).map_err(|e| error_with!(super::io_error(e), "failed to remove {}", path.display()))?;
+    Ok(height)
+}
+
+/// Prepare `data_dir` for use by this node, migrating older layouts to
+/// `DATA_DIR_VERSION`. Refuses to open layouts newer than `DATA_DIR_VERSION`,
+/// which were written by a newer node.
+pub fn prepare_data_dir(data_dir: &str) -> Result<(), Error> {
+    let mut version = match read_version(data_dir)? {
+        Some(version) => version,
+        None => {
+            fs::create_dir_all(data_dir).map_err(super::io_error)?;
+            write_version(data_dir, DATA_DIR_VERSION)?;
+            return Ok(());
+        }
+    };
+    if version > DATA_DIR_VERSION {
+        return Err(error!(
+            "data directory {} has version {}, but this node only supports up to version {}",
+            data_dir,
+            version,
+            DATA_DIR_VERSION
+        ));
+    }
+    while version < DATA_DIR_VERSION {
+        match version {
+            0 => migrate_0_to_1(data_dir)?,
+            _ => unreachable!(),
+        }
+        version += 1;
+        write_version(data_dir, version)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Hash;
+
+    fn test_dir() -> String {
+        format!("{}/starlight_data_dir_{}", std::env::temp_dir().display(), Hash::random())
+    }
+
+    #[test]
+    fn test_new() {
+        let dir = test_dir();
+        prepare_data_dir(&dir).unwrap();
+        assert_eq!(read_version(&dir).unwrap(), Some(DATA_DIR_VERSION));
+        prepare_data_dir(&dir).unwrap();
+        assert_eq!(read_version(&dir).unwrap(), Some(DATA_DIR_VERSION));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = test_dir();
+        prepare_data_dir(&dir).unwrap();
+        assert_eq!(take_checkpoint(&dir).unwrap(), None);
+        write_checkpoint(&dir, 7).unwrap();
+        write_checkpoint(&dir, 12).unwrap();
+        assert_eq!(take_checkpoint(&dir).unwrap(), Some(12));
+        // a checkpoint is only trusted once
+        assert_eq!(take_checkpoint(&dir).unwrap(), None);
+        fs::write(Path::new(&dir).join(CHECKPOINT_FILE), "garbage").unwrap();
+        assert_eq!(take_checkpoint(&dir).unwrap(), None);
+        assert!(!Path::new(&dir).join(CHECKPOINT_FILE).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_future_version() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        write_version(&dir, DATA_DIR_VERSION + 1).unwrap();
+        let e = prepare_data_dir(&dir).unwrap_err();
+        assert!(e.as_str().contains("only supports up to version"));
+        assert_eq!(read_version(&dir).unwrap(), Some(DATA_DIR_VERSION + 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_version() {
+        let dir = test_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(Path::new(&dir).join(VERSION_FILE), "banana").unwrap();
+        assert!(prepare_data_dir(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_from_unversioned() {
+        let dir = test_dir();
+        fs::create_dir_all(db_dir(&dir)).unwrap();
+        fs::write(Path::new(&dir).join("memmap_0.bin"), [1, 2, 3]).unwrap();
+        prepare_data_dir(&dir).unwrap();
+        assert_eq!(read_version(&dir).unwrap(), Some(DATA_DIR_VERSION));
+        assert!(!Path::new(&dir).join("memmap_0.bin").exists());
+        assert_eq!(fs::read(Path::new(&bank_dir(&dir)).join("memmap_0.bin")).unwrap(), [1, 2, 3]);
+        assert!(Path::new(&db_dir(&dir)).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_accounts() {
+        let dir = test_dir();
+        prepare_data_dir(&dir).unwrap();
+        fs::create_dir_all(bank_dir(&dir)).unwrap();
+        fs::create_dir_all(db_dir(&dir)).unwrap();
+        fs::write(finalized_path(&dir), [1, 2, 3]).unwrap();
+        remove_accounts(&dir).unwrap();
+        assert!(!Path::new(&bank_dir(&dir)).exists());
+        assert!(!Path::new(&db_dir(&dir)).exists());
+        assert_eq!(fs::read(finalized_path(&dir)).unwrap(), [1, 2, 3]);
+        assert_eq!(read_version(&dir).unwrap(), Some(DATA_DIR_VERSION));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
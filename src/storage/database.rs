@@ -1,6 +1,8 @@
 use std::fs;
 use heed::{bytemuck::Pod, types::OwnedType, Database as HeedDatabase, Env, EnvOpenOptions};
-use crate::util::Error;
+use crate::{log_error, util::Error};
+
+use super::Flush;
 
 /// Simple key-value storage built upon LMDB.
 pub struct Database<K: Pod, V: Pod> {
@@ -10,7 +12,7 @@ pub struct Database<K: Pod, V: Pod> {
 
 impl<K: Pod, V: Pod> Database<K, V> {
     pub fn open(directory: &str) -> Result<Self, Error> {
-        fs::create_dir_all(directory)?;
+        fs::create_dir_all(directory).map_err(super::io_error)?;
         let env = EnvOpenOptions::new()
             .max_dbs(1)
             .max_readers(u32::MAX)
@@ -28,6 +30,7 @@ impl<K: Pod, V: Pod> Database<K, V> {
         let rtxn = self.env.read_txn().unwrap();
         self.db.get(&rtxn, k).unwrap()
     }
+    #[cfg(test)]
     pub fn contains_key(&self, k: &K) -> bool {
         self.get(k).is_some()
     }
@@ -36,9 +39,59 @@ impl<K: Pod, V: Pod> Database<K, V> {
         self.db.put(&mut wtxn, k, v).unwrap();
         wtxn.commit().unwrap();
     }
+    /// Call `f` with every key and value, in key order.
+    pub fn for_each(&self, mut f: impl FnMut(K, V)) {
+        let rtxn = self.env.read_txn().unwrap();
+        for entry in self.db.iter(&rtxn).unwrap() {
+            let (k, v) = entry.unwrap();
+            f(k, v);
+        }
+    }
     pub fn remove(&self, k: &K) {
         let mut wtxn = self.env.write_txn().unwrap();
         self.db.delete(&mut wtxn, k).unwrap();
         wtxn.commit().unwrap();
     }
+    /// Force all committed writes to disk.
+    pub fn sync(&self) -> Result<(), Error> {
+        self.env.force_sync()?;
+        Ok(())
+    }
+}
+
+impl<K: Pod, V: Pod> Flush for Database<K, V> {
+    fn flush(&self) -> Result<(), Error> {
+        self.sync()
+    }
+}
+
+/// Syncs on drop, so that writes since the last flush survive a shutdown.
+impl<K: Pod, V: Pod> Drop for Database<K, V> {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            log_error!("Failed to sync database on drop: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reopen_after_drop() {
+        let dir = "test_database_reopen";
+        let db = Database::<u64, u64>::open(dir).unwrap();
+        for i in 0..100u64 {
+            db.put(&i, &(i * 2));
+        }
+        db.remove(&7);
+        drop(db);
+        let db = Database::<u64, u64>::open(dir).unwrap();
+        assert_eq!(db.len(), 99);
+        assert_eq!(db.get(&99), Some(198));
+        assert!(!db.contains_key(&7));
+        drop(db);
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{log_error, process::{self, ProcessSolitaryEndless}, util::Error};
+
+/// A persistent store whose writes can be forced to disk.
+pub trait Flush {
+    fn flush(&self) -> Result<(), Error>;
+}
+
+/// Flushes every registered store at a regular interval, trading off
+/// durability against the I/O spent flushing.
+pub struct Flusher {
+    stores: Vec<Arc<dyn Flush + Send + Sync>>,
+    interval: Duration
+}
+
+/// Flush every store of `stores`, logging those which fail
+pub fn flush_all(stores: &[Arc<dyn Flush + Send + Sync>]) {
+    for store in stores.iter() {
+        if let Err(e) = store.flush() {
+            log_error!("Failed to flush store: {}", e);
+        }
+    }
+}
+
+impl Flusher {
+    pub fn new(stores: Vec<Arc<dyn Flush + Send + Sync>>, interval: Duration) -> Self {
+        Self { stores, interval }
+    }
+}
+
+impl ProcessSolitaryEndless for Flusher {
+    fn run(&mut self) -> ! {
+        loop {
+            process::sleep(self.interval);
+            flush_all(&self.stores);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    struct MockStore(AtomicU64);
+
+    impl Flush for MockStore {
+        fn flush(&self) -> Result<(), Error> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flush_at_interval() {
+        let store = Arc::new(MockStore(AtomicU64::new(0)));
+        process::spawn_solitary_endless(Flusher::new(vec![store.clone()], Duration::from_millis(50)));
+        process::sleep(Duration::from_millis(25));
+        assert_eq!(store.0.load(Ordering::Relaxed), 0);
+        process::sleep(Duration::from_millis(250));
+        let flushes = store.0.load(Ordering::Relaxed);
+        assert!((3..=6).contains(&flushes), "{}", flushes);
+    }
+}
@@ -1,6 +1,8 @@
 use memmap2::MmapMut;
-use std::marker::PhantomData;
-use crate::util::Error;
+use std::{fs::OpenOptions, marker::PhantomData};
+use crate::{error, util::Error};
+
+use super::Flush;
 
 const FILE_SIZE: u64 = 4 * 1024 * 1024; // 4MB
 
@@ -10,8 +12,12 @@ const FILE_SIZE: u64 = 4 * 1024 * 1024; // 4MB
 /// that together provide the backing store for a list.
 /// `ListStore` will append to the end of the last file until there is no more 
 /// space, at which point it will create a new memory map.
+/// The length is kept in a memory-mapped `len` file, so that reopening the
+/// directory restores the list.
 pub struct ListStore<T> {
     memmaps: Vec<MmapMut>,
+    /// The length, as 8 little-endian bytes
+    len_memmap: MmapMut,
     directory: String,
     capacity: u64,
     len: u64,
@@ -19,42 +25,53 @@ pub struct ListStore<T> {
 }
 
 impl<T> ListStore<T> {
-    /// Opens a new `ListStore` in the given directory.
+    /// Opens the `ListStore` in the given directory, mapping back in the
+    /// `memmap_*.bin` files of any previous run. Other files are ignored.
     pub fn open(directory: &str) -> Result<Self, Error> {
-        std::fs::create_dir_all(directory)?;
-        Ok(Self { 
+        std::fs::create_dir_all(directory).map_err(super::io_error)?;
+        let len_memmap = map_file(&format!("{}/len", directory), 8)?;
+        let mut store = Self { 
             memmaps: Vec::new(),
+            len_memmap,
             capacity: 0, 
             directory: directory.to_string(),
             len: 0,
             _phantom: PhantomData,
-        })
+        };
+        // files are numbered consecutively, so the first missing one ends the list;
+        // a last file that was only partly created is extended to the full size
+        while std::path::Path::new(&store.memmap_path(store.memmaps.len())).exists() {
+            store.add_memmap()?;
+        }
+        let len = u64::from_le_bytes(store.len_memmap[..8].try_into().unwrap());
+        if len > store.capacity {
+            return Err(error!("list store in {} has length {} beyond its capacity {}", directory, len, store.capacity));
+        }
+        store.len = len;
+        Ok(store)
     }
 
-    /// Adds a new memory-mapped file to the list of memmaps.
-    fn add_memmap(&mut self) {
-        let path = format!("{}/memmap_{}.bin", self.directory, self.memmaps.len());
-        let file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&path)
-            .expect("Unable to open file");
-
-        file.set_len(FILE_SIZE as u64).expect("Unable to set file size");
-        
-        let mmap = unsafe { 
-            MmapMut::map_mut(&file).expect("Unable to memory map file") 
-        };
+    fn memmap_path(&self, index: usize) -> String {
+        format!("{}/memmap_{}.bin", self.directory, index)
+    }
 
+    /// Adds a new memory-mapped file to the list of memmaps.
+    fn add_memmap(&mut self) -> Result<(), Error> {
+        let mmap = map_file(&self.memmap_path(self.memmaps.len()), FILE_SIZE)?;
         self.memmaps.push(mmap);
         self.capacity += FILE_SIZE / std::mem::size_of::<T>() as u64;
+        Ok(())
+    }
+
+    fn set_len(&mut self, len: u64) {
+        self.len = len;
+        self.len_memmap[..8].copy_from_slice(&len.to_le_bytes());
     }
 
     /// Appends an item to the end of the list.
     pub fn push(&mut self, item: T) {
         if self.len == self.capacity {
-            self.add_memmap();
+            self.add_memmap().expect("Unable to add memory map");
         }
         
         let memmap_index = self.len / (FILE_SIZE / std::mem::size_of::<T>() as u64);
@@ -65,7 +82,7 @@ impl<T> ListStore<T> {
             ptr.write(item);
         }
         
-        self.len += 1;
+        self.set_len(self.len + 1);
     }
     
     /// Removes the last item from the list and returns it, or `None` if empty.
@@ -73,7 +90,7 @@ impl<T> ListStore<T> {
         if self.len == 0 {
             None
         } else {
-            self.len -= 1;
+            self.set_len(self.len - 1);
             let memmap_index = self.len / (FILE_SIZE / std::mem::size_of::<T>() as u64);
             let offset = (self.len % (FILE_SIZE / std::mem::size_of::<T>() as u64)) * std::mem::size_of::<T>() as u64;
             
@@ -99,8 +116,110 @@ impl<T> ListStore<T> {
         }
     }
 
+    /// Returns a mutable reference to the item at the given index, or `None` if out of bounds.
+    pub fn get_mut(&mut self, index: u64) -> Option<&mut T> {
+        if index >= self.len {
+            None
+        } else {
+            let memmap_index = index / (FILE_SIZE / std::mem::size_of::<T>() as u64);
+            let offset = (index % (FILE_SIZE / std::mem::size_of::<T>() as u64)) * std::mem::size_of::<T>() as u64;
+
+            unsafe {
+                let ptr = self.memmaps[memmap_index as usize].as_mut_ptr().add(offset as usize) as *mut T;
+                Some(&mut *ptr)
+            }
+        }
+    }
+
     pub fn len(&self) -> u64 {
         self.len
     }
 }
 
+/// Open the file at `path`, creating it or extending it to `len` bytes if shorter, and map it
+fn map_file(path: &str, len: u64) -> Result<MmapMut, Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(super::io_error)?;
+    if file.metadata().map_err(super::io_error)?.len() < len {
+        file.set_len(len).map_err(super::io_error)?;
+    }
+    Ok(unsafe { MmapMut::map_mut(&file).map_err(super::io_error)? })
+}
+
+impl<T> Flush for ListStore<T> {
+    fn flush(&self) -> Result<(), Error> {
+        for memmap in self.memmaps.iter() {
+            memmap.flush()?;
+        }
+        // only once the items are on disk, so the length never covers unwritten items
+        self.len_memmap.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Hash;
+
+    fn test_dir() -> String {
+        format!("{}/starlight_list_store_{}", std::env::temp_dir().display(), Hash::random())
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = test_dir();
+        let per_file = FILE_SIZE / 8;
+        let n = per_file + 10;
+        let mut store = ListStore::<u64>::open(&dir).unwrap();
+        for i in 0..n {
+            store.push(i * 3);
+        }
+        store.pop();
+        store.flush().unwrap();
+        drop(store);
+
+        // unrelated files are ignored
+        std::fs::write(format!("{}/notes.txt", dir), "hello").unwrap();
+        std::fs::write(format!("{}/memmap_x.bin", dir), "hello").unwrap();
+        let mut store = ListStore::<u64>::open(&dir).unwrap();
+        assert_eq!(store.len(), n - 1);
+        for i in 0..n - 1 {
+            assert_eq!(store.get(i), Some(&(i * 3)));
+        }
+        assert_eq!(store.get(n - 1), None);
+        store.push(7);
+        assert_eq!(store.get(n - 1), Some(&7));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_reopen_partial_file() {
+        let dir = test_dir();
+        let mut store = ListStore::<u64>::open(&dir).unwrap();
+        store.push(1);
+        drop(store);
+
+        // a file whose creation was interrupted before it was sized
+        std::fs::write(format!("{}/memmap_1.bin", dir), [0u8; 16]).unwrap();
+        let mut store = ListStore::<u64>::open(&dir).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.capacity, 2 * FILE_SIZE / 8);
+        for i in 0..FILE_SIZE / 8 {
+            store.push(i);
+        }
+        assert_eq!(store.get(FILE_SIZE / 8), Some(&(FILE_SIZE / 8 - 1)));
+
+        // a length beyond the files is rejected rather than reading past them
+        drop(store);
+        std::fs::write(format!("{}/len", dir), (3 * FILE_SIZE).to_le_bytes()).unwrap();
+        assert!(ListStore::<u64>::open(&dir).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
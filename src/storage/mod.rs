@@ -1,7 +1,45 @@
 mod list_store;
 mod object_store;
 mod database;
+mod data_dir;
+mod flusher;
+mod record_log;
+
+use std::io::{self, ErrorKind};
+
+use crate::util::Error;
 
 pub use list_store::ListStore;
 pub use object_store::ObjectStore;
-pub use database::Database;
\ No newline at end of file
+pub use database::Database;
+pub use flusher::{flush_all, Flush, Flusher};
+pub use record_log::RecordLog;
+pub use data_dir::{bank_dir, db_dir, finalized_path, peers_path, prepare_data_dir, proofs_path, publics_dir, remove_accounts, retired_dir, take_checkpoint, write_checkpoint};
+#[cfg(test)]
+pub use data_dir::DATA_DIR_VERSION;
+
+/// Convert an I/O error, marking as transient those which may not recur
+/// if retried, such as a busy or temporarily full disk
+pub fn io_error(e: io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::Interrupted
+        | ErrorKind::WouldBlock
+        | ErrorKind::TimedOut
+        | ErrorKind::ResourceBusy
+        | ErrorKind::StorageFull => Error::transient(e),
+        _ => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error() {
+        assert!(io_error(io::Error::from(ErrorKind::ResourceBusy)).is_transient());
+        assert!(io_error(io::Error::from(ErrorKind::Interrupted)).is_transient());
+        assert!(!io_error(io::Error::from(ErrorKind::PermissionDenied)).is_transient());
+        assert!(!io_error(io::Error::from(ErrorKind::InvalidData)).is_transient());
+    }
+}
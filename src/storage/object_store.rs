@@ -15,17 +15,23 @@ impl<T: Copy> ObjectStore<T> {
             .read(true)
             .write(true)
             .create(true)
-            .open(file_path)?;
+            .truncate(false)
+            .open(file_path)
+            .map_err(super::io_error)?;
 
         // Ensure the file is at least as large as T
-        file.set_len(std::mem::size_of::<T>() as u64)?;
+        file.set_len(std::mem::size_of::<T>() as u64).map_err(super::io_error)?;
 
-        let memmap = unsafe { MmapRaw::map_raw(&file)? };
+        let memmap = MmapRaw::map_raw(&file).map_err(super::io_error)?;
 
-        Ok(Self {
+        let mut store = Self {
             memmap,
             _phantom: PhantomData,
-        })
+        };
+        if !exists {
+            store.put(default)?;
+        }
+        Ok(store)
     }
 
     pub fn get(&self) -> T {
@@ -0,0 +1,105 @@
+use std::{fs::{self, File, OpenOptions}, io::{BufReader, Read, Seek, SeekFrom, Write}, marker::PhantomData, path::Path};
+
+use bincode::{Decode, Encode};
+
+use crate::{error, util::{self, Error}};
+
+/// An append-only file of encoded records, each prefixed by its length as a `u32`.
+/// Only the offsets of the records are kept in memory.
+pub struct RecordLog<T> {
+    file: File,
+    offsets: Vec<u64>,
+    end: u64,
+    _record: PhantomData<T>,
+}
+
+impl<T: Encode + Decode> RecordLog<T> {
+    /// Opens the `RecordLog` at `path`, creating it if it doesn't exist.
+    /// A last record left incomplete by an interrupted write is discarded.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent).map_err(super::io_error)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(super::io_error)?;
+        let file_len = file.metadata().map_err(super::io_error)?.len();
+        let (mut offsets, mut end) = (vec![], 0);
+        let mut reader = BufReader::new(&file);
+        let mut len = [0u8; 4];
+        while end + 4 <= file_len {
+            reader.read_exact(&mut len).map_err(super::io_error)?;
+            let next = end + 4 + u32::from_le_bytes(len) as u64;
+            if next > file_len {
+                break;
+            }
+            reader.seek_relative(u32::from_le_bytes(len) as i64).map_err(super::io_error)?;
+            offsets.push(end);
+            end = next;
+        }
+        file.set_len(end).map_err(super::io_error)?;
+        Ok(Self { file, offsets, end, _record: PhantomData })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Append `record` to the end of the log
+    pub fn push(&mut self, record: &T) -> Result<(), Error> {
+        let mut bytes = vec![0u8; 4];
+        util::encode_into_writer(&mut bytes, record)?;
+        let len = u32::try_from(bytes.len() - 4).map_err(|_| error!("record too large"))?;
+        bytes[0..4].copy_from_slice(&len.to_le_bytes());
+        (&self.file).seek(SeekFrom::Start(self.end)).map_err(super::io_error)?;
+        (&self.file).write_all(&bytes).map_err(super::io_error)?;
+        self.offsets.push(self.end);
+        self.end += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Read the record at `index` back from disk
+    pub fn get(&self, index: usize) -> Result<T, Error> {
+        let &offset = self.offsets.get(index).ok_or_else(|| error!("record {} out of range", index))?;
+        (&self.file).seek(SeekFrom::Start(offset)).map_err(super::io_error)?;
+        let mut len = [0u8; 4];
+        (&self.file).read_exact(&mut len).map_err(super::io_error)?;
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        (&self.file).read_exact(&mut bytes).map_err(super::io_error)?;
+        Ok(util::decode_from_slice(&bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_get() {
+        let path = "test_record_log/records";
+        let mut log = RecordLog::<Vec<u64>>::open(path).unwrap();
+        for i in 0..100u64 {
+            log.push(&(0..i).collect()).unwrap();
+        }
+        assert!(log.len() == 100);
+        for i in (0..100u64).rev() {
+            assert!(log.get(i as usize).unwrap() == (0..i).collect::<Vec<_>>());
+        }
+        assert!(log.get(100).is_err());
+        drop(log);
+
+        // opening the log again keeps its records, but not one cut off mid-write
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&[9, 0, 0, 0, 1, 2]).unwrap();
+        let mut log = RecordLog::<Vec<u64>>::open(path).unwrap();
+        assert!(log.len() == 100);
+        assert!(log.get(99).unwrap() == (0..99).collect::<Vec<_>>());
+        log.push(&vec![7]).unwrap();
+        assert!(log.get(100).unwrap() == vec![7]);
+        fs::remove_dir_all("test_record_log").unwrap();
+    }
+}
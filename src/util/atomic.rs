@@ -20,6 +20,7 @@ impl<T: ArchivableTo<u64>> Atomic<T> {
         T::unarchive(self.value.load(order))
     }
 
+    #[cfg(test)]
     pub fn store(&self, val: T, order: Ordering) {
         self.value.store(val.archive(), order);
     }
@@ -30,20 +31,6 @@ impl<T: ArchivableTo<u64>> Atomic<T> {
         T::unarchive(self.value.swap(val.archive(), order))
     }
 
-    pub fn compare_exchange(&self, current: T, new: T, success: Ordering, failure: Ordering) -> Result<T, T> {
-        match self.value.compare_exchange(current.archive(), new.archive(), success, failure) {
-            Ok(v) => Ok(T::unarchive(v)),
-            Err(v) => Err(T::unarchive(v)),
-        }
-    }
-
-    pub fn compare_exchange_weak(&self, current: T, new: T, success: Ordering, failure: Ordering) -> Result<T, T> {
-        match self.value.compare_exchange_weak(current.archive(), new.archive(), success, failure) {
-            Ok(v) => Ok(T::unarchive(v)),
-            Err(v) => Err(T::unarchive(v)),
-        }
-    }
-
     pub fn fetch_add(&self, val: T, order: Ordering) -> T {
         T::unarchive(self.value.fetch_add(val.archive(), order))
     }
@@ -51,28 +38,4 @@ impl<T: ArchivableTo<u64>> Atomic<T> {
     pub fn fetch_sub(&self, val: T, order: Ordering) -> T {
         T::unarchive(self.value.fetch_sub(val.archive(), order))
     }
-
-    pub fn fetch_and(&self, val: T, order: Ordering) -> T {
-        T::unarchive(self.value.fetch_and(val.archive(), order))
-    }
-
-    pub fn fetch_nand(&self, val: T, order: Ordering) -> T {
-        T::unarchive(self.value.fetch_nand(val.archive(), order))
-    }
-
-    pub fn fetch_or(&self, val: T, order: Ordering) -> T {
-        T::unarchive(self.value.fetch_or(val.archive(), order))
-    }
-
-    pub fn fetch_xor(&self, val: T, order: Ordering) -> T {
-        T::unarchive(self.value.fetch_xor(val.archive(), order))
-    }
-
-    pub fn fetch_max(&self, val: T, order: Ordering) -> T {
-        T::unarchive(self.value.fetch_max(val.archive(), order))
-    }
-
-    pub fn fetch_min(&self, val: T, order: Ordering) -> T {
-        T::unarchive(self.value.fetch_min(val.archive(), order))
-    }
 }
@@ -1,9 +1,10 @@
 // Derived from the keys module of github.com/feeless/feeless@978eba7.
 use crate::util::Error;
-use crate::{bail, error};
+use crate::bail;
+#[cfg(test)]
+use crate::error;
+#[cfg(test)]
 use bitvec::prelude::*;
-use std::fmt::{Display, UpperHex};
-use std::str::FromStr;
 
 pub fn to_hex(bytes: &[u8]) -> String {
     let mut s = String::with_capacity(bytes.len() * 2);
@@ -21,23 +22,12 @@ pub fn to_hex_lower(bytes: &[u8]) -> String {
     s
 }
 
-pub fn hex_formatter(f: &mut std::fmt::Formatter<'_>, bytes: &[u8]) -> std::fmt::Result {
-    for byte in bytes {
-        write!(f, "{:02X}", byte)?;
-    }
-    Ok(())
-}
-
-pub fn hex_formatter_lower(f: &mut std::fmt::Formatter<'_>, bytes: &[u8]) -> std::fmt::Result {
-    for byte in bytes {
-        write!(f, "{:02x}", byte)?;
-    }
-    Ok(())
-}
-
+#[cfg(test)]
 static ALPHABET: &str = "13456789abcdefghijkmnopqrstuwxyz";
+#[cfg(test)]
 const ENCODING_BITS: usize = 5;
 
+#[cfg(test)]
 fn encode_slt_base_32(bits: &BitSlice<u8, Msb0>) -> String {
     debug_assert_eq!(
         bits.len() % ENCODING_BITS,
@@ -54,6 +44,7 @@ fn encode_slt_base_32(bits: &BitSlice<u8, Msb0>) -> String {
     s
 }
 
+#[cfg(test)]
 fn decode_slt_base_32(s: &str) -> Result<BitVec<u8, Msb0>, Error> {
     let mut bits: BitVec<u8, Msb0> = BitVec::new(); // TODO: with_capacity
     for char in s.chars() {
@@ -85,28 +76,29 @@ fn decode_slt_base_32(s: &str) -> Result<BitVec<u8, Msb0>, Error> {
 macro_rules! hexify {
     ($struct:ident, $description:expr) => {
         impl $struct {
+            #[allow(dead_code)] // not every hexified type is viewed as bytes
             pub fn as_bytes(&self) -> &[u8] {
                 &self.0
             }
 
             pub fn as_hex(&self) -> String {
-                crate::util::to_hex(&self.0)
+                $crate::util::to_hex(&self.0)
             }
 
             pub fn as_hex_lower(&self) -> String {
-                crate::util::to_hex_lower(&self.0)
+                $crate::util::to_hex_lower(&self.0)
             }
         }
 
         impl ::std::str::FromStr for $struct {
-            type Err = crate::util::Error;
+            type Err = $crate::util::Error;
 
             fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
                 use ::std::convert::TryFrom;
 
-                crate::util::expect_len(s.len(), Self::LEN * 2, $description)?;
+                $crate::util::expect_len(s.len(), Self::LEN * 2, $description)?;
                 let vec = hex::decode(s.as_bytes())
-                    .map_err(|e| crate::error!("can't parse hex: {} {}", $description, e))?;
+                    .map_err(|e| $crate::error!("can't parse hex: {} {}", $description, e))?;
                 let bytes = vec.as_slice();
                 let x = <[u8; Self::LEN]>::try_from(bytes)?;
                 Ok(Self(x))
@@ -125,13 +117,13 @@ macro_rules! hexify {
                     f,
                     "{}({})",
                     stringify!($struct),
-                    crate::util::to_hex(self.0.as_ref()),
+                    $crate::util::to_hex(self.0.as_ref()),
                 )
             }
         }
 
         impl ::std::convert::TryFrom<&[u8]> for $struct {
-            type Error = crate::util::Error;
+            type Error = $crate::util::Error;
 
             fn try_from(v: &[u8]) -> std::result::Result<Self, Self::Error> {
                 Ok(Self(<[u8; Self::LEN]>::try_from(v)?))
@@ -171,6 +163,18 @@ macro_rules! hexify {
     };
 }
 
+pub fn expect_len(got_len: usize, expected_len: usize, msg: &str) -> Result<(), Error> {
+    if got_len != expected_len {
+        bail!(
+            "wrong length; expected: {}, found: {}: {}",
+            expected_len,
+            got_len,
+            msg
+        );
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::keys::Public;
@@ -244,22 +248,3 @@ mod tests {
         }
     }
 }
-
-pub fn expect_len(got_len: usize, expected_len: usize, msg: &str) -> Result<(), Error> {
-    if got_len != expected_len {
-        bail!(
-            "wrong length; expected: {}, found: {}: {}",
-            expected_len,
-            got_len,
-            msg
-        );
-    }
-    Ok(())
-}
-
-pub fn len_err_note(got_len: usize, expected_len: usize, msg: &str) -> String {
-    format!(
-        "{} is the wrong length: got: {} expected: {}",
-        msg, got_len, expected_len,
-    )
-}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+const TRANSIENT_PREFIX: &str = "transient: ";
+
 // if len <= 0, Error is a statically-allocated string
 // else, Error is a heap-allocated string
 pub struct Error {
@@ -41,9 +43,28 @@ impl Error {
         }
     }
 
+    /// An error which may not recur if the failed operation is retried
+    pub fn transient(e: impl fmt::Display) -> Self {
+        Self::from_string(format!("{}{}", TRANSIENT_PREFIX, e))
+    }
+
+    /// Might the failed operation succeed if retried?
+    pub fn is_transient(&self) -> bool {
+        self.as_str().starts_with(TRANSIENT_PREFIX)
+    }
+
+    /// Prepend `context` to the message, keeping the error transient if it was,
+    /// such as `failed to open bank: permission denied`
+    pub fn context(self, context: impl fmt::Display) -> Self {
+        match self.as_str().strip_prefix(TRANSIENT_PREFIX) {
+            Some(message) => Self::from_string(format!("{}{}: {}", TRANSIENT_PREFIX, context, message)),
+            None => Self::from_string(format!("{}: {}", context, self.as_str())),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         unsafe {
-            let len = self.len.abs() as usize;
+            let len = self.len.unsigned_abs();
             let bytes = std::slice::from_raw_parts(self.ptr, len);
             std::str::from_utf8_unchecked(bytes)
         }
@@ -96,19 +117,70 @@ where
     }
 }
 
+/// Adds context to the errors of a `Result`, converting them into `Error`
+pub trait Context<T> {
+    fn context(self, context: impl fmt::Display) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Error>> Context<T> for Result<T, E> {
+    fn context(self, context: impl fmt::Display) -> Result<T, Error> {
+        self.map_err(|e| e.into().context(context))
+    }
+}
+
 #[macro_export]
 macro_rules! error {
     ($msg:expr) => (
-        crate::util::Error::from_static(concat!($msg, " @ ", file!(), ":", line!()))
+        $crate::util::Error::from_static(concat!($msg, " @ ", file!(), ":", line!()))
     );
     ($fmt:expr, $($arg:tt)*) => (
-        crate::util::Error::from_string(format!(concat!($fmt, " @ {}:{}"), $($arg)*, file!(), line!()))
+        $crate::util::Error::from_string(format!(concat!($fmt, " @ {}:{}"), $($arg)*, file!(), line!()))
     );
 }
 
 #[macro_export]
 macro_rules! bail {
     ($($arg:tt)*) => (
-        return std::result::Result::Err(crate::error!($($arg)*))
+        return std::result::Result::Err($crate::error!($($arg)*))
     );
 }
+
+/// Like `error!`, but wrapping the error `$source` with the message as context
+#[macro_export]
+macro_rules! error_with {
+    ($source:expr, $msg:expr) => ({
+        let e = Into::<$crate::util::Error>::into($source).context($msg);
+        $crate::util::Error::from_string(format!("{} @ {}:{}", e.as_str(), file!(), line!()))
+    });
+    ($source:expr, $fmt:expr, $($arg:tt)*) => ({
+        let e = Into::<$crate::util::Error>::into($source).context(format!($fmt, $($arg)*));
+        $crate::util::Error::from_string(format!("{} @ {}:{}", e.as_str(), file!(), line!()))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_context() {
+        let result: Result<(), io::Error> = Err(io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"));
+        let e = result.context("failed to open bank").unwrap_err();
+        assert_eq!(e.as_str(), "failed to open bank: permission denied");
+        let e = Err::<(), _>(e).context("failed to create state").unwrap_err();
+        assert_eq!(e.as_str(), "failed to create state: failed to open bank: permission denied");
+        assert!(Error::transient("disk busy").context("failed to flush").is_transient());
+    }
+
+    #[test]
+    fn test_error_with() {
+        let source = io::Error::new(io::ErrorKind::PermissionDenied, "permission denied");
+        let e = crate::error_with!(source, "failed to open {}", "bank");
+        assert!(e.as_str().starts_with("failed to open bank: permission denied @ "));
+        assert!(e.as_str().contains(file!()));
+        let e = crate::error_with!(Error::transient("disk busy"), "failed to flush");
+        assert!(e.is_transient());
+        assert!(e.as_str().contains("failed to flush: disk busy @ "));
+    }
+}
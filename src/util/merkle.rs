@@ -1,23 +1,20 @@
 use crate::keys::Hash;
 
+/// Hash each pair of `hashes` into the next row, pairing an odd last hash with `Hash::zero()`
 fn merkle_row_direct(hashes: &[Hash]) -> Vec<Hash> {
-    assert_eq!(hashes.len() % 2, 0);
-    let mut row = Vec::with_capacity(hashes.len() / 2 + ((hashes.len() / 2) % 2));
     let mut buf = [0u8; 64];
-    for pair in hashes.chunks(2) {
-        buf[0..32].copy_from_slice(&pair[0].as_bytes());
-        buf[32..64].copy_from_slice(&pair[1].as_bytes());
-        row.push(Hash::digest(&buf));
-    }
-    if row.len() < row.capacity() {
-        row.push(Hash::zero());
-    }
-    row
+    hashes.chunks(2).map(|pair| {
+        buf[0..32].copy_from_slice(pair[0].as_bytes());
+        buf[32..64].copy_from_slice(pair.get(1).unwrap_or(&Hash::zero()).as_bytes());
+        Hash::digest(&buf)
+    }).collect()
 }
 
-pub fn merkle_root_direct(mut hashes: Vec<Hash>) -> Hash {
-    if hashes.len() % 2 != 0 {
-        hashes.push(Hash::zero());
+/// Get the merkle root of `hashes`, which is `Hash::zero()` if there are none
+/// and the hash itself if there is only one
+pub fn merkle_root_direct(hashes: Vec<Hash>) -> Hash {
+    if hashes.is_empty() {
+        return Hash::zero();
     }
     let mut row = hashes;
     while row.len() > 1 {
@@ -30,7 +27,7 @@ pub fn merkle_root<T, E, F: Fn(&T) -> Result<Hash, E>>(ts: &[T], f: F) -> Result
     match ts.len() {
         0 => Ok(Hash::zero()),
         1 => f(&ts[0]),
-        n => {
+        _ => {
             let mut tx_hashes = Vec::with_capacity(ts.len() + ts.len() % 2);
             for item in ts.iter() {
                 tx_hashes.push(f(item)?);
@@ -42,3 +39,61 @@ pub fn merkle_root<T, E, F: Fn(&T) -> Result<Hash, E>>(ts: &[T], f: F) -> Result
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_root_matches_direct() {
+        for n in 0..10 {
+            let hashes: Vec<Hash> = (0..n).map(|_| Hash::random()).collect();
+            let root = merkle_root(&hashes, |h| Ok::<_, ()>(*h)).unwrap();
+            assert!(root == merkle_root_direct(hashes.clone()));
+        }
+        let (a, b, c) = (Hash::random(), Hash::random(), Hash::random());
+        let digest = |l: Hash, r: Hash| {
+            let mut buf = [0u8; 64];
+            buf[0..32].copy_from_slice(l.as_bytes());
+            buf[32..64].copy_from_slice(r.as_bytes());
+            Hash::digest(&buf)
+        };
+        assert!(merkle_root_direct(vec![a]) == a);
+        assert!(merkle_root_direct(vec![a, b, c]) == digest(digest(a, b), digest(c, Hash::zero())));
+    }
+
+    #[test]
+    fn test_merkle_root_known_vectors() {
+        use std::str::FromStr;
+        // blake3 of no input, as published with the algorithm
+        assert_eq!(Hash::digest(&[]).as_hex_lower(), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        let leaves: Vec<Hash> = (0..6u8).map(|i| Hash::digest(&[i])).collect();
+        let roots = [
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+            "29cb5491b53991b0ed542e8e6e9a07ca078a9e63c29bebe2005c7f0d38fc5fe3",
+            "705d8bf5dc311201166c30bbda2e3cc3a92f171725cbea0135a7913a30c4533e",
+            "1f7f54f7a6d7440e0e8a681ee8aff25664d4354b7a6afd0fbde51af4246f908b",
+            "616779d68f4fdf0de5b2f11c50a4d9f3a7e849abd33765191f9ae3734f605649",
+            "77b3811eda9b9280122033cbe23030a2505b7c41ddc241fefe76750ae210724c",
+        ];
+        for (n, root) in roots.iter().enumerate() {
+            let root = Hash::from_str(root).unwrap();
+            assert!(merkle_root_direct(leaves[..n].to_vec()) == root, "{} leaves", n);
+            assert!(merkle_root(&leaves[..n], |h| Ok::<_, ()>(*h)).unwrap() == root, "{} leaves", n);
+        }
+        // the vectors are of the tree built pair by pair, padding odd rows with zero
+        let digest = |l: Hash, r: Hash| {
+            let mut buf = [0u8; 64];
+            buf[0..32].copy_from_slice(l.as_bytes());
+            buf[32..64].copy_from_slice(r.as_bytes());
+            Hash::digest(&buf)
+        };
+        let l = &leaves;
+        let five = digest(
+            digest(digest(l[0], l[1]), digest(l[2], l[3])),
+            digest(digest(l[4], Hash::zero()), Hash::zero()),
+        );
+        assert!(five == Hash::from_str(roots[5]).unwrap());
+    }
+}
@@ -5,22 +5,30 @@ mod merkle;
 mod version;
 mod atomic;
 mod interval;
+mod node_info;
+mod retry;
+mod semaphore;
 
 use std::io::Write;
 
-use bincode::{config::{Configuration, Fixint, LittleEndian, NoLimit}, enc::write::Writer, error::{DecodeError, EncodeError}, Decode, Encode};
+use bincode::{config::{Configuration, Fixint, LittleEndian, NoLimit}, error::{DecodeError, EncodeError}, Decode, Encode};
 use bitvec::{order::BitOrder, store::BitStore, vec::BitVec};
 
-pub use archived::{ArchivableTo, Archived};
+pub use archived::ArchivableTo;
 pub use encoding::{
     expect_len,
     to_hex, to_hex_lower,
 };
-pub use error::Error;
-pub use merkle::{merkle_root, merkle_root_direct};
+pub use error::{Context, Error};
+pub use merkle::merkle_root;
+#[cfg(test)]
+pub use merkle::merkle_root_direct;
 pub use version::Version;
 pub use atomic::Atomic;
 pub use interval::Interval;
+pub use node_info::NodeInfo;
+pub use retry::{retry, Backoff};
+pub use semaphore::Semaphore;
 
 #[macro_export]
 macro_rules! static_assert {
@@ -30,6 +38,8 @@ macro_rules! static_assert {
 }
 
 pub trait UninitVec<T: Copy> {
+    // callers must write every element before reading it
+    #[allow(clippy::uninit_vec)]
     unsafe fn uninit(len: usize) -> Vec<T> {
         let mut v = Vec::with_capacity(len);
         v.set_len(len);
@@ -74,12 +84,3 @@ impl<T: BitStore, O: BitOrder> UninitBitVec<T, O> for BitVec<T, O> {}
 pub const fn view_as_bytes<T: Copy>(value: &T) -> &[u8] {
     unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) }
 }
-
-pub const unsafe fn view_as_value<T: Copy>(bytes: &[u8]) -> Result<&T, ()> {
-    if bytes.len() != std::mem::size_of::<T>() {
-        return Err(());
-    }
-    return Ok(unsafe {
-        &*(bytes.as_ptr() as *const T)
-    })
-}
\ No newline at end of file
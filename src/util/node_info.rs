@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use super::Version;
+
+/// What the node knows about itself, recorded once at startup.
+#[derive(Clone, Copy)]
+pub struct NodeInfo {
+    pub version: Version,
+    /// The wall-clock time at which the node started
+    start_time: SystemTime,
+    /// The monotonic time at which the node started, to measure uptime
+    started_at: Instant,
+}
+
+impl NodeInfo {
+    /// Record that the node with `version` is starting now.
+    pub fn start(version: Version) -> Self {
+        Self {
+            version,
+            start_time: SystemTime::now(),
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Get the wall-clock time at which the node started.
+    pub fn start_time(&self) -> SystemTime {
+        self.start_time
+    }
+
+    /// Get how long the node has been running.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uptime_increases() {
+        let node_info = NodeInfo::start(Version::new(0, 1, 0));
+        let first = node_info.uptime();
+        std::thread::sleep(Duration::from_millis(5));
+        let second = node_info.uptime();
+        assert!(second > first);
+        assert!(second >= Duration::from_millis(5));
+        assert!(node_info.start_time() <= SystemTime::now());
+    }
+}
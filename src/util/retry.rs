@@ -0,0 +1,102 @@
+use std::{thread, time::Duration};
+
+use rand::Rng;
+
+use super::Error;
+
+/// Run `f` up to `attempts` times while it fails with transient errors,
+/// doubling the wait between attempts from `backoff`.
+/// Errors which aren't transient are returned immediately.
+pub fn retry<T>(attempts: u32, backoff: Duration, mut f: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+    let mut wait = backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if e.is_transient() && attempt < attempts => {
+                thread::sleep(wait);
+                wait *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Waits between reconnection attempts which double up to `max`, with random
+/// jitter so that clients which lost a connection together don't retry in lockstep
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max: max.max(initial), current: initial }
+    }
+
+    /// Get the wait before the next attempt, between half and all of the
+    /// current backoff, and double the backoff
+    pub fn next_wait(&mut self) -> Duration {
+        let wait = self.current.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        self.current = (self.current * 2).min(self.max);
+        wait
+    }
+
+    /// Start again from the initial backoff, once an attempt has succeeded
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+
+    #[test]
+    fn test_retry_transient() {
+        let mut calls = 0;
+        let result = retry(3, Duration::from_millis(1), || {
+            calls += 1;
+            match calls {
+                1 => Err(Error::transient("disk busy")),
+                _ => Ok(calls),
+            }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        let mut calls = 0;
+        let result: Result<(), _> = retry(3, Duration::from_millis(1), || {
+            calls += 1;
+            Err(Error::transient("disk busy"))
+        });
+        assert!(result.unwrap_err().is_transient());
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_no_retry_fatal() {
+        let mut calls = 0;
+        let result: Result<(), _> = retry(3, Duration::from_millis(1), || {
+            calls += 1;
+            Err(error!("corrupt data"))
+        });
+        assert!(!result.unwrap_err().is_transient());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let (initial, max) = (Duration::from_millis(100), Duration::from_millis(1000));
+        let mut backoff = Backoff::new(initial, max);
+        let mut ceiling = initial;
+        for _ in 0..10 {
+            let wait = backoff.next_wait();
+            assert!(wait >= ceiling / 2 && wait <= ceiling);
+            ceiling = (ceiling * 2).min(max);
+        }
+        backoff.reset();
+        assert!(backoff.next_wait() <= initial);
+    }
+}
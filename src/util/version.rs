@@ -18,7 +18,7 @@ impl Version {
         }
     }
 
-    pub fn to_bytes(&self) -> [u8; 6] {
+    pub fn to_bytes(self) -> [u8; 6] {
         let mut bytes = [0u8; 6];
         bytes[0..2].copy_from_slice(&self.major.to_le_bytes());
         bytes[2..4].copy_from_slice(&self.minor.to_le_bytes());
@@ -29,18 +29,6 @@ impl Version {
     pub fn is_compatible(self, other: Version) -> bool {
         self.major == other.major
     }
-
-    pub fn unknown() -> Self {
-        Self {
-            major: 0,
-            minor: 0,
-            patch: 0,
-        }
-    }
-
-    pub fn is_unknown(self) -> bool {
-        self.major == 0 && self.minor == 0 && self.patch == 0
-    }
 }
 
 impl fmt::Display for Version {
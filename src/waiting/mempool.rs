@@ -26,10 +26,40 @@ impl<T: Eq + Hash> Mempool<T> {
     pub fn clear(&mut self) {
         self.pool.clear();
     }
+    #[cfg(test)]
     /// Drains this `Mempool` with `f: T -> U`, returning all items as a `Vec<U>`.
     pub fn drain<U>(&mut self, f: impl Fn(T) -> U) -> Vec<U> {
         let new_pool = FilteredSpaceSaving::new(self.pool.k());
         let pool = std::mem::replace(&mut self.pool, new_pool);
         pool.into_iter().map(|(t, _)| f(t)).collect()
     }
+    /// Drains up to `limit` items of the highest difficulty with `f: T -> U`,
+    /// keeping the rest in this `Mempool`.
+    pub fn drain_top<U>(&mut self, limit: usize, f: impl Fn(T) -> U) -> Vec<U> {
+        let new_pool = FilteredSpaceSaving::new(self.pool.k());
+        let pool = std::mem::replace(&mut self.pool, new_pool);
+        let mut items = pool.into_sorted_iter();
+        let top = items.by_ref().take(limit).map(|(t, _)| f(t)).collect();
+        for (t, counter) in items {
+            self.pool.insert(t, counter.estimated_count());
+        }
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain_top() {
+        let mut mempool = Mempool::new(8);
+        for i in 1..=5u64 {
+            mempool.insert(i, Difficulty::from_le_fixed(&(i * 100).to_le_bytes()));
+        }
+        assert_eq!(mempool.drain_top(2, |x| x), vec![5, 4]);
+        assert_eq!(mempool.drain_top(2, |x| x), vec![3, 2]);
+        assert_eq!(mempool.drain_top(2, |x| x), vec![1]);
+        assert!(mempool.drain(|x| x).is_empty());
+    }
 }
\ No newline at end of file
@@ -1,5 +1,5 @@
 use std::hash::{Hash, Hasher};
-use crate::{process::{Handle, Mailbox, Message, Process}, protocol::OpenFull, util::Error};
+use crate::{keys::Public, process::{Handle, Mailbox, Message, Process}, protocol::{Open, OpenFull}, util::Error};
 use super::Mempool;
 
 struct Entry(Box<OpenFull>);
@@ -18,15 +18,26 @@ impl Hash for Entry {
 pub struct OpenPool {
     pool: Mempool<Entry>,
     state: Handle,
-    leader_mode: bool
+    leader_mode: bool,
+    /// The most opens included in a single block; the rest wait for the next slot
+    max_opens_per_block: usize,
+    /// Opens of accounts which already exist are rejected
+    account_exists: Box<dyn Fn(&Public) -> bool + Send>
 }
 
 impl OpenPool {
-    pub fn new(size: usize, state: Handle) -> Self {
+    pub fn new(
+        size: usize,
+        state: Handle,
+        max_opens_per_block: usize,
+        account_exists: Box<dyn Fn(&Public) -> bool + Send>
+    ) -> Self {
         Self {
             pool: Mempool::new(size),
             state,
-            leader_mode: false
+            leader_mode: false,
+            max_opens_per_block,
+            account_exists
         }
     }
 }
@@ -44,15 +55,19 @@ impl Process for OpenPool {
                     self.leader_mode = false;
                 },
                 Message::Open(open) if self.leader_mode => {
+                    if (self.account_exists)(&open.account) {
+                        continue;
+                    }
                     let hash = match open.verify_and_hash() {
                         Ok(v) => v,
                         _ => continue
                     };
-                    let difficulty = open.work.difficulty(&hash);
+                    // Prioritize by the difficulty of the work, which is over the work hash
+                    let difficulty = open.work.difficulty(&Open::work_hash(&open.account, &open.representative));
                     self.pool.insert(Entry(Box::new(OpenFull::new(*open, hash))), difficulty);
                 },
                 Message::NewLeaderSlot(slot) if self.leader_mode => {
-                    let opens = self.pool.drain(|x| x.0);
+                    let opens = self.pool.drain_top(self.max_opens_per_block, |x| x.0);
                     self.state.send(Message::OpenList(Box::new((slot, opens))));
                 },
                 _ => {}
@@ -60,3 +75,89 @@ impl Process for OpenPool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::{work_fixtures, Work}, process, protocol::Slot};
+    use std::time::Duration;
+
+    #[test]
+    fn test_emit_opens_on_slot() {
+        let (id, representative) = (work_fixtures::identity(0), work_fixtures::identity(1).public);
+        let valid = Open::sign(&id, representative, work_fixtures::open_work(0));
+        let insufficient = Open::sign(&work_fixtures::identity(2), representative, Work::zero());
+
+        let (state, mut mailbox) = process::channel();
+        let open_pool = process::spawn(OpenPool::new(25, state, 16, Box::new(|_| false)));
+        // opens are only pooled while leading
+        open_pool.send(Message::Open(Box::new(valid)));
+        open_pool.send(Message::StartLeaderMode);
+        for open in [valid, insufficient] {
+            open_pool.send(Message::Open(Box::new(open)));
+        }
+        let slot = Slot::zero().next();
+        open_pool.send(Message::NewLeaderSlot(slot));
+        let (at, opens) = match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::OpenList(v)) => *v,
+            _ => panic!("expected an open list"),
+        };
+        assert!(at == slot);
+        assert_eq!(opens.len(), 1);
+        assert!(opens[0].open.account == id.public && opens[0].hash == valid.hash());
+
+        // drained opens aren't emitted again
+        open_pool.send(Message::NewLeaderSlot(slot.next()));
+        match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::OpenList(v)) => assert!(v.1.is_empty()),
+            _ => panic!("expected an open list"),
+        }
+    }
+
+    #[test]
+    fn test_reject_existing_account() {
+        let genesis = work_fixtures::identity(0);
+        let open = Open::sign(&genesis, work_fixtures::identity(1).public, work_fixtures::open_work(0));
+
+        let (state, mut mailbox) = process::channel();
+        let open_pool = process::spawn(OpenPool::new(25, state, 16, Box::new(move |public| *public == genesis.public)));
+        open_pool.send(Message::StartLeaderMode);
+        open_pool.send(Message::Open(Box::new(open)));
+        open_pool.send(Message::NewLeaderSlot(Slot::zero().next()));
+        match mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::OpenList(v)) => assert!(v.1.is_empty()),
+            _ => panic!("expected an open list"),
+        }
+    }
+
+    #[test]
+    fn test_max_opens_per_block() {
+        let representative = work_fixtures::identity(1).public;
+        let opens: Vec<Open> = [0, 3, 4, 5].into_iter()
+            .map(|index| Open::sign(&work_fixtures::identity(index), representative, work_fixtures::open_work(index)))
+            .collect();
+
+        let (state, mut mailbox) = process::channel();
+        let open_pool = process::spawn(OpenPool::new(25, state, 3, Box::new(|_| false)));
+        open_pool.send(Message::StartLeaderMode);
+        for open in &opens {
+            open_pool.send(Message::Open(Box::new(*open)));
+        }
+        // only the limit is emitted per block, and the rest wait for the next
+        let mut emitted = Vec::new();
+        for expected in [3, 1, 0] {
+            open_pool.send(Message::NewLeaderSlot(Slot::zero().next()));
+            match mailbox.recv_timeout(Duration::from_secs(1)) {
+                Some(Message::OpenList(v)) => {
+                    assert_eq!(v.1.len(), expected);
+                    emitted.extend(v.1.iter().map(|open| open.open.account));
+                }
+                _ => panic!("expected an open list"),
+            }
+        }
+        emitted.sort();
+        let mut accounts: Vec<Public> = opens.iter().map(|open| open.account).collect();
+        accounts.sort();
+        assert!(emitted == accounts);
+    }
+}
@@ -1,16 +1,26 @@
-use std::{sync::Arc, time::Duration};
+use std::time::Duration;
 
-use crate::{error, keys::Public, process::{Handle, Mailbox, Message, Process}, protocol::{Task, Tx, TxFull, TxHalf}, state::{Bank, Batch}, util::Error};
+use crate::{error, keys::Public, process::{Handle, Mailbox, Message, Process}, protocol::{Amount, TxFull, TxHalf}, util::Error};
 
+// the transactions are boxed, so that each stage is transmuted into the next in place
+#[allow(clippy::vec_box)]
 pub struct TxFiller {
     tx_half_list: Vec<Box<TxHalf>>,
     db: Handle,
-    state: Handle
+    state: Handle,
+    /// Transfers of less than this amount are left out of the block
+    min_transaction_amount: Amount
 }
 
 impl TxFiller {
-    pub fn new(tx_half_list: Vec<Box<TxHalf>>, db: Handle, state: Handle) -> Self {
-        Self { tx_half_list, db, state }
+    #[allow(clippy::vec_box)]
+    pub fn new(
+        tx_half_list: Vec<Box<TxHalf>>,
+        db: Handle,
+        state: Handle,
+        min_transaction_amount: Amount
+    ) -> Self {
+        Self { tx_half_list, db, state, min_transaction_amount }
     }
 }
 
@@ -19,9 +29,11 @@ impl Process for TxFiller {
     const RESTART_ON_CRASH: bool = false;
 
     fn run(&mut self, mut mailbox: Mailbox, handle: Handle) -> Result<(), Error> {
-        let requests: Vec<Public> = self.tx_half_list.iter().map(|x| {
-            [x.tx.from, x.tx.to]
-        }).flatten().collect();
+        let min_transaction_amount = self.min_transaction_amount;
+        self.tx_half_list.retain(|x| x.tx().meets_min_amount(min_transaction_amount));
+        let requests: Vec<Public> = self.tx_half_list.iter().flat_map(|x| {
+            [x.tx().from, x.tx().to]
+        }).collect();
         let requests_len = requests.len();
         self.db.send(Message::BatchedRetrieveRequest(Box::new((handle.clone(), requests))));
         let responses = loop {
@@ -39,15 +51,50 @@ impl Process for TxFiller {
                 requests_len
             ));
         }
+        // Leave out transactions with an account that isn't indexed, after pairing each with its own indices
         let tx_full_list: Vec<Box<TxFull>> = responses
             .chunks_exact(2)
-            .filter_map(|x| Some((x[0]?, x[1]?)))
             .zip(self.tx_half_list.drain(..))
-            .map(|((from_index, to_index), tx_half)| {
-                tx_half.provide(from_index, to_index)
-            })
+            .filter_map(|(x, tx_half)| Some(tx_half.provide(x[0]?, x[1]?)))
             .collect();
         self.state.send(Message::TxFullList(Box::new(tx_full_list)));
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::work_fixtures, process, protocol::{Tx, TxEmpty, TxKind}};
+
+    #[test]
+    fn test_fill_indices() {
+        let (id, work) = (work_fixtures::identity(0), work_fixtures::first_tx_work());
+        let amounts = [1, 2, 3];
+        let tx_half_list = amounts.iter()
+            .map(|&amount| TxEmpty::boxed(Tx::sign(&id, 0, Public::random(), TxKind::Transfer(Amount::from_raw(amount)), work)).verify().unwrap())
+            .collect::<Vec<_>>();
+        let hashes: Vec<_> = tx_half_list.iter().map(|x| x.hash()).collect();
+        let (db, mut db_mailbox) = process::channel();
+        let (state, mut state_mailbox) = process::channel();
+        process::spawn(TxFiller::new(tx_half_list, db, state, Amount::zero()));
+
+        // the receiver of the second transaction isn't indexed
+        let (filler, requests) = match db_mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::BatchedRetrieveRequest(v)) => *v,
+            _ => panic!("expected a retrieve request"),
+        };
+        assert_eq!(requests.len(), 6);
+        filler.send(Message::BatchedRetrieveResponse(Box::new(vec![Some(0), Some(10), Some(0), None, Some(0), Some(30)])));
+        let tx_full_list = match state_mailbox.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::TxFullList(v)) => *v,
+            _ => panic!("expected transactions"),
+        };
+        assert_eq!(tx_full_list.len(), 2);
+        for (tx_full, (hash, to_index)) in tx_full_list.iter().zip([(hashes[0], 10), (hashes[2], 30)]) {
+            assert_eq!(tx_full.hash(), hash);
+            assert_eq!((tx_full.from_index, tx_full.to_index), (0, to_index));
+        }
+        assert_eq!(tx_full_list[1].tx().amount, Amount::from_raw(3));
+    }
+}
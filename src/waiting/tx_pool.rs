@@ -1,19 +1,22 @@
-use std::hash::{Hash, Hasher};
-use crate::{process::{self, Handle, Mailbox, Message, Process}, protocol::TxHalf, util::Error};
+use std::{hash::{Hash, Hasher}, time::{Duration, Instant}};
+use crate::{keys::Public, process::{self, Handle, Mailbox, Message, Process}, protocol::{Amount, TxEmpty, TxHalf}, util::Error};
 use super::{Mempool, TxFiller};
 
+/// The longest finalized account writes wait before being flushed to the db
+const DB_FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
 struct Entry(Box<TxHalf>);
 impl PartialEq for Entry {
     fn eq(&self, other: &Self) -> bool {
-        self.0.tx.nonce == other.0.tx.nonce
-        && self.0.tx.from == other.0.tx.from
+        self.0.tx().nonce == other.0.tx().nonce
+        && self.0.tx().from == other.0.tx().from
     }
 }
 impl Eq for Entry {}
 impl Hash for Entry {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.0.tx.nonce.hash(state);
-        self.0.tx.from.hash(state);
+        self.0.tx().nonce.hash(state);
+        self.0.tx().from.hash(state);
     }
 }
 
@@ -21,51 +24,200 @@ pub struct TxPool {
     pool: Mempool<Entry>,
     db: Handle,
     state: Handle,
-    leader_mode: bool
+    leader_mode: bool,
+    /// Transfers of less than this amount are rejected as dust
+    min_transaction_amount: Amount,
+    /// The most transactions drained from this pool each slot; the rest wait for the next slot
+    max_txs_per_slot: usize,
+    /// Finalized account writes waiting to be flushed to the db
+    db_batch: Vec<(Public, u64)>,
+    /// The number of account writes at which `db_batch` is flushed
+    db_batch_size: usize,
+    /// When the oldest write in `db_batch` was queued
+    db_batch_since: Option<Instant>
 }
 
 impl TxPool {
-    pub fn new(size: usize, db: Handle, state: Handle) -> Self {
+    pub fn new(
+        size: usize,
+        db: Handle,
+        state: Handle,
+        db_batch_size: usize,
+        min_transaction_amount: Amount,
+        max_txs_per_slot: usize
+    ) -> Self {
         Self {
             pool: Mempool::new(size),
             db,
             state,
-            leader_mode: false
+            leader_mode: false,
+            min_transaction_amount,
+            max_txs_per_slot,
+            db_batch: Vec::with_capacity(db_batch_size),
+            db_batch_size,
+            db_batch_since: None
+        }
+    }
+    /// Admit a transaction into the pool if it's valid and not dust,
+    /// returning whether it was admitted.
+    fn on_tx_empty(&mut self, tx_empty: Box<TxEmpty>) -> bool {
+        let tx_half = match tx_empty.verify() {
+            Ok(v) => v,
+            Err(_) => return false
+        };
+        if !tx_half.tx().meets_min_amount(self.min_transaction_amount) {
+            return false;
+        }
+        let difficulty = tx_half.tx().work.difficulty(&tx_half.hash());
+        self.pool.insert(Entry(tx_half), difficulty);
+        true
+    }
+    /// Drain the transactions of the next slot, highest work first, leaving
+    /// any beyond `max_txs_per_slot` pooled for the slots after it.
+    #[allow(clippy::vec_box)] // boxed to be transmuted into `TxFull` in place
+    fn drain_slot(&mut self) -> Vec<Box<TxHalf>> {
+        self.pool.drain_top(self.max_txs_per_slot, |x| x.0)
+    }
+    /// Queue the account writes of finalized transactions, flushing them
+    /// to the db if the batch is full.
+    fn on_finalized_accounts(&mut self, accounts: Vec<(Public, u64)>, handle: &Handle) {
+        if self.db_batch.is_empty() {
+            self.db_batch_since = Some(Instant::now());
+        }
+        self.db_batch.extend(accounts);
+        if self.db_batch.len() >= self.db_batch_size {
+            self.flush_db_batch(handle);
         }
     }
+    /// Write all queued account writes to the db in a single request.
+    fn flush_db_batch(&mut self, handle: &Handle) {
+        self.db_batch_since = None;
+        if self.db_batch.is_empty() {
+            return;
+        }
+        let batch = std::mem::replace(&mut self.db_batch, Vec::with_capacity(self.db_batch_size));
+        self.db.send(Message::BatchedTryInsertRequest(Box::new((handle.clone(), batch))));
+    }
+    /// Get the time until the queued account writes must be flushed, if any.
+    fn time_until_flush(&self) -> Option<Duration> {
+        self.db_batch_since.map(|since| DB_FLUSH_INTERVAL.saturating_sub(since.elapsed()))
+    }
 }
 
 impl Process for TxPool {
     const NAME: &'static str = "TxPool";
     const RESTART_ON_CRASH: bool = true;
 
-    fn run(&mut self, mut mailbox: Mailbox, _: Handle) -> Result<(), Error> {
+    fn run(&mut self, mut mailbox: Mailbox, handle: Handle) -> Result<(), Error> {
         loop {
-            match mailbox.recv() {
+            let msg = match self.time_until_flush() {
+                Some(timeout) => match mailbox.recv_timeout(timeout) {
+                    Some(msg) => msg,
+                    None => {
+                        self.flush_db_batch(&handle);
+                        continue;
+                    }
+                },
+                None => mailbox.recv()
+            };
+            match msg {
                 Message::StartLeaderMode => self.leader_mode = true,
                 Message::EndLeaderMode => {
                     self.pool.clear();
                     self.leader_mode = false;
                 },
                 Message::TxEmpty(tx_empty) => {
-                    let hash = match tx_empty.tx.verify_and_hash() {
-                        Ok(v) => v,
-                        Err(_) => continue
-                    };
-                    let tx_half = tx_empty.provide(hash);
-                    let difficulty = tx_half.tx.work.difficulty(&tx_half.hash);
-                    self.pool.insert(Entry(tx_half), difficulty);
+                    self.on_tx_empty(tx_empty);
                 },
-                Message::NewLeaderSlot(slot) => {
-                    let tx_half_list = self.pool.drain(|x| x.0);
+                Message::NewLeaderSlot(_) => {
+                    let tx_half_list = self.drain_slot();
                     process::spawn(TxFiller::new(
                         tx_half_list,
                         self.db.clone(),
                         self.state.clone(),
+                        self.min_transaction_amount,
                     ));
                 },
+                Message::FinalizedAccounts(accounts) => {
+                    self.on_finalized_accounts(*accounts, &handle);
+                },
                 _ => {}
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keys::{work_fixtures, Difficulty, Work}, protocol::{Tx, TxKind}};
+
+    const BATCH_SIZE: usize = 16;
+
+    fn tx_pool() -> (TxPool, Handle, Mailbox) {
+        let (db, db_mailbox) = process::channel();
+        let (state, _) = process::channel();
+        let (handle, _) = process::channel();
+        (TxPool::new(100, db, state, BATCH_SIZE, Amount::zero(), 100), handle, db_mailbox)
+    }
+
+    #[test]
+    fn test_min_transaction_amount() {
+        let (mut tx_pool, _, _) = tx_pool();
+        tx_pool.min_transaction_amount = Amount::from_raw(100);
+        let (id, work) = (work_fixtures::identity(0), work_fixtures::first_tx_work());
+        let tx = |kind| TxEmpty::boxed(Tx::sign(&id, 0, Public::random(), kind, work));
+
+        assert!(!tx_pool.on_tx_empty(tx(TxKind::Transfer(Amount::from_raw(99)))));
+        assert!(tx_pool.on_tx_empty(tx(TxKind::Transfer(Amount::from_raw(100)))));
+        assert_eq!(tx_pool.pool.drain(|x| x.0.tx().amount), vec![Amount::from_raw(100)]);
+        // changing representative transfers nothing, so it's never dust
+        assert!(tx_pool.on_tx_empty(tx(TxKind::ChangeRepresentative)));
+    }
+
+    #[test]
+    fn test_batched_db_writes() {
+        let (mut tx_pool, handle, mut db) = tx_pool();
+        let accounts: Vec<(Public, u64)> = (0..BATCH_SIZE as u64).map(|i| (Public::random(), i)).collect();
+        for &account in accounts.iter() {
+            tx_pool.on_finalized_accounts(vec![account], &handle);
+        }
+        match db.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::BatchedTryInsertRequest(v)) => assert_eq!(v.1, accounts),
+            _ => panic!("expected a batched write"),
+        }
+        assert!(db.recv_timeout(Duration::from_millis(10)).is_none());
+        assert!(tx_pool.time_until_flush().is_none());
+    }
+
+    #[test]
+    fn test_flush_on_timer() {
+        let (mut tx_pool, handle, mut db) = tx_pool();
+        tx_pool.on_finalized_accounts(vec![(Public::random(), 0)], &handle);
+        assert!(db.recv_timeout(Duration::from_millis(10)).is_none());
+        assert!(tx_pool.time_until_flush().unwrap() <= DB_FLUSH_INTERVAL);
+        process::spawn(tx_pool).send(Message::FinalizedAccounts(Box::new(vec![(Public::random(), 1)])));
+        match db.recv_timeout(Duration::from_secs(1)) {
+            Some(Message::BatchedTryInsertRequest(v)) => assert_eq!(v.1.len(), 2),
+            _ => panic!("expected a batched write"),
+        }
+    }
+
+    #[test]
+    fn test_max_txs_per_slot() {
+        let (mut tx_pool, _, _) = tx_pool();
+        tx_pool.max_txs_per_slot = 3;
+        let id = work_fixtures::identity(0);
+        // Work at `Difficulty::BASE` is too slow to generate for each nonce, so
+        // these skip `TxEmpty::verify`
+        for nonce in 0..5u64 {
+            let tx = Tx::sign(&id, nonce, Public::random(), TxKind::Transfer(Amount::from_raw(1)), Work::zero());
+            let tx_half = TxEmpty::boxed(tx).assume_verified();
+            tx_pool.pool.insert(Entry(tx_half), Difficulty::from_le_fixed(&((nonce + 1) * 100).to_le_bytes()));
+        }
+        let nonces = |txs: Vec<Box<TxHalf>>| txs.iter().map(|tx| tx.tx().nonce).collect::<Vec<_>>();
+        assert_eq!(nonces(tx_pool.drain_slot()), vec![4, 3, 2]);
+        assert_eq!(nonces(tx_pool.drain_slot()), vec![1, 0]);
+        assert!(tx_pool.drain_slot().is_empty());
+    }
+}